@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use image::{ImageFormat, ImageReader};
+use std::io::Cursor;
+
+/// Longest edge of a thumbnail, in pixels, unless the caller asks for another size
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 128;
+
+/// Scale album art down so its longest edge is at most `size` pixels, keeping the
+/// aspect ratio, and encode it as PNG. Art that is already small enough is only
+/// re-encoded. Returns `None` for formats the enabled decoders can't read, so
+/// callers can fall back to the full image.
+pub fn thumbnail(art: &[u8], size: u32) -> Result<Option<Vec<u8>>> {
+    let reader = ImageReader::new(Cursor::new(art))
+        .with_guessed_format()
+        .context("Failed to read album art")?;
+    if reader.format().is_none_or(|format| !format.reading_enabled()) {
+        return Ok(None);
+    }
+    let Ok(image) = reader.decode() else {
+        return Ok(None);
+    };
+
+    let image = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .context("Failed to encode thumbnail")?;
+    Ok(Some(png))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn dimensions(png: &[u8]) -> (u32, u32) {
+        image::load_from_memory(png).unwrap().dimensions()
+    }
+
+    #[test]
+    fn test_thumbnail_keeps_aspect_ratio() {
+        let thumb = thumbnail(&png(512, 256), 128).unwrap().unwrap();
+        assert_eq!(dimensions(&thumb), (128, 64));
+
+        // Small art is never scaled up
+        let thumb = thumbnail(&png(64, 32), 128).unwrap().unwrap();
+        assert_eq!(dimensions(&thumb), (64, 32));
+    }
+
+    #[test]
+    fn test_unsupported_art_is_skipped() {
+        assert!(thumbnail(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", 128).unwrap().is_none());
+        assert!(thumbnail(b"GIF89a not really a gif", 128).unwrap().is_none());
+    }
+}
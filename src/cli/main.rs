@@ -1,24 +1,125 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+use chrono::{Datelike, Duration, Local, TimeZone};
 use clap::{Parser, Subcommand};
-use serde_json;
+use serde::Serialize;
+use std::fmt;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
+use std::process::ExitCode;
 
-use gopal::database::{Database, ListeningStats};
+mod render;
+mod report;
+#[cfg(feature = "tui")]
+mod tui;
+use render::{renderer_for, write_ndjson_line, StatsRenderer};
+
+use gopal::content_type::ContentType;
+use gopal::daemon_status::{DaemonHealth, DaemonStatus, DEFAULT_STALE_AFTER_SECS};
+use gopal::recent_activity::HourlyActivity;
+use gopal::database::{database_key, listening_day, listening_day_start, AnonymousPlay, Database, DashboardLimits, DayZone, GroupBy, ListeningStats, PlayerShare, RankEntity, SessionWithMetadata, StatsFilter, TimeBucket, DEFAULT_PAUSE_PENALTY, DEFAULT_TIMING_TOLERANCE, DEFAULT_RECENCY_HALF_LIFE_DAYS, LISTENING_BLOCK_GAP, QUERY_LOG_TARGET};
+use gopal::import::DEFAULT_IMPORT_DURATION_SECS;
+use gopal::error::GopalError;
+use gopal::locale::OutputLocale;
+use gopal::metadata_rules::ArtistSplitter;
+use gopal::now_playing::{NowPlaying, NowPlayingTemplate};
+use gopal::paths::{default_database_path, default_recording_pause_file, default_stats_checkpoint_file, default_status_file, expand_path};
 
 #[derive(Parser)]
 #[command(name = "gopal-cli")]
 #[command(about = "Query music listening statistics")]
 #[command(version = "0.1.0")]
+#[command(after_help = "Exit codes:\n  0  Success\n  1  Query or other error\n  2  Invalid arguments\n  3  Database not found\n  4  Query returned no results\n  5  Daemon not running or stale (ping)")]
 struct Args {
-    /// Path to the SQLite database file
-    #[arg(short, long, default_value = "~/.local/share/gopal/music.db")]
-    database: String,
+    /// Path to the SQLite database file [default: $XDG_DATA_HOME/gopal/music.db].
+    /// `:memory:` opens an empty in-memory database.
+    #[arg(short, long)]
+    database: Option<String>,
+
+    /// Key of a database encrypted with SQLCipher [default: $GOPAL_DB_KEY]. Needs a
+    /// build with the `encryption` feature.
+    #[arg(long, value_name = "KEY")]
+    key: Option<String>,
+
+    /// Query an export instead of the database: it is loaded into an in-memory
+    /// database first. `-` reads the export from stdin.
+    #[arg(long, value_name = "FILE", conflicts_with = "database")]
+    from_export: Option<PathBuf>,
+
+    /// Format of --from-export [default: lastfm for .csv files, otherwise listenbrainz]
+    #[arg(long, requires = "from_export")]
+    export_format: Option<ExternalFormat>,
 
     /// Output format
     #[arg(short, long, default_value = "human")]
     format: OutputFormat,
 
+    /// Shell command that renders results for `--format command`. It receives the
+    /// result as JSON on stdin and the command name in $GOPAL_REPORT.
+    #[arg(long, value_name = "COMMAND", required_if_eq("format", "command"))]
+    render_command: Option<String>,
+
+    /// Columns to print with `--format csv`, in order, e.g. `title,artist,listened_time`
+    #[arg(long, global = true, value_delimiter = ',', value_name = "COLUMNS")]
+    columns: Option<Vec<String>>,
+
+    /// Locale for dates and number grouping in human output, e.g. `de_DE` or `en_US`.
+    /// Defaults to $GOPAL_LOCALE, then the system locale.
+    #[arg(long, global = true, value_name = "LOCALE")]
+    locale: Option<String>,
+
+    /// Suppress decorative output such as headers, separators and hints
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Count replays of a track within this many seconds as a single play
+    #[arg(long, global = true, value_name = "SECONDS")]
+    dedupe_window_seconds: Option<i64>,
+
+    /// Round durations in human and csv output to this unit (json keeps raw seconds)
+    #[arg(long, global = true, default_value = "second")]
+    granularity: Granularity,
+
+    /// Always recompute statistics instead of reusing cached results
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Leave out session ids, timestamps and raw player metadata so results can be
+    /// shared; history is reduced to title, artist, album and listened time, and export
+    /// to each track's plays and listened time. Only for stats, top-tracks, top-artists,
+    /// history and export.
+    #[arg(long, global = true)]
+    anonymize: bool,
+
+    /// Count each artist of a multi-artist credit like "A, B feat. C" separately in
+    /// artist stats instead of the combined string
+    #[arg(long, global = true)]
+    split_artists: bool,
+
+    /// Separator between artists for --split-artists, matched case-insensitively
+    /// (repeatable) [default: ", ", " & ", " feat. ", " ft. ", " featuring "]
+    #[arg(long, global = true, value_name = "SEPARATOR", requires = "split_artists")]
+    artist_separator: Vec<String>,
+
+    /// Local hour at which a day begins, for listeners up past midnight: with 4, a
+    /// session at 2am counts toward the previous day in today, dates and weekday stats
+    #[arg(long, global = true, value_name = "HOUR", default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..24))]
+    day_start_hour: u32,
+
+    /// Divide a session that runs past the start of a day between both days, by how
+    /// long it lasted in each, in weekday, consistency and heatmap stats
+    #[arg(long, global = true)]
+    split_day_boundary: bool,
+
+    /// Reckon days, and the periods and stats built from them, in UTC instead of local
+    /// time. Defaults to on when $GOPAL_UTC is 1.
+    #[arg(long, global = true)]
+    utc: bool,
+
+    /// Print each SQL statement the command runs to stderr, with its parameters.
+    /// `--explain=plan` also prints SQLite's query plans once the command finishes.
+    #[arg(long, global = true, hide = true, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "sql")]
+    explain: Option<Explain>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -28,6 +129,12 @@ enum OutputFormat {
     Human,
     Json,
     Csv,
+    /// One compact JSON object per line
+    Ndjson,
+    /// Pipe JSON to the program given by --render-command
+    Command,
+    /// Prometheus text format, e.g. for a Pushgateway (stats and status only)
+    Prometheus,
 }
 
 #[derive(Subcommand)]
@@ -49,6 +156,30 @@ enum Commands {
         /// Limit number of results for top lists
         #[arg(short, long, default_value = "10")]
         limit: usize,
+
+        /// Break listening time down by a single dimension instead of the summary
+        #[arg(short, long)]
+        group_by: Option<GroupDimension>,
+
+        /// Also show engaged time, which discounts sessions spent mostly paused
+        #[arg(long)]
+        engagement: bool,
+
+        /// How strongly paused time discounts engaged time, from 0 (no effect) to 1
+        #[arg(long, default_value_t = DEFAULT_PAUSE_PENALTY)]
+        pause_penalty: f64,
+
+        /// Only cover what was played since the last `--since-last` run, or since the
+        /// start of --period the first time
+        #[arg(long, conflicts_with_all = ["start_date", "end_date"])]
+        since_last: bool,
+
+        /// Forget where `--since-last` left off, starting from --period again
+        #[arg(long, requires = "since_last")]
+        reset_checkpoint: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 
     /// Show top tracks
@@ -64,6 +195,20 @@ enum Commands {
         /// Sort by listening time or play count
         #[arg(short, long, default_value = "time")]
         sort_by: SortBy,
+
+        /// Compare ranks against the previous period of the same length
+        #[arg(long)]
+        with_movement: bool,
+
+        /// List how much of each track was played on each player
+        #[arg(long, conflicts_with_all = ["with_movement", "recency_weighted"])]
+        breakdown_by_player: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        recency: RecencyArgs,
     },
 
     /// Show top artists
@@ -75,6 +220,12 @@ enum Commands {
         /// Number of artists to show
         #[arg(short, long, default_value = "20")]
         limit: usize,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        recency: RecencyArgs,
     },
 
     /// Show listening history
@@ -83,126 +234,1265 @@ enum Commands {
         #[arg(short, long, default_value = "today")]
         period: TimePeriod,
 
-        /// Number of sessions to show
-        #[arg(short, long, default_value = "50")]
-        limit: usize,
-    },
+        /// Number of sessions to show, or 0 for all of them
+        #[arg(short, long, default_value = "50")]
+        limit: usize,
+
+        /// Leave out a player, by bus name or identity; repeat to exclude several
+        #[arg(long, value_name = "PLAYER")]
+        exclude_player: Vec<String>,
+
+        /// Merge back-to-back sessions of the same track, such as a pause and resume,
+        /// when at most GAP apart, e.g. "30s" or "10m"
+        #[arg(long, value_name = "GAP", num_args = 0..=1, default_missing_value = "5m")]
+        collapse: Option<String>,
+    },
+
+    /// Show what is currently playing
+    Now {
+        /// Format string, e.g. "{artist} - {title} ({elapsed}/{length})".
+        /// Available fields: title, artist, album, player, elapsed, length, percent, block.
+        /// A width such as {title:30} cuts the field to that many terminal columns
+        #[arg(short = 't', long)]
+        format_template: Option<String>,
+
+        /// Report elapsed as the time since the track started, pauses included
+        #[arg(long)]
+        include_paused: bool,
+    },
+
+    /// Show how often tracks are skipped, overall and per artist
+    SkipStats {
+        /// Time period to analyze
+        #[arg(short, long, default_value = "week")]
+        period: TimePeriod,
+
+        /// Number of artists to show
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+
+    /// Show what you listened to on this date in earlier years
+    OnThisDay {
+        /// Calendar day as MM-DD [default: today]
+        #[arg(long, value_name = "MM-DD")]
+        date: Option<String>,
+
+        /// Number of tracks to show per year
+        #[arg(short, long, default_value = "5")]
+        limit: usize,
+    },
+
+    /// Replay a day's listening as a timeline, with the gaps between plays
+    Replay {
+        /// Listening day as YYYY-MM-DD [default: today]
+        date: Option<String>,
+    },
+
+    /// Show listening time by source: the site web players streamed from, or the player
+    Sources {
+        /// Time period to analyze
+        #[arg(short, long, default_value = "week")]
+        period: TimePeriod,
+
+        /// Number of sources to show
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+
+    /// Show tracks you played a lot but haven't heard lately
+    Rediscover {
+        /// Only tracks not played since this long ago, e.g. "3 months ago", or a YYYY-MM-DD date
+        #[arg(long, default_value = "3 months ago")]
+        not_since: String,
+
+        /// Only tracks played at least this many times
+        #[arg(long, default_value = "10", value_parser = clap::value_parser!(i64).range(0..))]
+        min_plays: i64,
+
+        /// Number of tracks to show
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// Show tracks by the rating your players report, trusting ratings of tracks you
+    /// play often over ones heard once
+    TopRated {
+        /// Time period to analyze
+        #[arg(short, long, default_value = "all-time")]
+        period: TimePeriod,
+
+        /// Number of tracks to show
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// Show whether you're listening more or less than usual: the last window's
+    /// listening against the average of the windows before it
+    Trend {
+        /// Length of each window in days
+        #[arg(long, default_value = "7", value_parser = clap::value_parser!(i64).range(1..))]
+        window_days: i64,
+
+        /// Earlier windows to average for the baseline
+        #[arg(long, default_value = "4")]
+        lookback: usize,
+    },
+
+    /// Score how regularly you listen, from 0 to 100
+    Consistency {
+        /// Time period to score
+        #[arg(short, long, default_value = "month")]
+        period: TimePeriod,
+    },
+
+    /// Show average daily listening for each day of the week
+    Weekdays {
+        /// Time period to average over
+        #[arg(short, long, default_value = "month")]
+        period: TimePeriod,
+    },
+
+    /// Show an album's tracks in album order with their listening totals
+    Album {
+        /// Album name
+        name: String,
+
+        /// Only tracks by this artist, for album names shared by several artists
+        #[arg(long)]
+        artist: Option<String>,
+    },
+
+    /// Show an artist's listening totals and, as catalog exploration, when you first
+    /// heard each of their tracks
+    Artist {
+        /// Artist name, matched case-insensitively
+        name: String,
+    },
+
+    /// Show which albums you've heard in full: distinct tracks heard against the
+    /// album's track count, from the highest track number seen
+    AlbumCompleteness {
+        /// Time period to analyze
+        #[arg(short, long, default_value = "all-time")]
+        period: TimePeriod,
+
+        /// Number of albums to show
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// List tracks missing their artist, album, length or art, most played first
+    Incomplete {
+        /// Number of tracks to show, or 0 for all of them
+        #[arg(short, long, default_value = "50")]
+        limit: usize,
+    },
+
+    /// Show how an artist's or track's rank changed over time
+    #[command(group(clap::ArgGroup::new("entity").required(true).args(["artist", "track_id"])))]
+    RankHistory {
+        /// Artist to follow
+        #[arg(long)]
+        artist: Option<String>,
+
+        /// Track id to follow, as shown in JSON output
+        #[arg(long)]
+        track_id: Option<String>,
+
+        /// Period to rank within
+        #[arg(long, default_value = "month")]
+        by: BucketSize,
+    },
+
+    /// Tag a session with a label, such as "gym" or "focus"
+    #[command(group(clap::ArgGroup::new("change").required(true).args(["set", "clear"])))]
+    Label {
+        /// Session id, as shown in JSON history output
+        #[arg(long)]
+        session: i64,
+
+        /// Label to give the session
+        #[arg(long)]
+        set: Option<String>,
+
+        /// Remove the session's label
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Show current database status
+    Status,
+
+    /// List players with how much each was used
+    Players {
+        /// Move every session of the first player to the second and remove the first,
+        /// for one app recorded under several names. Players are given by bus name or identity.
+        #[arg(long, num_args = 2, value_names = ["FROM", "INTO"])]
+        merge: Option<Vec<String>>,
+    },
+
+    /// Rebuild the stored all-time artist totals that `stats` reads instead of
+    /// recomputing them, when they're up to date
+    RefreshAggregates,
+
+    /// List finished sessions whose listened and paused time don't add up to how
+    /// long they lasted, to spot timing bugs
+    CheckTiming {
+        /// Seconds of drift to allow before reporting a session
+        #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMING_TOLERANCE)]
+        tolerance: i64,
+    },
+
+    /// List plays waiting to be scrobbled, with their attempts and last error
+    ScrobbleQueue {
+        /// Make every queued scrobble due now, including ones the daemon gave up on;
+        /// a running daemon submits them at its next queue check
+        #[arg(long)]
+        retry_now: bool,
+    },
+
+    /// List sessions still marked active, with how long ago they started. Active
+    /// sessions far older than any track usually mean the daemon crashed.
+    Active,
+
+    /// Close active sessions older than --max-age as orphaned, as the daemon does at startup
+    Cleanup {
+        /// Age past which an active session counts as orphaned, e.g. "90m", "24h" or "2d"
+        #[arg(long, default_value = "24h")]
+        max_age: String,
+    },
+
+    /// Interactive dashboard of now playing, top tracks and artists, and a listening
+    /// heatmap, refreshed from the database
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Time period to start on; press `p` to cycle through the others
+        #[arg(short, long, default_value = "week")]
+        period: TimePeriod,
+
+        /// Seconds between refreshes
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        refresh: u64,
+    },
+
+    /// Check that the daemon is running and polling players
+    Ping {
+        /// Daemon status file [default: $XDG_RUNTIME_DIR/gopal/gopald.status]
+        #[arg(long)]
+        status_file: Option<PathBuf>,
+
+        /// Report the daemon as stale when its last successful poll is older than this
+        #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_STALE_AFTER_SECS)]
+        stale_after: i64,
+    },
+
+    /// Pause or resume recording without stopping the daemon; sessions in progress end
+    /// when it's paused. Shows whether recording is on without --on or --off.
+    Record {
+        /// Resume recording
+        #[arg(long, conflicts_with = "off")]
+        on: bool,
+
+        /// Pause recording
+        #[arg(long)]
+        off: bool,
+
+        /// The daemon's recording pause file [default: $XDG_RUNTIME_DIR/gopal/recording-paused]
+        #[arg(long)]
+        pause_file: Option<PathBuf>,
+    },
+
+    /// Import listening history exported from another tracker
+    ImportExternal {
+        /// Format of the export file
+        #[arg(long)]
+        format: ExternalFormat,
+
+        /// Export file to import
+        file: PathBuf,
+
+        /// Seconds to record for plays whose duration isn't in the export or already known
+        #[arg(long, default_value_t = DEFAULT_IMPORT_DURATION_SECS)]
+        default_duration: i64,
+
+        /// Import a gopal export written by a newer version, dropping what this version
+        /// doesn't understand
+        #[arg(long)]
+        force: bool,
+
+        /// Give new tracks ids that ignore case, for a daemon with
+        /// `metadata.case_insensitive_track_ids` on
+        #[arg(long)]
+        case_insensitive_track_ids: bool,
+    },
+
+    /// Export players, tracks and finished sessions as JSON, for `import-external --format gopal`.
+    /// With --anonymize, only each track's plays and listened time, which can't be imported.
+    Export {
+        /// File to write [default: stdout]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Write a period's summary, top tracks and artists, and heatmap as a single HTML
+    /// file for sharing. Local album art is embedded when built with `thumbnails`.
+    Report {
+        /// Time period to report on
+        #[arg(short, long, default_value = "month")]
+        period: TimePeriod,
+
+        /// Custom start date (YYYY-MM-DD format, used with 'custom' period)
+        #[arg(long)]
+        start_date: Option<String>,
+
+        /// Custom end date (YYYY-MM-DD format, used with 'custom' period)
+        #[arg(long)]
+        end_date: Option<String>,
+
+        /// HTML file to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ExternalFormat {
+    /// ListenBrainz JSON or JSON-lines export
+    Listenbrainz,
+    /// Last.fm scrobble CSV
+    Lastfm,
+    /// JSON written by `gopal-cli export`
+    Gopal,
+}
+
+#[derive(Clone, Debug, PartialEq, clap::ValueEnum)]
+enum TimePeriod {
+    Today,
+    Week,
+    Month,
+    Year,
+    AllTime,
+    Custom,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum GroupDimension {
+    Track,
+    Artist,
+    Album,
+    Genre,
+    Player,
+    /// Web player source domain, or the player without one
+    Source,
+    /// Foreground or background listening, when the daemon tracks window focus
+    Focus,
+}
+
+impl From<GroupDimension> for GroupBy {
+    fn from(dimension: GroupDimension) -> Self {
+        match dimension {
+            GroupDimension::Track => GroupBy::Track,
+            GroupDimension::Artist => GroupBy::Artist,
+            GroupDimension::Album => GroupBy::Album,
+            GroupDimension::Genre => GroupBy::Genre,
+            GroupDimension::Player => GroupBy::Player,
+            GroupDimension::Source => GroupBy::Source,
+            GroupDimension::Focus => GroupBy::Focus,
+        }
+    }
+}
+
+/// Restrictions shared by the commands built on listening stats
+#[derive(Clone, Debug, Default, clap::Args)]
+struct FilterArgs {
+    /// Only count one kind of content
+    #[arg(long)]
+    content_type: Option<ContentTypeArg>,
+
+    /// Leave out an artist; repeat to exclude several
+    #[arg(long, value_name = "ARTIST")]
+    exclude_artist: Vec<String>,
+
+    /// Leave out tracks with this title; repeat to exclude several
+    #[arg(long, value_name = "TITLE")]
+    exclude_track: Vec<String>,
+
+    /// Only count sessions tagged with this label
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Leave out a player, by bus name or identity; repeat to exclude several
+    #[arg(long, value_name = "PLAYER")]
+    exclude_player: Vec<String>,
+
+    /// Only count tracks whose title contains this, ignoring case, e.g. "(Live)"
+    #[arg(long, value_name = "TEXT")]
+    title_contains: Option<String>,
+
+    /// Only count tracks whose artist contains this, ignoring case
+    #[arg(long, value_name = "TEXT")]
+    artist_contains: Option<String>,
+}
+
+impl From<FilterArgs> for StatsFilter {
+    fn from(args: FilterArgs) -> Self {
+        StatsFilter {
+            content_type: args.content_type.map(Into::into),
+            exclude_artists: args.exclude_artist,
+            exclude_tracks: args.exclude_track,
+            label: args.label,
+            exclude_players: args.exclude_player,
+            title_contains: args.title_contains,
+            artist_contains: args.artist_contains,
+        }
+    }
+}
+
+/// Recency weighting for top lists
+#[derive(Clone, Copy, Debug, clap::Args)]
+struct RecencyArgs {
+    /// Weight listening by age so recent plays count more
+    #[arg(long)]
+    recency_weighted: bool,
+
+    /// Days after which a play counts half as much, with --recency-weighted
+    #[arg(long, default_value_t = DEFAULT_RECENCY_HALF_LIFE_DAYS, requires = "recency_weighted", value_parser = parse_half_life)]
+    half_life_days: f64,
+}
+
+/// A half-life of zero or less would weigh every play as infinite or not a number
+fn parse_half_life(value: &str) -> std::result::Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(days) if days.is_finite() && days > 0.0 => Ok(days),
+        Ok(_) => Err("must be a positive number of days".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl RecencyArgs {
+    fn fetch(&self, database: &Database, start: Option<i64>, end: Option<i64>, filter: &StatsFilter) -> Result<ListeningStats> {
+        if self.recency_weighted {
+            Ok(database.get_recency_weighted_stats(start, end, filter, self.half_life_days * 86400.0)?)
+        } else {
+            Ok(database.get_filtered_listening_stats(start, end, filter)?)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ContentTypeArg {
+    Music,
+    Podcast,
+    Audiobook,
+}
+
+impl From<ContentTypeArg> for ContentType {
+    fn from(arg: ContentTypeArg) -> Self {
+        match arg {
+            ContentTypeArg::Music => ContentType::Music,
+            ContentTypeArg::Podcast => ContentType::Podcast,
+            ContentTypeArg::Audiobook => ContentType::Audiobook,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum BucketSize {
+    Week,
+    Month,
+}
+
+impl From<BucketSize> for TimeBucket {
+    fn from(size: BucketSize) -> Self {
+        match size {
+            BucketSize::Week => TimeBucket::Week,
+            BucketSize::Month => TimeBucket::Month,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+enum Explain {
+    /// Just the statements
+    Sql,
+    /// The statements, then `EXPLAIN QUERY PLAN` for each
+    Plan,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Granularity {
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Granularity {
+    fn seconds(self) -> i64 {
+        match self {
+            Granularity::Second => 1,
+            Granularity::Minute => 60,
+            Granularity::Hour => 3600,
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            Granularity::Second => "sec",
+            Granularity::Minute => "min",
+            Granularity::Hour => "hr",
+        }
+    }
+
+    /// Round `seconds` to the nearest whole unit, still expressed in seconds
+    fn round(self, seconds: i64) -> i64 {
+        let unit = self.seconds();
+        (seconds + unit / 2).div_euclid(unit) * unit
+    }
+}
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum SortBy {
+    Time,
+    Count,
+}
+
+/// Process exit codes, kept stable for scripts. Clap exits with 2 on invalid arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CliExit {
+    Success = 0,
+    QueryError = 1,
+    DatabaseNotFound = 3,
+    EmptyResult = 4,
+    DaemonUnavailable = 5,
+}
+
+impl CliExit {
+    fn from_error(error: &anyhow::Error) -> Self {
+        if matches!(error.downcast_ref::<GopalError>(), Some(GopalError::NotFound(_))) {
+            CliExit::DatabaseNotFound
+        } else if error.downcast_ref::<DaemonNotRunning>().is_some() {
+            CliExit::DaemonUnavailable
+        } else {
+            CliExit::QueryError
+        }
+    }
+
+    fn from_result_count(count: usize) -> Self {
+        if count == 0 {
+            CliExit::EmptyResult
+        } else {
+            CliExit::Success
+        }
+    }
+}
+
+impl From<CliExit> for ExitCode {
+    fn from(exit: CliExit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+#[derive(Debug)]
+struct DaemonNotRunning(PathBuf);
+
+impl fmt::Display for DaemonNotRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gopald is not running (no status file at {})", self.0.display())
+    }
+}
+
+impl std::error::Error for DaemonNotRunning {}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let quiet = args.quiet;
+
+    match run(args).await {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            let exit = CliExit::from_error(&e);
+            if exit == CliExit::DatabaseNotFound && !quiet {
+                eprintln!("Make sure the gopald daemon has been running to collect data.");
+            }
+            exit.into()
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<CliExit> {
+    if args.columns.is_some() && !matches!(args.format, OutputFormat::Csv) {
+        anyhow::bail!("--columns only applies to --format csv");
+    }
+    if args.explain.is_some() {
+        // The CLI logs nothing else, so only the statements reach stderr
+        env_logger::Builder::new()
+            .filter_module(QUERY_LOG_TARGET, log::LevelFilter::Debug)
+            .format(|buf, record| writeln!(buf, "[sql] {}", record.args()))
+            .init();
+    }
+    let locale = OutputLocale::resolve(args.locale.as_deref())?;
+    let mut renderer = renderer_for(&args.format, args.quiet, args.granularity, args.render_command.clone(), args.columns.clone(), locale);
+    run_with_renderer(args, renderer.as_mut()).await
+}
+
+fn artist_splitter(enabled: bool, separators: &[String]) -> Result<Option<ArtistSplitter>> {
+    match (enabled, separators.is_empty()) {
+        (false, _) => Ok(None),
+        (true, true) => Ok(Some(ArtistSplitter::default())),
+        (true, false) => ArtistSplitter::new(separators).map(Some),
+    }
+}
+
+/// SQLite's name for a private database that lives only in memory
+const IN_MEMORY_DATABASE: &str = ":memory:";
+
+/// Environment variable that makes `--utc` the default when set to 1
+const UTC_ENV: &str = "GOPAL_UTC";
+
+/// Sessions `stats` includes in its recent history
+const STATS_HISTORY_LIMIT: usize = 100;
+
+async fn run_with_renderer(args: Args, renderer: &mut dyn StatsRenderer) -> Result<CliExit> {
+    // The other commands show single sessions, ids or times that can't be left out
+    if args.anonymize && !matches!(args.command, Commands::Stats { .. } | Commands::TopTracks { .. } | Commands::TopArtists { .. } | Commands::History { .. } | Commands::Export { .. }) {
+        anyhow::bail!("--anonymize only applies to stats, top-tracks, top-artists, history and export");
+    }
+    // The daemon's health doesn't depend on the database, which may not exist yet
+    if let Commands::Ping { status_file, stale_after } = &args.command {
+        return ping(status_file.clone(), *stale_after, Local::now().timestamp(), renderer);
+    }
+    if let Commands::Record { on, off, pause_file } = &args.command {
+        return record(*on, *off, pause_file.clone(), renderer);
+    }
+
+    let database = match &args.from_export {
+        Some(file) => open_export(file, args.export_format)?,
+        None => open_database(
+            args.database.as_deref(),
+            database_key(args.key.clone()).as_deref(),
+            matches!(args.command, Commands::ImportExternal { .. }),
+        )?,
+    };
+    let database = database
+        .with_play_dedupe_window(args.dedupe_window_seconds)
+        .with_stats_cache(!args.no_cache)
+        .with_artist_splitter(artist_splitter(args.split_artists, &args.artist_separator)?)
+        .with_query_log(args.explain.is_some(), args.explain == Some(Explain::Plan))
+        .with_day_start_hour(args.day_start_hour)
+        .with_day_zone(day_zone(args.utc))
+        .with_day_boundary_split(args.split_day_boundary)
+        .with_case_insensitive_track_ids(matches!(args.command, Commands::ImportExternal { case_insensitive_track_ids: true, .. }));
+    let explain = args.explain;
+    let day_start_hour = args.day_start_hour;
+    let day_zone = database.day_zone();
+
+    let checkpoint = match &args.command {
+        Commands::Stats { since_last: true, reset_checkpoint, .. } => {
+            Some(StatsCheckpoint::open(default_stats_checkpoint_file()?, *reset_checkpoint, Local::now().timestamp())?)
+        }
+        _ => None,
+    };
+
+    let exit = match args.command {
+        Commands::Stats { period, start_date, end_date, limit, group_by: Some(dimension), filter, .. } => {
+            let period_range = parse_time_period(period, start_date, end_date, day_start_hour, day_zone)?;
+            let (start_time, end_time) = checkpoint.as_ref().map_or(period_range, |checkpoint| checkpoint.range(period_range));
+            let groups = database.get_grouped_stats(dimension.into(), start_time, end_time, &filter.into(), limit)?;
+
+            renderer.grouped(&groups, dimension)?;
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint.advance()?;
+            }
+            CliExit::from_result_count(groups.len())
+        }
+
+        Commands::Stats { period, start_date, end_date, limit, group_by: None, engagement, pause_penalty, filter, .. } => {
+            let period_range = parse_time_period(period, start_date, end_date, day_start_hour, day_zone)?;
+            let (start_time, end_time) = checkpoint.as_ref().map_or(period_range, |checkpoint| checkpoint.range(period_range));
+            let limits = DashboardLimits { top: limit, history: STATS_HISTORY_LIMIT };
+            let filter = StatsFilter::from(filter);
+            let mut dashboard = database.get_dashboard(start_time, end_time, &filter, limits)?;
+            let engagement = if engagement {
+                Some(database.get_engagement_stats(start_time, end_time, &filter, pause_penalty)?)
+            } else {
+                None
+            };
+
+            let session_count = dashboard.stats.listening_history.len();
+            if args.anonymize {
+                dashboard = dashboard.anonymized();
+            }
+            
+            renderer.stats(&dashboard, engagement.as_ref(), limit)?;
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint.advance()?;
+            }
+            CliExit::from_result_count(session_count)
+        }
+
+        Commands::TopTracks { period, limit, sort_by, with_movement, breakdown_by_player, filter, recency } => {
+            let filter = StatsFilter::from(filter);
+            let (start_time, end_time) = parse_time_period(period.clone(), None, None, day_start_hour, day_zone)?;
+            let stats = recency.fetch(&database, start_time, end_time, &filter)?;
+
+            let mut tracks = if args.anonymize { stats.anonymized() } else { stats }.top_tracks;
+            if matches!(sort_by, SortBy::Count) {
+                tracks.sort_by_key(|t| std::cmp::Reverse(t.play_count));
+            }
+            tracks.truncate(limit);
+
+            if with_movement {
+                let (prev_start, prev_end) = previous_period(&period, start_time, end_time, Local::now().timestamp())?;
+                let mut previous = recency.fetch(&database, Some(prev_start), Some(prev_end), &filter)?.top_tracks;
+                if matches!(sort_by, SortBy::Count) {
+                    previous.sort_by_key(|t| std::cmp::Reverse(t.play_count));
+                }
+
+                let current_ids: Vec<&str> = tracks.iter().map(|t| t.track.id.as_str()).collect();
+                let previous_ids: Vec<&str> = previous.iter().map(|t| t.track.id.as_str()).collect();
+                let ranked: Vec<RankedTrack> = compute_rank_movement(&current_ids, &previous_ids)
+                    .into_iter()
+                    .zip(tracks)
+                    .map(|(movement, stats)| RankedTrack { movement, stats })
+                    .collect();
+
+                renderer.ranked_tracks(&ranked, &sort_by)?;
+                CliExit::from_result_count(ranked.len())
+            } else if breakdown_by_player {
+                let mut breakdown = database.get_track_player_breakdown(start_time, end_time, &filter)?;
+                let tracks: Vec<TrackBreakdown> = tracks
+                    .into_iter()
+                    .map(|stats| TrackBreakdown {
+                        players: breakdown.remove(&stats.track.id).unwrap_or_default(),
+                        stats,
+                    })
+                    .collect();
+
+                renderer.track_breakdown(&tracks, &sort_by)?;
+                CliExit::from_result_count(tracks.len())
+            } else {
+                renderer.top_tracks(&tracks, &sort_by)?;
+                CliExit::from_result_count(tracks.len())
+            }
+        }
+
+        Commands::TopArtists { period, limit, filter, recency } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let stats = recency.fetch(&database, start_time, end_time, &filter.into())?;
+            
+            let mut artists = stats.top_artists;
+            artists.truncate(limit);
+
+            renderer.top_artists(&artists)?;
+            CliExit::from_result_count(artists.len())
+        }
+
+        Commands::History { period, limit, exclude_player, collapse } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let limit = (limit > 0).then_some(limit);
+            let filter = StatsFilter { exclude_players: exclude_player, ..StatsFilter::default() };
+            let collapse_gap = collapse.as_deref().map(parse_max_age).transpose()?;
+
+            let count = match (args.format, collapse_gap) {
+                // Stream straight from the database so huge histories never sit in memory
+                (OutputFormat::Ndjson, None) => {
+                    let mut out = BufWriter::new(std::io::stdout().lock());
+                    let mut count = 0;
+                    database.for_each_history_session(start_time, end_time, limit, &filter, |session| {
+                        count += 1;
+                        if args.anonymize {
+                            write_ndjson_line(&mut out, &AnonymousPlay::from(&session))
+                        } else {
+                            write_ndjson_line(&mut out, &session)
+                        }
+                    })?;
+                    out.flush()?;
+                    count
+                }
+                _ => {
+                    let mut history = Vec::new();
+                    // The limit counts entries after collapsing, so the database can't apply it
+                    let fetch_limit = if collapse_gap.is_some() { None } else { limit };
+                    database.for_each_history_session(start_time, end_time, fetch_limit, &filter, |session| {
+                        match collapse_gap {
+                            // Once an entry past the limit has begun, the ones before it are final
+                            Some(_) if limit.is_some_and(|limit| history.len() > limit) => {}
+                            Some(gap) => collapse_into(&mut history, session, gap),
+                            None => history.push(session),
+                        }
+                        Ok::<_, GopalError>(())
+                    })?;
+                    if let Some(limit) = limit {
+                        history.truncate(limit);
+                    }
+
+                    if args.anonymize {
+                        let plays: Vec<AnonymousPlay> = history.iter().map(AnonymousPlay::from).collect();
+                        renderer.anonymous_history(&plays)?;
+                    } else {
+                        renderer.history(&history)?;
+                    }
+                    history.len()
+                }
+            };
+
+            CliExit::from_result_count(count)
+        }
+
+        Commands::Now { format_template, include_paused } => {
+            // Validate the template before touching the database so typos fail fast
+            let template = match format_template {
+                Some(template) => NowPlayingTemplate::parse(&template)?,
+                None => NowPlayingTemplate::default(),
+            };
+
+            let now_playing = now_playing(&database, Local::now().timestamp(), include_paused)?;
+            renderer.now_playing(&now_playing, &template)?;
+            CliExit::from_result_count(now_playing.len())
+        }
+
+        Commands::SkipStats { period, limit } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let mut stats = database.get_skip_stats(start_time, end_time)?;
+            stats.artists.truncate(limit);
+
+            renderer.skip_stats(&stats)?;
+            CliExit::from_result_count(stats.total_sessions as usize)
+        }
+
+        Commands::OnThisDay { date, limit } => {
+            let now = Local::now().timestamp();
+            let (month, day) = match date {
+                Some(date) => parse_month_day(&date)?,
+                None => {
+                    let today = listening_day(now, day_start_hour, day_zone).context("Invalid current time")?;
+                    (today.month(), today.day())
+                }
+            };
+
+            let mut years = database.get_on_this_day(month, day, now)?;
+            for year in &mut years {
+                year.top_tracks.truncate(limit);
+            }
+
+            renderer.on_this_day(month, day, &years)?;
+            CliExit::from_result_count(years.len())
+        }
+
+        Commands::Replay { date } => {
+            let date = match date {
+                Some(date) => chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .context("Invalid date format. Use YYYY-MM-DD")?,
+                None => listening_day(Local::now().timestamp(), day_start_hour, day_zone).context("Invalid current time")?,
+            };
+            let timeline = database.get_day_timeline(date)?;
+
+            renderer.replay(date, &timeline)?;
+            CliExit::from_result_count(timeline.len())
+        }
+
+        Commands::Sources { period, limit } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let sources = database.get_source_stats(start_time, end_time, limit)?;
+
+            renderer.sources(&sources)?;
+            CliExit::from_result_count(sources.len())
+        }
+
+        Commands::Rediscover { not_since, min_plays, limit } => {
+            let not_since = parse_not_since(&not_since, Local::now())?;
+            let tracks = database.get_rediscovery(min_plays, not_since, limit)?;
+
+            renderer.rediscovery(&tracks)?;
+            CliExit::from_result_count(tracks.len())
+        }
+
+        Commands::TopRated { period, limit } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let tracks = database.get_top_rated(start_time, end_time, limit)?;
+
+            renderer.top_rated(&tracks)?;
+            CliExit::from_result_count(tracks.len())
+        }
+
+        Commands::Trend { window_days, lookback } => {
+            let trend = database.get_trend(window_days, lookback)?;
+
+            renderer.trend(&trend)?;
+            CliExit::Success
+        }
+
+        Commands::Consistency { period } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let consistency = database.get_consistency(start_time, end_time)?;
+
+            renderer.consistency(&consistency)?;
+            CliExit::from_result_count(consistency.active_days as usize)
+        }
+
+        Commands::Weekdays { period } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let averages = database.get_weekday_averages(start_time, end_time)?;
+
+            renderer.weekdays(&averages)?;
+            CliExit::from_result_count(averages.iter().filter(|avg| avg.total_listened_time > 0).count())
+        }
+
+        Commands::AlbumCompleteness { period, limit } => {
+            let (start_time, end_time) = parse_time_period(period, None, None, day_start_hour, day_zone)?;
+            let mut albums = database.get_album_completeness(start_time, end_time)?;
+            albums.truncate(limit);
+
+            renderer.album_completeness(&albums)?;
+            CliExit::from_result_count(albums.len())
+        }
+
+        Commands::Incomplete { limit } => {
+            let mut tracks = database.get_incomplete_tracks()?;
+            if limit > 0 {
+                tracks.truncate(limit);
+            }
+
+            renderer.incomplete_tracks(&tracks)?;
+            CliExit::from_result_count(tracks.len())
+        }
+
+        Commands::Artist { name } => {
+            let catalog = database.get_artist_catalog(&name)?;
+
+            renderer.artist(&catalog)?;
+            CliExit::from_result_count(catalog.discoveries.len())
+        }
+
+        Commands::Album { name, artist } => {
+            let tracks = database.get_album_tracks(&name, artist.as_deref())?;
+
+            renderer.album(&name, &tracks)?;
+            CliExit::from_result_count(tracks.len())
+        }
+
+        Commands::RankHistory { artist, track_id, by } => {
+            let (entity, name) = match (artist, track_id) {
+                (Some(artist), _) => (RankEntity::Artist(artist.clone()), artist),
+                (None, Some(track_id)) => (RankEntity::Track(track_id.clone()), track_id),
+                (None, None) => unreachable!("clap requires --artist or --track-id"),
+            };
+            let history = database.get_rank_history(&entity, by.into())?;
+
+            renderer.rank_history(&name, &history)?;
+            CliExit::from_result_count(history.iter().filter(|point| point.rank.is_some()).count())
+        }
+
+        Commands::Label { session, set, clear: _ } => {
+            if !database.set_session_label(session, set.as_deref())? {
+                anyhow::bail!("No session with id {}", session);
+            }
+
+            renderer.session_label(&SessionLabel { session_id: session, label: set })?;
+            CliExit::Success
+        }
+
+        Commands::Status => {
+            let stats = database.get_database_stats()
+                .context("Error reading database stats")?;
+
+            renderer.status(&stats)?;
+            CliExit::Success
+        }
+
+        Commands::Players { merge } => {
+            if let Some([from, into]) = merge.as_deref() {
+                database.merge_players(from, into)
+                    .with_context(|| format!("Error merging player '{}' into '{}'", from, into))?;
+            }
+            let players = database.get_player_summaries()?;
+
+            renderer.players(&players)?;
+            CliExit::from_result_count(players.len())
+        }
+
+        Commands::RefreshAggregates => {
+            database.refresh_artist_aggregates(Local::now().timestamp())?;
+            let status = database.get_artist_aggregate_status()?;
+
+            renderer.aggregates(&status)?;
+            CliExit::Success
+        }
+
+        Commands::CheckTiming { tolerance } => {
+            let mismatches = database.get_timing_report(tolerance)
+                .context("Error checking session timing")?;
+
+            renderer.timing_report(&mismatches, tolerance)?;
+            CliExit::Success
+        }
+
+        Commands::ScrobbleQueue { retry_now } => {
+            if retry_now {
+                database.retry_scrobbles_now(Local::now().timestamp())
+                    .context("Error rescheduling scrobbles")?;
+            }
+            let queue = database.get_scrobble_queue(None)
+                .context("Error reading the scrobble queue")?;
+
+            renderer.scrobble_queue(&queue)?;
+            CliExit::Success
+        }
+
+        #[cfg(feature = "tui")]
+        Commands::Tui { period, refresh } => {
+            tui::run(&database, period, std::time::Duration::from_secs(refresh.max(1)))?;
+            CliExit::Success
+        }
+
+        Commands::Active => {
+            let now = Local::now().timestamp();
+            let sessions: Vec<ActiveSession> = database.list_active_sessions()?
+                .into_iter()
+                .map(|session| ActiveSession { age: now - session.session.start_time, session })
+                .collect();
+
+            renderer.active_sessions(&sessions)?;
+            CliExit::from_result_count(sessions.len())
+        }
+
+        Commands::Cleanup { max_age } => {
+            let max_age = parse_max_age(&max_age)?;
+            let closed = database.cleanup_orphaned_sessions(Local::now().timestamp(), max_age)
+                .context("Failed to clean up orphaned sessions")?;
+
+            renderer.cleanup(&closed, max_age)?;
+            CliExit::Success
+        }
+
+        Commands::Ping { .. } => unreachable!("ping is answered before the database is opened"),
+        Commands::Record { .. } => unreachable!("record is handled before the database is opened"),
+
+        Commands::ImportExternal { format, file, default_duration, force, .. } => {
+            let contents = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+
+            let summary = match format {
+                ExternalFormat::Listenbrainz => database.import_listenbrainz(&contents, default_duration)?,
+                ExternalFormat::Lastfm => database.import_lastfm_csv(&contents, default_duration)?,
+                ExternalFormat::Gopal => database.import_gopal_export(&contents, force)?,
+            };
+
+            renderer.import_summary(&summary, default_duration)?;
+            CliExit::Success
+        }
+
+        Commands::Export { output } => {
+            let json = if args.anonymize {
+                serde_json::to_string_pretty(&database.export_anonymized()?)?
+            } else {
+                serde_json::to_string_pretty(&database.export_all(Local::now().timestamp())?)?
+            };
+            match output {
+                Some(path) => std::fs::write(&path, json)
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => println!("{}", json),
+            }
+            CliExit::Success
+        }
+
+        Commands::Report { period, start_date, end_date, output } => {
+            let title = match clap::ValueEnum::to_possible_value(&period) {
+                Some(name) if period != TimePeriod::Custom => format!("Listening Report: {}", name.get_name()),
+                _ => "Listening Report".to_string(),
+            };
+            let (start_time, end_time) = parse_time_period(period, start_date, end_date, day_start_hour, day_zone)?;
+            let report = report::Report::load(&database, title, start_time, end_time)?;
+            let locale = OutputLocale::resolve(args.locale.as_deref())?;
+            std::fs::write(&output, report::render_html(&report, &locale))
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            CliExit::Success
+        }
+    };
+
+    if explain == Some(Explain::Plan) {
+        print_query_plans(&database)?;
+    }
 
-    /// Show current database status
-    Status,
+    Ok(exit)
 }
 
-#[derive(Clone, Debug, clap::ValueEnum)]
-enum TimePeriod {
-    Today,
-    Week,
-    Month,
-    Year,
-    AllTime,
-    Custom,
+/// `EXPLAIN QUERY PLAN` for each distinct statement logged by `--explain=plan`
+fn print_query_plans(database: &Database) -> Result<()> {
+    let mut queries = Database::take_logged_queries();
+    queries.dedup();
+
+    for sql in queries {
+        // Transaction control and pragmas have no plan worth showing
+        let Ok(plan) = database.query_plan(&sql) else { continue };
+        if plan.is_empty() {
+            continue;
+        }
+        eprintln!("[plan] {}", sql.split_whitespace().collect::<Vec<_>>().join(" "));
+        for step in plan {
+            eprintln!("[plan]   {}", step);
+        }
+    }
+    Ok(())
 }
 
-#[derive(Clone, Debug, clap::ValueEnum)]
-enum SortBy {
-    Time,
-    Count,
+/// What each player is playing right now, as shown by `now` and the TUI
+fn now_playing(database: &Database, current_time: i64, include_paused: bool) -> Result<Vec<NowPlaying>> {
+    let block_seconds = database.get_current_block_seconds(current_time, LISTENING_BLOCK_GAP)?;
+    Ok(database.list_active_sessions()?
+        .iter()
+        .map(|session| NowPlaying {
+            current_block_seconds: block_seconds,
+            ..NowPlaying::from_session(session, current_time, include_paused)
+        })
+        .collect())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Open the database at `path` or the default location. Only `creates_database`
+/// commands may open a database that doesn't exist yet.
+fn open_database(path: Option<&str>, key: Option<&str>, creates_database: bool) -> Result<Database> {
+    if path == Some(IN_MEMORY_DATABASE) {
+        return Ok(Database::new_with_key(IN_MEMORY_DATABASE, key)?);
+    }
 
     // Resolve database path
-    let db_path = expand_path(&args.database)?;
+    let db_path = match path {
+        Some(path) => expand_path(path)?,
+        None => default_database_path()?,
+    };
+
+    // Importing history is allowed to create the database
+    let database = if creates_database {
+        if let Some(parent) = db_path.parent().filter(|_| !db_path.exists()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create database directory {}", parent.display()))?;
+        }
+        Database::new_with_key(&db_path, key)
+    } else {
+        Database::open_existing(&db_path, key)
+    };
 
-    // Check if database exists
-    if !db_path.exists() {
-        eprintln!("Database not found at: {}", db_path.display());
-        eprintln!("Make sure the gopald daemon has been running to collect data.");
-        std::process::exit(1);
+    match database {
+        Err(e @ GopalError::NotFound(_)) => Err(e.into()),
+        database => database.context("Failed to open database"),
     }
+}
 
-    // Initialize database
-    let database = Database::new(&db_path)
-        .context("Failed to open database")?;
+/// Load a ListenBrainz or Last.fm export into a fresh in-memory database
+fn open_export(file: &std::path::Path, format: Option<ExternalFormat>) -> Result<Database> {
+    let contents = if file.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read export from stdin")?
+    } else {
+        std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?
+    };
 
-    match args.command {
-        Commands::Stats { period, start_date, end_date, limit } => {
-            let (start_time, end_time) = parse_time_period(period, start_date, end_date)?;
-            let stats = database.get_listening_stats(start_time, end_time)?;
-            
-            match args.format {
-                OutputFormat::Human => print_stats_human(&stats, limit),
-                OutputFormat::Json => print_stats_json(&stats)?,
-                OutputFormat::Csv => print_stats_csv(&stats)?,
-            }
-        }
+    let format = format.unwrap_or(match file.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("csv") => ExternalFormat::Lastfm,
+        _ => ExternalFormat::Listenbrainz,
+    });
 
-        Commands::TopTracks { period, limit, sort_by } => {
-            let (start_time, end_time) = parse_time_period(period, None, None)?;
-            let stats = database.get_listening_stats(start_time, end_time)?;
-            
-            let mut tracks = stats.top_tracks;
-            if matches!(sort_by, SortBy::Count) {
-                tracks.sort_by(|a, b| b.play_count.cmp(&a.play_count));
-            }
-            tracks.truncate(limit);
+    let database = Database::new(IN_MEMORY_DATABASE)?;
+    match format {
+        ExternalFormat::Listenbrainz => database.import_listenbrainz(&contents, DEFAULT_IMPORT_DURATION_SECS)?,
+        ExternalFormat::Lastfm => database.import_lastfm_csv(&contents, DEFAULT_IMPORT_DURATION_SECS)?,
+        ExternalFormat::Gopal => database.import_gopal_export(&contents, false)?,
+    };
+    Ok(database)
+}
 
-            match args.format {
-                OutputFormat::Human => print_top_tracks_human(&tracks, &sort_by),
-                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&tracks)?),
-                OutputFormat::Csv => print_top_tracks_csv(&tracks)?,
-            }
-        }
+/// Parse `MM-DD`, accepting February 29
+fn parse_month_day(value: &str) -> Result<(u32, u32)> {
+    let parsed = value
+        .split_once('-')
+        .and_then(|(month, day)| Some((month.parse().ok()?, day.parse().ok()?)))
+        .filter(|&(month, day)| chrono::NaiveDate::from_ymd_opt(2000, month, day).is_some());
 
-        Commands::TopArtists { period, limit } => {
-            let (start_time, end_time) = parse_time_period(period, None, None)?;
-            let stats = database.get_listening_stats(start_time, end_time)?;
-            
-            let mut artists = stats.top_artists;
-            artists.truncate(limit);
+    parsed.with_context(|| format!("Invalid date '{}'. Use MM-DD", value))
+}
 
-            match args.format {
-                OutputFormat::Human => print_top_artists_human(&artists),
-                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&artists)?),
-                OutputFormat::Csv => print_top_artists_csv(&artists)?,
+/// Where `stats --since-last` left off, kept as a unix time in a small state file. A run
+/// covers everything started since the previous run up to the second before it began,
+/// so consecutive reports never overlap.
+struct StatsCheckpoint {
+    path: PathBuf,
+    previous: Option<i64>,
+    now: i64,
+}
+
+impl StatsCheckpoint {
+    /// Read the checkpoint at `path` for a run starting at `now`, first deleting it if `reset`
+    fn open(path: PathBuf, reset: bool, now: i64) -> Result<Self> {
+        if reset {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+                }
+                _ => {}
             }
         }
 
-        Commands::History { period, limit } => {
-            let (start_time, end_time) = parse_time_period(period, None, None)?;
-            let stats = database.get_listening_stats(start_time, end_time)?;
-            
-            let mut history = stats.listening_history;
-            history.truncate(limit);
+        let previous = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents.trim().parse::<i64>()
+                .with_context(|| format!("Invalid checkpoint in {}; reset it with --reset-checkpoint", path.display()))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(StatsCheckpoint { path, previous, now })
+    }
 
-            match args.format {
-                OutputFormat::Human => print_history_human(&history),
-                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&history)?),
-                OutputFormat::Csv => print_history_csv(&history)?,
-            }
-        }
+    /// The time range of this run, starting from `period_range` when there's no checkpoint yet
+    fn range(&self, (period_start, _): (Option<i64>, Option<i64>)) -> (Option<i64>, Option<i64>) {
+        (self.previous.or(period_start), Some(self.now - 1))
+    }
 
-        Commands::Status => {
-            print_status(&database)?;
+    /// Record that this run succeeded, so the next one starts where it stopped
+    fn advance(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
         }
+        std::fs::write(&self.path, format!("{}\n", self.now))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
     }
+}
 
-    Ok(())
+/// UTC with `--utc` or `GOPAL_UTC=1`, otherwise local time
+fn day_zone(utc: bool) -> DayZone {
+    if utc || std::env::var(UTC_ENV).is_ok_and(|value| value == "1") {
+        DayZone::Utc
+    } else {
+        DayZone::Local
+    }
 }
 
+/// Start and end of a period. Today and custom dates follow listening days, which
+/// begin at `day_start_hour` local time; the rolling periods end now.
 fn parse_time_period(
     period: TimePeriod,
     start_date: Option<String>,
     end_date: Option<String>,
+    day_start_hour: u32,
+    day_zone: DayZone,
 ) -> Result<(Option<i64>, Option<i64>)> {
-    let now = Local::now();
+    time_period_at(period, start_date, end_date, day_start_hour, day_zone, Local::now())
+}
 
+fn time_period_at(
+    period: TimePeriod,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    day_start_hour: u32,
+    day_zone: DayZone,
+    now: chrono::DateTime<Local>,
+) -> Result<(Option<i64>, Option<i64>)> {
     match period {
         TimePeriod::Today => {
-            let start_of_day = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
-            let start_timestamp = Local.from_local_datetime(&start_of_day).unwrap().timestamp();
-            Ok((Some(start_timestamp), None))
+            let today = listening_day(now.timestamp(), day_start_hour, day_zone).context("Invalid current time")?;
+            Ok((Some(listening_day_start(today, day_start_hour, day_zone)?), None))
         }
 
         TimePeriod::Week => {
@@ -226,8 +1516,7 @@ fn parse_time_period(
             let start_timestamp = if let Some(start_str) = start_date {
                 let start_date = chrono::NaiveDate::parse_from_str(&start_str, "%Y-%m-%d")
                     .context("Invalid start date format. Use YYYY-MM-DD")?;
-                let start_datetime = start_date.and_hms_opt(0, 0, 0).unwrap();
-                Some(Local.from_local_datetime(&start_datetime).unwrap().timestamp())
+                Some(listening_day_start(start_date, day_start_hour, day_zone)?)
             } else {
                 None
             };
@@ -235,8 +1524,8 @@ fn parse_time_period(
             let end_timestamp = if let Some(end_str) = end_date {
                 let end_date = chrono::NaiveDate::parse_from_str(&end_str, "%Y-%m-%d")
                     .context("Invalid end date format. Use YYYY-MM-DD")?;
-                let end_datetime = end_date.and_hms_opt(23, 59, 59).unwrap();
-                Some(Local.from_local_datetime(&end_datetime).unwrap().timestamp())
+                let next_day = end_date.succ_opt().context("End date out of range")?;
+                Some(listening_day_start(next_day, day_start_hour, day_zone)? - 1)
             } else {
                 None
             };
@@ -246,235 +1535,293 @@ fn parse_time_period(
     }
 }
 
-fn print_stats_human(stats: &ListeningStats, limit: usize) {
-    println!("🎵 Music Listening Statistics");
-    println!("═══════════════════════════════");
-    println!();
-
-    // Total listening time
-    let total_hours = stats.total_listening_time as f64 / 3600.0;
-    println!("📊 Total Listening Time: {:.1} hours ({} minutes)", 
-             total_hours, stats.total_listening_time / 60);
-    println!();
-
-    // Top tracks
-    if !stats.top_tracks.is_empty() {
-        println!("🎵 Top Tracks (by listening time):");
-        for (i, track_stat) in stats.top_tracks.iter().take(limit).enumerate() {
-            let time_str = format_duration(track_stat.total_listened_time);
-            println!("  {}. {} - {} ({}, {} plays)",
-                     i + 1,
-                     track_stat.track.title,
-                     track_stat.track.artist,
-                     time_str,
-                     track_stat.play_count);
-        }
-        println!();
+/// Parse a point in time such as "3 months ago", "2 weeks ago" or "2024-01-31" (local
+/// midnight). Months are 30 days and years 365, as with `--period`.
+fn parse_not_since(value: &str, now: chrono::DateTime<Local>) -> Result<i64> {
+    let value = value.trim();
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        return Local.from_local_datetime(&midnight)
+            .earliest()
+            .map(|time| time.timestamp())
+            .with_context(|| format!("'{}' doesn't exist in the local time zone", value));
     }
 
-    // Top artists
-    if !stats.top_artists.is_empty() {
-        println!("🎤 Top Artists (by listening time):");
-        for (i, artist_stat) in stats.top_artists.iter().take(limit).enumerate() {
-            let time_str = format_duration(artist_stat.total_listened_time);
-            println!("  {}. {} ({}, {} tracks)",
-                     i + 1,
-                     artist_stat.artist,
-                     time_str,
-                     artist_stat.track_count);
-        }
-        println!();
-    }
+    let relative = value.strip_suffix("ago").unwrap_or(value);
+    let mut words = relative.split_whitespace();
+    let parsed = match (words.next(), words.next(), words.next()) {
+        (Some(count), Some(unit), None) => parse_count(count).and_then(|count| {
+            let days = match unit.trim_end_matches('s') {
+                "day" => 1,
+                "week" => 7,
+                "month" => 30,
+                "year" => 365,
+                _ => return None,
+            };
+            Some(count * days)
+        }),
+        _ => None,
+    };
 
-    // Recent listening
-    if !stats.listening_history.is_empty() {
-        println!("🕒 Recent Listening:");
-        for session in stats.listening_history.iter().take(5) {
-            let datetime = DateTime::<Local>::from(
-                DateTime::<Utc>::from_timestamp(session.session.start_time, 0).unwrap()
-            );
-            let time_str = format_duration(session.session.listened_time.unwrap_or(0));
-            println!("  {} - {} ({}) [{}]",
-                     session.track.title,
-                     session.track.artist,
-                     time_str,
-                     datetime.format("%Y-%m-%d %H:%M"));
+    let days = parsed.with_context(|| format!("Invalid time '{}'. Use e.g. \"3 months ago\" or YYYY-MM-DD", value))?;
+    Ok((now - Duration::days(days)).timestamp())
+}
+
+/// Add the next, older history entry to `collapsed`, merging it into the last one when
+/// both are the same track at most `gap` seconds apart. A merged entry spans from its
+/// first fragment's start to its last fragment's end and sums their listened and
+/// paused time.
+fn collapse_into(collapsed: &mut Vec<SessionWithMetadata>, entry: SessionWithMetadata, gap: i64) {
+    if let Some(newer) = collapsed.last_mut() {
+        let session = &entry.session;
+        let end_time = session.end_time
+            .unwrap_or(session.start_time + session.paused_time + session.listened_time.unwrap_or(0));
+        if newer.track.id == entry.track.id && newer.session.start_time - end_time <= gap {
+            newer.session.start_time = session.start_time;
+            newer.session.paused_time += session.paused_time;
+            newer.session.listened_time = Some(newer.session.listened_time.unwrap_or(0) + session.listened_time.unwrap_or(0));
+            return;
         }
     }
+    collapsed.push(entry);
 }
 
-fn print_stats_json(stats: &ListeningStats) -> Result<()> {
-    println!("{}", serde_json::to_string_pretty(stats)?);
-    Ok(())
+/// A whole number of units in a time such as "3 months" or "90m"; never negative
+fn parse_count(value: &str) -> Option<i64> {
+    value.trim().parse::<u32>().ok().map(i64::from)
 }
 
-fn print_stats_csv(stats: &ListeningStats) -> Result<()> {
-    println!("type,name,value");
-    println!("total_time,Total Listening Time,{}", stats.total_listening_time);
-    
-    for track_stat in &stats.top_tracks {
-        println!("track,\"{} - {}\",{}", 
-                 track_stat.track.title, 
-                 track_stat.track.artist, 
-                 track_stat.total_listened_time);
-    }
-    
-    for artist_stat in &stats.top_artists {
-        println!("artist,\"{}\",{}", 
-                 artist_stat.artist, 
-                 artist_stat.total_listened_time);
-    }
-    
-    Ok(())
+/// Parse an age such as "90s", "30m", "24h" or "2d" into seconds; a bare number is seconds
+fn parse_max_age(value: &str) -> Result<i64> {
+    let value = value.trim();
+    let (count, unit_seconds) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 3600),
+        Some((i, 'd')) => (&value[..i], 86400),
+        _ => (value, 1),
+    };
+
+    parse_count(count)
+        .map(|count| count * unit_seconds)
+        .with_context(|| format!("Invalid age '{}'. Use e.g. \"90m\", \"24h\" or \"2d\"", value))
 }
 
-fn print_top_tracks_human(tracks: &[gopal::database::TrackStats], sort_by: &SortBy) {
-    let sort_desc = match sort_by {
-        SortBy::Time => "listening time",
-        SortBy::Count => "play count",
+/// Compute the period immediately preceding `(start_time, end_time)` with the same length.
+fn previous_period(
+    period: &TimePeriod,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    now: i64,
+) -> Result<(i64, i64)> {
+    let start = match start_time {
+        Some(start) => start,
+        None => anyhow::bail!("Movement needs a bounded period; 'all-time' has no previous period"),
     };
-    
-    println!("🎵 Top Tracks (by {}):", sort_desc);
-    println!("═══════════════════════════");
-    
-    for (i, track_stat) in tracks.iter().enumerate() {
-        let time_str = format_duration(track_stat.total_listened_time);
-        println!("{}. {} - {}", i + 1, track_stat.track.title, track_stat.track.artist);
-        println!("   {} listened, {} plays", time_str, track_stat.play_count);
-        println!();
-    }
-}
-
-fn print_top_tracks_csv(tracks: &[gopal::database::TrackStats]) -> Result<()> {
-    println!("rank,title,artist,album,listened_time,play_count");
-    for (i, track_stat) in tracks.iter().enumerate() {
-        println!("{},\"{}\",\"{}\",\"{}\",{},{}", 
-                 i + 1,
-                 track_stat.track.title,
-                 track_stat.track.artist,
-                 track_stat.track.album,
-                 track_stat.total_listened_time,
-                 track_stat.play_count);
-    }
-    Ok(())
+
+    let span = match period {
+        // Compare today against the whole of yesterday rather than the same partial span
+        TimePeriod::Today => 24 * 3600,
+        _ => end_time.unwrap_or(now) - start,
+    };
+
+    Ok((start - span, start - 1))
 }
 
-fn print_top_artists_human(artists: &[gopal::database::ArtistStats]) {
-    println!("🎤 Top Artists:");
-    println!("═══════════════");
-    
-    for (i, artist_stat) in artists.iter().enumerate() {
-        let time_str = format_duration(artist_stat.total_listened_time);
-        println!("{}. {}", i + 1, artist_stat.artist);
-        println!("   {} listened, {} tracks", time_str, artist_stat.track_count);
-        println!();
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Movement {
+    Up,
+    Down,
+    Same,
+    New,
 }
 
-fn print_top_artists_csv(artists: &[gopal::database::ArtistStats]) -> Result<()> {
-    println!("rank,artist,listened_time,track_count");
-    for (i, artist_stat) in artists.iter().enumerate() {
-        println!("{},\"{}\",{},{}", 
-                 i + 1,
-                 artist_stat.artist,
-                 artist_stat.total_listened_time,
-                 artist_stat.track_count);
+impl Movement {
+    fn indicator(&self) -> &'static str {
+        match self {
+            Movement::Up => "▲",
+            Movement::Down => "▼",
+            Movement::Same => "–",
+            Movement::New => "★",
+        }
     }
-    Ok(())
 }
 
-fn print_history_human(history: &[gopal::database::SessionWithMetadata]) {
-    println!("🕒 Listening History:");
-    println!("═══════════════════");
-    
-    for session in history {
-        let datetime = DateTime::<Local>::from(
-            DateTime::<Utc>::from_timestamp(session.session.start_time, 0).unwrap()
-        );
-        let time_str = format_duration(session.session.listened_time.unwrap_or(0));
-        
-        println!("{} - {}", session.track.title, session.track.artist);
-        println!("   {} on {} [{}]",
-                 time_str,
-                 datetime.format("%Y-%m-%d %H:%M"),
-                 session.player.name);
-        println!();
-    }
-}
-
-fn print_history_csv(history: &[gopal::database::SessionWithMetadata]) -> Result<()> {
-    println!("timestamp,title,artist,album,listened_time,player");
-    for session in history {
-        println!("{},\"{}\",\"{}\",\"{}\",{},\"{}\"", 
-                 session.session.start_time,
-                 session.track.title,
-                 session.track.artist,
-                 session.track.album,
-                 session.session.listened_time.unwrap_or(0),
-                 session.player.name);
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct RankMovement {
+    rank: usize,
+    previous_rank: Option<usize>,
+    movement: Movement,
+}
+
+#[derive(Serialize)]
+struct RankedTrack {
+    #[serde(flatten)]
+    movement: RankMovement,
+    #[serde(flatten)]
+    stats: gopal::database::TrackStats,
+}
+
+#[derive(Serialize)]
+struct ActiveSession {
+    #[serde(flatten)]
+    session: gopal::database::SessionWithMetadata,
+    /// Seconds since the session started
+    age: i64,
+}
+
+#[derive(Serialize)]
+struct TrackBreakdown {
+    #[serde(flatten)]
+    stats: gopal::database::TrackStats,
+    players: Vec<PlayerShare>,
+}
+
+/// Report the daemon's health from its status file, failing if it's stale or missing
+fn ping(status_file: Option<PathBuf>, stale_after: i64, current_time: i64, renderer: &mut dyn StatsRenderer) -> Result<CliExit> {
+    let path = match status_file {
+        Some(path) => path,
+        None => default_status_file()?,
+    };
+    if !path.exists() {
+        return Err(DaemonNotRunning(path).into());
     }
-    Ok(())
+
+    let status = DaemonStatus::read(&path)?;
+    let health = status.health(current_time, stale_after);
+    renderer.ping(&DaemonPing { pid: status.pid, health, recent_activity: status.recent_activity })?;
+
+    Ok(if health.is_healthy() { CliExit::Success } else { CliExit::DaemonUnavailable })
 }
 
-fn print_status(database: &Database) -> Result<()> {
-    println!("📊 Database Status:");
-    println!("═══════════════════");
-    
-    match database.get_database_stats() {
-        Ok(stats) => {
-            println!("Database file: Available");
-            println!("Total sessions: {}", stats.total_sessions);
-            println!("Active sessions: {}", stats.active_sessions);
-            println!("Total tracks: {}", stats.total_tracks);
-            println!("Total players: {}", stats.total_players);
-            
-            if stats.active_sessions > 0 {
-                println!();
-                println!("⚠️  Warning: {} active sessions found.", stats.active_sessions);
-                println!("   This may indicate the daemon was not properly shut down.");
-                println!("   These will be cleaned up on next daemon start.");
-            }
+/// Create or remove the daemon's recording pause file, then report whether recording is on
+fn record(on: bool, off: bool, pause_file: Option<PathBuf>, renderer: &mut dyn StatsRenderer) -> Result<CliExit> {
+    let path = match pause_file {
+        Some(path) => path,
+        None => default_recording_pause_file()?,
+    };
+    if off {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
         }
-        Err(e) => {
-            println!("Error reading database stats: {}", e);
+        std::fs::write(&path, "")
+            .with_context(|| format!("Failed to create recording pause file {}", path.display()))?;
+    } else if on {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(anyhow::Error::new(e).context(format!("Failed to remove recording pause file {}", path.display())));
+            }
+            _ => {}
         }
     }
-    
-    println!();
-    println!("Use 'music-cli stats' to view listening statistics.");
-    Ok(())
+
+    renderer.recording(&RecordingState { recording: !path.exists(), pause_file: path })?;
+    Ok(CliExit::Success)
 }
 
-fn expand_path(path: &str) -> Result<PathBuf> {
-    if path.starts_with('~') {
-        let home = std::env::var("HOME")
-            .context("HOME environment variable not set")?;
-        Ok(PathBuf::from(path.replacen('~', &home, 1)))
-    } else {
-        Ok(PathBuf::from(path))
-    }
+/// Whether the daemon is recording, as `record` reports it
+#[derive(Serialize)]
+struct RecordingState {
+    recording: bool,
+    pause_file: PathBuf,
+}
+
+/// The daemon's health as `ping` reports it
+#[derive(Serialize)]
+struct DaemonPing {
+    pid: u32,
+    #[serde(flatten)]
+    health: DaemonHealth,
+    /// Listening per hour over the last day, as of the daemon's last status write
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recent_activity: Vec<HourlyActivity>,
+}
+
+/// A session's label after `label` changed it
+#[derive(Serialize)]
+struct SessionLabel {
+    session_id: i64,
+    label: Option<String>,
+}
+
+/// Annotate each entry of `current` with its rank in `previous` (both ordered best-first).
+fn compute_rank_movement<K: PartialEq>(current: &[K], previous: &[K]) -> Vec<RankMovement> {
+    current
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let rank = i + 1;
+            let previous_rank = previous.iter().position(|p| p == key).map(|p| p + 1);
+            let movement = match previous_rank {
+                None => Movement::New,
+                Some(prev) if prev > rank => Movement::Up,
+                Some(prev) if prev < rank => Movement::Down,
+                Some(_) => Movement::Same,
+            };
+            RankMovement { rank, previous_rank, movement }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn test_expand_path() {
-        let path = expand_path("/tmp/test.db").unwrap();
-        assert_eq!(path, PathBuf::from("/tmp/test.db"));
-    }
+    use render::ValueRenderer;
+    use serde::Serialize;
 
     #[test]
     fn test_parse_today_period() {
-        let (start, end) = parse_time_period(TimePeriod::Today, None, None).unwrap();
+        let (start, end) = parse_time_period(TimePeriod::Today, None, None, 0, DayZone::Local).unwrap();
         assert!(start.is_some());
         assert!(end.is_none());
     }
 
+    #[test]
+    fn test_today_follows_day_start_hour() {
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap();
+
+        // At 2am with days starting at 4am, today is still yesterday
+        let (start, _) = time_period_at(TimePeriod::Today, None, None, 4, DayZone::Local, at(2, 2)).unwrap();
+        assert_eq!(start, Some(at(1, 4).timestamp()));
+        let (start, _) = time_period_at(TimePeriod::Today, None, None, 4, DayZone::Local, at(2, 5)).unwrap();
+        assert_eq!(start, Some(at(2, 4).timestamp()));
+        let (start, _) = time_period_at(TimePeriod::Today, None, None, 0, DayZone::Local, at(2, 2)).unwrap();
+        assert_eq!(start, Some(at(2, 0).timestamp()));
+
+        // Custom days run from one day start to the next
+        let (start, end) = time_period_at(
+            TimePeriod::Custom, Some("2024-01-01".to_string()), Some("2024-01-01".to_string()), 4, DayZone::Local, at(10, 12),
+        ).unwrap();
+        assert_eq!((start, end), (Some(at(1, 4).timestamp()), Some(at(2, 4).timestamp() - 1)));
+    }
+
+    #[test]
+    fn test_utc_today_ignores_the_local_zone() {
+        // The tests run in whatever zone the machine is in, so check both ends of the UTC
+        // day: 05:00 and 23:00 UTC on 2024-01-01 fall on another local day in most zones
+        for now in [1_704_085_200, 1_704_150_000] {
+            let now = Local.timestamp_opt(now, 0).unwrap();
+
+            let (utc, _) = time_period_at(TimePeriod::Today, None, None, 0, DayZone::Utc, now).unwrap();
+            assert_eq!(utc, Some(1_704_067_200)); // 2024-01-01 00:00 UTC
+            // Day starts shift from UTC midnight too
+            let (utc, _) = time_period_at(TimePeriod::Today, None, None, 4, DayZone::Utc, now).unwrap();
+            assert_eq!(utc, Some(1_704_067_200 + 4 * 3600));
+
+            let local_midnight = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
+            let (local, _) = time_period_at(TimePeriod::Today, None, None, 0, DayZone::Local, now).unwrap();
+            assert_eq!(local, Local.from_local_datetime(&local_midnight).earliest().map(|midnight| midnight.timestamp()));
+        }
+
+        let args = Args::try_parse_from(["gopal-cli", "stats", "--utc"]).unwrap();
+        assert_eq!(day_zone(args.utc), DayZone::Utc);
+    }
+
     #[test]
     fn test_parse_all_time_period() {
-        let (start, end) = parse_time_period(TimePeriod::AllTime, None, None).unwrap();
+        let (start, end) = parse_time_period(TimePeriod::AllTime, None, None, 0, DayZone::Local).unwrap();
         assert!(start.is_none());
         assert!(end.is_none());
     }
@@ -484,23 +1831,526 @@ mod tests {
         let (start, end) = parse_time_period(
             TimePeriod::Custom, 
             Some("2023-01-01".to_string()), 
-            Some("2023-12-31".to_string())
+            Some("2023-12-31".to_string()),
+            0,
+            DayZone::Local,
         ).unwrap();
         assert!(start.is_some());
         assert!(end.is_some());
     }
-}
 
-fn format_duration(seconds: i64) -> String {
-    if seconds < 60 {
-        format!("{} sec", seconds)
-    } else {
-        let minutes = seconds / 60;
-        let remaining_seconds = seconds % 60;
-        if remaining_seconds == 0 {
-            format!("{} min", minutes)
-        } else {
-            format!("{} min {} sec", minutes, remaining_seconds)
+    #[test]
+    fn test_stats_checkpoint_advances_between_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gopal").join(gopal::paths::STATS_CHECKPOINT_FILE);
+
+        // The first run falls back to the period
+        let first = StatsCheckpoint::open(path.clone(), false, 1000).unwrap();
+        assert_eq!(first.range((Some(500), None)), (Some(500), Some(999)));
+        first.advance().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), "1000");
+
+        let second = StatsCheckpoint::open(path.clone(), false, 2000).unwrap();
+        assert_eq!(second.range((Some(500), None)), (Some(1000), Some(1999)));
+        second.advance().unwrap();
+
+        // A run that doesn't advance leaves the next one starting at the same place
+        let failed = StatsCheckpoint::open(path.clone(), false, 3000).unwrap();
+        assert_eq!(failed.range((None, None)), (Some(2000), Some(2999)));
+        assert_eq!(StatsCheckpoint::open(path.clone(), false, 4000).unwrap().range((None, None)).0, Some(2000));
+
+        let reset = StatsCheckpoint::open(path.clone(), true, 5000).unwrap();
+        assert_eq!(reset.range((Some(500), None)), (Some(500), Some(4999)));
+        assert!(!path.exists());
+        StatsCheckpoint::open(path.clone(), true, 5000).unwrap();
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(StatsCheckpoint::open(path.clone(), false, 6000).is_err());
+    }
+
+    #[test]
+    fn test_compute_rank_movement() {
+        let previous = ["a", "b", "c", "d"];
+        let current = ["c", "b", "a", "e"];
+
+        let movement = compute_rank_movement(&current, &previous);
+        let summary: Vec<(usize, Option<usize>, Movement)> = movement
+            .iter()
+            .map(|m| (m.rank, m.previous_rank, m.movement))
+            .collect();
+
+        assert_eq!(summary, vec![
+            (1, Some(3), Movement::Up),
+            (2, Some(2), Movement::Same),
+            (3, Some(1), Movement::Down),
+            (4, None, Movement::New),
+        ]);
+    }
+
+    #[test]
+    fn test_quiet_flag_is_global() {
+        let args = Args::try_parse_from(["gopal-cli", "--quiet", "status"]).unwrap();
+        assert!(args.quiet);
+
+        let args = Args::try_parse_from(["gopal-cli", "top-tracks", "-q"]).unwrap();
+        assert!(args.quiet);
+
+        let args = Args::try_parse_from(["gopal-cli", "status"]).unwrap();
+        assert!(!args.quiet);
+    }
+
+    #[test]
+    fn test_parse_import_external() {
+        let args = Args::try_parse_from(["gopal-cli", "import-external", "--format", "listenbrainz", "listens.json"]).unwrap();
+        match args.command {
+            Commands::ImportExternal { format, file, default_duration, force, .. } => {
+                assert!(matches!(format, ExternalFormat::Listenbrainz));
+                assert_eq!(file, PathBuf::from("listens.json"));
+                assert_eq!(default_duration, DEFAULT_IMPORT_DURATION_SECS);
+                assert!(!force);
+            }
+            _ => panic!("Expected import-external"),
+        }
+
+        let args = Args::try_parse_from(["gopal-cli", "import-external", "--format", "gopal", "--force", "backup.json"]).unwrap();
+        assert!(matches!(args.command, Commands::ImportExternal { format: ExternalFormat::Gopal, force: true, .. }));
+
+        assert!(Args::try_parse_from(["gopal-cli", "import-external", "listens.json"]).is_err());
+    }
+
+    #[test]
+    fn test_ndjson_lines_parse_independently() {
+        let sessions: Vec<gopal::database::SessionWithMetadata> = (0..3)
+            .map(|i| gopal::database::SessionWithMetadata {
+                session: gopal::database::Session {
+                    id: i,
+                    track_id: format!("Song {}::Artist::Album", i),
+                    player_id: 1,
+                    start_time: 1000 + i * 100,
+                    end_time: Some(1060 + i * 100),
+                    paused_time: 0,
+                    listened_time: Some(60),
+                    status: "completed".to_string(),
+                    label: None,
+                    paused_since: None,
+                    content_type: None,
+                },
+                track: gopal::database::Track {
+                    id: format!("Song {}::Artist::Album", i),
+                    // Embedded newlines must not split a record across lines
+                    title: format!("Song {}\nwith a newline", i),
+                    artist: "Artist".to_string(),
+                    album: "Album".to_string(),
+                    length: None,
+                    art_url: None,
+                    genre: None,
+                    raw_metadata: None,
+                    track_number: None,
+                    disc_number: None,
+                    primary_artist: None,
+                    rating: None,
+                    comment: None,
+                },
+                player: gopal::database::Player {
+                    id: 1,
+                    name: "org.mpris.MediaPlayer2.spotify".to_string(),
+                    identity: "Spotify".to_string(),
+                },
+            })
+            .collect();
+
+        let mut out = Vec::new();
+        for session in &sessions {
+            write_ndjson_line(&mut out, session).unwrap();
+        }
+
+        let output = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (i, line) in lines.iter().enumerate() {
+            let parsed: gopal::database::SessionWithMetadata = serde_json::from_str(line).unwrap();
+            assert_eq!(parsed.session.id, i as i64);
+            assert_eq!(parsed.track.title, sessions[i].track.title);
+        }
+    }
+
+    #[test]
+    fn test_collapse_history_merges_fragments_of_a_track() {
+        let entry = |id: i64, title: &str, start_time: i64, listened: i64| SessionWithMetadata {
+            session: gopal::database::Session {
+                id,
+                track_id: format!("{}::Artist::Album", title),
+                player_id: 1,
+                start_time,
+                end_time: Some(start_time + listened + 5),
+                paused_time: 5,
+                listened_time: Some(listened),
+                status: "completed".to_string(),
+                label: None,
+                paused_since: None,
+                content_type: None,
+            },
+            track: gopal::database::Track {
+                id: format!("{}::Artist::Album", title),
+                title: title.to_string(),
+                artist: "Artist".to_string(),
+                album: "Album".to_string(),
+                length: None,
+                art_url: None,
+                genre: None,
+                raw_metadata: None,
+                track_number: None,
+                disc_number: None,
+                primary_artist: None,
+                rating: None,
+                comment: None,
+            },
+            player: gopal::database::Player {
+                id: 1,
+                name: "org.mpris.MediaPlayer2.spotify".to_string(),
+                identity: "Spotify".to_string(),
+            },
+        };
+        // Newest first; three fragments of Song with short gaps, then Song again much earlier
+        let history = vec![
+            entry(5, "Other", 2000, 100),
+            entry(4, "Song", 1250, 50),
+            entry(3, "Song", 1150, 60),
+            entry(2, "Song", 1000, 120),
+            entry(1, "Song", 100, 180),
+        ];
+
+        let mut collapsed = Vec::new();
+        for entry in history {
+            collapse_into(&mut collapsed, entry, 60);
+        }
+        let summary: Vec<[i64; 5]> = collapsed.iter()
+            .map(|e| [e.session.id, e.session.start_time, e.session.end_time.unwrap(), e.session.listened_time.unwrap(), e.session.paused_time])
+            .collect();
+        assert_eq!(summary, vec![
+            [5, 2000, 2105, 100, 5],
+            [4, 1000, 1305, 230, 15],
+            [1, 100, 285, 180, 5],
+        ]);
+
+        let args = Args::try_parse_from(["gopal-cli", "history", "--collapse"]).unwrap();
+        assert!(matches!(args.command, Commands::History { collapse: Some(gap), .. } if gap == "5m"));
+    }
+
+    #[test]
+    fn test_half_life_must_be_positive() {
+        let parse = |days: &str| Args::try_parse_from(["gopal-cli", "top-tracks", "--recency-weighted", "--half-life-days", days]);
+        for days in ["0", "-7", "inf", "NaN", "soon"] {
+            assert!(parse(days).is_err(), "{}", days);
+        }
+        let args = parse("2.5").unwrap();
+        assert!(matches!(args.command, Commands::TopTracks { recency, .. } if recency.half_life_days == 2.5));
+    }
+
+    #[tokio::test]
+    async fn test_anonymize_is_refused_where_it_cannot_apply() {
+        let mut renderer = RecordingRenderer::default();
+        for command in ["status", "now", "active"] {
+            let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "--anonymize", command]).unwrap();
+            let error = run_with_renderer(args, &mut renderer).await.unwrap_err();
+            assert!(error.to_string().contains("--anonymize only applies"), "{}: {:#}", command, error);
+        }
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "--anonymize", "rank-history", "--artist", "A"]).unwrap();
+        assert!(run_with_renderer(args, &mut renderer).await.is_err());
+        assert!(renderer.reports.is_empty());
+
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "--anonymize", "history"]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+        assert_eq!(renderer.reports[0].0, "history");
+    }
+
+    #[tokio::test]
+    async fn test_anonymized_export_has_only_track_totals() {
+        let temp_db = tempfile::NamedTempFile::new().unwrap();
+        let db_path = temp_db.path().to_str().unwrap().to_string();
+        Database::new(&db_path).unwrap().import_listenbrainz(r#"[
+            {"listened_at": 1700000000, "track_metadata": {"track_name": "Song", "artist_name": "Band", "release_name": "Album"}},
+            {"listened_at": 1700000500, "track_metadata": {"track_name": "Song", "artist_name": "Band", "release_name": "Album"}},
+            {"listened_at": 1700001000, "track_metadata": {"track_name": "Other", "artist_name": "Band", "release_name": "Album"}}
+        ]"#, 180).unwrap();
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let args = Args::try_parse_from([
+            "gopal-cli", "--database", &db_path, "--anonymize", "export", "--output", output.path().to_str().unwrap(),
+        ]).unwrap();
+        run_with_renderer(args, &mut RecordingRenderer::default()).await.unwrap();
+
+        let json = std::fs::read_to_string(output.path()).unwrap();
+        let export: gopal::export::AnonymizedExport = serde_json::from_str(&json).unwrap();
+        assert_eq!((export.play_count, export.total_listening_time), (3, 540));
+        assert_eq!((export.tracks[0].title.as_str(), export.tracks[0].play_count), ("Song", 2));
+        for field in ["start_time", "end_time", "exported_at", "session", "\"id\"", "player", "1700000000"] {
+            assert!(!json.contains(field), "{} leaked into {}", field, json);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_limit_counts_collapsed_entries() {
+        let temp_db = tempfile::NamedTempFile::new().unwrap();
+        let db_path = temp_db.path().to_str().unwrap().to_string();
+        // Three fragments of Song 20 seconds apart, then Other
+        Database::new(&db_path).unwrap().import_listenbrainz(r#"[
+            {"listened_at": 1000, "track_metadata": {"artist_name": "Artist", "track_name": "Song"}},
+            {"listened_at": 1200, "track_metadata": {"artist_name": "Artist", "track_name": "Song"}},
+            {"listened_at": 1400, "track_metadata": {"artist_name": "Artist", "track_name": "Song"}},
+            {"listened_at": 1600, "track_metadata": {"artist_name": "Artist", "track_name": "Other"}}
+        ]"#, 180).unwrap();
+
+        let mut renderer = RecordingRenderer::default();
+        let args = Args::try_parse_from([
+            "gopal-cli", "--database", &db_path, "history", "--period", "all-time", "--limit", "2", "--collapse", "1m",
+        ]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+
+        let (report, history) = &renderer.reports[0];
+        assert_eq!(report, "history");
+        let entries: Vec<(&str, i64)> = history.as_array().unwrap().iter()
+            .map(|entry| (entry["track"]["title"].as_str().unwrap(), entry["session"]["listened_time"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(entries, vec![("Other", 180), ("Song", 540)]);
+    }
+
+    /// Keeps every report as JSON, the way a custom renderer would see it
+    #[derive(Default)]
+    struct RecordingRenderer {
+        reports: Vec<(String, serde_json::Value)>,
+    }
+
+    impl ValueRenderer for RecordingRenderer {
+        fn value<T: Serialize + ?Sized>(&mut self, report: &str, value: &T) -> Result<()> {
+            self.reports.push((report.to_string(), serde_json::to_value(value)?));
+            Ok(())
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_custom_renderer_receives_results() {
+        let temp_db = tempfile::NamedTempFile::new().unwrap();
+        let db_path = temp_db.path().to_str().unwrap().to_string();
+        Database::new(&db_path).unwrap().import_listenbrainz(r#"[
+            {"listened_at": 1000, "track_metadata": {"artist_name": "Artist", "track_name": "One"}},
+            {"listened_at": 2000, "track_metadata": {"artist_name": "Artist", "track_name": "Two"}}
+        ]"#, 200).unwrap();
+
+        let mut renderer = RecordingRenderer::default();
+        let args = Args::try_parse_from(["gopal-cli", "--database", &db_path, "top-artists", "--period", "all-time"]).unwrap();
+        let exit = run_with_renderer(args, &mut renderer).await.unwrap();
+        assert_eq!(exit, CliExit::Success);
+
+        let args = Args::try_parse_from(["gopal-cli", "--database", &db_path, "status"]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+
+        let (report, artists) = &renderer.reports[0];
+        assert_eq!(report, "top-artists");
+        assert_eq!(artists[0]["artist"], "Artist");
+        assert_eq!(artists[0]["total_listened_time"], 400);
+        assert_eq!(artists[0]["track_count"], 2);
+
+        let (report, status) = &renderer.reports[1];
+        assert_eq!(report, "status");
+        assert_eq!(status["total_sessions"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_group_by_applies_the_filters() {
+        let temp_db = tempfile::NamedTempFile::new().unwrap();
+        let db_path = temp_db.path().to_str().unwrap().to_string();
+        Database::new(&db_path).unwrap().import_listenbrainz(r#"[
+            {"listened_at": 1000, "track_metadata": {"artist_name": "The Band", "track_name": "One", "release_name": "First"}},
+            {"listened_at": 2000, "track_metadata": {"artist_name": "The Band", "track_name": "Two", "release_name": "Second"}},
+            {"listened_at": 3000, "track_metadata": {"artist_name": "Singer", "track_name": "Three", "release_name": "First"}}
+        ]"#, 200).unwrap();
+
+        let mut renderer = RecordingRenderer::default();
+        let args = Args::try_parse_from([
+            "gopal-cli", "--database", &db_path, "stats", "--period", "all-time", "--group-by", "artist", "--artist-contains", "band",
+        ]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+
+        let (report, groups) = &renderer.reports[0];
+        assert_eq!(report, "stats");
+        let groups: Vec<(&str, i64)> = groups.as_array().unwrap().iter()
+            .map(|group| (group["label"].as_str().unwrap(), group["total_listened_time"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(groups, vec![("The Band", 400)]);
+    }
+
+    #[tokio::test]
+    async fn test_explain_logs_the_commands_queries() {
+        let mut renderer = RecordingRenderer::default();
+        Database::take_logged_queries();
+
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "status"]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+        assert!(Database::take_logged_queries().is_empty());
+
+        // Plain `--explain` only logs, so nothing is kept for plans
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "--explain", "status"]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+        assert!(Database::take_logged_queries().is_empty());
+
+        // Plans are printed from the logged queries, which are used up
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "--explain=plan", "status"]).unwrap();
+        run_with_renderer(args, &mut renderer).await.unwrap();
+        assert!(Database::take_logged_queries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stats_from_export_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("listens.json");
+        std::fs::write(&export, r#"[
+            {"listened_at": 1000, "track_metadata": {"artist_name": "Artist", "track_name": "One",
+             "additional_info": {"duration_ms": 180000}}},
+            {"listened_at": 2000, "track_metadata": {"artist_name": "Artist", "track_name": "Two"}},
+            {"listened_at": 3000, "track_metadata": {"artist_name": "Other", "track_name": "Three"}}
+        ]"#).unwrap();
+
+        let mut renderer = RecordingRenderer::default();
+        let args = Args::try_parse_from([
+            "gopal-cli", "--from-export", export.to_str().unwrap(), "stats", "--period", "all-time",
+        ]).unwrap();
+        assert_eq!(run_with_renderer(args, &mut renderer).await.unwrap(), CliExit::Success);
+
+        let (_, stats) = &renderer.reports[0];
+        let default = DEFAULT_IMPORT_DURATION_SECS;
+        assert_eq!(stats["total_listening_time"], 180 + 2 * default);
+        assert_eq!(stats["top_artists"][0]["artist"], "Artist");
+        assert_eq!(stats["top_artists"][0]["total_listened_time"], 180 + default);
+
+        // Nothing but the export was written
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        // An empty in-memory database is fine to query and never missing
+        let args = Args::try_parse_from(["gopal-cli", "--database", ":memory:", "top-tracks"]).unwrap();
+        assert_eq!(run_with_renderer(args, &mut renderer).await.unwrap(), CliExit::EmptyResult);
+        assert!(!std::path::Path::new(":memory:").exists());
+    }
+
+    #[test]
+    fn test_ping_reports_stale_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gopald.status");
+        DaemonStatus { pid: 42, started_at: 1000, last_poll: Some(1100), updated_at: 1100, recent_activity: Vec::new() }.write(&path).unwrap();
+
+        let mut renderer = RecordingRenderer::default();
+        assert_eq!(ping(Some(path.clone()), 60, 1130, &mut renderer).unwrap(), CliExit::Success);
+        assert_eq!(ping(Some(path.clone()), 60, 1200, &mut renderer).unwrap(), CliExit::DaemonUnavailable);
+
+        let (report, alive) = &renderer.reports[0];
+        assert_eq!(report, "ping");
+        assert_eq!(alive["pid"], 42);
+        assert_eq!(alive["state"], "alive");
+        assert_eq!(alive["last_poll_age"], 30);
+        assert_eq!(renderer.reports[1].1["state"], "stale");
+
+        let missing = ping(Some(dir.path().join("missing")), 60, 1130, &mut renderer).unwrap_err();
+        assert_eq!(CliExit::from_error(&missing), CliExit::DaemonUnavailable);
+    }
+
+    #[test]
+    fn test_parse_not_since() {
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let days_ago = |days: i64| (now - Duration::days(days)).timestamp();
+
+        assert_eq!(parse_not_since("3 months ago", now).unwrap(), days_ago(90));
+        assert_eq!(parse_not_since("1 week ago", now).unwrap(), days_ago(7));
+        assert_eq!(parse_not_since("2 years", now).unwrap(), days_ago(730));
+        assert_eq!(
+            parse_not_since("2024-01-31", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap().timestamp()
+        );
+        assert!(parse_not_since("3 fortnights ago", now).is_err());
+        assert!(parse_not_since("recently", now).is_err());
+        // A time in the future isn't "since"
+        assert!(parse_not_since("-3 months ago", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_max_age() {
+        assert_eq!(parse_max_age("24h").unwrap(), 86400);
+        assert_eq!(parse_max_age("90m").unwrap(), 5400);
+        assert_eq!(parse_max_age("2d").unwrap(), 172800);
+        assert_eq!(parse_max_age("45s").unwrap(), 45);
+        assert_eq!(parse_max_age("600").unwrap(), 600);
+        assert!(parse_max_age("-1h").is_err());
+        assert!(parse_max_age("a day").is_err());
+        assert!(parse_max_age("").is_err());
+    }
+
+    #[test]
+    fn test_counts_are_range_checked() {
+        assert!(Args::try_parse_from(["gopal-cli", "rediscover", "--min-plays=-1"]).is_err());
+        assert!(Args::try_parse_from(["gopal-cli", "rediscover", "--min-plays", "0"]).is_ok());
+        assert!(Args::try_parse_from(["gopal-cli", "trend", "--window-days", "0"]).is_err());
+        assert!(Args::try_parse_from(["gopal-cli", "trend", "--window-days", "14"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_month_day() {
+        assert_eq!(parse_month_day("03-14").unwrap(), (3, 14));
+        assert_eq!(parse_month_day("2-29").unwrap(), (2, 29));
+        assert!(parse_month_day("02-30").is_err());
+        assert!(parse_month_day("14").is_err());
+    }
+
+    #[test]
+    fn test_parse_repeated_excludes() {
+        let args = Args::try_parse_from([
+            "gopal-cli", "top-artists",
+            "--exclude-artist", "Calm App", "--exclude-artist", "Test Band",
+            "--exclude-track", "Test Tone",
+        ]).unwrap();
+        let Commands::TopArtists { filter, .. } = args.command else {
+            panic!("expected top-artists");
+        };
+        let filter = StatsFilter::from(filter);
+        assert_eq!(filter.exclude_artists, vec!["Calm App", "Test Band"]);
+        assert_eq!(filter.exclude_tracks, vec!["Test Tone"]);
+    }
+
+    #[test]
+    fn test_render_command_required_for_command_format() {
+        assert!(Args::try_parse_from(["gopal-cli", "--format", "command", "status"]).is_err());
+        let args = Args::try_parse_from(["gopal-cli", "--format", "command", "--render-command", "jq .", "status"]).unwrap();
+        assert_eq!(args.render_command.as_deref(), Some("jq ."));
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let not_found = anyhow::Error::new(Database::open_existing("/missing.db", None).err().unwrap());
+        assert_eq!(CliExit::from_error(&not_found), CliExit::DatabaseNotFound);
+
+        // Context added on top must not hide the original cause
+        let wrapped = not_found.context("Failed to open database");
+        assert_eq!(CliExit::from_error(&wrapped), CliExit::DatabaseNotFound);
+
+        let query_error = anyhow::anyhow!("no such table: sessions");
+        assert_eq!(CliExit::from_error(&query_error), CliExit::QueryError);
+
+        assert_eq!(CliExit::from_result_count(0), CliExit::EmptyResult);
+        assert_eq!(CliExit::from_result_count(3), CliExit::Success);
+
+        let not_running = anyhow::Error::new(DaemonNotRunning(PathBuf::from("/run/gopald.status")));
+        assert_eq!(CliExit::from_error(&not_running), CliExit::DaemonUnavailable);
+
+        let codes = [CliExit::Success, CliExit::QueryError, CliExit::DatabaseNotFound, CliExit::EmptyResult, CliExit::DaemonUnavailable]
+            .map(|exit| exit as u8);
+        assert_eq!(codes, [0, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_previous_period() {
+        let now = 1_000_000;
+        let (start, end) = previous_period(&TimePeriod::Week, Some(now - 7 * 86400), None, now).unwrap();
+        assert_eq!(start, now - 14 * 86400);
+        assert_eq!(end, now - 7 * 86400 - 1);
+
+        assert!(previous_period(&TimePeriod::AllTime, None, None, now).is_err());
+    }
+}
@@ -0,0 +1,2073 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::process::{Command, Stdio};
+
+use gopal::database::{
+    metadata_label, AlbumCompleteness, AnonymousPlay, ArtistAggregateStatus, ArtistCatalog, ArtistStats, ConsistencyScore, Dashboard, DatabaseStats, EngagementStats, GroupedStats, PlayerSummary, RankPoint,
+    QueuedScrobble, RatedTrack, TrendDirection, TrendReport, SessionWithMetadata, SkipStats, TimelineEntry, TimingMismatch, Track, TrackStats, WeekdayAvg, YearSlice,
+};
+use gopal::import::ImportSummary;
+use gopal::locale::OutputLocale;
+use gopal::now_playing::{NowPlaying, NowPlayingTemplate};
+
+use super::{ActiveSession, DaemonPing, Granularity, GroupDimension, OutputFormat, RankedTrack, RecordingState, SessionLabel, SortBy, TrackBreakdown};
+use gopal::daemon_status::DaemonHealth;
+
+/// Presents each kind of command result. Commands hand their results to a renderer
+/// instead of printing, so output formats can be added without touching the queries.
+pub(crate) trait StatsRenderer {
+    fn stats(&mut self, dashboard: &Dashboard, engagement: Option<&EngagementStats>, limit: usize) -> Result<()>;
+    fn grouped(&mut self, groups: &[GroupedStats], dimension: GroupDimension) -> Result<()>;
+    fn sources(&mut self, sources: &[GroupedStats]) -> Result<()>;
+    fn top_tracks(&mut self, tracks: &[TrackStats], sort_by: &SortBy) -> Result<()>;
+    fn ranked_tracks(&mut self, tracks: &[RankedTrack], sort_by: &SortBy) -> Result<()>;
+    fn track_breakdown(&mut self, tracks: &[TrackBreakdown], sort_by: &SortBy) -> Result<()>;
+    fn top_artists(&mut self, artists: &[ArtistStats]) -> Result<()>;
+    fn rediscovery(&mut self, tracks: &[TrackStats]) -> Result<()>;
+    fn top_rated(&mut self, tracks: &[RatedTrack]) -> Result<()>;
+    fn trend(&mut self, trend: &TrendReport) -> Result<()>;
+    fn history(&mut self, history: &[SessionWithMetadata]) -> Result<()>;
+    fn anonymous_history(&mut self, plays: &[AnonymousPlay]) -> Result<()>;
+    fn now_playing(&mut self, now_playing: &[NowPlaying], template: &NowPlayingTemplate) -> Result<()>;
+    fn skip_stats(&mut self, stats: &SkipStats) -> Result<()>;
+    fn consistency(&mut self, consistency: &ConsistencyScore) -> Result<()>;
+    fn weekdays(&mut self, averages: &[WeekdayAvg]) -> Result<()>;
+    fn on_this_day(&mut self, month: u32, day: u32, years: &[YearSlice]) -> Result<()>;
+    fn replay(&mut self, date: NaiveDate, timeline: &[TimelineEntry]) -> Result<()>;
+    fn artist(&mut self, catalog: &ArtistCatalog) -> Result<()>;
+    fn album(&mut self, name: &str, tracks: &[TrackStats]) -> Result<()>;
+    fn album_completeness(&mut self, albums: &[AlbumCompleteness]) -> Result<()>;
+    fn incomplete_tracks(&mut self, tracks: &[Track]) -> Result<()>;
+    fn rank_history(&mut self, name: &str, history: &[RankPoint]) -> Result<()>;
+    fn session_label(&mut self, update: &SessionLabel) -> Result<()>;
+    fn status(&mut self, stats: &DatabaseStats) -> Result<()>;
+    fn aggregates(&mut self, status: &ArtistAggregateStatus) -> Result<()>;
+    fn players(&mut self, players: &[PlayerSummary]) -> Result<()>;
+    fn timing_report(&mut self, mismatches: &[TimingMismatch], tolerance: i64) -> Result<()>;
+    fn scrobble_queue(&mut self, queue: &[QueuedScrobble]) -> Result<()>;
+    fn active_sessions(&mut self, sessions: &[ActiveSession]) -> Result<()>;
+    fn cleanup(&mut self, closed: &[SessionWithMetadata], max_age: i64) -> Result<()>;
+    fn import_summary(&mut self, summary: &ImportSummary, default_duration: i64) -> Result<()>;
+    fn ping(&mut self, ping: &DaemonPing) -> Result<()>;
+    fn recording(&mut self, state: &RecordingState) -> Result<()>;
+}
+
+/// Renderer for `--format`; `render_command` is required by clap for `--format command`
+pub(crate) fn renderer_for(
+    format: &OutputFormat,
+    quiet: bool,
+    granularity: Granularity,
+    render_command: Option<String>,
+    columns: Option<Vec<String>>,
+    locale: OutputLocale,
+) -> Box<dyn StatsRenderer> {
+    match (format, render_command) {
+        (OutputFormat::Human, _) => Box::new(HumanRenderer { quiet, granularity, locale }),
+        (OutputFormat::Json, _) => Box::new(JsonRenderer),
+        (OutputFormat::Ndjson, _) => Box::new(NdjsonRenderer),
+        (OutputFormat::Csv, _) => Box::new(CsvRenderer { granularity, columns }),
+        (OutputFormat::Command, Some(command)) => Box::new(CommandRenderer { command }),
+        (OutputFormat::Command, None) => unreachable!("clap requires --render-command with --format command"),
+        (OutputFormat::Prometheus, _) => Box::new(PrometheusRenderer),
+    }
+}
+
+/// Formats that serialize results as data. Each report is named after the command
+/// that produced it. Implementing `value` is enough to get a full `StatsRenderer`.
+pub(crate) trait ValueRenderer {
+    fn value<T: Serialize + ?Sized>(&mut self, report: &str, value: &T) -> Result<()>;
+
+    fn list<T: Serialize>(&mut self, report: &str, items: &[T]) -> Result<()> {
+        self.value(report, items)
+    }
+}
+
+impl<R: ValueRenderer> StatsRenderer for R {
+    fn stats(&mut self, dashboard: &Dashboard, engagement: Option<&EngagementStats>, _limit: usize) -> Result<()> {
+        self.value("stats", &StatsOutput { dashboard, engagement })
+    }
+
+    fn grouped(&mut self, groups: &[GroupedStats], _dimension: GroupDimension) -> Result<()> {
+        self.list("stats", groups)
+    }
+
+    fn sources(&mut self, sources: &[GroupedStats]) -> Result<()> {
+        self.list("sources", sources)
+    }
+
+    fn top_tracks(&mut self, tracks: &[TrackStats], _sort_by: &SortBy) -> Result<()> {
+        self.list("top-tracks", tracks)
+    }
+
+    fn ranked_tracks(&mut self, tracks: &[RankedTrack], _sort_by: &SortBy) -> Result<()> {
+        self.list("top-tracks", tracks)
+    }
+
+    fn track_breakdown(&mut self, tracks: &[TrackBreakdown], _sort_by: &SortBy) -> Result<()> {
+        self.list("top-tracks", tracks)
+    }
+
+    fn top_artists(&mut self, artists: &[ArtistStats]) -> Result<()> {
+        self.list("top-artists", artists)
+    }
+
+    fn rediscovery(&mut self, tracks: &[TrackStats]) -> Result<()> {
+        self.list("rediscover", tracks)
+    }
+
+    fn top_rated(&mut self, tracks: &[RatedTrack]) -> Result<()> {
+        self.list("top-rated", tracks)
+    }
+
+    fn trend(&mut self, trend: &TrendReport) -> Result<()> {
+        self.value("trend", trend)
+    }
+
+    fn history(&mut self, history: &[SessionWithMetadata]) -> Result<()> {
+        self.list("history", history)
+    }
+
+    fn anonymous_history(&mut self, plays: &[AnonymousPlay]) -> Result<()> {
+        self.list("history", plays)
+    }
+
+    fn now_playing(&mut self, now_playing: &[NowPlaying], _template: &NowPlayingTemplate) -> Result<()> {
+        self.list("now", now_playing)
+    }
+
+    fn skip_stats(&mut self, stats: &SkipStats) -> Result<()> {
+        self.value("skip-stats", stats)
+    }
+
+    fn consistency(&mut self, consistency: &ConsistencyScore) -> Result<()> {
+        self.value("consistency", consistency)
+    }
+
+    fn weekdays(&mut self, averages: &[WeekdayAvg]) -> Result<()> {
+        self.list("weekdays", averages)
+    }
+
+    fn on_this_day(&mut self, _month: u32, _day: u32, years: &[YearSlice]) -> Result<()> {
+        self.list("on-this-day", years)
+    }
+
+    fn replay(&mut self, _date: NaiveDate, timeline: &[TimelineEntry]) -> Result<()> {
+        self.list("replay", timeline)
+    }
+
+    fn artist(&mut self, catalog: &ArtistCatalog) -> Result<()> {
+        self.value("artist", catalog)
+    }
+
+    fn album(&mut self, _name: &str, tracks: &[TrackStats]) -> Result<()> {
+        self.list("album", tracks)
+    }
+
+    fn album_completeness(&mut self, albums: &[AlbumCompleteness]) -> Result<()> {
+        self.list("album-completeness", albums)
+    }
+
+    fn incomplete_tracks(&mut self, tracks: &[Track]) -> Result<()> {
+        let tracks: Vec<_> = tracks.iter()
+            .map(|track| IncompleteTrackOutput { track, missing: track.missing_fields() })
+            .collect();
+        self.list("incomplete", &tracks)
+    }
+
+    fn rank_history(&mut self, _name: &str, history: &[RankPoint]) -> Result<()> {
+        self.list("rank-history", history)
+    }
+
+    fn session_label(&mut self, update: &SessionLabel) -> Result<()> {
+        self.value("label", update)
+    }
+
+    fn status(&mut self, stats: &DatabaseStats) -> Result<()> {
+        self.value("status", stats)
+    }
+
+    fn aggregates(&mut self, status: &ArtistAggregateStatus) -> Result<()> {
+        self.value("refresh-aggregates", status)
+    }
+
+    fn players(&mut self, players: &[PlayerSummary]) -> Result<()> {
+        self.list("players", players)
+    }
+
+    fn timing_report(&mut self, mismatches: &[TimingMismatch], _tolerance: i64) -> Result<()> {
+        self.list("check-timing", mismatches)
+    }
+
+    fn scrobble_queue(&mut self, queue: &[QueuedScrobble]) -> Result<()> {
+        self.list("scrobble-queue", queue)
+    }
+
+    fn active_sessions(&mut self, sessions: &[ActiveSession]) -> Result<()> {
+        self.list("active", sessions)
+    }
+
+    fn cleanup(&mut self, closed: &[SessionWithMetadata], _max_age: i64) -> Result<()> {
+        self.list("cleanup", closed)
+    }
+
+    fn import_summary(&mut self, summary: &ImportSummary, _default_duration: i64) -> Result<()> {
+        self.value("import-external", summary)
+    }
+
+    fn ping(&mut self, ping: &DaemonPing) -> Result<()> {
+        self.value("ping", ping)
+    }
+
+    fn recording(&mut self, state: &RecordingState) -> Result<()> {
+        self.value("record", state)
+    }
+}
+
+struct JsonRenderer;
+
+impl ValueRenderer for JsonRenderer {
+    fn value<T: Serialize + ?Sized>(&mut self, _report: &str, value: &T) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(value)?);
+        Ok(())
+    }
+}
+
+struct NdjsonRenderer;
+
+impl ValueRenderer for NdjsonRenderer {
+    fn value<T: Serialize + ?Sized>(&mut self, _report: &str, value: &T) -> Result<()> {
+        print_ndjson([value])
+    }
+
+    fn list<T: Serialize>(&mut self, _report: &str, items: &[T]) -> Result<()> {
+        print_ndjson(items)
+    }
+}
+
+/// Hands results to a user-supplied program, for report formats gopal doesn't ship
+struct CommandRenderer {
+    command: String,
+}
+
+impl ValueRenderer for CommandRenderer {
+    fn value<T: Serialize + ?Sized>(&mut self, report: &str, value: &T) -> Result<()> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("GOPAL_REPORT", report)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run render command '{}'", self.command))?;
+
+        // Dropping stdin after writing lets the command see end of input
+        let written = match child.stdin.take() {
+            Some(stdin) => {
+                let mut writer = BufWriter::new(stdin);
+                serde_json::to_writer(&mut writer, value)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| Ok(writer.flush()?))
+            }
+            None => Ok(()),
+        };
+
+        // Wait even if writing failed, which is usually the command exiting early
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("Render command '{}' failed with {}", self.command, status);
+        }
+        written.with_context(|| format!("Failed to write to render command '{}'", self.command))
+    }
+}
+
+/// One-shot Prometheus metrics for cron jobs feeding a Pushgateway. Only the stats
+/// dashboard, database status and the daemon's recent activity from `ping` make sense
+/// as gauges; other reports are refused.
+struct PrometheusRenderer;
+
+impl ValueRenderer for PrometheusRenderer {
+    fn value<T: Serialize + ?Sized>(&mut self, report: &str, value: &T) -> Result<()> {
+        print!("{}", prometheus_metrics(report, &serde_json::to_value(value)?)?);
+        Ok(())
+    }
+}
+
+/// Metrics text for a report, as serialized for the other data formats
+fn prometheus_metrics(report: &str, value: &serde_json::Value) -> Result<String> {
+    let mut metrics = PrometheusText::default();
+    let number = |key: &str| value[key].as_f64().unwrap_or(0.0);
+
+    match report {
+        // `stats --group-by` reports a list under the same name
+        "stats" if value.is_object() => {
+            metrics.gauge("listening_seconds", "Seconds listened in the period", number("total_listening_time"));
+            metrics.gauge("plays", "Sessions in the period", number("plays"));
+            metrics.gauge("scrobbles", "Plays in the period long enough to scrobble", number("scrobbles"));
+            metrics.gauge("active_days", "Days in the period with any listening", number("active_days"));
+
+            let tracks = value["top_tracks"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let track_labels = |track: &serde_json::Value| -> Vec<(&'static str, String)> {
+                ["title", "artist", "album"].into_iter()
+                    .map(|key| (key, track["track"][key].as_str().unwrap_or_default().to_string()))
+                    .collect()
+            };
+            metrics.family("track_listening_seconds", "Seconds listened to each top track in the period");
+            for track in tracks {
+                metrics.sample("track_listening_seconds", &track_labels(track), track["total_listened_time"].as_f64().unwrap_or(0.0));
+            }
+            metrics.family("track_plays", "Plays of each top track in the period");
+            for track in tracks {
+                metrics.sample("track_plays", &track_labels(track), track["play_count"].as_f64().unwrap_or(0.0));
+            }
+
+            let artists = value["top_artists"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let artist_labels = |artist: &serde_json::Value| vec![("artist", artist["artist"].as_str().unwrap_or_default().to_string())];
+            metrics.family("artist_listening_seconds", "Seconds listened to each top artist in the period");
+            for artist in artists {
+                metrics.sample("artist_listening_seconds", &artist_labels(artist), artist["total_listened_time"].as_f64().unwrap_or(0.0));
+            }
+            metrics.family("artist_plays", "Plays of each top artist in the period");
+            for artist in artists {
+                metrics.sample("artist_plays", &artist_labels(artist), artist["play_count"].as_f64().unwrap_or(0.0));
+            }
+        }
+        "status" => {
+            metrics.gauge("sessions", "Recorded sessions", number("total_sessions"));
+            metrics.gauge("active_sessions", "Sessions still being recorded", number("active_sessions"));
+            metrics.gauge("tracks", "Distinct tracks", number("total_tracks"));
+            metrics.gauge("players", "Players seen", number("total_players"));
+            metrics.gauge("lifetime_listening_seconds", "Seconds listened across all finished sessions", number("total_listened_time"));
+            metrics.gauge("lifetime_scrobbles", "Finished plays long enough to scrobble", number("total_scrobbles"));
+            metrics.gauge("database_size_bytes", "Database file size", number("file_size"));
+        }
+        "ping" => {
+            // Labelled by age rather than start time, so each push updates the same
+            // series instead of adding one per hour
+            let hours = value["recent_activity"].as_array().map(Vec::as_slice).unwrap_or_default();
+            metrics.family("recent_listening_seconds", "Seconds listened in each of the last hours, 0 being the latest");
+            for (hours_ago, hour) in hours.iter().rev().enumerate() {
+                let labels = [("hours_ago", hours_ago.to_string())];
+                metrics.sample("recent_listening_seconds", &labels, hour["listened_time"].as_f64().unwrap_or(0.0));
+            }
+        }
+        _ => anyhow::bail!("--format prometheus only supports the stats dashboard, status and ping, not {}", report),
+    }
+
+    Ok(metrics.text)
+}
+
+/// Builds Prometheus text exposition format; every metric is a gauge prefixed `gopal_`
+#[derive(Default)]
+struct PrometheusText {
+    text: String,
+}
+
+impl PrometheusText {
+    fn family(&mut self, name: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP gopal_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE gopal_{} gauge", name);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: f64) {
+        let _ = write!(self.text, "gopal_{}", name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// Label values escape backslashes, double quotes and newlines
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+struct HumanRenderer {
+    quiet: bool,
+    granularity: Granularity,
+    locale: OutputLocale,
+}
+
+impl StatsRenderer for HumanRenderer {
+    fn stats(&mut self, dashboard: &Dashboard, engagement: Option<&EngagementStats>, limit: usize) -> Result<()> {
+        print_stats_human(dashboard, engagement, limit, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn grouped(&mut self, groups: &[GroupedStats], dimension: GroupDimension) -> Result<()> {
+        print_grouped_human(groups, dimension, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn sources(&mut self, sources: &[GroupedStats]) -> Result<()> {
+        print_grouped_human(sources, GroupDimension::Source, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn top_tracks(&mut self, tracks: &[TrackStats], sort_by: &SortBy) -> Result<()> {
+        print_top_tracks_human(tracks, sort_by, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn ranked_tracks(&mut self, tracks: &[RankedTrack], sort_by: &SortBy) -> Result<()> {
+        print_ranked_tracks_human(tracks, sort_by, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn track_breakdown(&mut self, tracks: &[TrackBreakdown], sort_by: &SortBy) -> Result<()> {
+        print_track_breakdown_human(tracks, sort_by, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn top_artists(&mut self, artists: &[ArtistStats]) -> Result<()> {
+        print_top_artists_human(artists, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn rediscovery(&mut self, tracks: &[TrackStats]) -> Result<()> {
+        print_rediscovery_human(tracks, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn top_rated(&mut self, tracks: &[RatedTrack]) -> Result<()> {
+        print_top_rated_human(tracks, self.quiet);
+        Ok(())
+    }
+
+    fn trend(&mut self, trend: &TrendReport) -> Result<()> {
+        print_trend_human(trend, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn history(&mut self, history: &[SessionWithMetadata]) -> Result<()> {
+        print_history_human(history, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn anonymous_history(&mut self, plays: &[AnonymousPlay]) -> Result<()> {
+        print_anonymous_history_human(plays, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn now_playing(&mut self, now_playing: &[NowPlaying], template: &NowPlayingTemplate) -> Result<()> {
+        print_now_playing_human(now_playing, template, self.quiet);
+        Ok(())
+    }
+
+    fn skip_stats(&mut self, stats: &SkipStats) -> Result<()> {
+        print_skip_stats_human(stats, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn consistency(&mut self, consistency: &ConsistencyScore) -> Result<()> {
+        print_consistency_human(consistency, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn weekdays(&mut self, averages: &[WeekdayAvg]) -> Result<()> {
+        print_weekdays_human(averages, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn on_this_day(&mut self, month: u32, day: u32, years: &[YearSlice]) -> Result<()> {
+        print_on_this_day_human(month, day, years, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn replay(&mut self, date: NaiveDate, timeline: &[TimelineEntry]) -> Result<()> {
+        print_replay_human(date, timeline, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn artist(&mut self, catalog: &ArtistCatalog) -> Result<()> {
+        print_artist_human(catalog, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn album(&mut self, name: &str, tracks: &[TrackStats]) -> Result<()> {
+        print_album_human(name, tracks, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn album_completeness(&mut self, albums: &[AlbumCompleteness]) -> Result<()> {
+        print_album_completeness_human(albums, self.quiet);
+        Ok(())
+    }
+
+    fn incomplete_tracks(&mut self, tracks: &[Track]) -> Result<()> {
+        print_incomplete_tracks_human(tracks, self.quiet);
+        Ok(())
+    }
+
+    fn rank_history(&mut self, name: &str, history: &[RankPoint]) -> Result<()> {
+        print_rank_history_human(name, history, self.quiet, self.granularity);
+        Ok(())
+    }
+
+    fn session_label(&mut self, update: &SessionLabel) -> Result<()> {
+        match &update.label {
+            Some(label) => println!("Labelled session {} as '{}'", update.session_id, label),
+            None => println!("Cleared the label of session {}", update.session_id),
+        }
+        Ok(())
+    }
+
+    fn status(&mut self, stats: &DatabaseStats) -> Result<()> {
+        print_status_human(stats, self.quiet, &self.locale);
+        Ok(())
+    }
+
+    fn aggregates(&mut self, status: &ArtistAggregateStatus) -> Result<()> {
+        print_header(self.quiet, "🔄 Artist Aggregates:", "═══════════════════");
+        println!("Stored all-time totals for {} artists", status.artists);
+        if !status.fresh {
+            println!("⚠️  Sessions changed since; top artists are computed live until the next refresh");
+        }
+        Ok(())
+    }
+
+    fn players(&mut self, players: &[PlayerSummary]) -> Result<()> {
+        print_header(self.quiet, "🎛️ Players:", "═══════════════════");
+        if players.is_empty() {
+            println!("No players recorded yet");
+        }
+        for summary in players {
+            println!("{} ({}): {} sessions, {}",
+                     summary.player.identity,
+                     summary.player.name,
+                     summary.session_count,
+                     format_duration(summary.total_listened_time, self.granularity));
+        }
+        Ok(())
+    }
+
+    fn timing_report(&mut self, mismatches: &[TimingMismatch], tolerance: i64) -> Result<()> {
+        print_timing_report_human(mismatches, tolerance, self.quiet, &self.locale);
+        Ok(())
+    }
+
+    fn scrobble_queue(&mut self, queue: &[QueuedScrobble]) -> Result<()> {
+        print_scrobble_queue_human(queue, self.quiet, &self.locale);
+        Ok(())
+    }
+
+    fn active_sessions(&mut self, sessions: &[ActiveSession]) -> Result<()> {
+        print_active_sessions_human(sessions, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn cleanup(&mut self, closed: &[SessionWithMetadata], max_age: i64) -> Result<()> {
+        print_cleanup_human(closed, max_age, self.quiet, self.granularity, &self.locale);
+        Ok(())
+    }
+
+    fn import_summary(&mut self, summary: &ImportSummary, default_duration: i64) -> Result<()> {
+        print_import_summary_human(summary, default_duration, self.granularity);
+        Ok(())
+    }
+
+    fn ping(&mut self, ping: &DaemonPing) -> Result<()> {
+        match ping.health {
+            DaemonHealth::Alive { last_poll_age } => {
+                println!("alive (pid {}), last poll {}s ago", ping.pid, last_poll_age)
+            }
+            DaemonHealth::Starting { started_age } => {
+                println!("starting (pid {}), started {}s ago, no poll yet", ping.pid, started_age)
+            }
+            DaemonHealth::Stale { last_poll_age: Some(age), .. } => {
+                println!("stale (pid {}), last poll {}s ago", ping.pid, age)
+            }
+            DaemonHealth::Stale { last_poll_age: None, started_age } => {
+                println!("stale (pid {}), no poll since starting {}s ago", ping.pid, started_age)
+            }
+        }
+        if !ping.recent_activity.is_empty() {
+            let total: i64 = ping.recent_activity.iter().map(|hour| hour.listened_time).sum();
+            println!("last {}h: {} {}",
+                     ping.recent_activity.len(),
+                     sparkline(ping.recent_activity.iter().map(|hour| hour.listened_time)),
+                     format_duration(total, self.granularity));
+        }
+        Ok(())
+    }
+
+    fn recording(&mut self, state: &RecordingState) -> Result<()> {
+        if state.recording {
+            println!("Recording is on");
+        } else {
+            println!("Recording is paused, `gopal-cli record --on` resumes it");
+        }
+        Ok(())
+    }
+}
+
+struct CsvRenderer {
+    granularity: Granularity,
+    /// `--columns`: which columns to print and in what order, all of them if unset
+    columns: Option<Vec<String>>,
+}
+
+impl CsvRenderer {
+    /// Run a csv printer into a buffer and print it, cut down to `--columns`
+    fn emit(&self, print: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+        let mut buffer = Vec::new();
+        print(&mut buffer)?;
+        let csv = String::from_utf8(buffer).context("csv output wasn't valid UTF-8")?;
+        let csv = match &self.columns {
+            Some(columns) => select_csv_columns(&csv, columns)?,
+            None => csv,
+        };
+        std::io::stdout().lock().write_all(csv.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Keep only `columns` of `csv`, in the order given. The first line is the header, and
+/// any name that isn't in it is an error listing the columns that are.
+fn select_csv_columns(csv: &str, columns: &[String]) -> Result<String> {
+    let mut lines = csv.lines();
+    let header = split_csv_fields(lines.next().unwrap_or_default());
+    let indices = columns.iter()
+        .map(|column| header.iter().position(|name| name == column).with_context(|| {
+            format!("Unknown column '{}'; available columns are: {}", column, header.join(", "))
+        }))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut selected = String::new();
+    for line in std::iter::once(header.join(",")).chain(lines.map(str::to_string)) {
+        let fields = split_csv_fields(&line);
+        let row: Vec<&str> = indices.iter().map(|&i| fields.get(i).copied().unwrap_or_default()).collect();
+        selected.push_str(&row.join(","));
+        selected.push('\n');
+    }
+    Ok(selected)
+}
+
+/// Split a csv line on commas outside quotes, keeping each field exactly as written
+fn split_csv_fields(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+impl StatsRenderer for CsvRenderer {
+    fn stats(&mut self, dashboard: &Dashboard, engagement: Option<&EngagementStats>, _limit: usize) -> Result<()> {
+        self.emit(|out| print_stats_csv(out, dashboard, engagement, self.granularity))
+    }
+
+    fn grouped(&mut self, groups: &[GroupedStats], _dimension: GroupDimension) -> Result<()> {
+        self.emit(|out| print_grouped_csv(out, groups, self.granularity))
+    }
+
+    fn sources(&mut self, sources: &[GroupedStats]) -> Result<()> {
+        self.emit(|out| print_grouped_csv(out, sources, self.granularity))
+    }
+
+    fn top_tracks(&mut self, tracks: &[TrackStats], _sort_by: &SortBy) -> Result<()> {
+        self.emit(|out| print_top_tracks_csv(out, tracks, self.granularity))
+    }
+
+    fn ranked_tracks(&mut self, tracks: &[RankedTrack], _sort_by: &SortBy) -> Result<()> {
+        self.emit(|out| print_ranked_tracks_csv(out, tracks, self.granularity))
+    }
+
+    fn track_breakdown(&mut self, tracks: &[TrackBreakdown], _sort_by: &SortBy) -> Result<()> {
+        self.emit(|out| print_track_breakdown_csv(out, tracks, self.granularity))
+    }
+
+    fn top_artists(&mut self, artists: &[ArtistStats]) -> Result<()> {
+        self.emit(|out| print_top_artists_csv(out, artists, self.granularity))
+    }
+
+    fn rediscovery(&mut self, tracks: &[TrackStats]) -> Result<()> {
+        self.emit(|out| print_top_tracks_csv(out, tracks, self.granularity))
+    }
+
+    fn top_rated(&mut self, tracks: &[RatedTrack]) -> Result<()> {
+        self.emit(|out| print_top_rated_csv(out, tracks))
+    }
+
+    fn trend(&mut self, trend: &TrendReport) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "window_days,lookback_windows,recent_listened_time,baseline_listened_time,change_percent,direction")?;
+            writeln!(out, "{},{},{},{},{},{}",
+                          trend.window_days,
+                          trend.lookback_windows,
+                          self.granularity.round(trend.recent_listened_time),
+                          trend.baseline_listened_time.map(|b| self.granularity.round(b.round() as i64).to_string()).unwrap_or_default(),
+                          trend.change_percent.map(|c| format!("{:.1}", c)).unwrap_or_default(),
+                          serde_json::to_value(trend.direction)?.as_str().unwrap_or_default())?;
+            Ok(())
+        })
+    }
+
+    fn history(&mut self, history: &[SessionWithMetadata]) -> Result<()> {
+        self.emit(|out| print_history_csv(out, history, self.granularity))
+    }
+
+    fn anonymous_history(&mut self, plays: &[AnonymousPlay]) -> Result<()> {
+        self.emit(|out| print_anonymous_history_csv(out, plays, self.granularity))
+    }
+
+    fn now_playing(&mut self, now_playing: &[NowPlaying], _template: &NowPlayingTemplate) -> Result<()> {
+        self.emit(|out| print_now_playing_csv(out, now_playing))
+    }
+
+    fn skip_stats(&mut self, stats: &SkipStats) -> Result<()> {
+        self.emit(|out| print_skip_stats_csv(out, stats, self.granularity))
+    }
+
+    fn consistency(&mut self, consistency: &ConsistencyScore) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "days,active_days,mean_daily_listened_time,daily_std_dev,coefficient_of_variation,score")?;
+            writeln!(out, "{},{},{},{},{:.3},{:.1}",
+                          consistency.days,
+                          consistency.active_days,
+                          self.granularity.round(consistency.mean_daily_listened_time.round() as i64),
+                          self.granularity.round(consistency.daily_std_dev.round() as i64),
+                          consistency.coefficient_of_variation,
+                          consistency.score)?;
+            Ok(())
+        })
+    }
+
+    fn weekdays(&mut self, averages: &[WeekdayAvg]) -> Result<()> {
+        self.emit(|out| print_weekdays_csv(out, averages, self.granularity))
+    }
+
+    fn on_this_day(&mut self, _month: u32, _day: u32, years: &[YearSlice]) -> Result<()> {
+        self.emit(|out| print_on_this_day_csv(out, years, self.granularity))
+    }
+
+    fn replay(&mut self, _date: NaiveDate, timeline: &[TimelineEntry]) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "start_time,end_time,gap_before,title,artist,player,listened_time")?;
+            for entry in timeline {
+                writeln!(out, "{},{},{},\"{}\",\"{}\",\"{}\",{}",
+                              entry.play.session.start_time,
+                              entry.end_time,
+                              entry.gap_before.map(|gap| self.granularity.round(gap).to_string()).unwrap_or_default(),
+                              entry.play.track.title,
+                              entry.play.track.artist,
+                              entry.play.player.name,
+                              self.granularity.round(entry.play.session.listened_time.unwrap_or(0)))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn artist(&mut self, catalog: &ArtistCatalog) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "discovered,first_played,title,album,play_count,listened_time")?;
+            for (i, discovery) in catalog.discoveries.iter().enumerate() {
+                writeln!(out, "{},{},\"{}\",\"{}\",{},{}",
+                              i + 1,
+                              discovery.first_played,
+                              discovery.track.title,
+                              discovery.track.album,
+                              discovery.play_count,
+                              self.granularity.round(discovery.total_listened_time))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn album(&mut self, _name: &str, tracks: &[TrackStats]) -> Result<()> {
+        self.emit(|out| print_album_csv(out, tracks, self.granularity))
+    }
+
+    fn album_completeness(&mut self, albums: &[AlbumCompleteness]) -> Result<()> {
+        self.emit(|out| print_album_completeness_csv(out, albums))
+    }
+
+    fn incomplete_tracks(&mut self, tracks: &[Track]) -> Result<()> {
+        self.emit(|out| print_incomplete_tracks_csv(out, tracks))
+    }
+
+    fn rank_history(&mut self, _name: &str, history: &[RankPoint]) -> Result<()> {
+        self.emit(|out| print_rank_history_csv(out, history, self.granularity))
+    }
+
+    fn session_label(&mut self, update: &SessionLabel) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "session_id,label")?;
+            writeln!(out, "{},\"{}\"", update.session_id, update.label.as_deref().unwrap_or_default())?;
+            Ok(())
+        })
+    }
+
+    fn status(&mut self, stats: &DatabaseStats) -> Result<()> {
+        self.emit(|out| print_status_csv(out, stats, self.granularity))
+    }
+
+    fn players(&mut self, players: &[PlayerSummary]) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "id,name,identity,session_count,listened_time")?;
+            for summary in players {
+                writeln!(out, "{},\"{}\",\"{}\",{},{}",
+                              summary.player.id,
+                              summary.player.name,
+                              summary.player.identity,
+                              summary.session_count,
+                              self.granularity.round(summary.total_listened_time))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn aggregates(&mut self, status: &ArtistAggregateStatus) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "artists,refreshed_at,fresh")?;
+            writeln!(out, "{},{},{}", status.artists, status.refreshed_at.map(|t| t.to_string()).unwrap_or_default(), status.fresh)?;
+            Ok(())
+        })
+    }
+
+    fn timing_report(&mut self, mismatches: &[TimingMismatch], _tolerance: i64) -> Result<()> {
+        self.emit(|out| print_timing_report_csv(out, mismatches))
+    }
+
+    fn scrobble_queue(&mut self, queue: &[QueuedScrobble]) -> Result<()> {
+        self.emit(|out| print_scrobble_queue_csv(out, queue))
+    }
+
+    fn active_sessions(&mut self, sessions: &[ActiveSession]) -> Result<()> {
+        self.emit(|out| print_active_sessions_csv(out, sessions))
+    }
+
+    fn cleanup(&mut self, closed: &[SessionWithMetadata], _max_age: i64) -> Result<()> {
+        self.emit(|out| print_cleanup_csv(out, closed))
+    }
+
+    fn import_summary(&mut self, summary: &ImportSummary, _default_duration: i64) -> Result<()> {
+        self.emit(|out| {
+            writeln!(out, "imported,skipped_duplicates,estimated_durations")?;
+            writeln!(out, "{},{},{}", summary.imported, summary.skipped_duplicates, summary.estimated_durations)?;
+            Ok(())
+        })
+    }
+
+    fn ping(&mut self, ping: &DaemonPing) -> Result<()> {
+        let (state, last_poll_age) = match ping.health {
+            DaemonHealth::Alive { last_poll_age } => ("alive", Some(last_poll_age)),
+            DaemonHealth::Starting { .. } => ("starting", None),
+            DaemonHealth::Stale { last_poll_age, .. } => ("stale", last_poll_age),
+        };
+        println!("pid,state,last_poll_age");
+        println!("{},{},{}", ping.pid, state, last_poll_age.map(|age| age.to_string()).unwrap_or_default());
+        Ok(())
+    }
+
+    fn recording(&mut self, state: &RecordingState) -> Result<()> {
+        println!("recording,pause_file");
+        println!("{},{}", state.recording, state.pause_file.display());
+        Ok(())
+    }
+}
+
+/// Write `item` as a single line of compact JSON
+pub(crate) fn write_ndjson_line<W: Write, T: Serialize>(out: &mut W, item: &T) -> Result<()> {
+    serde_json::to_writer(&mut *out, item)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+fn print_ndjson<T: Serialize>(items: impl IntoIterator<Item = T>) -> Result<()> {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    for item in items {
+        write_ndjson_line(&mut out, &item)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print a decorative section banner unless `--quiet` was given
+fn print_header(quiet: bool, title: &str, separator: &str) {
+    if !quiet {
+        println!("{}", title);
+        println!("{}", separator);
+    }
+}
+
+/// Engaged seconds for `track_id`, if engagement was requested
+fn engaged_time_for(engagement: Option<&EngagementStats>, track_id: &str) -> Option<i64> {
+    engagement?
+        .tracks
+        .iter()
+        .find(|t| t.track_id == track_id)
+        .map(|t| t.engaged_time)
+}
+
+fn print_stats_human(dashboard: &Dashboard, engagement: Option<&EngagementStats>, limit: usize, quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    let stats = &dashboard.stats;
+    print_header(quiet, "🎵 Music Listening Statistics", "═══════════════════════════════");
+    if !quiet {
+        println!();
+    }
+
+    // Total listening time
+    let total_hours = stats.total_listening_time as f64 / 3600.0;
+    if stats.active_listening_time > 0 {
+        println!("📊 Total Listening Time: {:.1} hours ({} minutes, {} currently in progress)",
+                 total_hours, stats.total_listening_time / 60, format_duration(stats.active_listening_time, granularity));
+    } else {
+        println!("📊 Total Listening Time: {:.1} hours ({} minutes)",
+                 total_hours, stats.total_listening_time / 60);
+    }
+    println!("▶️  Plays: {} ({} scrobbles)", locale.format_number(dashboard.plays), locale.format_number(dashboard.scrobbles));
+    if let Some(engagement) = engagement {
+        println!("🎯 Engaged Time: {:.1} hours ({} minutes, pause penalty {})",
+                 engagement.total_engaged_time as f64 / 3600.0,
+                 engagement.total_engaged_time / 60,
+                 engagement.pause_penalty);
+    }
+    if stats.content_type_totals.len() > 1 {
+        let breakdown: Vec<String> = stats.content_type_totals
+            .iter()
+            .map(|total| format!("{} {:.1} h", total.content_type.as_str(), total.total_listening_time as f64 / 3600.0))
+            .collect();
+        println!("🎧 By content type: {}", breakdown.join(", "));
+    }
+    if let Some(longest) = &dashboard.longest_session {
+        println!("🏆 Longest single listen: {} - {} ({})",
+                 metadata_label(&longest.track.title),
+                 metadata_label(&longest.track.artist),
+                 format_duration(longest.session.listened_time.unwrap_or(0), granularity));
+    }
+    println!();
+
+    // Top tracks
+    if !stats.top_tracks.is_empty() {
+        println!("🎵 Top Tracks (by listening time):");
+        for (i, track_stat) in stats.top_tracks.iter().take(limit).enumerate() {
+            let time_str = format_duration(track_stat.total_listened_time, granularity);
+            let engaged_str = engaged_time_for(engagement, &track_stat.track.id)
+                .map(|engaged| format!(", {} engaged", format_duration(engaged, granularity)))
+                .unwrap_or_default();
+            println!("  {}. {} - {} ({}{}, {} plays)",
+                     i + 1,
+                     metadata_label(&track_stat.track.title),
+                     metadata_label(&track_stat.track.artist),
+                     time_str,
+                     engaged_str,
+                     locale.format_number(track_stat.play_count));
+        }
+        println!();
+    }
+
+    // Top artists
+    if !stats.top_artists.is_empty() {
+        println!("🎤 Top Artists (by listening time):");
+        for (i, artist_stat) in stats.top_artists.iter().take(limit).enumerate() {
+            let time_str = format_duration(artist_stat.total_listened_time, granularity);
+            println!("  {}. {} ({}, {} tracks)",
+                     i + 1,
+                     metadata_label(&artist_stat.artist),
+                     time_str,
+                     locale.format_number(artist_stat.track_count));
+        }
+        println!();
+    }
+
+    // Top albums
+    if !dashboard.top_albums.is_empty() {
+        println!("💿 Top Albums (by listening time):");
+        for (i, album) in dashboard.top_albums.iter().take(limit).enumerate() {
+            println!("  {}. {} ({}, {} plays)",
+                     i + 1,
+                     album.label,
+                     format_duration(album.total_listened_time, granularity),
+                     locale.format_number(album.play_count));
+        }
+        println!();
+    }
+
+    // Recent listening
+    if !stats.listening_history.is_empty() {
+        println!("🕒 Recent Listening:");
+        for session in stats.listening_history.iter().take(5) {
+            let datetime = DateTime::<Local>::from(
+                DateTime::<Utc>::from_timestamp(session.session.start_time, 0).unwrap()
+            );
+            let time_str = format_duration(session.session.listened_time.unwrap_or(0), granularity);
+            println!("  {} - {} ({}) [{}]",
+                     metadata_label(&session.track.title),
+                     metadata_label(&session.track.artist),
+                     time_str,
+                     locale.format_datetime(datetime));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatsOutput<'a> {
+    #[serde(flatten)]
+    dashboard: &'a Dashboard,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    engagement: Option<&'a EngagementStats>,
+}
+
+fn print_stats_csv(out: &mut impl Write, dashboard: &Dashboard, engagement: Option<&EngagementStats>, granularity: Granularity) -> Result<()> {
+    let stats = &dashboard.stats;
+    writeln!(out, "type,name,value")?;
+    writeln!(out, "total_time,Total Listening Time,{}", granularity.round(stats.total_listening_time))?;
+    writeln!(out, "active_time,Listening Time In Progress,{}", granularity.round(stats.active_listening_time))?;
+    writeln!(out, "plays,Plays,{}", dashboard.plays)?;
+    writeln!(out, "scrobbles,Scrobbles,{}", dashboard.scrobbles)?;
+    if let Some(engagement) = engagement {
+        writeln!(out, "engaged_time,Total Engaged Time,{}", granularity.round(engagement.total_engaged_time))?;
+    }
+    if let Some(longest) = &dashboard.longest_session {
+        writeln!(out, "longest_session,\"{} - {}\",{}",
+                      longest.track.title,
+                      longest.track.artist,
+                      granularity.round(longest.session.listened_time.unwrap_or(0)))?;
+    }
+    
+    for track_stat in &stats.top_tracks {
+        writeln!(out, "track,\"{} - {}\",{}", 
+                      track_stat.track.title, 
+                      track_stat.track.artist, 
+                      granularity.round(track_stat.total_listened_time))?;
+
+        if let Some(engaged) = engaged_time_for(engagement, &track_stat.track.id) {
+            writeln!(out, "track_engaged,\"{} - {}\",{}",
+                          track_stat.track.title,
+                          track_stat.track.artist,
+                          granularity.round(engaged))?;
+        }
+    }
+    
+    for artist_stat in &stats.top_artists {
+        writeln!(out, "artist,\"{}\",{}", 
+                      artist_stat.artist, 
+                      granularity.round(artist_stat.total_listened_time))?;
+    }
+
+    for album in &dashboard.top_albums {
+        writeln!(out, "album,\"{}\",{}", album.label, granularity.round(album.total_listened_time))?;
+    }
+    
+    Ok(())
+}
+
+fn print_grouped_human(groups: &[GroupedStats], dimension: GroupDimension, quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    let dimension_name = match dimension {
+        GroupDimension::Track => "track",
+        GroupDimension::Artist => "artist",
+        GroupDimension::Album => "album",
+        GroupDimension::Genre => "genre",
+        GroupDimension::Player => "player",
+        GroupDimension::Source => "source",
+        GroupDimension::Focus => "focus",
+    };
+
+    print_header(quiet, &format!("📊 Listening by {}:", dimension_name), "═══════════════════════════");
+
+    for (i, group) in groups.iter().enumerate() {
+        let time_str = format_duration(group.total_listened_time, granularity);
+        println!("{}. {}", i + 1, group.label);
+        println!("   {} listened, {} plays", time_str, locale.format_number(group.play_count));
+        println!();
+    }
+}
+
+fn print_grouped_csv(out: &mut impl Write, groups: &[GroupedStats], granularity: Granularity) -> Result<()> {
+    writeln!(out, "rank,name,listened_time,play_count")?;
+    for (i, group) in groups.iter().enumerate() {
+        writeln!(out, "{},\"{}\",{},{}",
+                      i + 1,
+                      group.label,
+                      granularity.round(group.total_listened_time),
+                      group.play_count)?;
+    }
+    Ok(())
+}
+
+fn print_top_tracks_human(tracks: &[TrackStats], sort_by: &SortBy, quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    let sort_desc = match sort_by {
+        SortBy::Time => "listening time",
+        SortBy::Count => "play count",
+    };
+    
+    print_header(quiet, &format!("🎵 Top Tracks (by {}):", sort_desc), "═══════════════════════════");
+    
+    for (i, track_stat) in tracks.iter().enumerate() {
+        let time_str = format_duration(track_stat.total_listened_time, granularity);
+        println!("{}. {} - {}", i + 1, metadata_label(&track_stat.track.title), metadata_label(&track_stat.track.artist));
+        println!("   {} listened, {} plays{}", time_str, locale.format_number(track_stat.play_count), per_active_day(track_stat.plays_per_active_day));
+        println!();
+    }
+}
+
+/// ", 1.5 plays/day" when the plays per active day are known
+fn per_active_day(plays_per_active_day: Option<f64>) -> String {
+    plays_per_active_day
+        .map(|per_day| format!(", {:.1} plays/day", per_day))
+        .unwrap_or_default()
+}
+
+fn print_rediscovery_human(tracks: &[TrackStats], quiet: bool, granularity: Granularity) {
+    print_header(quiet, "🔁 Rediscover:", "═══════════════════");
+
+    for (i, track_stat) in tracks.iter().enumerate() {
+        let time_str = format_duration(track_stat.total_listened_time, granularity);
+        println!("{}. {} - {}", i + 1, metadata_label(&track_stat.track.title), metadata_label(&track_stat.track.artist));
+        println!("   {} plays, {} listened", track_stat.play_count, time_str);
+        println!();
+    }
+}
+
+fn print_trend_human(trend: &TrendReport, quiet: bool, granularity: Granularity) {
+    print_header(quiet, "📈 Listening Trend:", "═══════════════════");
+    println!("Last {} days: {}", trend.window_days, format_duration(trend.recent_listened_time, granularity));
+
+    let Some(baseline) = trend.baseline_listened_time else {
+        println!("Not enough data: no full {}-day window of history before this one", trend.window_days);
+        return;
+    };
+    println!("Average of the {} windows before: {}", trend.lookback_windows, format_duration(baseline.round() as i64, granularity));
+
+    let arrow = match trend.direction {
+        TrendDirection::Up => "↑ listening more",
+        TrendDirection::Down => "↓ listening less",
+        TrendDirection::Flat | TrendDirection::NotEnoughData => "→ about the same",
+    };
+    match trend.change_percent {
+        Some(change) => println!("{} ({:+.0}%)", arrow, change),
+        None => println!("{}", arrow),
+    }
+}
+
+/// One block per value, scaled to the largest
+fn sparkline(values: impl Iterator<Item = i64> + Clone) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.clone().max().unwrap_or(0).max(1);
+    values.map(|value| BLOCKS[(value.max(0) * (BLOCKS.len() as i64 - 1) / max) as usize]).collect()
+}
+
+fn print_consistency_human(consistency: &ConsistencyScore, quiet: bool, granularity: Granularity) {
+    print_header(quiet, "🗓️ Listening Consistency:", "═══════════════════");
+    if consistency.active_days == 0 {
+        println!("No listening in this period");
+        return;
+    }
+
+    println!("Score: {:.0}/100", consistency.score);
+    println!("Listened on {} of {} days ({:.0}%)",
+             consistency.active_days,
+             consistency.days,
+             consistency.active_day_fraction * 100.0);
+    println!("{} a day on average, give or take {}",
+             format_duration(consistency.mean_daily_listened_time.round() as i64, granularity),
+             format_duration(consistency.daily_std_dev.round() as i64, granularity));
+}
+
+fn print_top_rated_human(tracks: &[RatedTrack], quiet: bool) {
+    print_header(quiet, "⭐ Top Rated:", "═══════════════════");
+    if tracks.is_empty() {
+        println!("No plays with a rating; not every player reports one");
+        return;
+    }
+
+    for (i, rated) in tracks.iter().enumerate() {
+        println!("{}. {} - {}", i + 1, metadata_label(&rated.track.title), metadata_label(&rated.track.artist));
+        println!("   {} {:.2} weighted ({:.2} average over {} of {} plays)",
+                 stars(rated.weighted_rating),
+                 rated.weighted_rating,
+                 rated.average_rating,
+                 rated.rated_plays,
+                 rated.play_count);
+        println!();
+    }
+}
+
+/// A 0.0 to 1.0 rating as five stars, rounded to the nearest star
+fn stars(rating: f64) -> String {
+    let filled = (rating.clamp(0.0, 1.0) * 5.0).round() as usize;
+    format!("{}{}", "★".repeat(filled), "☆".repeat(5 - filled))
+}
+
+fn print_top_rated_csv(out: &mut impl Write, tracks: &[RatedTrack]) -> Result<()> {
+    writeln!(out, "rank,title,artist,album,weighted_rating,average_rating,rated_plays,play_count")?;
+    for (i, rated) in tracks.iter().enumerate() {
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{:.4},{:.4},{},{}",
+                      i + 1,
+                      rated.track.title,
+                      rated.track.artist,
+                      rated.track.album,
+                      rated.weighted_rating,
+                      rated.average_rating,
+                      rated.rated_plays,
+                      rated.play_count)?;
+    }
+    Ok(())
+}
+
+fn print_top_tracks_csv(out: &mut impl Write, tracks: &[TrackStats], granularity: Granularity) -> Result<()> {
+    writeln!(out, "rank,title,artist,album,listened_time,play_count")?;
+    for (i, track_stat) in tracks.iter().enumerate() {
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{},{}", 
+                      i + 1,
+                      track_stat.track.title,
+                      track_stat.track.artist,
+                      track_stat.track.album,
+                      granularity.round(track_stat.total_listened_time),
+                      track_stat.play_count)?;
+    }
+    Ok(())
+}
+
+fn print_track_breakdown_human(tracks: &[TrackBreakdown], sort_by: &SortBy, quiet: bool, granularity: Granularity) {
+    let sort_desc = match sort_by {
+        SortBy::Time => "listening time",
+        SortBy::Count => "play count",
+    };
+
+    print_header(quiet, &format!("🎵 Top Tracks (by {}, per player):", sort_desc), "═══════════════════════════");
+
+    for (i, track) in tracks.iter().enumerate() {
+        let time_str = format_duration(track.stats.total_listened_time, granularity);
+        println!("{}. {} - {}", i + 1, metadata_label(&track.stats.track.title), metadata_label(&track.stats.track.artist));
+        println!("   {} listened, {} plays", time_str, track.stats.play_count);
+        for share in &track.players {
+            println!("     {}: {}, {} plays",
+                     share.player,
+                     format_duration(share.total_listened_time, granularity),
+                     share.play_count);
+        }
+        println!();
+    }
+}
+
+/// One row per track and player, with the player's share of the track's listening
+fn print_track_breakdown_csv(out: &mut impl Write, tracks: &[TrackBreakdown], granularity: Granularity) -> Result<()> {
+    writeln!(out, "rank,title,artist,album,player,listened_time,play_count")?;
+    for (i, track) in tracks.iter().enumerate() {
+        for share in &track.players {
+            writeln!(out, "{},\"{}\",\"{}\",\"{}\",\"{}\",{},{}",
+                          i + 1,
+                          track.stats.track.title,
+                          track.stats.track.artist,
+                          track.stats.track.album,
+                          share.player,
+                          granularity.round(share.total_listened_time),
+                          share.play_count)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_ranked_tracks_human(tracks: &[RankedTrack], sort_by: &SortBy, quiet: bool, granularity: Granularity) {
+    let sort_desc = match sort_by {
+        SortBy::Time => "listening time",
+        SortBy::Count => "play count",
+    };
+
+    print_header(quiet, &format!("🎵 Top Tracks (by {}, vs previous period):", sort_desc), "═══════════════════════════");
+
+    for ranked in tracks {
+        let time_str = format_duration(ranked.stats.total_listened_time, granularity);
+        let previous = match ranked.movement.previous_rank {
+            Some(prev) => format!("was #{}", prev),
+            None => "new".to_string(),
+        };
+        println!("{}. {} {} - {}",
+                 ranked.movement.rank,
+                 ranked.movement.movement.indicator(),
+                 metadata_label(&ranked.stats.track.title),
+                 metadata_label(&ranked.stats.track.artist));
+        println!("   {} listened, {} plays ({})", time_str, ranked.stats.play_count, previous);
+        println!();
+    }
+}
+
+fn print_ranked_tracks_csv(out: &mut impl Write, tracks: &[RankedTrack], granularity: Granularity) -> Result<()> {
+    writeln!(out, "rank,previous_rank,movement,title,artist,album,listened_time,play_count")?;
+    for ranked in tracks {
+        writeln!(out, "{},{},{},\"{}\",\"{}\",\"{}\",{},{}",
+                      ranked.movement.rank,
+                      ranked.movement.previous_rank.map(|r| r.to_string()).unwrap_or_default(),
+                      ranked.movement.movement.indicator(),
+                      ranked.stats.track.title,
+                      ranked.stats.track.artist,
+                      ranked.stats.track.album,
+                      granularity.round(ranked.stats.total_listened_time),
+                      ranked.stats.play_count)?;
+    }
+    Ok(())
+}
+
+fn print_top_artists_human(artists: &[ArtistStats], quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, "🎤 Top Artists:", "═══════════════");
+    
+    for (i, artist_stat) in artists.iter().enumerate() {
+        let time_str = format_duration(artist_stat.total_listened_time, granularity);
+        println!("{}. {}", i + 1, metadata_label(&artist_stat.artist));
+        println!("   {} listened, {} tracks, {} plays{}",
+                 time_str,
+                 locale.format_number(artist_stat.track_count),
+                 locale.format_number(artist_stat.play_count),
+                 per_active_day(artist_stat.plays_per_active_day));
+        println!();
+    }
+}
+
+fn print_top_artists_csv(out: &mut impl Write, artists: &[ArtistStats], granularity: Granularity) -> Result<()> {
+    writeln!(out, "rank,artist,listened_time,track_count")?;
+    for (i, artist_stat) in artists.iter().enumerate() {
+        writeln!(out, "{},\"{}\",{},{}", 
+                      i + 1,
+                      artist_stat.artist,
+                      granularity.round(artist_stat.total_listened_time),
+                      artist_stat.track_count)?;
+    }
+    Ok(())
+}
+
+fn print_history_human(history: &[SessionWithMetadata], quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, "🕒 Listening History:", "═══════════════════");
+    
+    for session in history {
+        let datetime = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(session.session.start_time, 0).unwrap()
+        );
+        let time_str = format_duration(session.session.listened_time.unwrap_or(0), granularity);
+        
+        println!("{} - {}", metadata_label(&session.track.title), metadata_label(&session.track.artist));
+        println!("   {} on {} [{}]",
+                 time_str,
+                 locale.format_datetime(datetime),
+                 session.player.name);
+        println!();
+    }
+}
+
+fn print_history_csv(out: &mut impl Write, history: &[SessionWithMetadata], granularity: Granularity) -> Result<()> {
+    writeln!(out, "timestamp,title,artist,album,listened_time,player")?;
+    for session in history {
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{},\"{}\"", 
+                      session.session.start_time,
+                      session.track.title,
+                      session.track.artist,
+                      session.track.album,
+                      granularity.round(session.session.listened_time.unwrap_or(0)),
+                      session.player.name)?;
+    }
+    Ok(())
+}
+
+fn print_anonymous_history_human(plays: &[AnonymousPlay], quiet: bool, granularity: Granularity) {
+    print_header(quiet, "🕒 Listening History:", "═══════════════════");
+
+    for play in plays {
+        println!("{} - {}", metadata_label(&play.title), metadata_label(&play.artist));
+        println!("   {}", format_duration(play.listened_time, granularity));
+        println!();
+    }
+}
+
+fn print_anonymous_history_csv(out: &mut impl Write, plays: &[AnonymousPlay], granularity: Granularity) -> Result<()> {
+    writeln!(out, "title,artist,album,listened_time")?;
+    for play in plays {
+        writeln!(out, "\"{}\",\"{}\",\"{}\",{}",
+                      play.title,
+                      play.artist,
+                      play.album,
+                      granularity.round(play.listened_time))?;
+    }
+    Ok(())
+}
+
+fn print_now_playing_human(now_playing: &[NowPlaying], template: &NowPlayingTemplate, quiet: bool) {
+    if now_playing.is_empty() {
+        if !quiet {
+            println!("Nothing playing");
+        }
+        return;
+    }
+
+    for now in now_playing {
+        println!("{}", template.render(now));
+    }
+}
+
+fn print_now_playing_csv(out: &mut impl Write, now_playing: &[NowPlaying]) -> Result<()> {
+    writeln!(out, "title,artist,album,player,elapsed,length,percent,current_block_seconds")?;
+    for now in now_playing {
+        writeln!(out, "\"{}\",\"{}\",\"{}\",\"{}\",{},{},{},{}",
+                      now.title,
+                      now.artist,
+                      now.album,
+                      now.player,
+                      now.elapsed,
+                      now.length.map(|l| l.to_string()).unwrap_or_default(),
+                      now.percent.map(|p| format!("{:.1}", p)).unwrap_or_default(),
+                      now.current_block_seconds.map(|b| b.to_string()).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn print_skip_stats_human(stats: &SkipStats, quiet: bool, granularity: Granularity) {
+    print_header(quiet, "⏭️  Skipping:", "═══════════════════");
+    println!("Skipped {} of {} plays ({:.0}%)",
+             stats.skipped_sessions,
+             stats.total_sessions,
+             stats.skip_rate * 100.0);
+    println!("Time saved by skipping: {}", format_duration(stats.time_saved, granularity));
+    println!();
+
+    for (i, artist) in stats.artists.iter().enumerate() {
+        println!("{}. {}", i + 1, metadata_label(&artist.artist));
+        println!("   {:.0}% skipped ({} of {} plays), {} saved",
+                 artist.skip_rate * 100.0,
+                 artist.skipped_sessions,
+                 artist.total_sessions,
+                 format_duration(artist.time_saved, granularity));
+        println!();
+    }
+}
+
+fn print_skip_stats_csv(out: &mut impl Write, stats: &SkipStats, granularity: Granularity) -> Result<()> {
+    writeln!(out, "artist,total_sessions,skipped_sessions,skip_rate,time_saved")?;
+    for artist in &stats.artists {
+        writeln!(out, "\"{}\",{},{},{:.3},{}",
+                      artist.artist,
+                      artist.total_sessions,
+                      artist.skipped_sessions,
+                      artist.skip_rate,
+                      granularity.round(artist.time_saved))?;
+    }
+    Ok(())
+}
+
+/// Width of the longest bar in the weekday chart
+const WEEKDAY_BAR_WIDTH: i64 = 30;
+
+fn print_weekdays_human(averages: &[WeekdayAvg], quiet: bool, granularity: Granularity) {
+    print_header(quiet, "📅 Average Listening by Weekday:", "═══════════════════════════");
+
+    let max = averages.iter().map(|avg| avg.average_listened_time).max().unwrap_or(0);
+    for avg in averages {
+        let width = if max > 0 { avg.average_listened_time * WEEKDAY_BAR_WIDTH / max } else { 0 };
+        println!("{}  {:<width$}  {}",
+                 avg.weekday,
+                 "█".repeat(width as usize),
+                 format_duration(avg.average_listened_time, granularity),
+                 width = WEEKDAY_BAR_WIDTH as usize);
+    }
+}
+
+fn print_weekdays_csv(out: &mut impl Write, averages: &[WeekdayAvg], granularity: Granularity) -> Result<()> {
+    writeln!(out, "weekday,days,total_listened_time,average_listened_time")?;
+    for avg in averages {
+        writeln!(out, "{},{},{},{}",
+                      avg.weekday,
+                      avg.days,
+                      granularity.round(avg.total_listened_time),
+                      granularity.round(avg.average_listened_time))?;
+    }
+    Ok(())
+}
+
+fn print_on_this_day_human(month: u32, day: u32, years: &[YearSlice], quiet: bool, granularity: Granularity) {
+    print_header(quiet, &format!("📆 On this day ({:02}-{:02}):", month, day), "═══════════════════════════");
+
+    if years.is_empty() && !quiet {
+        println!("No listening on this day in earlier years");
+    }
+
+    for year in years {
+        println!("{}: {} listened", year.year, format_duration(year.total_listening_time, granularity));
+        for (i, track_stat) in year.top_tracks.iter().enumerate() {
+            println!("  {}. {} - {} ({})",
+                     i + 1,
+                     metadata_label(&track_stat.track.title),
+                     metadata_label(&track_stat.track.artist),
+                     format_duration(track_stat.total_listened_time, granularity));
+        }
+        println!();
+    }
+}
+
+fn print_on_this_day_csv(out: &mut impl Write, years: &[YearSlice], granularity: Granularity) -> Result<()> {
+    writeln!(out, "year,total_listening_time,rank,title,artist,listened_time,play_count")?;
+    for year in years {
+        for (i, track_stat) in year.top_tracks.iter().enumerate() {
+            writeln!(out, "{},{},{},\"{}\",\"{}\",{},{}",
+                          year.year,
+                          granularity.round(year.total_listening_time),
+                          i + 1,
+                          track_stat.track.title,
+                          track_stat.track.artist,
+                          granularity.round(track_stat.total_listened_time),
+                          track_stat.play_count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Position on the album as `disc-track`, or just the track number on single-disc albums
+fn album_position(track: &gopal::database::Track) -> String {
+    match (track.disc_number, track.track_number) {
+        (Some(disc), Some(number)) => format!("{}-{:02}", disc, number),
+        (None, Some(number)) => format!("{:02}", number),
+        _ => "--".to_string(),
+    }
+}
+
+/// Breaks shorter than this between plays are just the next track starting
+const REPLAY_MIN_GAP_SECS: i64 = 60;
+
+fn print_replay_human(date: NaiveDate, timeline: &[TimelineEntry], quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, &format!("📼 Replay of {} {}:", date.format("%A"), locale.format_date(date)), "═══════════════════════════");
+    if timeline.is_empty() {
+        println!("Nothing played on {}", locale.format_date(date));
+        return;
+    }
+
+    let clock = |timestamp: i64| DateTime::<Local>::from(DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap()).format("%H:%M");
+    for entry in timeline {
+        if let Some(gap) = entry.gap_before.filter(|&gap| gap >= REPLAY_MIN_GAP_SECS) {
+            println!("              ⋯ {} of silence", format_duration(gap, granularity));
+        }
+        println!("{} – {} {} – {} ({}) on {}",
+                 clock(entry.play.session.start_time),
+                 clock(entry.end_time),
+                 metadata_label(&entry.play.track.artist),
+                 metadata_label(&entry.play.track.title),
+                 format_duration(entry.play.session.listened_time.unwrap_or(0), granularity),
+                 entry.play.player.name);
+    }
+}
+
+fn print_artist_human(catalog: &ArtistCatalog, quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, &format!("🎤 {}:", catalog.artist), "═══════════════════════════");
+    if catalog.discoveries.is_empty() {
+        println!("Nothing heard by {}", catalog.artist);
+        return;
+    }
+
+    println!("{} listened, {} plays of {} tracks",
+             format_duration(catalog.total_listened_time, granularity),
+             locale.format_number(catalog.play_count),
+             locale.format_number(catalog.distinct_tracks));
+    println!();
+    println!("🧭 Catalog exploration:");
+
+    let mut last_day = None;
+    for (i, discovery) in catalog.discoveries.iter().enumerate() {
+        let first_played = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(discovery.first_played, 0).unwrap()
+        );
+        // Only the first discovery of each day gets the date
+        let day = locale.format_date(first_played.date_naive());
+        let label = if last_day.as_ref() == Some(&day) { " ".repeat(day.len()) } else { day.clone() };
+        last_day = Some(day);
+
+        println!("{}  #{} {} ({} plays)", label, i + 1, metadata_label(&discovery.track.title), locale.format_number(discovery.play_count));
+    }
+}
+
+fn print_album_human(name: &str, tracks: &[TrackStats], quiet: bool, granularity: Granularity) {
+    print_header(quiet, &format!("💿 {}:", name), "═══════════════════════════");
+
+    for track_stat in tracks {
+        println!("{}. {} - {}", album_position(&track_stat.track), metadata_label(&track_stat.track.title), metadata_label(&track_stat.track.artist));
+        println!("   {} listened, {} plays", format_duration(track_stat.total_listened_time, granularity), track_stat.play_count);
+        println!();
+    }
+}
+
+fn print_album_csv(out: &mut impl Write, tracks: &[TrackStats], granularity: Granularity) -> Result<()> {
+    writeln!(out, "disc_number,track_number,title,artist,listened_time,play_count")?;
+    for track_stat in tracks {
+        writeln!(out, "{},{},\"{}\",\"{}\",{},{}",
+                      track_stat.track.disc_number.map_or(String::new(), |n| n.to_string()),
+                      track_stat.track.track_number.map_or(String::new(), |n| n.to_string()),
+                      track_stat.track.title,
+                      track_stat.track.artist,
+                      granularity.round(track_stat.total_listened_time),
+                      track_stat.play_count)?;
+    }
+    Ok(())
+}
+
+fn print_album_completeness_human(albums: &[AlbumCompleteness], quiet: bool) {
+    print_header(quiet, "💿 Album Completeness:", "═══════════════════════════");
+
+    for (i, album) in albums.iter().enumerate() {
+        println!("{}. {} - {}", i + 1, metadata_label(&album.album), metadata_label(&album.artist));
+        match (album.total_tracks, album.percent) {
+            (Some(total), Some(percent)) if album.heard_tracks >= total => {
+                println!("   ✓ heard in full, all {} tracks ({:.0}%)", total, percent)
+            }
+            (Some(total), Some(percent)) => println!("   {} of {} tracks heard ({:.0}%)", album.heard_tracks, total, percent),
+            _ => println!("   {} tracks heard, track count unknown", album.heard_tracks),
+        }
+        println!();
+    }
+}
+
+fn print_album_completeness_csv(out: &mut impl Write, albums: &[AlbumCompleteness]) -> Result<()> {
+    writeln!(out, "album,artist,heard_tracks,total_tracks,percent")?;
+    for album in albums {
+        writeln!(out, "\"{}\",\"{}\",{},{},{}",
+                      album.album,
+                      album.artist,
+                      album.heard_tracks,
+                      album.total_tracks.map_or(String::new(), |n| n.to_string()),
+                      album.percent.map_or(String::new(), |p| format!("{:.1}", p)))?;
+    }
+    Ok(())
+}
+
+fn print_incomplete_tracks_human(tracks: &[Track], quiet: bool) {
+    print_header(quiet, "🩹 Incomplete Tracks:", "═══════════════════════════");
+    if tracks.is_empty() {
+        println!("Every track has its artist, album, length and art");
+        return;
+    }
+
+    for track in tracks {
+        println!("{} - {}", metadata_label(&track.title), metadata_label(&track.artist));
+        println!("   missing {}", track.missing_fields().join(", "));
+    }
+}
+
+#[derive(Serialize)]
+struct IncompleteTrackOutput<'a> {
+    #[serde(flatten)]
+    track: &'a Track,
+    missing: Vec<&'static str>,
+}
+
+fn print_incomplete_tracks_csv(out: &mut impl Write, tracks: &[Track]) -> Result<()> {
+    writeln!(out, "track_id,title,artist,album,missing")?;
+    for track in tracks {
+        writeln!(out, "\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"",
+                      track.id,
+                      track.title,
+                      track.artist,
+                      track.album,
+                      track.missing_fields().join(","))?;
+    }
+    Ok(())
+}
+
+fn print_rank_history_human(name: &str, history: &[RankPoint], quiet: bool, granularity: Granularity) {
+    print_header(quiet, &format!("📈 Rank history for {}:", name), "═══════════════════════════");
+
+    for point in history {
+        match point.rank {
+            Some(rank) => println!("{}  #{} of {}  {}",
+                                   point.bucket,
+                                   rank,
+                                   point.ranked_entities,
+                                   format_duration(point.listened_time, granularity)),
+            None => println!("{}  -", point.bucket),
+        }
+    }
+
+    if !history.is_empty() {
+        let ranks: Vec<String> = history
+            .iter()
+            .map(|point| point.rank.map_or("-".to_string(), |rank| format!("#{}", rank)))
+            .collect();
+        println!();
+        println!("{}", ranks.join(" → "));
+    }
+}
+
+fn print_rank_history_csv(out: &mut impl Write, history: &[RankPoint], granularity: Granularity) -> Result<()> {
+    writeln!(out, "bucket,rank,listened_time,ranked_entities")?;
+    for point in history {
+        writeln!(out, "{},{},{},{}",
+                      point.bucket,
+                      point.rank.map_or(String::new(), |rank| rank.to_string()),
+                      granularity.round(point.listened_time),
+                      point.ranked_entities)?;
+    }
+    Ok(())
+}
+
+fn print_import_summary_human(summary: &ImportSummary, default_duration: i64, granularity: Granularity) {
+    println!("Imported {} listens", summary.imported);
+    if summary.skipped_duplicates > 0 {
+        println!("Skipped {} listens already in the database", summary.skipped_duplicates);
+    }
+    if summary.estimated_durations > 0 {
+        println!("{} listens had no known duration and were recorded as {}",
+                 summary.estimated_durations,
+                 format_duration(default_duration, granularity));
+    }
+}
+
+fn print_status_human(stats: &DatabaseStats, quiet: bool, locale: &OutputLocale) {
+    let format_time = |timestamp: Option<i64>| {
+        timestamp
+            .and_then(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+            .map(|datetime| locale.format_datetime(DateTime::<Local>::from(datetime)))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    print_header(quiet, "📊 Database Status:", "═══════════════════");
+    println!("Database file: {}", format_file_size(stats.file_size));
+    println!("Schema version: {}", stats.schema_version);
+    println!("Total sessions: {}", locale.format_number(stats.total_sessions));
+    println!("Total scrobbles: {}", locale.format_number(stats.total_scrobbles));
+    println!("Active sessions: {}", locale.format_number(stats.active_sessions));
+    println!("Total tracks: {}", locale.format_number(stats.total_tracks));
+    println!("Total players: {}", locale.format_number(stats.total_players));
+    println!("Total listened: {:.1} hours", stats.total_listened_time as f64 / 3600.0);
+    println!("First session: {}", format_time(stats.earliest_session));
+    println!("Latest session: {}", format_time(stats.latest_session));
+
+    if stats.active_sessions > 0 {
+        println!();
+        println!("⚠️  Warning: {} active sessions found.", stats.active_sessions);
+        println!("   This may indicate the daemon was not properly shut down.");
+        println!("   These will be cleaned up on next daemon start.");
+    }
+
+    if !quiet {
+        println!();
+        println!("Use 'gopal-cli stats' to view listening statistics.");
+    }
+}
+
+fn print_status_csv(out: &mut impl Write, stats: &DatabaseStats, granularity: Granularity) -> Result<()> {
+    writeln!(out, "total_sessions,active_sessions,total_tracks,total_players,earliest_session,latest_session,total_listened_time,file_size,schema_version,total_scrobbles")?;
+    writeln!(out, "{},{},{},{},{},{},{},{},{},{}",
+                  stats.total_sessions,
+                  stats.active_sessions,
+                  stats.total_tracks,
+                  stats.total_players,
+                  stats.earliest_session.map_or(String::new(), |t| t.to_string()),
+                  stats.latest_session.map_or(String::new(), |t| t.to_string()),
+                  granularity.round(stats.total_listened_time),
+                  stats.file_size,
+                  stats.schema_version,
+                  stats.total_scrobbles)?;
+    Ok(())
+}
+
+fn print_timing_report_human(mismatches: &[TimingMismatch], tolerance: i64, quiet: bool, locale: &OutputLocale) {
+    print_header(quiet, "⏱️  Session Timing:", "═══════════════════");
+    if mismatches.is_empty() {
+        println!("All finished sessions add up to within {}s", tolerance);
+        return;
+    }
+
+    println!("{} sessions drift by more than {}s", mismatches.len(), tolerance);
+    println!();
+    for mismatch in mismatches {
+        let datetime = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(mismatch.start_time, 0).unwrap()
+        );
+        println!("#{} {} - {} [{}]", mismatch.session_id, metadata_label(&mismatch.title), metadata_label(&mismatch.artist), mismatch.player);
+        println!("   {} on {}: {}s listened + {}s paused over {}s ({:+}s)",
+                 mismatch.status,
+                 locale.format_datetime(datetime),
+                 mismatch.listened_time,
+                 mismatch.paused_time,
+                 mismatch.end_time - mismatch.start_time,
+                 mismatch.drift);
+        println!();
+    }
+}
+
+fn print_scrobble_queue_human(queue: &[QueuedScrobble], quiet: bool, locale: &OutputLocale) {
+    print_header(quiet, "📤 Scrobble Queue:", "══════════════════");
+    if queue.is_empty() {
+        println!("No scrobbles waiting");
+        return;
+    }
+
+    let failed = queue.iter().filter(|scrobble| scrobble.state() == "failed").count();
+    println!("{} scrobbles waiting, {} given up on", queue.len(), failed);
+    println!();
+    for scrobble in queue {
+        let listened_at = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(scrobble.listened_at, 0).unwrap()
+        );
+        println!("#{} {} - {}", scrobble.id, metadata_label(&scrobble.title), metadata_label(&scrobble.artist));
+        match scrobble.next_attempt_at.and_then(|next| DateTime::<Utc>::from_timestamp(next, 0)) {
+            Some(next) => println!("   played {}, {} after {} attempts, next try {}",
+                                   locale.format_datetime(listened_at),
+                                   scrobble.state(),
+                                   scrobble.attempts,
+                                   {
+                                       let next = DateTime::<Local>::from(next);
+                                       format!("{} {}", locale.format_date(next.date_naive()), next.format("%H:%M:%S"))
+                                   }),
+            None => println!("   played {}, failed after {} attempts",
+                             locale.format_datetime(listened_at),
+                             scrobble.attempts),
+        }
+        if let Some(error) = &scrobble.last_error {
+            println!("   last error: {}", error);
+        }
+        println!();
+    }
+}
+
+fn print_active_sessions_human(sessions: &[ActiveSession], quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, "▶️  Active Sessions:", "═══════════════════");
+    if sessions.is_empty() {
+        println!("No active sessions");
+        return;
+    }
+
+    for active in sessions {
+        let entry = &active.session;
+        let datetime = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(entry.session.start_time, 0).unwrap()
+        );
+        println!("#{} {} - {} [{}]", entry.session.id, metadata_label(&entry.track.title), metadata_label(&entry.track.artist), entry.player.identity);
+        println!("   started {} ({} ago), {} listened at last checkpoint",
+                 locale.format_datetime(datetime),
+                 format_duration(active.age, granularity),
+                 format_duration(entry.session.listened_time.unwrap_or(0), granularity));
+        println!();
+    }
+}
+
+fn print_active_sessions_csv(out: &mut impl Write, sessions: &[ActiveSession]) -> Result<()> {
+    writeln!(out, "session_id,title,artist,player,start_time,age,listened_time")?;
+    for active in sessions {
+        let entry = &active.session;
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{},{},{}",
+                      entry.session.id,
+                      entry.track.title,
+                      entry.track.artist,
+                      entry.player.identity,
+                      entry.session.start_time,
+                      active.age,
+                      entry.session.listened_time.map(|l| l.to_string()).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn print_cleanup_human(closed: &[SessionWithMetadata], max_age: i64, quiet: bool, granularity: Granularity, locale: &OutputLocale) {
+    print_header(quiet, "🧹 Orphaned Sessions:", "═══════════════════");
+    if closed.is_empty() {
+        println!("No active sessions older than {}", format_duration(max_age, Granularity::Second));
+        return;
+    }
+
+    println!("Closed {} sessions older than {} as orphaned", closed.len(), format_duration(max_age, Granularity::Second));
+    println!();
+    for entry in closed {
+        let datetime = DateTime::<Local>::from(
+            DateTime::<Utc>::from_timestamp(entry.session.start_time, 0).unwrap()
+        );
+        println!("#{} {} - {} [{}]", entry.session.id, metadata_label(&entry.track.title), metadata_label(&entry.track.artist), entry.player.identity);
+        println!("   started {}, counted as {} listened",
+                 locale.format_datetime(datetime),
+                 format_duration(entry.session.listened_time.unwrap_or(0), granularity));
+        println!();
+    }
+}
+
+fn print_cleanup_csv(out: &mut impl Write, closed: &[SessionWithMetadata]) -> Result<()> {
+    writeln!(out, "session_id,title,artist,player,start_time,end_time,listened_time")?;
+    for entry in closed {
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{},{},{}",
+                      entry.session.id,
+                      entry.track.title,
+                      entry.track.artist,
+                      entry.player.identity,
+                      entry.session.start_time,
+                      entry.session.end_time.map(|e| e.to_string()).unwrap_or_default(),
+                      entry.session.listened_time.map(|l| l.to_string()).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn print_scrobble_queue_csv(out: &mut impl Write, queue: &[QueuedScrobble]) -> Result<()> {
+    writeln!(out, "id,session_id,title,artist,album,listened_at,state,attempts,next_attempt_at,last_error")?;
+    for scrobble in queue {
+        writeln!(out, "{},{},\"{}\",\"{}\",\"{}\",{},{},{},{},\"{}\"",
+                      scrobble.id,
+                      scrobble.session_id,
+                      scrobble.title,
+                      scrobble.artist,
+                      scrobble.album,
+                      scrobble.listened_at,
+                      scrobble.state(),
+                      scrobble.attempts,
+                      scrobble.next_attempt_at.map(|next| next.to_string()).unwrap_or_default(),
+                      scrobble.last_error.as_deref().unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn print_timing_report_csv(out: &mut impl Write, mismatches: &[TimingMismatch]) -> Result<()> {
+    writeln!(out, "session_id,title,artist,player,status,start_time,end_time,listened_time,paused_time,drift")?;
+    for mismatch in mismatches {
+        writeln!(out, "{},\"{}\",\"{}\",\"{}\",{},{},{},{},{},{}",
+                      mismatch.session_id,
+                      mismatch.title,
+                      mismatch.artist,
+                      mismatch.player,
+                      mismatch.status,
+                      mismatch.start_time,
+                      mismatch.end_time,
+                      mismatch.listened_time,
+                      mismatch.paused_time,
+                      mismatch.drift)?;
+    }
+    Ok(())
+}
+
+fn format_file_size(bytes: i64) -> String {
+    const KIB: f64 = 1024.0;
+    let bytes = bytes as f64;
+    if bytes < KIB {
+        format!("{} B", bytes)
+    } else if bytes < KIB * KIB {
+        format!("{:.1} KiB", bytes / KIB)
+    } else {
+        format!("{:.1} MiB", bytes / (KIB * KIB))
+    }
+}
+
+/// Format seconds as e.g. "1 hr 1 min 1 sec", rounded to `granularity` first
+fn format_duration(seconds: i64, granularity: Granularity) -> String {
+    let seconds = granularity.round(seconds);
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let secs = seconds % 60;
+
+    let mut parts = Vec::new();
+    if hours > 0 {
+        parts.push(format!("{} hr", hours));
+    }
+    if minutes > 0 {
+        parts.push(format!("{} min", minutes));
+    }
+    if secs > 0 {
+        parts.push(format!("{} sec", secs));
+    }
+
+    if parts.is_empty() {
+        format!("0 {}", granularity.unit())
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_command_that_stops_reading_fails() {
+        let mut renderer = CommandRenderer { command: "cat > /dev/null".to_string() };
+        renderer.value("stats", &serde_json::json!({ "plays": 1 })).unwrap();
+
+        // More than a pipe holds, to a command that exits without reading any of it
+        let mut renderer = CommandRenderer { command: "exec true".to_string() };
+        let error = renderer.value("stats", &vec!["x".repeat(1024); 1024]).unwrap_err();
+        assert!(error.to_string().contains("Failed to write to render command"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_prometheus_output_is_valid_exposition_format() {
+        let stats = serde_json::json!({
+            "total_listening_time": 3600,
+            "plays": 12,
+            "scrobbles": 9,
+            "active_days": 3,
+            "top_tracks": [
+                {"track": {"title": "Say \"Hi\"\nAgain", "artist": "Back\\slash", "album": ""}, "total_listened_time": 1800, "play_count": 6},
+            ],
+            "top_artists": [{"artist": "Band", "total_listened_time": 3600, "play_count": 12, "track_count": 2}],
+        });
+        let metrics = prometheus_metrics("stats", &stats).unwrap();
+        let status = prometheus_metrics("status", &serde_json::json!({"total_sessions": 40, "file_size": 8192})).unwrap();
+
+        let comment = regex::Regex::new(r"^# (HELP|TYPE) gopal_[a-z_]+ \S.*$").unwrap();
+        let sample = regex::Regex::new(
+            r#"^gopal_[a-z_]+(\{[a-z_]+="(\\[\\"n]|[^"\\\n])*"(,[a-z_]+="(\\[\\"n]|[^"\\\n])*")*\})? -?[0-9]+(\.[0-9]+)?$"#
+        ).unwrap();
+        for line in metrics.lines().chain(status.lines()) {
+            assert!(comment.is_match(line) || sample.is_match(line), "invalid line: {}", line);
+        }
+
+        assert!(metrics.contains("gopal_listening_seconds 3600\n"));
+        assert!(metrics.contains(r#"gopal_track_plays{title="Say \"Hi\"\nAgain",artist="Back\\slash",album=""} 6"#));
+        assert!(metrics.contains(r#"gopal_artist_plays{artist="Band"} 12"#));
+        assert!(status.contains("gopal_sessions 40\n"));
+
+        let ping = prometheus_metrics("ping", &serde_json::json!({"recent_activity": [
+            {"hour_start": 3600, "listened_time": 600},
+            {"hour_start": 7200, "listened_time": 120},
+        ]})).unwrap();
+        assert!(ping.contains("gopal_recent_listening_seconds{hours_ago=\"0\"} 120\n"), "{}", ping);
+        assert!(ping.contains("gopal_recent_listening_seconds{hours_ago=\"1\"} 600\n"), "{}", ping);
+        assert!(!ping.contains("hour_start"));
+
+        assert!(prometheus_metrics("history", &serde_json::json!([])).is_err());
+        assert!(prometheus_metrics("stats", &serde_json::json!([])).is_err());
+    }
+
+    #[test]
+    fn test_csv_columns_select_and_reorder() {
+        let plays = [AnonymousPlay {
+            title: "One, Two".to_string(),
+            artist: "Band".to_string(),
+            album: "Album".to_string(),
+            listened_time: 200,
+        }];
+        let mut csv = Vec::new();
+        print_anonymous_history_csv(&mut csv, &plays, Granularity::Minute).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        let columns = ["listened_time", "title"].map(String::from);
+        assert_eq!(select_csv_columns(&csv, &columns).unwrap(), "listened_time,title\n180,\"One, Two\"\n");
+
+        let error = select_csv_columns(&csv, &["length".to_string()]).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown column 'length'; available columns are: title, artist, album, listened_time");
+    }
+
+    #[test]
+    fn test_format_duration_granularity() {
+        assert_eq!(format_duration(3661, Granularity::Second), "1 hr 1 min 1 sec");
+        assert_eq!(format_duration(3661, Granularity::Minute), "1 hr 1 min");
+        assert_eq!(format_duration(3661, Granularity::Hour), "1 hr");
+        assert_eq!(format_duration(125, Granularity::Second), "2 min 5 sec");
+        assert_eq!(format_duration(20, Granularity::Minute), "0 min");
+    }
+
+    #[test]
+    fn test_granularity_rounds_to_nearest_unit() {
+        assert_eq!(Granularity::Second.round(3661), 3661);
+        assert_eq!(Granularity::Minute.round(89), 60);
+        assert_eq!(Granularity::Minute.round(90), 120);
+        assert_eq!(Granularity::Hour.round(5399), 3600);
+    }
+}
@@ -0,0 +1,281 @@
+//! `gopal-cli report`: one period's stats as a single self-contained HTML page, for sharing
+
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+use std::collections::HashMap;
+
+use gopal::database::{metadata_label, Dashboard, Database, DashboardLimits, StatsFilter, Track};
+use gopal::locale::OutputLocale;
+
+/// Page the report is rendered into; `{{name}}` placeholders are filled in by `render_html`
+const TEMPLATE: &str = include_str!("report.html");
+
+/// Entries in the top track and artist lists
+const REPORT_TOP_LIMIT: usize = 10;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Everything a report shows, loaded up front so rendering can't fail
+pub(crate) struct Report {
+    pub title: String,
+    pub start_time: Option<i64>,
+    pub end_time: i64,
+    pub dashboard: Dashboard,
+    pub heatmap: [[i64; 24]; 7],
+    /// Track id -> `data:` URI of its album art thumbnail
+    pub art: HashMap<String, String>,
+}
+
+impl Report {
+    pub fn load(database: &Database, title: String, start_time: Option<i64>, end_time: Option<i64>) -> Result<Self> {
+        let dashboard = database.get_dashboard(
+            start_time,
+            end_time,
+            &StatsFilter::default(),
+            DashboardLimits { top: REPORT_TOP_LIMIT, history: 0 },
+        )?;
+        let art = dashboard.stats.top_tracks
+            .iter()
+            .filter_map(|track| Some((track.track.id.clone(), embedded_art(&track.track)?)))
+            .collect();
+
+        Ok(Report {
+            title,
+            start_time,
+            end_time: end_time.unwrap_or_else(|| Local::now().timestamp()),
+            heatmap: database.get_hourly_heatmap(start_time, end_time)?,
+            dashboard,
+            art,
+        })
+    }
+}
+
+pub(crate) fn render_html(report: &Report, locale: &OutputLocale) -> String {
+    let date = |timestamp: i64| {
+        Local.timestamp_opt(timestamp, 0).earliest()
+            .map(|datetime| locale.format_date(datetime.date_naive()))
+            .unwrap_or_default()
+    };
+    let range = match report.start_time {
+        Some(start_time) => format!("{} – {}", date(start_time), date(report.end_time)),
+        None => format!("Everything up to {}", date(report.end_time)),
+    };
+
+    let stats = &report.dashboard.stats;
+    let summary = [
+        ("Listened", hours_minutes(stats.total_listening_time)),
+        ("Plays", locale.format_number(report.dashboard.plays)),
+        ("Scrobbles", locale.format_number(report.dashboard.scrobbles)),
+        ("Active days", locale.format_number(stats.active_days)),
+    ]
+    .iter()
+    .map(|(label, value)| format!("<div><strong>{}</strong>{}</div>", escape(value), label))
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let tracks = stats.top_tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let art = match report.art.get(&track.track.id) {
+                Some(uri) => format!("<img class=\"art\" src=\"{}\" alt=\"\" />", uri),
+                None => "<span class=\"art\"></span>".to_string(),
+            };
+            list_item(
+                i,
+                &art,
+                &format!("{}<small>{}</small>", escape(metadata_label(&track.track.title)), escape(metadata_label(&track.track.artist))),
+                &format!("{} · {} plays", hours_minutes(track.total_listened_time), locale.format_number(track.play_count)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let artists = stats.top_artists
+        .iter()
+        .enumerate()
+        .map(|(i, artist)| {
+            list_item(
+                i,
+                "",
+                &escape(metadata_label(&artist.artist)),
+                &format!("{} · {} tracks", hours_minutes(artist.total_listened_time), locale.format_number(artist.track_count)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    TEMPLATE
+        .replace("{{title}}", &escape(&report.title))
+        .replace("{{range}}", &escape(&range))
+        .replace("{{summary}}", &summary)
+        .replace("{{tracks}}", &tracks)
+        .replace("{{artists}}", &artists)
+        .replace("{{heatmap}}", &heatmap_rows(&report.heatmap))
+        .replace("{{generated}}", &escape(&locale.format_datetime(Local::now())))
+}
+
+fn list_item(index: usize, art: &str, name: &str, time: &str) -> String {
+    format!(
+        "<li><span class=\"rank\">{}</span>{}<span class=\"name\">{}</span><span class=\"time\">{}</span></li>",
+        index + 1, art, name, escape(time)
+    )
+}
+
+/// A header row of hours, then one row per weekday with each hour shaded relative to
+/// the busiest and its listening time as a tooltip
+fn heatmap_rows(heatmap: &[[i64; 24]; 7]) -> String {
+    let busiest = heatmap.iter().flatten().copied().max().unwrap_or(0);
+
+    let mut rows = vec![format!(
+        "<tr><th></th>{}</tr>",
+        (0..24).map(|hour| format!("<th>{}</th>", if hour % 3 == 0 { hour.to_string() } else { String::new() })).collect::<String>()
+    )];
+    for (weekday, hours) in WEEKDAYS.iter().zip(heatmap) {
+        let cells: String = hours.iter().map(|&seconds| {
+            if seconds <= 0 || busiest <= 0 {
+                return "<td></td>".to_string();
+            }
+            // Any listening at all stays visible
+            let opacity = 0.15 + 0.85 * seconds as f64 / busiest as f64;
+            format!(
+                "<td style=\"background: rgba(29, 185, 84, {:.2})\" title=\"{}\"></td>",
+                opacity, hours_minutes(seconds)
+            )
+        }).collect();
+        rows.push(format!("<tr><th>{}</th>{}</tr>", weekday, cells));
+    }
+    rows.join("\n")
+}
+
+fn hours_minutes(seconds: i64) -> String {
+    format!("{}h {:02}m", seconds / 3600, (seconds % 3600) / 60)
+}
+
+/// Escape text for use in HTML content and quoted attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Thumbnail of the track's art as a `data:` URI, when it's a local file the
+/// enabled decoders can read. Remote art would make the page depend on the network.
+#[cfg(feature = "thumbnails")]
+fn embedded_art(track: &Track) -> Option<String> {
+    use base64::Engine;
+
+    let path = local_art_path(track.art_url.as_deref()?)?;
+    let art = std::fs::read(path).ok()?;
+    let png = gopal::art::thumbnail(&art, 80).ok()??;
+    Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png)))
+}
+
+#[cfg(not(feature = "thumbnails"))]
+fn embedded_art(_track: &Track) -> Option<String> {
+    None
+}
+
+/// Path of a `file://` art URL, with percent-encoded bytes such as `%20` decoded
+#[cfg_attr(not(feature = "thumbnails"), allow(dead_code))]
+fn local_art_path(url: &str) -> Option<std::path::PathBuf> {
+    let encoded = url.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let decoded = (encoded[i] == b'%')
+            .then(|| std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(std::path::PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Open and close tags, in order, with void elements and the doctype left out
+    fn tags(html: &str) -> Vec<(bool, String)> {
+        let mut tags = Vec::new();
+        for chunk in html.split('<').skip(1) {
+            let tag = chunk.split('>').next().unwrap();
+            if tag.starts_with('!') || tag.ends_with('/') {
+                continue;
+            }
+            let closing = tag.starts_with('/');
+            let name = tag.trim_start_matches('/').split_whitespace().next().unwrap().to_string();
+            tags.push((closing, name));
+        }
+        tags
+    }
+
+    #[test]
+    fn test_report_lists_top_tracks_in_well_formed_html() {
+        let database = Database::new(":memory:").unwrap();
+        let player = database.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        for (title, artist, listened) in [("Tom & Jerry <Live>", "Band", 600), ("Quiet Song", "Other \"Artist\"", 300)] {
+            let track = Track {
+                id: format!("{}::{}::Album", title, artist),
+                title: title.to_string(),
+                artist: artist.to_string(),
+                album: "Album".to_string(),
+                length: Some(700_000_000),
+                art_url: Some("https://example.com/cover.jpg".to_string()),
+                genre: None,
+                raw_metadata: None,
+                track_number: None,
+                disc_number: None,
+                primary_artist: None,
+                rating: None,
+                comment: None,
+            };
+            database.insert_or_update_track(&track).unwrap();
+            let session_id = database.start_session(&track.id, player, 1_700_000_000).unwrap();
+            database.finalize_session(session_id, 1_700_000_000 + listened, "completed").unwrap();
+        }
+
+        let report = Report::load(&database, "Listening Report".to_string(), None, None).unwrap();
+        let html = render_html(&report, &OutputLocale::ISO);
+
+        assert!(html.contains("Tom &amp; Jerry &lt;Live&gt;"));
+        assert!(html.contains("Quiet Song"));
+        assert!(html.contains("Other &quot;Artist&quot;"));
+        assert!(!html.contains("{{"), "unfilled placeholder");
+        // Remote art isn't embedded
+        assert!(!html.contains("example.com"));
+
+        let mut open = Vec::new();
+        for (closing, name) in tags(&html) {
+            if !closing {
+                open.push(name);
+            } else {
+                assert_eq!(open.pop().as_deref(), Some(name.as_str()), "unbalanced </{}>", name);
+            }
+        }
+        assert!(open.is_empty(), "unclosed {:?}", open);
+        assert_eq!(tags(&html).first().map(|(_, name)| name.as_str()), Some("html"));
+
+        assert_eq!(local_art_path("file:///home/me/My%20Album/cover.jpg"), Some("/home/me/My Album/cover.jpg".into()));
+        assert_eq!(local_art_path("https://example.com/cover.jpg"), None);
+    }
+}
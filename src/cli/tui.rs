@@ -0,0 +1,274 @@
+//! `gopal-cli tui`: an interactive dashboard built on the same queries as the other commands
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::{Duration, Instant};
+
+use gopal::database::{metadata_label, ArtistStats, Database, DashboardLimits, StatsFilter, TrackStats};
+use gopal::now_playing::NowPlaying;
+
+use super::{now_playing, parse_time_period, TimePeriod};
+
+/// Periods `p` cycles through; custom ranges need dates, so they're left to `stats`
+const PERIODS: [TimePeriod; 5] = [TimePeriod::Today, TimePeriod::Week, TimePeriod::Month, TimePeriod::Year, TimePeriod::AllTime];
+
+const TAB_TITLES: [&str; 4] = ["Now Playing", "Top Tracks", "Top Artists", "Heatmap"];
+
+/// Entries shown in the top track and artist lists
+const TOP_LIMIT: usize = 50;
+
+/// Heatmap cells from no listening to the busiest hour
+const HEAT_SHADES: [&str; 5] = ["  ", "░░", "▒▒", "▓▓", "██"];
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Everything the tabs show, reloaded on every refresh
+struct Snapshot {
+    now_playing: Vec<NowPlaying>,
+    tracks: Vec<TrackStats>,
+    artists: Vec<ArtistStats>,
+    heatmap: [[i64; 24]; 7],
+}
+
+impl Snapshot {
+    fn load(database: &Database, period: &TimePeriod) -> Result<Self> {
+        let (start_time, end_time) = parse_time_period(period.clone(), None, None, database.day_start_hour(), database.day_zone())?;
+        let dashboard = database.get_dashboard(
+            start_time,
+            end_time,
+            &StatsFilter::default(),
+            DashboardLimits { top: TOP_LIMIT, history: 0 },
+        )?;
+
+        Ok(Snapshot {
+            now_playing: now_playing(database, Local::now().timestamp(), false)?,
+            tracks: dashboard.stats.top_tracks,
+            artists: dashboard.stats.top_artists,
+            heatmap: database.get_hourly_heatmap(start_time, end_time)?,
+        })
+    }
+}
+
+/// Which tab and period are shown and what's selected, independent of the data
+#[derive(Debug, Default, PartialEq, Eq)]
+struct View {
+    tab: usize,
+    period: usize,
+    selected: usize,
+    quit: bool,
+}
+
+impl View {
+    /// Apply a key press, returning whether the data has to be reloaded
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Right | KeyCode::Tab => self.switch_tab((self.tab + 1) % TAB_TITLES.len()),
+            KeyCode::Left | KeyCode::BackTab => self.switch_tab((self.tab + TAB_TITLES.len() - 1) % TAB_TITLES.len()),
+            KeyCode::Down | KeyCode::Char('j') => self.selected += 1,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') => {
+                self.period = (self.period + 1) % PERIODS.len();
+                self.selected = 0;
+                return true;
+            }
+            KeyCode::Char('r') => return true,
+            _ => {}
+        }
+        false
+    }
+
+    fn switch_tab(&mut self, tab: usize) {
+        self.tab = tab;
+        self.selected = 0;
+    }
+}
+
+/// Run the dashboard until the user quits, reloading from the database every `refresh`
+pub(crate) fn run(database: &Database, period: TimePeriod, refresh: Duration) -> Result<()> {
+    let period = PERIODS.iter()
+        .position(|candidate| *candidate == period)
+        .context("The TUI can't show a custom period; pick another with --period")?;
+    let mut view = View { period, ..View::default() };
+    let mut snapshot = Snapshot::load(database, &PERIODS[view.period])?;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, database, &mut view, &mut snapshot, refresh);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    database: &Database,
+    view: &mut View,
+    snapshot: &mut Snapshot,
+    refresh: Duration,
+) -> Result<()> {
+    let mut last_load = Instant::now();
+
+    while !view.quit {
+        terminal.draw(|frame| draw(frame, view, snapshot))?;
+
+        let timeout = refresh.saturating_sub(last_load.elapsed());
+        let mut reload = timeout.is_zero();
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    reload |= view.handle_key(key.code);
+                }
+            }
+        }
+
+        if reload && !view.quit {
+            *snapshot = Snapshot::load(database, &PERIODS[view.period])?;
+            last_load = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, view: &mut View, snapshot: &Snapshot) {
+    let [tabs_area, body, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+
+    let period = format!(" gopal - {:?} ", PERIODS[view.period]);
+    frame.render_widget(
+        Tabs::new(TAB_TITLES)
+            .select(view.tab)
+            .highlight_style(Style::new().bold().reversed())
+            .block(Block::bordered().title(period)),
+        tabs_area,
+    );
+
+    match view.tab {
+        0 => draw_list(frame, body, view, snapshot.now_playing.iter().map(now_playing_line).collect(), "Nothing playing"),
+        1 => draw_list(frame, body, view, snapshot.tracks.iter().enumerate().map(track_line).collect(), "No tracks in this period"),
+        2 => draw_list(frame, body, view, snapshot.artists.iter().enumerate().map(artist_line).collect(), "No artists in this period"),
+        _ => frame.render_widget(Paragraph::new(heatmap_lines(&snapshot.heatmap)).block(Block::bordered()), body),
+    }
+
+    frame.render_widget(
+        Paragraph::new("←/→ tab  ↑/↓ move  p period  r refresh  q quit").dim(),
+        footer,
+    );
+}
+
+fn draw_list(frame: &mut Frame, area: Rect, view: &mut View, lines: Vec<String>, empty: &str) {
+    if lines.is_empty() {
+        frame.render_widget(Paragraph::new(empty).block(Block::bordered()), area);
+        return;
+    }
+
+    view.selected = view.selected.min(lines.len() - 1);
+    let list = List::new(lines.into_iter().map(ListItem::new))
+        .block(Block::bordered())
+        .highlight_style(Style::new().reversed())
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, area, &mut ListState::default().with_selected(Some(view.selected)));
+}
+
+fn now_playing_line(now: &NowPlaying) -> String {
+    let progress = match (now.length, now.percent) {
+        (Some(length), Some(percent)) => format!("{} / {} ({:.0}%)", clock(now.elapsed), clock(length), percent),
+        _ => clock(now.elapsed),
+    };
+    format!("▶ {} - {} [{}]  {}", metadata_label(&now.title), metadata_label(&now.artist), now.player, progress)
+}
+
+fn track_line((i, track): (usize, &TrackStats)) -> String {
+    format!("{:>3}. {} - {}  ({}, {} plays)",
+            i + 1,
+            metadata_label(&track.track.title),
+            metadata_label(&track.track.artist),
+            hours_minutes(track.total_listened_time),
+            track.play_count)
+}
+
+fn artist_line((i, artist): (usize, &ArtistStats)) -> String {
+    format!("{:>3}. {}  ({}, {} tracks)",
+            i + 1,
+            metadata_label(&artist.artist),
+            hours_minutes(artist.total_listened_time),
+            artist.track_count)
+}
+
+/// One row per weekday, two columns per hour, shaded relative to the busiest hour
+fn heatmap_lines(heatmap: &[[i64; 24]; 7]) -> Vec<Line<'static>> {
+    let busiest = heatmap.iter().flatten().copied().max().unwrap_or(0);
+
+    let mut header = String::from("    ");
+    for hour in (0..24).step_by(3) {
+        header.push_str(&format!("{:<6}", hour));
+    }
+    let mut lines = vec![Line::from(header)];
+
+    for (weekday, hours) in WEEKDAYS.iter().zip(heatmap) {
+        let cells: String = hours.iter().map(|&seconds| heat_shade(seconds, busiest)).collect();
+        lines.push(Line::from(format!("{} {}", weekday, cells)));
+    }
+    lines
+}
+
+fn heat_shade(seconds: i64, busiest: i64) -> &'static str {
+    if seconds <= 0 || busiest <= 0 {
+        return HEAT_SHADES[0];
+    }
+    // Any listening at all gets at least the lightest shade
+    let level = (seconds * (HEAT_SHADES.len() as i64 - 1) + busiest - 1) / busiest;
+    HEAT_SHADES[level as usize]
+}
+
+fn clock(seconds: i64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn hours_minutes(seconds: i64) -> String {
+    format!("{}h {:02}m", seconds / 3600, (seconds % 3600) / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_switch_tabs_and_periods() {
+        let mut view = View { period: 1, ..View::default() };
+
+        assert!(!view.handle_key(KeyCode::Left));
+        assert_eq!(view.tab, TAB_TITLES.len() - 1);
+        view.handle_key(KeyCode::Right);
+        view.handle_key(KeyCode::Down);
+        view.handle_key(KeyCode::Down);
+        assert_eq!((view.tab, view.selected), (0, 2));
+
+        // Changing period reloads and starts the list over
+        assert!(view.handle_key(KeyCode::Char('p')));
+        assert_eq!((view.period, view.selected), (2, 0));
+        for _ in 0..3 {
+            view.handle_key(KeyCode::Char('p'));
+        }
+        assert_eq!(view.period, 0);
+
+        view.handle_key(KeyCode::Char('q'));
+        assert!(view.quit);
+    }
+
+    #[test]
+    fn test_heat_shades() {
+        assert_eq!(heat_shade(0, 100), HEAT_SHADES[0]);
+        assert_eq!(heat_shade(1, 100), HEAT_SHADES[1]);
+        assert_eq!(heat_shade(50, 100), HEAT_SHADES[2]);
+        assert_eq!(heat_shade(100, 100), HEAT_SHADES[4]);
+    }
+}
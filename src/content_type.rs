@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::database::Track;
+
+/// What kind of listening a session was, so long spoken-word content can be
+/// reported separately from music
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Music,
+    Podcast,
+    Audiobook,
+}
+
+impl ContentType {
+    /// Value stored in `sessions.content_type`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Music => "music",
+            ContentType::Podcast => "podcast",
+            ContentType::Audiobook => "audiobook",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "music" => Some(ContentType::Music),
+            "podcast" => Some(ContentType::Podcast),
+            "audiobook" => Some(ContentType::Audiobook),
+            _ => None,
+        }
+    }
+}
+
+/// Player identities (lowercased substrings) that only play audiobooks
+const AUDIOBOOK_PLAYERS: &[&str] = &["cozy", "audiobookshelf", "bookworm", "booksonic"];
+
+/// Player identities (lowercased substrings) that only play podcasts
+const PODCAST_PLAYERS: &[&str] = &["gpodder", "kasts", "podcasts", "pocket casts", "antennapod"];
+
+/// Single tracks at least this long are audiobook files or chapters
+const AUDIOBOOK_MIN_LENGTH_SECS: i64 = 2 * 3600;
+
+/// Album-less tracks at least this long are podcast episodes
+const PODCAST_MIN_LENGTH_SECS: i64 = 20 * 60;
+
+/// Guess the content type from the player and the track's metadata. The player is
+/// the strongest signal, then an explicit genre, then the track's shape.
+pub fn classify(track: &Track, player_identity: &str) -> ContentType {
+    let identity = player_identity.to_lowercase();
+    if AUDIOBOOK_PLAYERS.iter().any(|player| identity.contains(player)) {
+        return ContentType::Audiobook;
+    }
+    if PODCAST_PLAYERS.iter().any(|player| identity.contains(player)) {
+        return ContentType::Podcast;
+    }
+
+    if let Some(genre) = track.genre.as_deref().map(str::to_lowercase) {
+        if genre.contains("audiobook") || genre.contains("audio book") {
+            return ContentType::Audiobook;
+        }
+        if genre.contains("podcast") {
+            return ContentType::Podcast;
+        }
+    }
+
+    let length_secs = track.length.unwrap_or(0) / 1_000_000;
+    let has_album = !track.album.is_empty() && track.album != "Unknown";
+
+    if length_secs >= AUDIOBOOK_MIN_LENGTH_SECS {
+        ContentType::Audiobook
+    } else if length_secs >= PODCAST_MIN_LENGTH_SECS && !has_album {
+        ContentType::Podcast
+    } else {
+        ContentType::Music
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(album: &str, length_secs: i64, genre: Option<&str>) -> Track {
+        Track {
+            id: format!("Title::Artist::{}", album),
+            title: "Title".to_string(),
+            artist: "Artist".to_string(),
+            album: album.to_string(),
+            length: Some(length_secs * 1_000_000),
+            art_url: None,
+            genre: genre.map(|g| g.to_string()),
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn test_classify_by_length_and_album() {
+        assert_eq!(classify(&track("Album", 240, None), "Spotify"), ContentType::Music);
+        // A long album track is still music, a long track without an album is an episode
+        assert_eq!(classify(&track("Live Album", 45 * 60, None), "Spotify"), ContentType::Music);
+        assert_eq!(classify(&track("Unknown", 45 * 60, None), "Mozilla Firefox"), ContentType::Podcast);
+        assert_eq!(classify(&track("Unknown", 10 * 60, None), "Mozilla Firefox"), ContentType::Music);
+        assert_eq!(classify(&track("The Book", 11 * 3600, None), "VLC media player"), ContentType::Audiobook);
+    }
+
+    #[test]
+    fn test_classify_by_player_and_genre() {
+        assert_eq!(classify(&track("Album", 240, None), "Cozy"), ContentType::Audiobook);
+        assert_eq!(classify(&track("Album", 240, None), "GNOME Podcasts"), ContentType::Podcast);
+        assert_eq!(classify(&track("Album", 240, Some("Podcast")), "Spotify"), ContentType::Podcast);
+        assert_eq!(classify(&track("Album", 240, Some("Audiobook")), "Spotify"), ContentType::Audiobook);
+    }
+
+    #[test]
+    fn test_content_type_round_trips() {
+        for content_type in [ContentType::Music, ContentType::Podcast, ContentType::Audiobook] {
+            assert_eq!(ContentType::parse(content_type.as_str()), Some(content_type));
+        }
+        assert_eq!(ContentType::parse("video"), None);
+    }
+}
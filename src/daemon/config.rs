@@ -1,7 +1,12 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+use gopal::metadata_rules::{ArtistSplitter, MetadataRules, DEFAULT_ARTIST_SEPARATORS, DEFAULT_TITLE_SPLIT_PATTERNS};
+use gopal::mpris_monitor::MAX_TRACK_LENGTH;
+use gopal::session_labels::SessionLabelRules;
+use std::collections::BTreeMap;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Database configuration
@@ -12,6 +17,23 @@ pub struct Config {
     
     /// Logging configuration
     pub logging: LoggingConfig,
+
+    /// Metadata cleanup configuration
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+
+    /// Labels applied to new sessions
+    #[serde(default)]
+    pub labels: LabelsConfig,
+
+    /// Submitting plays to a scrobbling service
+    #[serde(default)]
+    pub scrobble: ScrobbleConfig,
+
+    /// Player bus name or identity -> bus name of the player to record it as. Aliased
+    /// players share one active session, so only alias players that never play at once.
+    #[serde(default)]
+    pub player_aliases: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +43,29 @@ pub struct DatabaseConfig {
     
     /// Database connection pool size (for future use)
     pub pool_size: Option<u32>,
+
+    /// Database left by releases from before the rename, brought over on startup
+    /// when the current database is missing or empty ("" to disable)
+    #[serde(default = "default_legacy_path")]
+    pub legacy_path: String,
+
+    /// Whether to copy or move the legacy database, or leave it alone
+    #[serde(default)]
+    pub legacy_migration: LegacyMigration,
+}
+
+fn default_legacy_path() -> String {
+    gopal::LEGACY_DB_PATH.to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LegacyMigration {
+    /// Copy it, leaving the old file as a backup
+    #[default]
+    Copy,
+    Move,
+    Off,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,8 +79,134 @@ pub struct MonitoringConfig {
     /// How often to run cleanup tasks (in seconds)
     pub cleanup_interval: u64,
     
-    /// Minimum session duration to record (in seconds)
+    /// Drop sessions listened to for less than this, e.g. quick skips (in seconds, 0
+    /// keeps every session). Skip stats can only count the skips that are kept.
+    #[serde(default)]
     pub min_session_duration: u64,
+
+    /// Keep sessions shorter than `min_session_duration` with the `too_short` status
+    /// instead of dropping them. Stats still leave them out.
+    #[serde(default)]
+    pub record_all_sessions: bool,
+
+    /// Record only one session when several players play the same track at once
+    #[serde(default)]
+    pub dedup_simultaneous: bool,
+
+    /// Count time the desktop session is locked as pause time, using logind's
+    /// `LockedHint` on the system bus. Off by default.
+    #[serde(default)]
+    pub pause_when_locked: bool,
+
+    /// Shell command printing the focused application, such as
+    /// `xdotool getactivewindow getwindowclassname`. Sessions are tagged foreground or
+    /// background by whether their player had focus ("" to disable). It's killed if it
+    /// runs for more than two seconds.
+    #[serde(default)]
+    pub focus_command: String,
+
+    /// Keep each track's full MPRIS metadata as JSON so later features can be
+    /// backfilled from it. Off by default to keep the database small.
+    #[serde(default)]
+    pub store_raw_metadata: bool,
+
+    /// Keep the all-time artist totals stored by `gopal-cli refresh-aggregates` up to
+    /// date as sessions finish, so they stay fresh between refreshes
+    #[serde(default)]
+    pub artist_aggregates: bool,
+
+    /// Most tracks whose raw metadata is kept; the least recently played lose it during
+    /// cleanup (0 keeps all of it). Only applied while `store_raw_metadata` is on.
+    #[serde(default = "default_max_raw_metadata_rows")]
+    pub max_raw_metadata_rows: u64,
+
+    /// How often active sessions' progress is written to the database (in seconds)
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+
+    /// How long a playing player may disappear from D-Bus (e.g. while the app restarts)
+    /// and resume the same track without splitting the session (in seconds, 0 to disable)
+    #[serde(default = "default_reappear_grace_period")]
+    pub reappear_grace_period: u64,
+
+    /// File kept up to date with the daemon's pid and last successful poll, read by
+    /// `gopal-cli ping`
+    #[serde(default = "default_status_file")]
+    pub status_file: String,
+
+    /// Treat title changes by the same artist within this many seconds of the previous
+    /// change as the same track, for players that scroll text through the title
+    /// (0 to disable). Quickly skipping through one artist's tracks is merged too.
+    #[serde(default)]
+    pub min_metadata_change_interval: u64,
+
+    /// When a player starts the track it just finished within this many seconds, continue
+    /// the previous session instead of recording a new one, for players that glitch and
+    /// restart the song (0 to disable). The time in between counts as pause.
+    #[serde(default)]
+    pub merge_consecutive_same_track_gap: u64,
+
+    /// Complete a paused session once it has been paused this much longer than what was
+    /// left of its track, ending it when it was paused, for players that pause instead
+    /// of stopping at the end of the queue (in seconds, 0 to disable)
+    #[serde(default = "default_paused_past_end_margin")]
+    pub paused_past_end_margin: u64,
+
+    /// Pauses shorter than this add no pause time, for players that report Paused for
+    /// a moment while seeking (in seconds, 0 counts every pause)
+    #[serde(default)]
+    pub pause_debounce: u64,
+
+    /// While a player is set to repeat one track, record the loops as one session
+    /// instead of a new play each time the track restarts
+    #[serde(default = "default_merge_repeat_one")]
+    pub merge_repeat_one: bool,
+
+    /// Sessions lasting longer than this are ended at this length and marked `capped`,
+    /// as more likely a stuck player than listening. Raise it for long streams (in seconds).
+    #[serde(default = "default_max_reasonable_duration")]
+    pub max_reasonable_duration: u64,
+
+    /// Nothing is recorded while this file exists; `gopal-cli record --off` creates it.
+    /// Point it somewhere persistent to keep recording paused across reboots.
+    #[serde(default = "default_recording_pause_file")]
+    pub recording_pause_file: String,
+}
+
+fn default_max_raw_metadata_rows() -> u64 {
+    10_000
+}
+
+fn default_checkpoint_interval() -> u64 {
+    gopal::mpris_monitor::CHECKPOINT_INTERVAL.as_secs()
+}
+
+fn default_reappear_grace_period() -> u64 {
+    10
+}
+
+fn default_max_reasonable_duration() -> u64 {
+    gopal::session_tracker::MAX_REASONABLE_DURATION as u64
+}
+
+fn default_paused_past_end_margin() -> u64 {
+    60
+}
+
+fn default_merge_repeat_one() -> bool {
+    true
+}
+
+fn default_recording_pause_file() -> String {
+    gopal::paths::default_recording_pause_file()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| gopal::paths::RECORDING_PAUSE_FILE.to_string())
+}
+
+fn default_status_file() -> String {
+    gopal::paths::default_status_file()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| gopal::paths::STATUS_FILE.to_string())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,26 +219,245 @@ pub struct LoggingConfig {
     
     /// Whether to include timestamps in logs
     pub timestamps: bool,
+
+    /// Log line format: "text" for env_logger's default, or "json" for one object per line
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataConfig {
+    /// Split "Artist - Title" style titles when the player reports no artist
+    pub split_unknown_artist_titles: bool,
+
+    /// Regexes tried in order; each needs `artist` and `title` named groups
+    pub title_split_patterns: Vec<String>,
+
+    /// Store the first artist of multi-artist credits as the track's primary artist
+    #[serde(default)]
+    pub split_artists: bool,
+
+    /// Separators between artists in a credit, matched case-insensitively
+    #[serde(default = "default_artist_separators")]
+    pub artist_separators: Vec<String>,
+
+    /// Longest track length to believe from a player (in seconds); longer ones and
+    /// zero are stored as unknown
+    #[serde(default = "default_max_track_length")]
+    pub max_track_length: u64,
+
+    /// Ignore case when telling tracks apart, so "SONG" and "Song" count as one track.
+    /// Whitespace differences never split a track. Existing tracks are re-keyed to match
+    /// when the daemon starts.
+    #[serde(default)]
+    pub case_insensitive_track_ids: bool,
+}
+
+fn default_max_track_length() -> u64 {
+    MAX_TRACK_LENGTH.as_secs()
+}
+
+fn default_artist_separators() -> Vec<String> {
+    DEFAULT_ARTIST_SEPARATORS.iter().map(|s| s.to_string()).collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelsConfig {
+    /// Time-of-day ranges, used when no player rule matches
+    #[serde(default)]
+    pub times: Vec<TimeLabelConfig>,
+
+    /// Player identity substring (case-insensitive) -> label
+    #[serde(default)]
+    pub players: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeLabelConfig {
+    /// Local start time, `HH:MM`
+    pub start: String,
+    /// Local end time, `HH:MM`; earlier than `start` to wrap past midnight
+    pub end: String,
+    pub label: String,
+}
+
+impl LabelsConfig {
+    pub fn rules(&self) -> Result<SessionLabelRules> {
+        let mut rules = SessionLabelRules::default();
+        for (player, label) in &self.players {
+            rules = rules.with_player(player, label);
+        }
+        for range in &self.times {
+            rules = rules.with_time_range(&range.start, &range.end, &range.label)?;
+        }
+        Ok(rules)
+    }
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        MetadataConfig {
+            split_unknown_artist_titles: false,
+            title_split_patterns: DEFAULT_TITLE_SPLIT_PATTERNS.iter().map(|p| p.to_string()).collect(),
+            split_artists: false,
+            artist_separators: default_artist_separators(),
+            max_track_length: default_max_track_length(),
+            case_insensitive_track_ids: false,
+        }
+    }
+}
+
+impl MetadataConfig {
+    /// Compile the configured rules, or no rules if splitting is disabled
+    pub fn rules(&self) -> Result<MetadataRules> {
+        if self.split_unknown_artist_titles {
+            MetadataRules::new(&self.title_split_patterns)
+        } else {
+            Ok(MetadataRules::default())
+        }
+    }
+
+    /// The configured artist splitter, or none if splitting is disabled
+    pub fn artist_splitter(&self) -> Result<Option<ArtistSplitter>> {
+        if self.split_artists {
+            ArtistSplitter::new(&self.artist_separators).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrobbleConfig {
+    /// Program run with each scrobble as JSON on stdin; a non-zero exit is retried
+    /// later. Scrobbles are only queued while this is set ("" to disable).
+    #[serde(default)]
+    pub command: String,
+
+    /// How often the queue is checked for scrobbles due for submission (in seconds)
+    #[serde(default = "default_scrobble_retry_interval")]
+    pub retry_interval: u64,
+
+    /// Failed submissions before a scrobble is left in the queue as failed
+    #[serde(default = "default_scrobble_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_scrobble_retry_interval() -> u64 {
+    30
+}
+
+fn default_scrobble_max_attempts() -> u32 {
+    gopal::scrobble::DEFAULT_MAX_ATTEMPTS as u32
 }
 
+impl Default for ScrobbleConfig {
+    fn default() -> Self {
+        ScrobbleConfig {
+            command: String::new(),
+            retry_interval: default_scrobble_retry_interval(),
+            max_attempts: default_scrobble_max_attempts(),
+        }
+    }
+}
+
+/// Comment written above each key in `Config::commented_default`, as (section, key, comment)
+const FIELD_COMMENTS: &[(&str, &str, &str)] = &[
+    ("database", "path", "Path to the SQLite database file (overridden by --database)"),
+    ("database", "pool_size", "Database connection pool size (for future use)"),
+    ("database", "legacy_path", "Database from before the rename to gopal, migrated on startup if the database is missing or empty (\"\" to disable)"),
+    ("database", "legacy_migration", "How to migrate the legacy database: \"copy\" (keeps the old file), \"move\" or \"off\""),
+    ("monitoring", "player_discovery_interval", "How often to check for new players (in seconds)"),
+    ("monitoring", "session_timeout", "How long to wait before considering a session stale (in seconds)"),
+    ("monitoring", "cleanup_interval", "How often to run cleanup tasks (in seconds)"),
+    ("monitoring", "min_session_duration", "Drop sessions listened to for less than this, leaving them out of skip stats too (in seconds, 0 keeps every session)"),
+    ("monitoring", "record_all_sessions", "Keep shorter sessions for auditing, marked too_short and left out of stats"),
+    ("monitoring", "dedup_simultaneous", "Record only one session when several players play the same track at once"),
+    ("monitoring", "pause_when_locked", "Count time the desktop session is locked as pause time (needs logind)"),
+    ("monitoring", "focus_command", "Command printing the focused application, e.g. `xdotool getactivewindow getwindowclassname`, to tag sessions foreground or background (\"\" to disable)"),
+    ("monitoring", "store_raw_metadata", "Keep each track's full MPRIS metadata as JSON"),
+    ("monitoring", "artist_aggregates", "Keep the artist totals stored by `gopal-cli refresh-aggregates` up to date as sessions finish"),
+    ("monitoring", "max_raw_metadata_rows", "Most tracks whose raw metadata is kept, dropping it from the least recently played first (0 keeps all)"),
+    ("monitoring", "checkpoint_interval", "How often active sessions' progress is written to the database (in seconds)"),
+    ("monitoring", "reappear_grace_period", "How long a player may vanish from D-Bus and resume the same session (in seconds, 0 to disable)"),
+    ("monitoring", "min_metadata_change_interval", "Treat title changes by the same artist this soon after the previous change as the same track (in seconds, 0 to disable)"),
+    ("monitoring", "merge_consecutive_same_track_gap", "Continue the previous session when a player restarts the same track this soon after it ended (in seconds, 0 to disable)"),
+    ("monitoring", "paused_past_end_margin", "Complete a paused session once paused this much longer than what was left of its track (in seconds, 0 to disable)"),
+    ("monitoring", "pause_debounce", "Pauses shorter than this add no pause time, e.g. 3 for players that pause while seeking (in seconds, 0 counts every pause)"),
+    ("monitoring", "merge_repeat_one", "Record a track looping on repeat-one as one session instead of a play per loop"),
+    ("monitoring", "max_reasonable_duration", "End sessions longer than this at this length and mark them capped, e.g. raise it for 12-hour streams (in seconds)"),
+    ("monitoring", "status_file", "File updated with the daemon's pid and last poll time, checked by `gopal-cli ping`"),
+    ("monitoring", "recording_pause_file", "Nothing is recorded while this file exists, see `gopal-cli record`; somewhere persistent keeps recording paused across reboots"),
+    ("logging", "level", "Log level (error, warn, info, debug, trace)"),
+    ("logging", "file", "Log file path (logs to stderr if not set)"),
+    ("logging", "timestamps", "Whether to include timestamps in logs"),
+    ("logging", "format", "Log line format: \"text\", or \"json\" for one JSON object per line"),
+    ("metadata", "split_unknown_artist_titles", "Split \"Artist - Title\" style titles when the player reports no artist"),
+    ("metadata", "title_split_patterns", "Regexes tried in order; each needs `artist` and `title` named groups"),
+    ("metadata", "split_artists", "Store the first artist of multi-artist credits like \"A, B feat. C\" separately"),
+    ("metadata", "artist_separators", "Separators between artists in a credit, matched case-insensitively"),
+    ("metadata", "max_track_length", "Longest track length to believe from a player (in seconds); longer ones and zero are stored as unknown"),
+    ("metadata", "case_insensitive_track_ids", "Count tracks differing only in case, like \"SONG\" and \"Song\", as one track"),
+    ("labels", "times", "Label sessions by local start time, e.g. [{ start = \"06:00\", end = \"08:00\", label = \"gym\" }]"),
+    ("labels.players", "", "Label sessions by player, e.g. spotify = \"focus\"; wins over times"),
+    ("player_aliases", "", "Record players under another player's bus name, keyed by bus name or identity, e.g. \"org.mpris.MediaPlayer2.spotifyd\" = \"org.mpris.MediaPlayer2.spotify\"; players playing at once would end each other's sessions"),
+    ("scrobble", "command", "Program given each scrobble as JSON on stdin, exiting non-zero to have it retried later (\"\" to disable)"),
+    ("scrobble", "retry_interval", "How often queued scrobbles are checked and submitted (in seconds)"),
+    ("scrobble", "max_attempts", "Failed submissions before a scrobble is given up on; `gopal-cli scrobble-queue --retry-now` tries again"),
+];
+
 impl Default for Config {
     fn default() -> Self {
         Config {
             database: DatabaseConfig {
-                path: "~/.local/share/gopal/music.db".to_string(),
+                path: gopal::paths::default_database_path()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|_| gopal::DEFAULT_DB_PATH.to_string()),
                 pool_size: None,
+                legacy_path: default_legacy_path(),
+                legacy_migration: LegacyMigration::default(),
             },
             monitoring: MonitoringConfig {
                 player_discovery_interval: 5,
                 session_timeout: 300, // 5 minutes
                 cleanup_interval: 300, // 5 minutes
-                min_session_duration: 10, // 10 seconds
+                min_session_duration: 0,
+                record_all_sessions: false,
+                dedup_simultaneous: false,
+                pause_when_locked: false,
+                focus_command: String::new(),
+                store_raw_metadata: false,
+                artist_aggregates: false,
+                max_raw_metadata_rows: default_max_raw_metadata_rows(),
+                checkpoint_interval: default_checkpoint_interval(),
+                reappear_grace_period: default_reappear_grace_period(),
+                status_file: default_status_file(),
+                min_metadata_change_interval: 0,
+                merge_consecutive_same_track_gap: 0,
+                paused_past_end_margin: default_paused_past_end_margin(),
+                pause_debounce: 0,
+                merge_repeat_one: default_merge_repeat_one(),
+                max_reasonable_duration: default_max_reasonable_duration(),
+                recording_pause_file: default_recording_pause_file(),
             },
             logging: LoggingConfig {
                 level: "info".to_string(),
                 file: None,
                 timestamps: true,
+                format: LogFormat::Text,
             },
+            metadata: MetadataConfig::default(),
+            labels: LabelsConfig::default(),
+            scrobble: ScrobbleConfig::default(),
+            player_aliases: BTreeMap::new(),
         }
     }
 }
@@ -106,6 +496,66 @@ impl Config {
         }
     }
     
+    /// Reject settings the daemon can't run with
+    pub fn validate(&self) -> Result<()> {
+        let intervals = [
+            ("monitoring.player_discovery_interval", self.monitoring.player_discovery_interval),
+            ("monitoring.cleanup_interval", self.monitoring.cleanup_interval),
+            ("monitoring.checkpoint_interval", self.monitoring.checkpoint_interval),
+            ("scrobble.retry_interval", self.scrobble.retry_interval),
+            ("metadata.max_track_length", self.metadata.max_track_length),
+        ];
+        for (name, seconds) in intervals {
+            if seconds == 0 {
+                bail!("{} must be at least 1 second", name);
+            }
+        }
+
+        self.logging.level.parse::<log::LevelFilter>()
+            .map_err(|_| anyhow::anyhow!("Unknown logging.level '{}'", self.logging.level))?;
+
+        self.metadata.rules()
+            .context("Invalid [metadata] configuration")?;
+        self.metadata.artist_splitter()
+            .context("Invalid [metadata] configuration")?;
+        self.labels.rules()
+            .context("Invalid [labels] configuration")?;
+
+        Ok(())
+    }
+
+    /// The default configuration as TOML, with a comment above every key
+    pub fn commented_default() -> Result<String> {
+        let toml_content = toml::to_string_pretty(&Config::default())
+            .context("Failed to serialize default configuration")?;
+
+        let mut output = String::from("# gopald configuration\n\n");
+        let mut section = "";
+        for line in toml_content.lines() {
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = FIELD_COMMENTS
+                    .iter()
+                    .map(|(section, _, _)| *section)
+                    .find(|known| *known == name)
+                    .unwrap_or("");
+                if let Some((_, _, comment)) = FIELD_COMMENTS.iter().find(|(s, k, _)| *s == name && k.is_empty()) {
+                    output.push_str(&format!("# {}\n", comment));
+                }
+            } else if let Some((key, _)) = line.split_once(" = ").filter(|_| !line.starts_with(char::is_whitespace)) {
+                let comment = FIELD_COMMENTS
+                    .iter()
+                    .find(|(s, k, _)| *s == section && *k == key);
+                if let Some((_, _, comment)) = comment {
+                    output.push_str(&format!("# {}\n", comment));
+                }
+            }
+            output.push_str(line);
+            output.push('\n');
+        }
+
+        Ok(output)
+    }
+
     /// Save configuration to file
     #[allow(dead_code)]
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
@@ -138,6 +588,38 @@ mod tests {
         assert_eq!(config.logging.level, "info");
     }
 
+    #[test]
+    fn test_default_config_keeps_quick_skips() {
+        let monitoring = Config::default().monitoring;
+        let database = gopal::database::Database::new(":memory:").unwrap()
+            .with_min_session_duration(monitoring.min_session_duration as i64)
+            .with_record_all_sessions(monitoring.record_all_sessions);
+        let player = database.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = gopal::database::Track {
+            id: "Song::Artist::Album".to_string(),
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            length: Some(180_000_000),
+            art_url: None,
+            genre: None,
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        };
+        database.insert_or_update_track(&track).unwrap();
+        for (start, listened) in [(1000, 3), (2000, 180)] {
+            let session_id = database.start_session(&track.id, player, start).unwrap();
+            database.finalize_session(session_id, start + listened, "interrupted").unwrap();
+        }
+
+        let skips = database.get_skip_stats(None, None).unwrap();
+        assert_eq!((skips.total_sessions, skips.skipped_sessions), (2, 1));
+    }
+
     #[test]
     fn test_config_serialization() {
         let config = Config::default();
@@ -148,6 +630,85 @@ mod tests {
         assert_eq!(config.database.path, parsed_config.database.path);
     }
 
+    #[test]
+    fn test_metadata_rules_from_config() {
+        let mut config = Config::default();
+        assert!(config.metadata.rules().unwrap().is_empty());
+
+        config.metadata.split_unknown_artist_titles = true;
+        let rules = config.metadata.rules().unwrap();
+        assert_eq!(rules.split_title("Band - Song"), Some(("Band".to_string(), "Song".to_string())));
+
+        config.metadata.title_split_patterns = vec!["(unnamed)".to_string()];
+        assert!(config.metadata.rules().is_err());
+    }
+
+    #[test]
+    fn test_artist_splitter_from_config() {
+        let mut config = Config::default();
+        assert!(config.metadata.artist_splitter().unwrap().is_none());
+
+        config.metadata.split_artists = true;
+        let splitter = config.metadata.artist_splitter().unwrap().unwrap();
+        assert_eq!(splitter.split("A, B feat. C"), vec!["A", "B", "C"]);
+
+        config.metadata.artist_separators = vec![" / ".to_string()];
+        let splitter = config.metadata.artist_splitter().unwrap().unwrap();
+        assert_eq!(splitter.split("A / B, C"), vec!["A", "B, C"]);
+
+        config.metadata.artist_separators.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_without_metadata_section() {
+        let mut value: toml::Value = toml::from_str(&toml::to_string(&Config::default()).unwrap()).unwrap();
+        value.as_table_mut().unwrap().remove("metadata");
+
+        let config: Config = toml::from_str(&toml::to_string(&value).unwrap()).unwrap();
+        assert!(!config.metadata.split_unknown_artist_titles);
+    }
+
+    #[test]
+    fn test_commented_default_is_parseable() {
+        let content = Config::commented_default().unwrap();
+        let parsed: Config = toml::from_str(&content).unwrap();
+        assert_eq!(toml::to_string(&parsed).unwrap(), toml::to_string(&Config::default()).unwrap());
+
+        // Every key is documented, so new settings can't be generated without a comment
+        let lines: Vec<&str> = content.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            if line.contains(" = ") && !line.starts_with(['#', ' ']) {
+                assert!(lines[i - 1].starts_with("# "), "no comment above '{}'", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_label_rules_from_config() {
+        let labels: LabelsConfig = toml::from_str(
+            "times = [{ start = \"06:00\", end = \"08:00\", label = \"gym\" }]\n\n[players]\nspotify = \"focus\"\n",
+        ).unwrap();
+
+        let rules = labels.rules().unwrap();
+        let seven = chrono::NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+        assert_eq!(rules.label_for("Spotify", seven), Some("focus"));
+        assert_eq!(rules.label_for("VLC", seven), Some("gym"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let mut config = Config::default();
+        config.monitoring.checkpoint_interval = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("checkpoint_interval"));
+
+        let mut config = Config::default();
+        config.logging.level = "loud".to_string();
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_config_load_nonexistent() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -0,0 +1,104 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+use serde_json::{Map, Value as JsonValue};
+use std::io::Write;
+
+use crate::config::LogFormat;
+
+/// Set up the global logger. `json` writes one object per line with `timestamp` (when
+/// `timestamps` is set), `level`, `target`, `message` and any key-value fields the
+/// log call attached, such as the session id and track of session events.
+pub fn init(level: log::LevelFilter, format: LogFormat, timestamps: bool) {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(level);
+
+    if format == LogFormat::Json {
+        builder.format(move |buf, record| {
+            let timestamp = timestamps.then(Utc::now);
+            writeln!(buf, "{}", json_line(record, timestamp))
+        });
+    }
+
+    builder.init();
+}
+
+fn json_line(record: &Record, timestamp: Option<DateTime<Utc>>) -> JsonValue {
+    let mut line = Map::new();
+    if let Some(timestamp) = timestamp {
+        line.insert("timestamp".to_string(), timestamp.to_rfc3339_opts(SecondsFormat::Millis, true).into());
+    }
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    line.insert("message".to_string(), record.args().to_string().into());
+
+    let mut fields = FieldCollector(Map::new());
+    // Collecting into a map can't fail
+    let _ = record.key_values().visit(&mut fields);
+    if !fields.0.is_empty() {
+        line.insert("fields".to_string(), fields.0.into());
+    }
+
+    line.into()
+}
+
+struct FieldCollector(Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_f64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_line_has_expected_fields() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let fields: &[(&str, &dyn kv::ToValue)] = &[("session_id", &42), ("track", &"Song"), ("resumed", &false)];
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Session started"))
+                .level(log::Level::Info)
+                .target("gopal::mpris_monitor")
+                .key_values(&fields)
+                .build(),
+            Some(timestamp),
+        );
+
+        let parsed: JsonValue = serde_json::from_str(&line.to_string()).unwrap();
+        assert_eq!(parsed["timestamp"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["target"], "gopal::mpris_monitor");
+        assert_eq!(parsed["message"], "Session started");
+        assert_eq!(parsed["fields"]["session_id"], 42);
+        assert_eq!(parsed["fields"]["track"], "Song");
+        assert_eq!(parsed["fields"]["resumed"], false);
+    }
+
+    #[test]
+    fn test_json_line_without_timestamp_or_fields() {
+        let line = json_line(
+            &Record::builder().args(format_args!("Starting")).level(log::Level::Warn).build(),
+            None,
+        );
+        let object = line.as_object().unwrap();
+        assert!(!object.contains_key("timestamp"));
+        assert!(!object.contains_key("fields"));
+        assert_eq!(object["level"], "WARN");
+    }
+}
@@ -1,26 +1,39 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use log::{error, info};
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::signal;
 
 mod config;
-use config::Config;
+mod logging;
+use config::{Config, LegacyMigration, MonitoringConfig, ScrobbleConfig};
 
 // Import modules from the parent src directory
-use gopal::database::Database;
-use gopal::mpris_monitor::MprisMonitor;
+use gopal::database::{database_key, Database, DEFAULT_ORPHAN_MAX_AGE};
+use gopal::error::GopalError;
+use gopal::focus::CommandFocusSource;
+use gopal::mpris_monitor::{MonitorOptions, MprisMonitor};
+use gopal::paths::{default_config_file, default_database_path, expand_path, migrate_legacy_database, needs_legacy_migration};
+use gopal::screen_lock::LogindLockSource;
+use gopal::scrobble::{flush_scrobble_queue, CommandSubmitter};
+use gopal::store::{DryRunStore, Store};
 
 #[derive(Parser)]
 #[command(name = "gopald")]
 #[command(about = "Music listening tracker daemon")]
 #[command(version = "0.1.0")]
 struct Args {
-    /// Path to the SQLite database file
-    #[arg(short, long, default_value = "~/.local/share/gopal/music.db")]
-    database: String,
+    /// Path to the SQLite database file [default: $XDG_DATA_HOME/gopal/music.db]
+    #[arg(short, long)]
+    database: Option<String>,
+
+    /// Key of a database encrypted with SQLCipher [default: $GOPAL_DB_KEY]. Needs a
+    /// build with the `encryption` feature.
+    #[arg(long, value_name = "KEY")]
+    key: Option<String>,
 
-    /// Configuration file path
+    /// Configuration file path [default: $XDG_CONFIG_HOME/gopal/config.toml, if it exists]
     #[arg(short, long)]
     config: Option<PathBuf>,
 
@@ -31,60 +44,184 @@ struct Args {
     /// Run in foreground (don't daemonize)
     #[arg(short, long)]
     foreground: bool,
+
+    /// Validate the configuration, print the settings the daemon would use and exit
+    #[arg(long)]
+    check_config: bool,
+
+    /// Watch players and log the sessions that would be recorded, without touching the database
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Exit if the session D-Bus isn't reachable at startup, instead of waiting for it
+    #[arg(long)]
+    require_dbus: bool,
+
+    #[command(subcommand)]
+    command: Option<DaemonCommand>,
+}
+
+#[derive(Subcommand)]
+enum DaemonCommand {
+    /// Write a commented default configuration file and exit
+    GenerateConfig {
+        /// Where to write it [default: $XDG_CONFIG_HOME/gopal/config.toml]
+        path: Option<PathBuf>,
+
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Initialize logging
     let log_level = if args.verbose {
         log::LevelFilter::Debug
     } else {
         log::LevelFilter::Info
     };
 
-    env_logger::Builder::from_default_env()
-        .filter_level(log_level)
-        .init();
+    if let Some(DaemonCommand::GenerateConfig { path, force }) = &args.command {
+        return generate_config(path.clone(), *force);
+    }
+
+    // Load configuration, picking up the default config file only if the user created one
+    let config_path = args.config.clone()
+        .or_else(|| default_config_file().ok().filter(|path| path.exists()));
+    let config = Config::load(config_path.as_deref())?;
+    config.validate()?;
 
-    info!("Starting gopald v{}", env!("CARGO_PKG_VERSION"));
+    // Initialize logging once the configuration says which format to use
+    logging::init(log_level, config.logging.format, config.logging.timestamps);
 
-    // Load configuration
-    let _config = Config::load(args.config.as_deref())?;
+    if !args.check_config {
+        info!("Starting gopald v{}", env!("CARGO_PKG_VERSION"));
+    }
     
     // Resolve database path (handle ~ expansion)
-    let db_path = expand_path(&args.database)?;
-    
-    // Ensure database directory exists
-    if let Some(parent) = db_path.parent() {
-        std::fs::create_dir_all(parent)
-            .context("Failed to create database directory")?;
+    let db_path = match &args.database {
+        Some(path) => expand_path(path)?,
+        None => default_database_path()?,
+    };
+
+    if args.check_config {
+        return print_effective_config(config, config_path.as_deref(), &db_path, log_level);
     }
 
-    // Initialize database
-    let database = Database::new(&db_path)
-        .context("Failed to initialize database")?;
+    let status_file = expand_path(&config.monitoring.status_file)?;
+
+    // Initialize MPRIS monitor
+    let monitor_options = MonitorOptions {
+        dedup_simultaneous: config.monitoring.dedup_simultaneous,
+        metadata_rules: config.metadata.rules()
+            .context("Invalid [metadata] configuration")?,
+        artist_splitter: config.metadata.artist_splitter()
+            .context("Invalid [metadata] configuration")?,
+        store_raw_metadata: config.monitoring.store_raw_metadata,
+        max_raw_metadata_rows: config.monitoring.max_raw_metadata_rows as usize,
+        checkpoint_interval: Some(Duration::from_secs(config.monitoring.checkpoint_interval)),
+        reappear_grace_period: config.monitoring.reappear_grace_period as i64,
+        session_labels: config.labels.rules()
+            .context("Invalid [labels] configuration")?,
+        // A dry run must not make `gopal-cli ping` report it as the real daemon
+        status_file: (!args.dry_run).then(|| status_file.clone()),
+        min_metadata_change_interval: config.monitoring.min_metadata_change_interval as i64,
+        merge_consecutive_same_track_gap: config.monitoring.merge_consecutive_same_track_gap as i64,
+        paused_past_end_margin: config.monitoring.paused_past_end_margin as i64,
+        pause_debounce: config.monitoring.pause_debounce as i64,
+        max_track_length: Some(Duration::from_secs(config.metadata.max_track_length)),
+        merge_repeat_one: config.monitoring.merge_repeat_one,
+        max_reasonable_duration: Some(Duration::from_secs(config.monitoring.max_reasonable_duration)),
+        case_insensitive_track_ids: config.metadata.case_insensitive_track_ids,
+        recording_pause_file: Some(expand_path(&config.monitoring.recording_pause_file)?),
+    };
+
+    if args.dry_run {
+        info!("Dry run: sessions are logged, nothing is written to {}", db_path.display());
+        let monitor = new_monitor(DryRunStore::new(), args.require_dbus)?
+            .with_options(monitor_options);
+        run_monitor(monitor, &config.monitoring).await;
+        info!("Music daemon stopped");
+        return Ok(());
+    }
+
+    migrate_legacy_data(&config, &db_path)?;
+
+    // Initialize database, waiting out read-only or full filesystems instead of crash-looping
+    let scrobbling = !config.scrobble.command.is_empty();
+    let db_key = database_key(args.key.clone());
+    let database = open_database(&db_path, db_key.as_deref()).await?
+        .with_scrobble_queue(scrobbling)
+        .with_artist_aggregates(config.monitoring.artist_aggregates)
+        .with_min_session_duration(config.monitoring.min_session_duration as i64)
+        .with_record_all_sessions(config.monitoring.record_all_sessions)
+        .with_player_aliases(config.player_aliases.clone())
+        .with_case_insensitive_track_ids(config.metadata.case_insensitive_track_ids);
 
     info!("Database initialized at: {}", db_path.display());
 
+    if scrobbling {
+        spawn_scrobble_retries(config.scrobble.clone(), db_path.clone(), db_key);
+    }
+
     // Clean up orphaned sessions from previous runs
     let current_time = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs() as i64;
     
-    let max_session_duration = 24 * 3600; // 24 hours - generous limit for long listening sessions
-    let orphaned_count = database.cleanup_orphaned_sessions(current_time, max_session_duration)
-        .context("Failed to cleanup orphaned sessions")?;
+    let orphaned_count = database.cleanup_orphaned_sessions(current_time, DEFAULT_ORPHAN_MAX_AGE)
+        .context("Failed to cleanup orphaned sessions")?
+        .len();
     
     if orphaned_count > 0 {
         info!("Cleaned up {} orphaned sessions from previous runs", orphaned_count);
     }
 
-    // Initialize MPRIS monitor
-    let mut monitor = MprisMonitor::new(database)
-        .context("Failed to initialize MPRIS monitor")?;
+    // Tracks recorded under other id rules, such as before case_insensitive_track_ids changed
+    let rekeyed = database.rekey_tracks().context("Failed to re-key tracks")?;
+    if rekeyed > 0 {
+        info!("Re-keyed {} tracks to the current track id rules", rekeyed);
+    }
+
+    let monitor = new_monitor(database, args.require_dbus)?
+        .with_options(monitor_options);
+    run_monitor(monitor, &config.monitoring).await;
+
+    // A missing status file tells `gopal-cli ping` the daemon stopped rather than hung
+    if let Err(e) = std::fs::remove_file(&status_file) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove status file {}: {}", status_file.display(), e);
+        }
+    }
+
+    info!("Music daemon stopped");
+    Ok(())
+}
+
+/// A monitor on the session bus, which waits for the bus to appear unless `require_dbus`
+fn new_monitor<S: Store>(store: S, require_dbus: bool) -> Result<MprisMonitor<S>> {
+    if require_dbus {
+        MprisMonitor::connect(store).context("Failed to initialize MPRIS monitor")
+    } else {
+        Ok(MprisMonitor::new(store))
+    }
+}
+
+/// Monitor players until a shutdown signal arrives or monitoring fails
+async fn run_monitor<S: Store>(mut monitor: MprisMonitor<S>, monitoring: &MonitoringConfig) {
+    if monitoring.pause_when_locked {
+        match LogindLockSource::new() {
+            Ok(lock_source) => monitor = monitor.with_lock_source(Box::new(lock_source)),
+            Err(e) => warn!("Screen lock detection unavailable, locked time will be counted: {:#}", e),
+        }
+    }
+    if !monitoring.focus_command.is_empty() {
+        monitor = monitor.with_focus_source(Box::new(CommandFocusSource::new(monitoring.focus_command.clone())));
+    }
 
     // Set up graceful shutdown
     let shutdown_signal = setup_shutdown_handler();
@@ -102,11 +239,125 @@ async fn main() -> Result<()> {
             }
         }
     }
+}
 
-    info!("Music daemon stopped");
+fn generate_config(path: Option<PathBuf>, force: bool) -> Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => default_config_file()?,
+    };
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists; pass --force to replace it", path.display());
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create config directory {}", parent.display()))?;
+    }
+    std::fs::write(&path, Config::commented_default()?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    println!("Wrote default configuration to {}", path.display());
+    Ok(())
+}
+
+/// Print the configuration with command-line overrides applied, as the daemon would run it
+fn print_effective_config(mut config: Config, config_path: Option<&Path>, db_path: &Path, log_level: log::LevelFilter) -> Result<()> {
+    config.database.path = db_path.display().to_string();
+    config.logging.level = log_level.to_string().to_lowercase();
+
+    match config_path {
+        Some(path) => println!("# Configuration loaded from {}", path.display()),
+        None => println!("# No configuration file, using defaults"),
+    }
+    print!("{}", toml::to_string_pretty(&config).context("Failed to serialize configuration")?);
     Ok(())
 }
 
+/// Bring over the database from before the rename to gopal, once, if ours has no sessions
+fn migrate_legacy_data(config: &Config, db_path: &Path) -> Result<()> {
+    let mode = config.database.legacy_migration;
+    if mode == LegacyMigration::Off || config.database.legacy_path.is_empty() {
+        return Ok(());
+    }
+
+    let legacy_path = expand_path(&config.database.legacy_path)?;
+    if !needs_legacy_migration(&legacy_path, db_path) {
+        return Ok(());
+    }
+
+    let remove_legacy = mode == LegacyMigration::Move;
+    info!(
+        "Found a database from an older release at {}; {} it to {}",
+        legacy_path.display(),
+        if remove_legacy { "moving" } else { "copying" },
+        db_path.display()
+    );
+    migrate_legacy_database(&legacy_path, db_path, remove_legacy)
+        .context("Failed to migrate the legacy database; set database.legacy_migration = \"off\" to skip it")?;
+
+    if remove_legacy {
+        info!("Legacy database migrated and removed from {}", legacy_path.display());
+    } else {
+        info!("Legacy database migrated; {} can be deleted once you've checked the new one", legacy_path.display());
+    }
+    Ok(())
+}
+
+/// Submit queued scrobbles every `retry_interval` on a thread of its own, with its own
+/// connection, so a slow scrobble command never holds up player polling
+fn spawn_scrobble_retries(config: ScrobbleConfig, db_path: PathBuf, db_key: Option<String>) {
+    std::thread::spawn(move || {
+        let database = match Database::new_with_key(&db_path, db_key.as_deref()) {
+            Ok(database) => database,
+            Err(e) => {
+                error!("Scrobbling disabled, failed to open the database: {:#}", e);
+                return;
+            }
+        };
+        let mut submitter = CommandSubmitter::new(config.command);
+        info!("Submitting scrobbles every {} seconds", config.retry_interval);
+
+        loop {
+            let current_time = chrono::Local::now().timestamp();
+            match flush_scrobble_queue(&database, &mut submitter, current_time, i64::from(config.max_attempts)) {
+                Ok(summary) if summary.failed > 0 => {
+                    warn!("Submitted {} scrobbles, {} failed and will be retried", summary.submitted, summary.failed - summary.gave_up);
+                }
+                Ok(_) => {}
+                Err(e) => error!("Failed to flush the scrobble queue: {:#}", e),
+            }
+            std::thread::sleep(Duration::from_secs(config.retry_interval));
+        }
+    });
+}
+
+/// How long to wait before retrying a database that couldn't be opened for writing
+const DATABASE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Open the database, waiting out filesystem problems. A wrong key won't fix itself, so
+/// that fails straight away.
+async fn open_database(db_path: &Path, key: Option<&str>) -> Result<Database> {
+    loop {
+        let result = match db_path.parent() {
+            Some(parent) => std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create database directory {}", parent.display()))
+                .and_then(|_| Ok(Database::open_writable(db_path, key)?)),
+            None => Database::open_writable(db_path, key).map_err(anyhow::Error::from),
+        };
+
+        match result {
+            Ok(database) => return Ok(database),
+            Err(e) if matches!(e.downcast_ref::<GopalError>(), Some(GopalError::Key(_))) => return Err(e),
+            Err(e) => {
+                error!("{:#}", e);
+                error!("Retrying in {} seconds...", DATABASE_RETRY_INTERVAL.as_secs());
+                tokio::time::sleep(DATABASE_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
 async fn setup_shutdown_handler() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -130,36 +381,3 @@ async fn setup_shutdown_handler() {
         _ = terminate => {},
     }
 }
-
-fn expand_path(path: &str) -> Result<PathBuf> {
-    if path.starts_with('~') {
-        let home = std::env::var("HOME")
-            .context("HOME environment variable not set")?;
-        Ok(PathBuf::from(path.replacen('~', &home, 1)))
-    } else {
-        Ok(PathBuf::from(path))
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_expand_path() {
-        // Test regular path
-        let path = expand_path("/tmp/test.db").unwrap();
-        assert_eq!(path, PathBuf::from("/tmp/test.db"));
-
-        // Test relative path
-        let path = expand_path("./test.db").unwrap();
-        assert_eq!(path, PathBuf::from("./test.db"));
-    }
-
-    #[test]
-    fn test_expand_home_path() {
-        std::env::set_var("HOME", "/home/testuser");
-        let path = expand_path("~/.local/share/test.db").unwrap();
-        assert_eq!(path, PathBuf::from("/home/testuser/.local/share/test.db"));
-    }
-}
\ No newline at end of file
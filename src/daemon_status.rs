@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::recent_activity::HourlyActivity;
+
+/// How often the daemon rewrites its status file
+pub const STATUS_WRITE_INTERVAL_SECS: u64 = 10;
+
+/// A daemon whose last successful poll is older than this is reported as stale
+pub const DEFAULT_STALE_AFTER_SECS: i64 = 60;
+
+/// What gopald writes to its status file, so `gopal-cli ping` and health checks can
+/// tell a running daemon from a hung or dead one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: i64,
+    /// When players were last polled without error
+    pub last_poll: Option<i64>,
+    /// When the file was last written
+    pub updated_at: i64,
+    /// Seconds listened in each of the last hours, oldest first, counting sessions
+    /// that finished while this daemon ran
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recent_activity: Vec<HourlyActivity>,
+}
+
+/// How a status file looks at a given moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DaemonHealth {
+    /// Polled within the staleness limit
+    Alive { last_poll_age: i64 },
+    /// Started recently and hasn't finished a poll yet
+    Starting { started_age: i64 },
+    /// No successful poll (or start, if it never polled) within the staleness limit
+    Stale { last_poll_age: Option<i64>, started_age: i64 },
+}
+
+impl DaemonHealth {
+    pub fn is_healthy(&self) -> bool {
+        !matches!(self, DaemonHealth::Stale { .. })
+    }
+}
+
+impl DaemonStatus {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read daemon status file {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid daemon status file {}", path.display()))
+    }
+
+    /// Replace the file atomically so readers never see a half-written status
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create status directory {}", parent.display()))?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    /// Judge the daemon's health at `now`, allowing up to `stale_after` seconds since the last
+    /// successful poll. A daemon that has never polled gets the same allowance from its start.
+    pub fn health(&self, now: i64, stale_after: i64) -> DaemonHealth {
+        let started_age = (now - self.started_at).max(0);
+        match self.last_poll {
+            Some(last_poll) => {
+                let last_poll_age = (now - last_poll).max(0);
+                if last_poll_age > stale_after {
+                    DaemonHealth::Stale { last_poll_age: Some(last_poll_age), started_age }
+                } else {
+                    DaemonHealth::Alive { last_poll_age }
+                }
+            }
+            None if started_age > stale_after => DaemonHealth::Stale { last_poll_age: None, started_age },
+            None => DaemonHealth::Starting { started_age },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(last_poll: Option<i64>) -> DaemonStatus {
+        DaemonStatus { pid: 42, started_at: 1000, last_poll, updated_at: 1000, recent_activity: Vec::new() }
+    }
+
+    #[test]
+    fn test_recent_poll_is_alive() {
+        assert_eq!(status(Some(1100)).health(1130, 60), DaemonHealth::Alive { last_poll_age: 30 });
+        assert_eq!(status(Some(1100)).health(1160, 60), DaemonHealth::Alive { last_poll_age: 60 });
+    }
+
+    #[test]
+    fn test_old_poll_is_stale() {
+        let health = status(Some(1100)).health(1161, 60);
+        assert_eq!(health, DaemonHealth::Stale { last_poll_age: Some(61), started_age: 161 });
+        assert!(!health.is_healthy());
+    }
+
+    #[test]
+    fn test_never_polled_is_starting_then_stale() {
+        assert_eq!(status(None).health(1010, 60), DaemonHealth::Starting { started_age: 10 });
+        assert!(status(None).health(1010, 60).is_healthy());
+        assert_eq!(status(None).health(1100, 60), DaemonHealth::Stale { last_poll_age: None, started_age: 100 });
+    }
+
+    #[test]
+    fn test_status_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/gopald.status");
+        status(Some(1100)).write(&path).unwrap();
+        assert_eq!(DaemonStatus::read(&path).unwrap(), status(Some(1100)));
+    }
+}
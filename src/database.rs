@@ -1,7 +1,19 @@
-use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use crate::error::OptionContext;
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Weekday};
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{GopalError, Result};
+use crate::content_type::ContentType;
+use crate::export::{AnonymizedExport, AnonymizedTrack, ExportData, ExportedSession, EXPORT_FORMAT_VERSION};
+use crate::focus::SessionFocus;
+use crate::import::{self, ExternalListen, ImportSummary};
+use crate::metadata_rules::{self, ArtistSplitter};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
@@ -18,6 +30,47 @@ pub struct Track {
     pub album: String,
     pub length: Option<i64>, // in microseconds
     pub art_url: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// The player's full MPRIS metadata map as JSON, kept only when enabled
+    #[serde(default)]
+    pub raw_metadata: Option<String>,
+    #[serde(default)]
+    pub track_number: Option<i32>,
+    #[serde(default)]
+    pub disc_number: Option<i32>,
+    /// First artist of a multi-artist credit, set when the daemon splits artists.
+    /// `artist` keeps the full credit.
+    #[serde(default)]
+    pub primary_artist: Option<String>,
+    /// `xesam:userRating` as last reported, from 0.0 to 1.0. Each session keeps the
+    /// rating reported when it started.
+    #[serde(default)]
+    pub rating: Option<f64>,
+    /// `xesam:comment`, one line per comment
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl Track {
+    /// Metadata fields the player never reported for this track, from "artist",
+    /// "album", "length" and "art"
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.artist == MISSING_METADATA {
+            missing.push("artist");
+        }
+        if self.album == MISSING_METADATA {
+            missing.push("album");
+        }
+        if self.length.is_none_or(|length| length <= 0) {
+            missing.push("length");
+        }
+        if self.art_url.as_deref().is_none_or(str::is_empty) {
+            missing.push("art");
+        }
+        missing
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,14 +83,177 @@ pub struct Session {
     pub paused_time: i64,
     pub listened_time: Option<i64>,
     pub status: String,
+    /// Free-form tag such as "gym" or "focus"
+    #[serde(default)]
+    pub label: Option<String>,
+    /// When the pause an active session is in began, as of its last checkpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_since: Option<i64>,
+    /// None for sessions recorded before classification, which count as music. Not
+    /// serialized; exports carry it in `ExportedSession`.
+    #[serde(skip)]
+    pub content_type: Option<ContentType>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListeningStats {
     pub total_listening_time: i64,
+    /// The part of `total_listening_time` from sessions still in progress
+    #[serde(default)]
+    pub active_listening_time: i64,
     pub top_tracks: Vec<TrackStats>,
     pub top_artists: Vec<ArtistStats>,
     pub listening_history: Vec<SessionWithMetadata>,
+    /// Listening time split by content type, largest first
+    #[serde(default)]
+    pub content_type_totals: Vec<ContentTypeTotal>,
+    /// Distinct local days with any listening in the period that the filters count
+    #[serde(default)]
+    pub active_days: i64,
+}
+
+impl ListeningStats {
+    /// Record the period's active days and each top track's and artist's plays per active day
+    fn with_active_days(mut self, active_days: i64) -> Self {
+        self.active_days = active_days;
+        let per_day = |plays: i64| (active_days > 0).then(|| plays as f64 / active_days as f64);
+        for track in &mut self.top_tracks {
+            track.plays_per_active_day = per_day(track.play_count);
+        }
+        for artist in &mut self.top_artists {
+            artist.plays_per_active_day = per_day(artist.play_count);
+        }
+        self
+    }
+
+    /// Copy for sharing: drops the per-session history and raw player metadata,
+    /// keeping only aggregate times and counts
+    pub fn anonymized(mut self) -> Self {
+        self.listening_history.clear();
+        for track_stat in &mut self.top_tracks {
+            track_stat.track.raw_metadata = None;
+        }
+        self
+    }
+}
+
+/// Everything `gopal-cli stats` shows, gathered from a single scan of the period's sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dashboard {
+    #[serde(flatten)]
+    pub stats: ListeningStats,
+    /// Keyed and labelled like `GroupBy::Album`
+    pub top_albums: Vec<GroupedStats>,
+    /// Sessions in the period, including active ones
+    pub plays: i64,
+    /// Plays that count as scrobbles, see `SCROBBLE_MIN_SECONDS`
+    pub scrobbles: i64,
+    /// The period's longest single listen, see `Database::get_longest_session`
+    #[serde(default)]
+    pub longest_session: Option<SessionWithMetadata>,
+}
+
+impl Dashboard {
+    pub fn anonymized(mut self) -> Self {
+        self.stats = self.stats.anonymized();
+        self.longest_session = None;
+        self
+    }
+}
+
+/// How many entries each of the dashboard's lists keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DashboardLimits {
+    /// Top tracks, artists and albums
+    pub top: usize,
+    pub history: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentTypeTotal {
+    pub content_type: ContentType,
+    pub total_listening_time: i64,
+}
+
+/// Restrictions applied on top of the time range in `get_filtered_listening_stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsFilter {
+    /// Only count sessions of this content type
+    pub content_type: Option<ContentType>,
+    /// Leave out sessions by these artists
+    pub exclude_artists: Vec<String>,
+    /// Leave out sessions of tracks with these titles
+    pub exclude_tracks: Vec<String>,
+    /// Only count sessions with this label
+    pub label: Option<String>,
+    /// Leave out sessions on these players, by bus name or identity
+    pub exclude_players: Vec<String>,
+    /// Only count tracks whose title contains this, ignoring case
+    pub title_contains: Option<String>,
+    /// Only count tracks whose artist contains this, ignoring case
+    pub artist_contains: Option<String>,
+}
+
+impl StatsFilter {
+    /// `AND ...` clause for a query over `sessions s`. User-supplied values are read from
+    /// `temp.stats_filter_values`, which `load_stats_filter_values` fills with bound values.
+    fn sql(&self) -> String {
+        let mut sql = String::new();
+        if let Some(content_type) = self.content_type {
+            // Sessions recorded before classification existed count as music
+            sql.push_str(&format!("AND COALESCE(s.content_type, 'music') = '{}' ", content_type.as_str()));
+        }
+        if !self.exclude_artists.is_empty() || !self.exclude_tracks.is_empty() {
+            sql.push_str(
+                "AND s.track_id NOT IN (
+                     SELECT id FROM tracks
+                     WHERE artist IN (SELECT value FROM temp.stats_filter_values WHERE kind = 'artist')
+                        OR title IN (SELECT value FROM temp.stats_filter_values WHERE kind = 'track')
+                 ) ",
+            );
+        }
+        if self.label.is_some() {
+            sql.push_str("AND s.label IN (SELECT value FROM temp.stats_filter_values WHERE kind = 'label') ");
+        }
+        if !self.exclude_players.is_empty() {
+            sql.push_str("AND s.player_id NOT IN (SELECT CAST(value AS INTEGER) FROM temp.stats_filter_values WHERE kind = 'player') ");
+        }
+        // LIKE ignores ASCII case; the values are stored as patterns, see `like_pattern`
+        for (column, kind, value) in [("title", "title_contains", &self.title_contains), ("artist", "artist_contains", &self.artist_contains)] {
+            if value.is_some() {
+                sql.push_str(&format!(
+                    "AND s.track_id IN (
+                         SELECT id FROM tracks
+                         WHERE {} LIKE (SELECT value FROM temp.stats_filter_values WHERE kind = '{}') ESCAPE '\\'
+                     ) ",
+                    column, kind
+                ));
+            }
+        }
+        sql
+    }
+
+    fn has_bound_values(&self) -> bool {
+        !self.exclude_artists.is_empty()
+            || !self.exclude_tracks.is_empty()
+            || self.label.is_some()
+            || !self.exclude_players.is_empty()
+            || self.title_contains.is_some()
+            || self.artist_contains.is_some()
+    }
+}
+
+/// LIKE pattern matching text containing `substring`, with its wildcards escaped by `\`
+fn like_pattern(substring: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in substring.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +261,68 @@ pub struct TrackStats {
     pub track: Track,
     pub total_listened_time: i64,
     pub play_count: i64,
+    /// `play_count` over the period's `ListeningStats::active_days`, where computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plays_per_active_day: Option<f64>,
+}
+
+/// When one of an artist's tracks was first heard, from `get_artist_catalog`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackDiscovery {
+    pub track: Track,
+    pub first_played: i64,
+    pub play_count: i64,
+    pub total_listened_time: i64,
+}
+
+/// An artist's listening and how their catalog was explored, from `get_artist_catalog`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistCatalog {
+    pub artist: String,
+    pub distinct_tracks: i64,
+    pub play_count: i64,
+    pub total_listened_time: i64,
+    /// Every track heard, in the order they were first played
+    pub discoveries: Vec<TrackDiscovery>,
+}
+
+/// One play in a day's `get_day_timeline`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineEntry {
+    pub play: SessionWithMetadata,
+    /// When the play ended, or where an active one has got to
+    pub end_time: i64,
+    /// Seconds since the previous play ended, `None` for the day's first play and 0
+    /// when plays overlap
+    pub gap_before: Option<i64>,
+}
+
+/// How much of an album was heard, from `get_album_completeness`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlbumCompleteness {
+    pub album: String,
+    pub artist: String,
+    /// Distinct tracks with a scrobbled play in the range
+    pub heard_tracks: i64,
+    /// Tracks on the album, from the highest track number seen on each disc, or `None`
+    /// if its tracks carry no numbers
+    pub total_tracks: Option<i64>,
+    /// `heard_tracks` as a percentage of `total_tracks`
+    pub percent: Option<f64>,
+}
+
+/// A track's player-reported rating, from `get_top_rated`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatedTrack {
+    pub track: Track,
+    pub play_count: i64,
+    /// Plays whose player reported a rating
+    pub rated_plays: i64,
+    /// Mean of the ratings reported for its plays, from 0.0 to 1.0
+    pub average_rating: f64,
+    /// `average_rating` pulled toward the mean of all rated plays, less so the more
+    /// the track was played, see `RATING_PRIOR_PLAYS`
+    pub weighted_rating: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +330,12 @@ pub struct ArtistStats {
     pub artist: String,
     pub total_listened_time: i64,
     pub track_count: i64,
+    /// Plays of the artist's tracks, after replays are deduped per track
+    #[serde(default)]
+    pub play_count: i64,
+    /// `play_count` over the period's `ListeningStats::active_days`, where computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plays_per_active_day: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,418 +345,5930 @@ pub struct SessionWithMetadata {
     pub player: Player,
 }
 
-pub struct Database {
-    conn: Connection,
+/// History entry without session ids, players or timestamps, for sharing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnonymousPlay {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub listened_time: i64,
 }
 
-impl Database {
-    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
-        let conn = Connection::open(db_path)
-            .context("Failed to open database connection")?;
-        
-        let db = Database { conn };
-        db.initialize_schema()?;
-        Ok(db)
+impl From<&SessionWithMetadata> for AnonymousPlay {
+    fn from(session: &SessionWithMetadata) -> Self {
+        AnonymousPlay {
+            title: session.track.title.clone(),
+            artist: session.track.artist.clone(),
+            album: session.track.album.clone(),
+            listened_time: session.session.listened_time.unwrap_or(0),
+        }
     }
+}
 
-    fn initialize_schema(&self) -> Result<()> {
-        // Create players table
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS players (
-                id INTEGER PRIMARY KEY,
-                name TEXT UNIQUE NOT NULL,
-                identity TEXT NOT NULL
-            )",
-            [],
-        ).context("Failed to create players table")?;
-
-        // Create tracks table
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS tracks (
-                id TEXT PRIMARY KEY,
-                title TEXT NOT NULL,
-                artist TEXT NOT NULL,
-                album TEXT NOT NULL,
-                length INTEGER,
-                art_url TEXT
-            )",
-            [],
-        ).context("Failed to create tracks table")?;
+/// Dimension to aggregate listening time by in `get_grouped_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Track,
+    Artist,
+    Album,
+    Genre,
+    Player,
+    /// Web player's source domain, or the player for sessions without one
+    Source,
+    /// Whether the player had focus, see `focus::SessionFocus`
+    Focus,
+}
 
-        // Create sessions table
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id INTEGER PRIMARY KEY,
-                track_id TEXT NOT NULL,
-                player_id INTEGER NOT NULL,
-                start_time INTEGER NOT NULL,
-                end_time INTEGER,
-                paused_time INTEGER NOT NULL DEFAULT 0,
-                listened_time INTEGER,
-                status TEXT NOT NULL DEFAULT 'active',
-                FOREIGN KEY (track_id) REFERENCES tracks (id),
-                FOREIGN KEY (player_id) REFERENCES players (id)
-            )",
-            [],
-        ).context("Failed to create sessions table")?;
+impl GroupBy {
+    /// SQL expression identifying a group. Only ever built from this enum, never user text.
+    fn key_sql(&self) -> &'static str {
+        match self {
+            GroupBy::Track => "t.id",
+            GroupBy::Artist => "t.artist",
+            GroupBy::Album => "t.album || '::' || t.artist",
+            GroupBy::Genre => "COALESCE(t.genre, 'Unknown')",
+            GroupBy::Player => "p.identity",
+            GroupBy::Source => "COALESCE(s.source_domain, p.identity)",
+            GroupBy::Focus => "COALESCE(s.focus, 'unknown')",
+        }
+    }
 
-        // Create indexes for better query performance
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_sessions_start_time ON sessions (start_time)",
-            [],
-        )?;
+    /// SQL expression for a human-readable group label
+    fn label_sql(&self) -> String {
+        let title = metadata_label_sql("t.title");
+        let artist = metadata_label_sql("t.artist");
+        let album = metadata_label_sql("t.album");
+        match self {
+            GroupBy::Track => format!("{} || ' - ' || {}", title, artist),
+            GroupBy::Artist => artist,
+            GroupBy::Album => format!("{} || ' - ' || {}", album, artist),
+            GroupBy::Genre => "COALESCE(t.genre, 'Unknown')".to_string(),
+            GroupBy::Player => "p.identity".to_string(),
+            GroupBy::Source => "COALESCE(s.source_domain, p.identity)".to_string(),
+            GroupBy::Focus => "COALESCE(s.focus, 'unknown')".to_string(),
+        }
+    }
+}
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_sessions_track_id ON sessions (track_id)",
-            [],
-        )?;
+/// Stored in place of a title, artist or album the player didn't report. Empty rather
+/// than a word like "Unknown", so it can't be mistaken for an artist of that name.
+pub const MISSING_METADATA: &str = "";
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_sessions_player_id ON sessions (player_id)",
-            [],
-        )?;
+/// How a missing title, artist or album is shown
+pub const MISSING_METADATA_LABEL: &str = "(unknown)";
 
-        Ok(())
+/// `value` for display, with missing metadata spelled out
+pub fn metadata_label(value: &str) -> &str {
+    if value == MISSING_METADATA {
+        MISSING_METADATA_LABEL
+    } else {
+        value
     }
+}
 
-    pub fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64> {
-        // Try to insert, if it fails due to unique constraint, update and get the ID
-        match self.conn.execute(
-            "INSERT INTO players (name, identity) VALUES (?1, ?2)",
-            params![name, identity],
-        ) {
-            Ok(_) => {
-                Ok(self.conn.last_insert_rowid())
-            }
-            Err(_) => {
-                // Update existing player
-                self.conn.execute(
-                    "UPDATE players SET identity = ?2 WHERE name = ?1",
-                    params![name, identity],
-                )?;
-                
-                // Get the player ID
-                let mut stmt = self.conn.prepare("SELECT id FROM players WHERE name = ?1")?;
-                let player_id: i64 = stmt.query_row(params![name], |row| row.get(0))?;
-                Ok(player_id)
-            }
+/// SQL version of `metadata_label` for a text column
+fn metadata_label_sql(column: &str) -> String {
+    format!("COALESCE(NULLIF({}, '{}'), '{}')", column, MISSING_METADATA, MISSING_METADATA_LABEL)
+}
+
+/// An artist or track whose rank is followed over time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "type", content = "key")]
+pub enum RankEntity {
+    Artist(String),
+    /// A track id
+    Track(String),
+}
+
+impl RankEntity {
+    fn group_by(&self) -> GroupBy {
+        match self {
+            RankEntity::Artist(_) => GroupBy::Artist,
+            RankEntity::Track(_) => GroupBy::Track,
         }
     }
 
-    pub fn insert_or_update_track(&self, track: &Track) -> Result<()> {
-        self.conn.execute(
-            "INSERT OR REPLACE INTO tracks (id, title, artist, album, length, art_url) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                track.id,
-                track.title,
-                track.artist,
-                track.album,
-                track.length,
-                track.art_url
-            ],
-        )?;
-        Ok(())
+    fn key(&self) -> &str {
+        match self {
+            RankEntity::Artist(key) | RankEntity::Track(key) => key,
+        }
     }
+}
 
-    pub fn start_session(&self, track_id: &str, player_id: i64, start_time: i64) -> Result<i64> {
-        // First check if there's already an active session for this player
-        let existing_active = self.conn.query_row(
-            "SELECT id FROM sessions WHERE player_id = ?1 AND status = 'active'",
-            params![player_id],
-            |row| row.get::<_, i64>(0)
-        );
-        
-        if let Ok(existing_id) = existing_active {
-            // Finalize the existing session first
-            self.finalize_session(existing_id, start_time, "interrupted")?;
+/// Calendar period to rank within, in local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeBucket {
+    Week,
+    Month,
+}
+
+impl TimeBucket {
+    /// SQL naming the bucket the unix time `time` falls in once `modifiers` turn it into
+    /// a listening day, such as "2024-W01" or "2024-01". Weeks are ISO weeks, numbered
+    /// within the year their Thursday falls in; the bundled SQLite has no `%G` or `%V`.
+    fn label_sql(&self, time: &str, modifiers: &str) -> String {
+        match self {
+            TimeBucket::Week => format!(
+                "printf('%s-W%02d',
+                        strftime('%Y', {time}, {modifiers}, '-3 days', 'weekday 4'),
+                        (strftime('%j', {time}, {modifiers}, '-3 days', 'weekday 4') - 1) / 7 + 1)",
+                time = time,
+                modifiers = modifiers,
+            ),
+            TimeBucket::Month => format!("strftime('%Y-%m', {}, {})", time, modifiers),
         }
-        
-        self.conn.execute(
-            "INSERT INTO sessions (track_id, player_id, start_time, status)
-             VALUES (?1, ?2, ?3, 'active')",
-            params![track_id, player_id, start_time],
-        )?;
-        Ok(self.conn.last_insert_rowid())
     }
+}
 
-    pub fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()> {
-        self.conn.execute(
-            "UPDATE sessions SET paused_time = paused_time + ?1 WHERE id = ?2",
-            params![additional_pause_time, session_id],
-        )?;
-        Ok(())
-    }
+/// Whether recent listening is up or down on the windows before it, from `get_trend`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrendDirection {
+    Up,
+    Down,
+    Flat,
+    /// History doesn't reach back one full window before the current one
+    NotEnoughData,
+}
 
-    pub fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()> {
-        // Calculate listened_time = (end_time - start_time - paused_time)
-        self.conn.execute(
-            "UPDATE sessions
-             SET end_time = ?1,
-                 listened_time = ?1 - start_time - paused_time,
-                 status = ?2
-             WHERE id = ?3",
-            params![end_time, status, session_id],
-        )?;
-        Ok(())
-    }
+/// Changes smaller than this percentage count as flat
+pub const TREND_FLAT_PERCENT: f64 = 5.0;
 
-    pub fn update_active_session_progress(&self, session_id: i64, current_time: i64) -> Result<()> {
-        // Update the progress of an active session without finalizing it
-        // This allows real-time viewing of current listening progress
-        self.conn.execute(
-            "UPDATE sessions
-             SET listened_time = ?1 - start_time - paused_time
-             WHERE id = ?2 AND status = 'active'",
-            params![current_time, session_id],
-        )?;
-        Ok(())
-    }
+/// Listening in the latest rolling window against the windows before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrendReport {
+    pub window_days: i64,
+    /// Prior windows averaged for the baseline; fewer than asked for when history is short
+    pub lookback_windows: usize,
+    /// Seconds listened in the latest window, ending now
+    pub recent_listened_time: i64,
+    /// Average seconds listened per prior window, if any prior window has full history
+    pub baseline_listened_time: Option<f64>,
+    /// Change from the baseline, or `None` without a baseline to compare to
+    pub change_percent: Option<f64>,
+    pub direction: TrendDirection,
+}
 
-    pub fn get_active_session_for_player(&self, player_id: i64) -> Result<Option<Session>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, track_id, player_id, start_time, end_time, paused_time, listened_time, status
-             FROM sessions 
-             WHERE player_id = ?1 AND status = 'active'
-             ORDER BY start_time DESC 
-             LIMIT 1"
-        )?;
+/// An entity's standing within one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RankPoint {
+    /// Bucket label such as `2024-03` or `2024-W09`
+    pub bucket: String,
+    /// 1-based rank by listening time, or `None` if not listened to in this bucket
+    pub rank: Option<usize>,
+    pub listened_time: i64,
+    /// How many entities were listened to in this bucket
+    pub ranked_entities: usize,
+}
 
-        let session = stmt.query_row(params![player_id], |row| {
-            Ok(Session {
-                id: row.get(0)?,
-                track_id: row.get(1)?,
-                player_id: row.get(2)?,
-                start_time: row.get(3)?,
-                end_time: row.get(4)?,
-                paused_time: row.get(5)?,
-                listened_time: row.get(6)?,
-                status: row.get(7)?,
-            })
-        });
+/// One earlier year's listening on a given calendar day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YearSlice {
+    pub year: i32,
+    pub total_listening_time: i64,
+    pub top_tracks: Vec<TrackStats>,
+}
 
-        match session {
-            Ok(s) => Ok(Some(s)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
-    }
+/// How regularly someone listens over a range, from `get_consistency`.
+///
+/// `score = 100 × active_day_fraction × 1 / (1 + coefficient_of_variation)`: listening
+/// every day for the same amount scores 100, and both skipped days and uneven days pull
+/// it down. The variation is over days with listening only, so skipped days count once,
+/// through `active_day_fraction`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConsistencyScore {
+    /// Listening days in the range
+    pub days: i64,
+    /// Days with any listening
+    pub active_days: i64,
+    /// `active_days / days`
+    pub active_day_fraction: f64,
+    /// Average seconds listened on days with listening
+    pub mean_daily_listened_time: f64,
+    /// Standard deviation of the seconds listened on days with listening
+    pub daily_std_dev: f64,
+    /// `daily_std_dev / mean_daily_listened_time`, 0 without listening
+    pub coefficient_of_variation: f64,
+    /// 0 to 100, higher is more regular
+    pub score: f64,
+}
 
-    pub fn get_listening_stats(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<ListeningStats> {
-        let current_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+/// State of the materialized `artist_stats` table, from `get_artist_aggregate_status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtistAggregateStatus {
+    pub artists: i64,
+    /// When `refresh_artist_aggregates` last rebuilt the table
+    pub refreshed_at: Option<i64>,
+    /// Whether the table reflects every finished session; all-time top artists are
+    /// computed live while it doesn't
+    pub fresh: bool,
+}
 
-        let time_filter = match (start_time, end_time) {
-            (Some(start), Some(end)) => format!("AND s.start_time >= {} AND s.start_time <= {}", start, end),
-            (Some(start), None) => format!("AND s.start_time >= {}", start),
-            (None, Some(end)) => format!("AND s.start_time <= {}", end),
-            (None, None) => String::new(),
-        };
+/// A player and how much it was used, from `get_player_summaries`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSummary {
+    pub player: Player,
+    pub session_count: i64,
+    pub total_listened_time: i64,
+}
 
-        // Get total listening time including active sessions
-        let total_listening_time: i64 = self.conn.query_row(
-            &format!(
-                "SELECT COALESCE(SUM(
-                    CASE
-                        WHEN listened_time IS NOT NULL THEN listened_time
-                        WHEN status = 'active' THEN {} - start_time - paused_time
-                        ELSE 0
-                    END
-                ), 0) FROM sessions s WHERE (listened_time IS NOT NULL OR status = 'active') {}",
-                current_time, time_filter
-            ),
-            [],
-            |row| row.get(0),
-        )?;
+/// Average listening on one day of the week, in local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeekdayAvg {
+    pub weekday: Weekday,
+    /// How many of this weekday fall in the range
+    pub days: i64,
+    pub total_listened_time: i64,
+    /// `total_listened_time / days`, or 0 if the range has none of this weekday
+    pub average_listened_time: i64,
+}
 
-        // Get top tracks including active sessions
-        let mut stmt = self.conn.prepare(&format!(
-            "SELECT t.id, t.title, t.artist, t.album, t.length, t.art_url,
-                    COALESCE(SUM(
-                        CASE
-                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
-                            WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
-                            ELSE 0
-                        END
-                    ), 0) as total_time,
-                    COUNT(s.id) as play_count
-             FROM tracks t
-             JOIN sessions s ON t.id = s.track_id
-             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
-             GROUP BY t.id
-             ORDER BY total_time DESC
-             LIMIT 20",
-            current_time, time_filter
-        ))?;
+/// One player's share of a track's listening, from `get_track_player_breakdown`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerShare {
+    /// Player identity, e.g. "Spotify"
+    pub player: String,
+    pub total_listened_time: i64,
+    pub play_count: i64,
+}
 
-        let top_tracks: Vec<TrackStats> = stmt.query_map([], |row| {
-            Ok(TrackStats {
-                track: Track {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    artist: row.get(2)?,
-                    album: row.get(3)?,
-                    length: row.get(4)?,
-                    art_url: row.get(5)?,
-                },
-                total_listened_time: row.get(6)?,
-                play_count: row.get(7)?,
-            })
-        })?.collect::<Result<Vec<_>, _>>()?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupedStats {
+    pub key: String,
+    pub label: String,
+    pub total_listened_time: i64,
+    pub play_count: i64,
+}
 
-        // Get top artists including active sessions
-        let mut stmt = self.conn.prepare(&format!(
-            "SELECT t.artist,
-                    COALESCE(SUM(
-                        CASE
-                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
-                            WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
-                            ELSE 0
-                        END
-                    ), 0) as total_time,
-                    COUNT(DISTINCT t.id) as track_count
-             FROM tracks t
-             JOIN sessions s ON t.id = s.track_id
-             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
-             GROUP BY t.artist
-             ORDER BY total_time DESC
-             LIMIT 20",
-            current_time, time_filter
-        ))?;
+/// Age after which an active session is assumed to be left over from a daemon that
+/// didn't shut down cleanly, in seconds - generous, for long listening sessions
+pub const DEFAULT_ORPHAN_MAX_AGE: i64 = 24 * 3600;
 
-        let top_artists: Vec<ArtistStats> = stmt.query_map([], |row| {
-            Ok(ArtistStats {
-                artist: row.get(0)?,
-                total_listened_time: row.get(1)?,
-                track_count: row.get(2)?,
-            })
-        })?.collect::<Result<Vec<_>, _>>()?;
+/// Sessions `bulk_insert_sessions` writes per transaction
+const BULK_INSERT_BATCH: usize = 5_000;
 
-        // Get listening history including active sessions, excluding very short sessions
-        let mut stmt = self.conn.prepare(&format!(
-            "SELECT s.id, s.track_id, s.player_id, s.start_time, s.end_time,
-                    s.paused_time,
-                    CASE
-                        WHEN s.listened_time IS NOT NULL THEN s.listened_time
-                        WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
-                        ELSE 0
-                    END as calculated_listened_time,
-                    s.status,
-                    t.title, t.artist, t.album, t.length, t.art_url,
-                    p.name, p.identity
-             FROM sessions s
-             JOIN tracks t ON s.track_id = t.id
-             JOIN players p ON s.player_id = p.id
-             WHERE (s.listened_time IS NOT NULL OR s.status = 'active')
-               AND (
-                   s.status = 'active' OR
-                   s.listened_time > 0
-               ) {}
-             ORDER BY s.start_time DESC
-             LIMIT 100",
-            current_time, time_filter
-        ))?;
+/// Default half-life for `get_recency_weighted_stats`, in days
+pub const DEFAULT_RECENCY_HALF_LIFE_DAYS: f64 = 30.0;
 
-        let listening_history: Vec<SessionWithMetadata> = stmt.query_map([], |row| {
-            Ok(SessionWithMetadata {
-                session: Session {
-                    id: row.get(0)?,
-                    track_id: row.get(1)?,
-                    player_id: row.get(2)?,
-                    start_time: row.get(3)?,
-                    end_time: row.get(4)?,
-                    paused_time: row.get(5)?,
-                    listened_time: Some(row.get(6)?), // Use calculated listened time
-                    status: row.get(7)?,
-                },
-                track: Track {
-                    id: row.get(1)?,
-                    title: row.get(8)?,
-                    artist: row.get(9)?,
-                    album: row.get(10)?,
-                    length: row.get(11)?,
-                    art_url: row.get(12)?,
-                },
-                player: Player {
-                    id: row.get(2)?,
-                    name: row.get(13)?,
-                    identity: row.get(14)?,
-                },
-            })
-        })?.collect::<Result<Vec<_>, _>>()?;
+/// Weight of a session `age_seconds` old: 1 when new, halving every `half_life_seconds`,
+/// i.e. `exp(-age * ln 2 / half_life)`
+pub fn recency_weight(age_seconds: i64, half_life_seconds: f64) -> f64 {
+    (-(age_seconds.max(0) as f64) * std::f64::consts::LN_2 / half_life_seconds).exp()
+}
 
-        Ok(ListeningStats {
-            total_listening_time,
-            top_tracks,
-            top_artists,
-            listening_history,
-        })
+/// Default weight of the pause penalty in `engaged_time`
+pub const DEFAULT_PAUSE_PENALTY: f64 = 0.5;
+
+/// Listening time discounted by how much of the session was spent paused:
+///
+/// `engaged = listened * (1 - penalty * paused / (listened + paused))`
+///
+/// `penalty` is clamped to `0.0..=1.0`. At 0 engaged time equals listened time;
+/// at 1 a session paused for 90% of its wall-clock time keeps only 10% of its listening.
+pub fn engaged_time(listened_time: i64, paused_time: i64, penalty: f64) -> f64 {
+    let listened = listened_time.max(0) as f64;
+    let paused = paused_time.max(0) as f64;
+    if listened + paused == 0.0 {
+        return 0.0;
     }
 
-    /// Clean up orphaned sessions (active sessions from previous runs)
-    pub fn cleanup_orphaned_sessions(&self, current_time: i64, max_session_duration: i64) -> Result<usize> {
-        // Find active sessions that are too old (likely from previous daemon runs)
-        let mut stmt = self.conn.prepare(
-            "SELECT id, start_time FROM sessions WHERE status = 'active' AND ?1 - start_time > ?2"
-        )?;
+    let pause_ratio = paused / (listened + paused);
+    listened * (1.0 - penalty.clamp(0.0, 1.0) * pause_ratio)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackEngagement {
+    pub track_id: String,
+    pub total_listened_time: i64,
+    pub total_paused_time: i64,
+    pub engaged_time: i64,
+}
 
-        let orphaned_sessions: Vec<(i64, i64)> = stmt.query_map(
-            params![current_time, max_session_duration],
-            |row| Ok((row.get(0)?, row.get(1)?))
-        )?.collect::<Result<Vec<_>, _>>()?;
+/// Listening time weighted by `engaged_time`, kept apart from the raw totals
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngagementStats {
+    pub pause_penalty: f64,
+    pub total_listened_time: i64,
+    pub total_paused_time: i64,
+    pub total_engaged_time: i64,
+    /// Per-track engagement, most engaged first
+    pub tracks: Vec<TrackEngagement>,
+}
 
-        let count = orphaned_sessions.len();
-        
-        for (session_id, start_time) in orphaned_sessions {
-            // Calculate a reasonable end time (start_time + max_session_duration)
-            let estimated_end_time = start_time + max_session_duration;
-            self.finalize_session(session_id, estimated_end_time, "orphaned")?;
+/// Sessions cut short by the next track before this fraction of the track was heard count as skips
+pub const SKIP_LISTEN_RATIO: f64 = 0.5;
+
+/// Skips aren't stored, so classify them from how a finalized session ended:
+/// replaced by another track (`interrupted`) before `SKIP_LISTEN_RATIO` of a known length
+const SKIPPED_SESSION_SQL: &str =
+    "(s.status = 'interrupted' AND s.completion_ratio < ?1)";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistSkipStats {
+    pub artist: String,
+    pub total_sessions: i64,
+    pub skipped_sessions: i64,
+    pub skip_rate: f64,
+    /// Seconds of track left unplayed by skipping
+    pub time_saved: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkipStats {
+    pub total_sessions: i64,
+    pub skipped_sessions: i64,
+    pub skip_rate: f64,
+    /// Seconds of track left unplayed by skipping: track length minus listened time
+    pub time_saved: i64,
+    /// Per-artist breakdown, most skipped first
+    pub artists: Vec<ArtistSkipStats>,
+}
+
+fn skip_rate(skipped: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        skipped as f64 / total as f64
+    }
+}
+
+/// Session columns in the order `session_from_row` reads them
+const SESSION_COLUMNS: &str =
+    "s.id, s.track_id, s.player_id, s.start_time, s.end_time, s.paused_time, s.listened_time, s.status, s.label, s.paused_since, s.content_type";
+const SESSION_COLUMN_COUNT: usize = 11;
+
+/// Track columns in the order `track_from_row` reads them
+const TRACK_COLUMNS: &str =
+    "t.id, t.title, t.artist, t.album, t.length, t.art_url, t.genre, t.raw_metadata, t.track_number, t.disc_number, t.primary_artist, t.rating, t.comment";
+const TRACK_COLUMN_COUNT: usize = 13;
+
+/// Player columns in the order `player_from_row` reads them
+const PLAYER_COLUMNS: &str = "p.id, p.name, p.identity";
+const PLAYER_COLUMN_COUNT: usize = 3;
+
+fn session_from_row(row: &Row, offset: usize) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get(offset)?,
+        track_id: row.get(offset + 1)?,
+        player_id: row.get(offset + 2)?,
+        start_time: row.get(offset + 3)?,
+        end_time: row.get(offset + 4)?,
+        paused_time: row.get(offset + 5)?,
+        listened_time: row.get(offset + 6)?,
+        status: row.get(offset + 7)?,
+        label: row.get(offset + 8)?,
+        paused_since: row.get(offset + 9)?,
+        content_type: row.get::<_, Option<String>>(offset + 10)?.as_deref().and_then(ContentType::parse),
+    })
+}
+
+fn track_from_row(row: &Row, offset: usize) -> rusqlite::Result<Track> {
+    Ok(Track {
+        id: row.get(offset)?,
+        title: row.get(offset + 1)?,
+        artist: row.get(offset + 2)?,
+        album: row.get(offset + 3)?,
+        length: row.get(offset + 4)?,
+        art_url: row.get(offset + 5)?,
+        genre: row.get(offset + 6)?,
+        raw_metadata: row.get(offset + 7)?,
+        track_number: row.get(offset + 8)?,
+        disc_number: row.get(offset + 9)?,
+        primary_artist: row.get(offset + 10)?,
+        rating: row.get(offset + 11)?,
+        comment: row.get(offset + 12)?,
+    })
+}
+
+fn player_from_row(row: &Row, offset: usize) -> rusqlite::Result<Player> {
+    Ok(Player {
+        id: row.get(offset)?,
+        name: row.get(offset + 1)?,
+        identity: row.get(offset + 2)?,
+    })
+}
+
+/// Read a row selected as `SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS`
+fn session_with_metadata_from_row(row: &Row) -> rusqlite::Result<SessionWithMetadata> {
+    Ok(SessionWithMetadata {
+        session: session_from_row(row, 0)?,
+        track: track_from_row(row, SESSION_COLUMN_COUNT)?,
+        player: player_from_row(row, SESSION_COLUMN_COUNT + TRACK_COLUMN_COUNT)?,
+    })
+}
+
+/// Sessions worth showing in history (finished with some listening, or still playing),
+/// newest first. Binds `?1` to the current time for active sessions and `?2` to the row limit.
+fn history_sql(time_filter: &str) -> String {
+    format!(
+        "SELECT {}, {}, {},
+                CASE
+                    WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                    WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                    ELSE 0
+                END as calculated_listened_time
+         FROM sessions s
+         JOIN tracks t ON s.track_id = t.id
+         JOIN players p ON s.player_id = p.id
+         WHERE (s.listened_time IS NOT NULL OR s.status = 'active')
+           AND (
+               s.status = 'active' OR
+               s.listened_time > 0
+           ) {}
+         ORDER BY s.start_time DESC
+         LIMIT ?2",
+        SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS, time_filter
+    )
+}
+
+fn history_entry_from_row(row: &Row) -> rusqlite::Result<SessionWithMetadata> {
+    let calculated_index = SESSION_COLUMN_COUNT + TRACK_COLUMN_COUNT + PLAYER_COLUMN_COUNT;
+    let mut entry = session_with_metadata_from_row(row)?;
+    entry.session.listened_time = Some(row.get(calculated_index)?); // Use calculated listened time
+    Ok(entry)
+}
+
+/// Build an `AND ...` clause restricting sessions to the given start time bounds
+fn time_filter(start_time: Option<i64>, end_time: Option<i64>) -> String {
+    match (start_time, end_time) {
+        (Some(start), Some(end)) => format!("AND s.start_time >= {} AND s.start_time <= {}", start, end),
+        (Some(start), None) => format!("AND s.start_time >= {}", start),
+        (None, Some(end)) => format!("AND s.start_time <= {}", end),
+        (None, None) => String::new(),
+    }
+}
+
+/// Schema changes applied in order on top of the base tables. The database's
+/// `user_version` records how many have run, so only append to this list.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE tracks ADD COLUMN genre TEXT",
+    "ALTER TABLE sessions ADD COLUMN last_seen INTEGER",
+    "ALTER TABLE tracks ADD COLUMN raw_metadata TEXT",
+    "ALTER TABLE tracks ADD COLUMN track_number INTEGER;
+     ALTER TABLE tracks ADD COLUMN disc_number INTEGER",
+    "ALTER TABLE sessions ADD COLUMN content_type TEXT",
+    // Cached stats are tagged with the generation they were computed at. Any write that
+    // touches finished sessions bumps the generation; active-session checkpoints don't.
+    "CREATE TABLE stats_cache (
+         cache_key TEXT PRIMARY KEY,
+         generation INTEGER NOT NULL,
+         stats TEXT NOT NULL
+     );
+     CREATE TABLE stats_generation (
+         id INTEGER PRIMARY KEY CHECK (id = 1),
+         generation INTEGER NOT NULL
+     );
+     INSERT INTO stats_generation (id, generation) VALUES (1, 0);
+     CREATE TRIGGER stats_cache_insert AFTER INSERT ON sessions WHEN NEW.status != 'active'
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;
+     CREATE TRIGGER stats_cache_update AFTER UPDATE ON sessions WHEN NEW.status != 'active' OR OLD.status != 'active'
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;
+     CREATE TRIGGER stats_cache_delete AFTER DELETE ON sessions
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;",
+    "ALTER TABLE sessions ADD COLUMN label TEXT",
+    "ALTER TABLE tracks ADD COLUMN primary_artist TEXT",
+    "ALTER TABLE sessions ADD COLUMN source_domain TEXT",
+    // Backfilled with the rule in `scrobbled_sql`
+    "ALTER TABLE sessions ADD COLUMN scrobbled INTEGER NOT NULL DEFAULT 0;
+     UPDATE sessions
+     SET scrobbled = COALESCE(
+         listened_time >= 240
+         OR listened_time * 2 >= (SELECT NULLIF(length, 0) / 1000000 FROM tracks WHERE tracks.id = sessions.track_id),
+         0)
+     WHERE status NOT IN ('active', 'orphaned')",
+    // Scrobbles waiting to be submitted, see `Database::with_scrobble_queue`. A row
+    // stays until submission succeeds; `next_attempt_at` is NULL once retries give up.
+    "CREATE TABLE scrobble_queue (
+         id INTEGER PRIMARY KEY,
+         session_id INTEGER NOT NULL UNIQUE,
+         queued_at INTEGER NOT NULL,
+         attempts INTEGER NOT NULL DEFAULT 0,
+         last_error TEXT,
+         next_attempt_at INTEGER,
+         FOREIGN KEY (session_id) REFERENCES sessions (id)
+     );
+     CREATE INDEX idx_scrobble_queue_next_attempt ON scrobble_queue (next_attempt_at)",
+    "ALTER TABLE tracks ADD COLUMN rating REAL;
+     ALTER TABLE tracks ADD COLUMN comment TEXT;
+     ALTER TABLE sessions ADD COLUMN rating REAL",
+    "ALTER TABLE sessions ADD COLUMN paused_since INTEGER",
+    // All-time artist totals over finished sessions, see `refresh_artist_aggregates`.
+    // They are fresh while `generation` matches `stats_generation`.
+    "CREATE TABLE artist_stats (
+         artist TEXT PRIMARY KEY,
+         total_listened_time INTEGER NOT NULL,
+         track_count INTEGER NOT NULL,
+         play_count INTEGER NOT NULL
+     );
+     CREATE TABLE artist_stats_state (
+         id INTEGER PRIMARY KEY CHECK (id = 1),
+         generation INTEGER,
+         refreshed_at INTEGER
+     );
+     INSERT INTO artist_stats_state (id) VALUES (1)",
+    // At most one active session per player. Duplicates left by older versions keep the
+    // newest and end the rest where they were last checkpointed.
+    "UPDATE sessions
+     SET status = 'interrupted',
+         end_time = COALESCE(last_seen, start_time),
+         listened_time = MAX(COALESCE(last_seen, start_time) - start_time - paused_time, 0)
+     WHERE status = 'active'
+       AND id NOT IN (SELECT MAX(id) FROM sessions WHERE status = 'active' GROUP BY player_id);
+     CREATE UNIQUE INDEX idx_sessions_one_active_per_player ON sessions (player_id) WHERE status = 'active'",
+    // 'foreground' or 'background' when the daemon has a focus source, see `focus`
+    "ALTER TABLE sessions ADD COLUMN focus TEXT",
+    // Backfilled with the rule in `completion_ratio_sql`, as `recalculate_sessions` does
+    "ALTER TABLE sessions ADD COLUMN completion_ratio REAL;
+     UPDATE sessions
+     SET completion_ratio = MIN(
+         CAST(listened_time AS REAL) / (SELECT NULLIF(length, 0) / 1000000.0 FROM tracks WHERE tracks.id = sessions.track_id),
+         1.0)
+     WHERE status NOT IN ('active', 'imported')",
+    // Renaming or removing a track or player changes what cached stats show. Raw
+    // metadata isn't part of any stats, so pruning it keeps the cache.
+    "CREATE TRIGGER stats_cache_track_update AFTER UPDATE ON tracks
+     WHEN OLD.id IS NOT NEW.id OR OLD.title IS NOT NEW.title OR OLD.artist IS NOT NEW.artist
+       OR OLD.album IS NOT NEW.album OR OLD.length IS NOT NEW.length OR OLD.art_url IS NOT NEW.art_url
+       OR OLD.genre IS NOT NEW.genre OR OLD.track_number IS NOT NEW.track_number
+       OR OLD.disc_number IS NOT NEW.disc_number OR OLD.primary_artist IS NOT NEW.primary_artist
+       OR OLD.rating IS NOT NEW.rating OR OLD.comment IS NOT NEW.comment
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;
+     CREATE TRIGGER stats_cache_track_delete AFTER DELETE ON tracks
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;
+     CREATE TRIGGER stats_cache_player_update AFTER UPDATE ON players
+     WHEN OLD.id IS NOT NEW.id OR OLD.name IS NOT NEW.name OR OLD.identity IS NOT NEW.identity
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;
+     CREATE TRIGGER stats_cache_player_delete AFTER DELETE ON players
+     BEGIN UPDATE stats_generation SET generation = generation + 1; END;",
+];
+
+/// A finished play counts as a scrobble, as on Last.fm and ListenBrainz, once half the
+/// track or this many seconds of it were listened to, whichever comes first
+pub const SCROBBLE_MIN_SECONDS: i64 = 240;
+
+/// SQL for whether `listened` seconds of the track with id `track_id` make a scrobble
+fn scrobbled_sql(listened: &str, track_id: &str) -> String {
+    format!(
+        "COALESCE(
+             {listened} >= {min_seconds}
+             OR {listened} * 2 >= (SELECT NULLIF(length, 0) / 1000000 FROM tracks WHERE tracks.id = {track_id}),
+             0)",
+        listened = listened,
+        track_id = track_id,
+        min_seconds = SCROBBLE_MIN_SECONDS,
+    )
+}
+
+/// SQL for the fraction of the track with id `track_id` that `listened` seconds cover,
+/// NULL when its length is unknown. Capped at 1, as seeking back or looping can play
+/// more than the whole track.
+fn completion_ratio_sql(listened: &str, track_id: &str) -> String {
+    format!(
+        "MIN(
+             CAST({listened} AS REAL) / (SELECT NULLIF(length, 0) / 1000000.0 FROM tracks WHERE tracks.id = {track_id}),
+             1.0)",
+        listened = listened,
+        track_id = track_id,
+    )
+}
+
+/// Plays' worth of the overall mean rating mixed into each track's `weighted_rating`,
+/// so a track rated once doesn't outrank one rated just as well over many plays
+pub const RATING_PRIOR_PLAYS: f64 = 3.0;
+
+/// Fills `artist_stats` from finished sessions; callers append any extra condition and
+/// the `GROUP BY`. Like the live query, artists that are `MISSING_METADATA` are left out.
+const ARTIST_AGGREGATE_SQL: &str =
+    "INSERT INTO artist_stats (artist, total_listened_time, track_count, play_count)
+     SELECT t.artist, SUM(s.listened_time), COUNT(DISTINCT t.id), COUNT(s.id)
+     FROM tracks t
+     JOIN sessions s ON t.id = s.track_id
+     WHERE s.status != 'active' AND s.listened_time IS NOT NULL AND t.artist != ''";
+
+/// Most `get_listening_stats` results kept in `stats_cache`
+const STATS_CACHE_ENTRIES: i64 = 32;
+
+/// When an active session was last checkpointed. Rows from before `last_seen`
+/// existed fall back to the end of their recorded listening.
+const LAST_SEEN_SQL: &str = "COALESCE(s.last_seen, s.start_time + s.paused_time + COALESCE(s.listened_time, 0))";
+
+/// An active session as last persisted, used to pick it back up after a restart
+#[derive(Debug, Clone)]
+pub struct SessionCheckpoint {
+    pub session: Session,
+    pub last_seen: i64,
+}
+
+/// Count plays of one track, ignoring replays within `window_seconds` of the last
+/// counted play. `start_times` must be sorted ascending.
+pub fn count_deduped_plays(start_times: &[i64], window_seconds: i64) -> i64 {
+    let mut count = 0;
+    let mut last_counted: Option<i64> = None;
+
+    for &start in start_times {
+        if last_counted.is_none_or(|last| start - last >= window_seconds) {
+            count += 1;
+            last_counted = Some(start);
         }
+    }
 
-        Ok(count)
+    count
+}
+
+/// Artists a credit counts toward in artist stats: each one `splitter` finds, or the
+/// whole credit. Missing artists count toward none.
+fn credited_artists(artist: &str, splitter: Option<&ArtistSplitter>) -> Vec<String> {
+    if artist == MISSING_METADATA {
+        return Vec::new();
     }
+    match splitter {
+        Some(splitter) => splitter.split(artist),
+        None => vec![artist.to_string()],
+    }
+}
 
-    /// Get database statistics
-    pub fn get_database_stats(&self) -> Result<DatabaseStats> {
-        let total_sessions: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM sessions",
-            [],
-            |row| row.get(0),
-        )?;
+/// Top tracks and artists in `ListeningStats`
+const STATS_TOP_LIMIT: usize = 20;
 
-        let active_sessions: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM sessions WHERE status = 'active'",
-            [],
-            |row| row.get(0),
-        )?;
+/// Plays in `ListeningStats::listening_history`
+const STATS_HISTORY_LIMIT: usize = 100;
 
-        let total_tracks: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM tracks",
-            [],
-            |row| row.get(0),
-        )?;
+/// How `compute_stats` aggregates, matching the `Database` builder options of the same names
+#[derive(Debug, Clone, Default)]
+pub struct StatsOptions {
+    /// Time active sessions without a listened time are counted up to
+    pub current_time: i64,
+    pub play_dedupe_window: Option<i64>,
+    pub artist_splitter: Option<ArtistSplitter>,
+}
 
-        let total_players: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM players",
-            [],
-            |row| row.get(0),
-        )?;
+/// The aggregates of `get_listening_stats` over sessions already in memory. Sessions
+/// with no listened time count only while active, up to `options.current_time`.
+/// `active_days` is left at 0; `Database::count_active_days` fills it in.
+pub fn compute_stats(sessions: &[SessionWithMetadata], options: &StatsOptions) -> ListeningStats {
+    let mut total_listening_time = 0;
+    let mut active_listening_time = 0;
+    // Each track's stats and its play start times
+    let mut tracks: HashMap<&str, (TrackStats, Vec<i64>)> = HashMap::new();
+    let mut content_types: HashMap<ContentType, i64> = HashMap::new();
+    let mut counted = Vec::new();
 
-        Ok(DatabaseStats {
-            total_sessions,
-            active_sessions,
-            total_tracks,
-            total_players,
+    for entry in sessions {
+        let session = &entry.session;
+        let listened = match session.listened_time {
+            Some(listened) => listened,
+            None if session.status == "active" => options.current_time - session.start_time - session.paused_time,
+            None => continue,
+        };
+        total_listening_time += listened;
+        if session.status == "active" {
+            active_listening_time += listened;
+        }
+        *content_types.entry(session.content_type.unwrap_or(ContentType::Music)).or_default() += listened;
+
+        let (track, starts) = tracks.entry(&entry.track.id).or_insert_with(|| {
+            (TrackStats { track: entry.track.clone(), total_listened_time: 0, play_count: 0, plays_per_active_day: None }, Vec::new())
+        });
+        track.total_listened_time += listened;
+        track.play_count += 1;
+        starts.push(session.start_time);
+        counted.push((entry, listened));
+    }
+
+    let mut top_tracks: Vec<TrackStats> = tracks
+        .into_values()
+        .map(|(mut track, mut starts)| {
+            if let Some(window) = options.play_dedupe_window {
+                starts.sort_unstable();
+                track.play_count = count_deduped_plays(&starts, window);
+            }
+            track
+        })
+        .collect();
+
+    // Artists are credited with their tracks' plays after replays are deduped
+    let mut artists: HashMap<String, (ArtistStats, HashSet<&str>)> = HashMap::new();
+    for track in &top_tracks {
+        for artist in credited_artists(&track.track.artist, options.artist_splitter.as_ref()) {
+            let (stats, track_ids) = artists.entry(artist.clone()).or_insert_with(|| {
+                (ArtistStats { artist, total_listened_time: 0, track_count: 0, play_count: 0, plays_per_active_day: None }, HashSet::new())
+            });
+            stats.total_listened_time += track.total_listened_time;
+            stats.play_count += track.play_count;
+            track_ids.insert(track.track.id.as_str());
+        }
+    }
+    let mut top_artists: Vec<ArtistStats> = artists
+        .into_values()
+        .map(|(mut stats, track_ids)| {
+            stats.track_count = track_ids.len() as i64;
+            stats
+        })
+        .collect();
+
+    top_tracks.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.track.id.cmp(&b.track.id)));
+    top_tracks.truncate(STATS_TOP_LIMIT);
+    top_artists.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.artist.cmp(&b.artist)));
+    top_artists.truncate(STATS_TOP_LIMIT);
+
+    // Like `history_sql`, newest first and leaving out finished sessions nothing was heard of
+    counted.sort_by_key(|(entry, _)| std::cmp::Reverse(entry.session.start_time));
+    let listening_history = counted
+        .into_iter()
+        .filter(|(entry, listened)| entry.session.status == "active" || *listened > 0)
+        .take(STATS_HISTORY_LIMIT)
+        .map(|(entry, listened)| {
+            let mut entry = entry.clone();
+            entry.session.listened_time = Some(listened);
+            entry
         })
+        .collect();
+
+    let mut content_type_totals: Vec<ContentTypeTotal> = content_types
+        .into_iter()
+        .map(|(content_type, total_listening_time)| ContentTypeTotal { content_type, total_listening_time })
+        .collect();
+    content_type_totals.sort_by(|a, b| b.total_listening_time.cmp(&a.total_listening_time).then_with(|| a.content_type.as_str().cmp(b.content_type.as_str())));
+
+    ListeningStats {
+        total_listening_time,
+        active_listening_time,
+        top_tracks,
+        top_artists,
+        listening_history,
+        content_type_totals,
+        active_days: 0,
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DatabaseStats {
-    pub total_sessions: i64,
-    pub active_sessions: i64,
-    pub total_tracks: i64,
-    pub total_players: i64,
-}
\ No newline at end of file
+/// Longest silence between sessions that still counts as one listening block (in seconds)
+pub const LISTENING_BLOCK_GAP: i64 = 300;
+
+/// Start of the listening block containing the first session. `sessions` are
+/// `(start, end)` pairs, newest start first; each older session joins the block
+/// if it ended no more than `gap` seconds before the block's earliest start.
+/// Stops reading at the first session outside the block.
+pub fn listening_block_start<E>(
+    sessions: impl IntoIterator<Item = std::result::Result<(i64, i64), E>>,
+    gap: i64,
+) -> std::result::Result<Option<i64>, E> {
+    let mut block_start: Option<i64> = None;
+
+    for session in sessions {
+        let (start, end) = session?;
+        match block_start {
+            None => block_start = Some(start),
+            Some(current) if end >= current - gap => block_start = Some(current.min(start)),
+            Some(_) => break,
+        }
+    }
+
+    Ok(block_start)
+}
+
+pub struct Database {
+    conn: Connection,
+    /// Collapse replays of a track within this many seconds into one play
+    play_dedupe_window: Option<i64>,
+    /// Serve repeated `get_listening_stats` calls from `stats_cache`
+    use_stats_cache: bool,
+    /// Credit each artist of a multi-artist track separately in top artists
+    artist_splitter: Option<ArtistSplitter>,
+    /// Local hour at which a listening day begins, see `with_day_start_hour`
+    day_start_hour: u32,
+    /// Time zone days are reckoned in, see `with_day_zone`
+    day_zone: DayZone,
+    /// Divide sessions spanning a day boundary between the days, see `with_day_boundary_split`
+    split_day_boundary: bool,
+    /// Aggregate listening stats with `compute_stats` rather than in SQL
+    in_memory_stats: bool,
+    /// Queue sessions that finish as scrobbles for submission
+    scrobble_queue: bool,
+    /// Keep `artist_stats` up to date as sessions finish, see `with_artist_aggregates`
+    artist_aggregates: bool,
+    /// Sessions finalized with less listening than this are dropped or marked, see
+    /// `with_min_session_duration`
+    min_session_duration: i64,
+    /// Keep sessions under `min_session_duration` as `too_short` instead of dropping them
+    record_all_sessions: bool,
+    /// Bus name or identity -> bus name of the player it is recorded as
+    player_aliases: HashMap<String, String>,
+    /// Case-fold the ids of tracks this database creates, see `with_case_insensitive_track_ids`
+    case_insensitive_track_ids: bool,
+}
+
+/// Time zone that listening days, and the periods made of them, follow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DayZone {
+    /// The system's local time
+    #[default]
+    Local,
+    /// UTC, so results don't depend on where they're computed
+    Utc,
+}
+
+impl DayZone {
+    /// Wall-clock time of a unix time in this zone
+    pub fn naive(self, timestamp: i64) -> Option<NaiveDateTime> {
+        match self {
+            DayZone::Local => Some(Local.timestamp_opt(timestamp, 0).earliest()?.naive_local()),
+            DayZone::Utc => Some(chrono::DateTime::from_timestamp(timestamp, 0)?.naive_utc()),
+        }
+    }
+
+    /// Unix time of a wall-clock time in this zone, the earlier one if it occurs twice
+    pub fn timestamp(self, datetime: NaiveDateTime) -> Option<i64> {
+        match self {
+            DayZone::Local => Local.from_local_datetime(&datetime).earliest().map(|datetime| datetime.timestamp()),
+            DayZone::Utc => Some(datetime.and_utc().timestamp()),
+        }
+    }
+
+    /// SQLite date modifiers that turn a unix time into wall-clock time in this zone
+    fn sql_modifiers(self) -> &'static str {
+        match self {
+            DayZone::Local => "'unixepoch', 'localtime'",
+            DayZone::Utc => "'unixepoch'",
+        }
+    }
+}
+
+/// Listening day a unix time falls in when days begin at `day_start_hour` in `zone`
+pub fn listening_day(timestamp: i64, day_start_hour: u32, zone: DayZone) -> Option<NaiveDate> {
+    Some((zone.naive(timestamp)? - chrono::Duration::hours(day_start_hour as i64)).date())
+}
+
+/// Unix time at which the listening day `date` begins
+pub fn listening_day_start(date: NaiveDate, day_start_hour: u32, zone: DayZone) -> Result<i64> {
+    zone.timestamp(date.and_hms_opt(day_start_hour, 0, 0).context("Invalid day start hour")?)
+        .context("Start of day does not exist in local time")
+}
+
+const QUERY_PLAN_PREFIX: &str = "EXPLAIN QUERY PLAN ";
+
+/// Log target of the statements `Database::with_query_log` logs, at debug level
+pub const QUERY_LOG_TARGET: &str = "gopal::sql";
+
+thread_local! {
+    /// Statements seen by `log_and_keep_query`, for `Database::take_logged_queries`
+    static LOGGED_QUERIES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// SQLite trace callback for `Database::with_query_log`
+fn log_query(sql: &str) {
+    // Looking up a plan for a logged query would log it again
+    if sql.starts_with(QUERY_PLAN_PREFIX) {
+        return;
+    }
+
+    // Bound values are user data such as track titles, so escape anything that could
+    // mess with the terminal
+    let display: String = sql.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .flat_map(|c| if c.is_control() { c.escape_default().collect() } else { vec![c] })
+        .collect();
+    log::debug!(target: QUERY_LOG_TARGET, "{}", display);
+}
+
+/// Trace callback for `Database::with_query_log` that also keeps each statement
+fn log_and_keep_query(sql: &str) {
+    log_query(sql);
+    if !sql.starts_with(QUERY_PLAN_PREFIX) {
+        LOGGED_QUERIES.with(|queries| queries.borrow_mut().push(sql.to_string()));
+    }
+}
+
+/// Environment variable both binaries read the database key from when `--key` isn't given
+pub const DATABASE_KEY_ENV: &str = "GOPAL_DB_KEY";
+
+/// The key given on the command line, else the one in `DATABASE_KEY_ENV`. An empty key
+/// means none.
+pub fn database_key(flag: Option<String>) -> Option<String> {
+    flag.or_else(|| std::env::var(DATABASE_KEY_ENV).ok())
+        .filter(|key| !key.is_empty())
+}
+
+/// A database that can't be opened with the key it was given, which retrying won't fix
+#[derive(Debug)]
+pub enum DatabaseKeyError {
+    /// The key is wrong, or the file is encrypted and no key was given
+    Wrong(PathBuf),
+    /// A key was given but this build has no SQLCipher
+    Unsupported,
+}
+
+impl fmt::Display for DatabaseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseKeyError::Wrong(path) => write!(
+                f,
+                "Cannot read database at {}: the key is wrong, or the database is encrypted and needs --key or {}",
+                path.display(),
+                DATABASE_KEY_ENV
+            ),
+            DatabaseKeyError::Unsupported => write!(
+                f,
+                "A database key was given, but gopal was built without encryption support (build with --features encryption)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseKeyError {}
+
+impl Database {
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        Self::new_with_key(db_path, None)
+    }
+
+    /// Open the database, unlocking it with `key` if it is encrypted with SQLCipher.
+    /// Fails with `DatabaseKeyError` if the key doesn't fit or encryption isn't built in.
+    pub fn new_with_key<P: AsRef<Path>>(db_path: P, key: Option<&str>) -> Result<Self> {
+        let db_path = db_path.as_ref();
+        if key.is_some() && !cfg!(feature = "encryption") {
+            return Err(DatabaseKeyError::Unsupported.into());
+        }
+
+        let conn = Connection::open(db_path)
+            .map_err(|e| Self::unwritable_error(db_path, e))?;
+
+        // SQLCipher needs the key before anything else touches the file
+        if let Some(key) = key {
+            conn.pragma_update(None, "key", key)?;
+        }
+        if let Err(e) = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)) {
+            if e.sqlite_error_code() == Some(rusqlite::ErrorCode::NotADatabase) {
+                return Err(DatabaseKeyError::Wrong(db_path.to_path_buf()).into());
+            }
+        }
+
+        let db = Database { conn, play_dedupe_window: None, use_stats_cache: false, artist_splitter: None, day_start_hour: 0, day_zone: DayZone::Local, split_day_boundary: false, in_memory_stats: false, scrobble_queue: false, artist_aggregates: false, min_session_duration: 0, record_all_sessions: false, player_aliases: HashMap::new(), case_insensitive_track_ids: false };
+        // Waiting on another connection's lock or a failed migration aren't about the file
+        db.initialize_schema().map_err(|e| match e {
+            GopalError::Sqlite(e) => Self::unwritable_error(db_path, e),
+            e => e,
+        })?;
+        Ok(db)
+    }
+
+    /// Like `new_with_key`, but also make sure writes will succeed, failing with
+    /// `GopalError::Unwritable` if they won't. Reading needs neither, so only the daemon
+    /// checks; the CLI can still query a database on a read-only mount.
+    pub fn open_writable<P: AsRef<Path>>(db_path: P, key: Option<&str>) -> Result<Self> {
+        let db_path = db_path.as_ref();
+        let db = Self::new_with_key(db_path, key)?;
+        db.check_writable().map_err(|e| Self::unwritable_error(db_path, e))?;
+        Ok(db)
+    }
+
+    /// Like `new_with_key`, but fail with `GopalError::NotFound` instead of creating the
+    /// database when nothing exists at `db_path`
+    pub fn open_existing<P: AsRef<Path>>(db_path: P, key: Option<&str>) -> Result<Self> {
+        let db_path = db_path.as_ref();
+        if db_path.as_os_str() != ":memory:" && !db_path.exists() {
+            return Err(GopalError::NotFound(db_path.to_path_buf()));
+        }
+        Self::new_with_key(db_path, key)
+    }
+
+    /// Count a track at most once per `window_seconds` in `get_listening_stats` play counts
+    pub fn with_play_dedupe_window(mut self, window_seconds: Option<i64>) -> Self {
+        self.play_dedupe_window = window_seconds.filter(|&w| w > 0);
+        self
+    }
+
+    /// Reuse stored `get_listening_stats` results while no session has finished since
+    pub fn with_stats_cache(mut self, enabled: bool) -> Self {
+        self.use_stats_cache = enabled;
+        self
+    }
+
+    /// Aggregate top artists by each individual artist of a credit like "A, B feat. C"
+    /// rather than by the combined string. Each artist gets the track's full time.
+    pub fn with_artist_splitter(mut self, splitter: Option<ArtistSplitter>) -> Self {
+        self.artist_splitter = splitter;
+        self
+    }
+
+    /// Start listening days at `hour` local time instead of midnight, so late-night
+    /// sessions count toward the day before in day and weekday groupings
+    pub fn with_day_start_hour(mut self, hour: u32) -> Self {
+        self.day_start_hour = hour.min(23);
+        self
+    }
+
+    pub fn day_start_hour(&self) -> u32 {
+        self.day_start_hour
+    }
+
+    /// Reckon listening days in `zone`, e.g. UTC for results that don't depend on
+    /// the machine's time zone
+    pub fn with_day_zone(mut self, zone: DayZone) -> Self {
+        self.day_zone = zone;
+        self
+    }
+
+    pub fn day_zone(&self) -> DayZone {
+        self.day_zone
+    }
+
+    /// Fetch the period's sessions and aggregate them with `compute_stats` in
+    /// `get_listening_stats`, instead of a query per aggregate
+    pub fn with_in_memory_stats(mut self, enabled: bool) -> Self {
+        self.in_memory_stats = enabled;
+        self
+    }
+
+    /// Divide the listened time of a session that runs past the start of the next
+    /// listening day between the days, in proportion to how long it lasted in each.
+    /// Only daily totals, weekday averages and the heatmap split; sessions are unchanged.
+    pub fn with_day_boundary_split(mut self, enabled: bool) -> Self {
+        self.split_day_boundary = enabled;
+        self
+    }
+
+    /// Record players under another bus name, given as bus name or identity -> canonical
+    /// bus name, so one app showing up under several names keeps a single player row.
+    /// Aliased players share that row's one active session: if two of them play at once,
+    /// each new track ends the other's session as interrupted.
+    pub fn with_player_aliases(mut self, aliases: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.player_aliases = aliases.into_iter().collect();
+        self
+    }
+
+    /// Drop sessions that `finalize_session` ends with fewer than `seconds` listened, as
+    /// noise from skipping through tracks
+    pub fn with_min_session_duration(mut self, seconds: i64) -> Self {
+        self.min_session_duration = seconds.max(0);
+        self
+    }
+
+    /// Keep sessions under the minimum duration for auditing instead of dropping them.
+    /// They get the `too_short` status and no listened time, so stats leave them out.
+    pub fn with_record_all_sessions(mut self, enabled: bool) -> Self {
+        self.record_all_sessions = enabled;
+        self
+    }
+
+    /// Give tracks this database creates, such as imported ones, ids that ignore case,
+    /// matching the daemon's `case_insensitive_track_ids`. `rekey_tracks` brings existing
+    /// tracks in line after the setting changes.
+    pub fn with_case_insensitive_track_ids(mut self, enabled: bool) -> Self {
+        self.case_insensitive_track_ids = enabled;
+        self
+    }
+
+    /// Add every session that finishes as a scrobble to `scrobble_queue`, for
+    /// `scrobble::flush_scrobble_queue` to submit
+    pub fn with_scrobble_queue(mut self, enabled: bool) -> Self {
+        self.scrobble_queue = enabled;
+        self
+    }
+
+    /// Update the artist's row in `artist_stats` whenever one of their sessions finishes,
+    /// so the table stays fresh between `refresh_artist_aggregates` runs. Only a fresh
+    /// table is maintained; once stale it waits for the next full refresh.
+    pub fn with_artist_aggregates(mut self, enabled: bool) -> Self {
+        self.artist_aggregates = enabled;
+        self
+    }
+
+    /// SQLite date modifiers that turn a unix time into wall-clock time shifted so that
+    /// its date is the listening day, e.g. `date(s.start_time, <modifiers>)`
+    fn listening_day_modifiers(&self) -> String {
+        match self.day_start_hour {
+            0 => self.day_zone.sql_modifiers().to_string(),
+            hour => format!("{}, '-{} hours'", self.day_zone.sql_modifiers(), hour),
+        }
+    }
+
+    /// Log every statement this connection runs to `QUERY_LOG_TARGET`, with bound
+    /// parameters inlined as SQL literals. With `keep`, each is also kept for
+    /// `take_logged_queries`.
+    pub fn with_query_log(mut self, enabled: bool, keep: bool) -> Self {
+        let callback: fn(&str) = if keep { log_and_keep_query } else { log_query };
+        self.conn.trace(enabled.then_some(callback));
+        self
+    }
+
+    /// Statements kept by `with_query_log` connections on this thread since the last call
+    pub fn take_logged_queries() -> Vec<String> {
+        LOGGED_QUERIES.with(|queries| queries.take())
+    }
+
+    /// `EXPLAIN QUERY PLAN` for `sql`, one line per step, indented under its parent step
+    pub fn query_plan(&self, sql: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(&format!("{}{}", QUERY_PLAN_PREFIX, sql))?;
+        let steps = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>("id")?, row.get::<_, i64>("parent")?, row.get::<_, String>("detail")?))
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut depths: HashMap<i64, usize> = HashMap::new();
+        Ok(steps.into_iter()
+            .map(|(id, parent, detail)| {
+                let depth = depths.get(&parent).map_or(0, |depth| depth + 1);
+                depths.insert(id, depth);
+                format!("{}{}", "  ".repeat(depth), detail)
+            })
+            .collect())
+    }
+
+    /// Names a track's artist credit is counted under in top artists. Tracks without
+    /// an artist aren't credited to anyone.
+    fn credited_artists(&self, artist: &str) -> Vec<String> {
+        credited_artists(artist, self.artist_splitter.as_ref())
+    }
+
+    /// Make sure writes will succeed by taking a write lock and touching the schema
+    fn check_writable(&self) -> rusqlite::Result<()> {
+        // Dropping the transaction rolls it back, even when the probe fails
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+        tx.execute_batch("CREATE TABLE write_probe (id INTEGER)")
+    }
+
+    /// Describe a failure to open or write the database, including the OS-level cause
+    fn unwritable_error(db_path: &Path, source: rusqlite::Error) -> GopalError {
+        // SQLite's own messages ("unable to open database file") hide the real
+        // reason, so ask the filesystem directly for read-only/full/permission errors
+        let os_error = if db_path.as_os_str() == ":memory:" {
+            None
+        } else {
+            std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(db_path)
+                .err()
+        };
+
+        GopalError::Unwritable { path: db_path.to_path_buf(), os_error, source }
+    }
+
+    fn initialize_schema(&self) -> Result<()> {
+        // Create players table
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS players (
+                id INTEGER PRIMARY KEY,
+                name TEXT UNIQUE NOT NULL,
+                identity TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Create tracks table
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS tracks (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                artist TEXT NOT NULL,
+                album TEXT NOT NULL,
+                length INTEGER,
+                art_url TEXT
+            )",
+            [],
+        )?;
+
+        // Create sessions table
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                track_id TEXT NOT NULL,
+                player_id INTEGER NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER,
+                paused_time INTEGER NOT NULL DEFAULT 0,
+                listened_time INTEGER,
+                status TEXT NOT NULL DEFAULT 'active',
+                FOREIGN KEY (track_id) REFERENCES tracks (id),
+                FOREIGN KEY (player_id) REFERENCES players (id)
+            )",
+            [],
+        )?;
+
+        // Create indexes for better query performance
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_sessions_start_time ON sessions (start_time)",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_sessions_track_id ON sessions (track_id)",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_sessions_player_id ON sessions (player_id)",
+            [],
+        )?;
+
+        self.run_migrations()?;
+
+        Ok(())
+    }
+
+    fn run_migrations(&self) -> Result<()> {
+        let version: usize = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            self.conn.execute_batch(migration)
+                .map_err(|source| GopalError::Schema { migration: index + 1, source })?;
+            self.conn.pragma_update(None, "user_version", index + 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Schema version of the open database (number of applied migrations)
+    pub fn schema_version(&self) -> Result<usize> {
+        Ok(self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+    }
+
+    pub fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64> {
+        let alias = self.player_aliases.get(name).or_else(|| self.player_aliases.get(identity));
+        if let Some(canonical) = alias.filter(|canonical| canonical.as_str() != name) {
+            // The canonical player keeps its own identity
+            self.conn.execute(
+                "INSERT OR IGNORE INTO players (name, identity) VALUES (?1, ?2)",
+                params![canonical, identity],
+            )?;
+            return Ok(self.conn.query_row("SELECT id FROM players WHERE name = ?1", params![canonical], |row| row.get(0))?);
+        }
+
+        // Try to insert, if it fails due to unique constraint, update and get the ID
+        match self.conn.execute(
+            "INSERT INTO players (name, identity) VALUES (?1, ?2)",
+            params![name, identity],
+        ) {
+            Ok(_) => {
+                Ok(self.conn.last_insert_rowid())
+            }
+            Err(_) => {
+                // Update existing player
+                self.conn.execute(
+                    "UPDATE players SET identity = ?2 WHERE name = ?1",
+                    params![name, identity],
+                )?;
+                
+                // Get the player ID
+                let mut stmt = self.conn.prepare("SELECT id FROM players WHERE name = ?1")?;
+                let player_id: i64 = stmt.query_row(params![name], |row| row.get(0))?;
+                Ok(player_id)
+            }
+        }
+    }
+
+    /// Every player with its number of sessions and listening, most listened first
+    pub fn get_player_summaries(&self) -> Result<Vec<PlayerSummary>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, COUNT(s.id), COALESCE(SUM(s.listened_time), 0) as total_time
+             FROM players p
+             LEFT JOIN sessions s ON s.player_id = p.id
+             GROUP BY p.id
+             ORDER BY total_time DESC, p.name",
+            PLAYER_COLUMNS
+        ))?;
+        let players = stmt.query_map([], |row| {
+            Ok(PlayerSummary {
+                player: player_from_row(row, 0)?,
+                session_count: row.get(PLAYER_COLUMN_COUNT)?,
+                total_listened_time: row.get(PLAYER_COLUMN_COUNT + 1)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(players)
+    }
+
+    /// Id of the player with bus name `player`, or else with that identity, ignoring case
+    fn find_player_id(&self, player: &str) -> Result<i64> {
+        self.conn.query_row(
+            "SELECT id FROM players WHERE name = ?1 OR identity = ?1 COLLATE NOCASE ORDER BY name = ?1 DESC LIMIT 1",
+            params![player],
+            |row| row.get(0),
+        ).optional()?.with_context(|| format!("No player named '{}'", player))
+    }
+
+    /// Ids of every player with bus name `player` or with that identity, ignoring case.
+    /// Several instances of one app share an identity under different bus names.
+    fn find_player_ids(&self, player: &str) -> Result<Vec<i64>> {
+        let ids = self.conn
+            .prepare("SELECT id FROM players WHERE name = ?1 COLLATE NOCASE OR identity = ?1 COLLATE NOCASE ORDER BY id")?
+            .query_map(params![player], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        if ids.is_empty() {
+            return Err(GopalError::invalid(format!("No player named '{}'", player)));
+        }
+        Ok(ids)
+    }
+
+    /// Move every session of player `from` to player `into` and remove `from`, returning
+    /// how many sessions moved. Players are given by bus name or identity.
+    pub fn merge_players(&self, from: &str, into: &str) -> Result<usize> {
+        let (from_id, into_id) = (self.find_player_id(from)?, self.find_player_id(into)?);
+        if from_id == into_id {
+            return Err(GopalError::invalid(format!("'{}' and '{}' are the same player", from, into)));
+        }
+
+        // Only one of the merged player's sessions can stay active
+        let both_active: Option<(i64, i64)> = self.conn.query_row(
+            "SELECT id, COALESCE(last_seen, start_time) FROM sessions
+             WHERE player_id = ?1 AND status = 'active'
+               AND EXISTS (SELECT 1 FROM sessions WHERE player_id = ?2 AND status = 'active')",
+            params![from_id, into_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?;
+        if let Some((session_id, last_seen)) = both_active {
+            self.finalize_session(session_id, last_seen, "interrupted")?;
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let moved = tx.execute("UPDATE sessions SET player_id = ?2 WHERE player_id = ?1", params![from_id, into_id])?;
+        tx.execute("DELETE FROM players WHERE id = ?1", params![from_id])?;
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    pub fn insert_or_update_track(&self, track: &Track) -> Result<()> {
+        let old_length: Option<Option<i64>> = self.conn.prepare_cached("SELECT length FROM tracks WHERE id = ?1")?
+            .query_row(params![track.id], |row| row.get(0))
+            .optional()?;
+        self.conn.execute(
+            // An upsert rather than INSERT OR REPLACE, so the stats cache triggers see an update
+            "INSERT INTO tracks (id, title, artist, album, length, art_url, genre, raw_metadata, track_number, disc_number, primary_artist, rating, comment) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+             ON CONFLICT (id) DO UPDATE SET
+                 title = excluded.title, artist = excluded.artist, album = excluded.album, length = excluded.length,
+                 art_url = excluded.art_url, genre = excluded.genre, raw_metadata = excluded.raw_metadata,
+                 track_number = excluded.track_number, disc_number = excluded.disc_number,
+                 primary_artist = excluded.primary_artist, rating = excluded.rating, comment = excluded.comment",
+            params![
+                track.id,
+                track.title,
+                track.artist,
+                track.album,
+                track.length,
+                track.art_url,
+                track.genre,
+                track.raw_metadata,
+                track.track_number,
+                track.disc_number,
+                track.primary_artist,
+                track.rating,
+                track.comment
+            ],
+        )?;
+
+        // Completion ratios were worked out from the old length
+        if old_length.is_some_and(|length| length != track.length) {
+            self.update_completion_ratios(Some(&track.id))?;
+        }
+        Ok(())
+    }
+
+    /// Give every track the id `metadata_rules::track_id` makes of its title, artist and
+    /// album, moving sessions along and merging tracks that end up with the same id.
+    /// Returns how many tracks were re-keyed; needed after `with_case_insensitive_track_ids`
+    /// changes, and for tracks recorded before ids were normalized.
+    pub fn rekey_tracks(&self) -> Result<usize> {
+        let tracks: Vec<(String, String, String, String)> = self.conn
+            .prepare("SELECT id, title, artist, album FROM tracks ORDER BY rowid")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<Result<_, _>>()?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut rekeyed = 0;
+        for (id, title, artist, album) in tracks {
+            let new_id = metadata_rules::track_id(&title, &artist, &album, self.case_insensitive_track_ids);
+            if new_id == id {
+                continue;
+            }
+            // A track already under the new id keeps its own metadata
+            tx.execute(
+                "INSERT OR IGNORE INTO tracks (id, title, artist, album, length, art_url, genre, raw_metadata, track_number, disc_number, primary_artist, rating, comment)
+                 SELECT ?2, title, artist, album, length, art_url, genre, raw_metadata, track_number, disc_number, primary_artist, rating, comment
+                 FROM tracks WHERE id = ?1",
+                params![id, new_id],
+            )?;
+            tx.execute("UPDATE sessions SET track_id = ?2 WHERE track_id = ?1", params![id, new_id])?;
+            tx.execute("DELETE FROM tracks WHERE id = ?1", params![id])?;
+            rekeyed += 1;
+        }
+        tx.commit()?;
+        Ok(rekeyed)
+    }
+
+    pub fn start_session(&self, track_id: &str, player_id: i64, start_time: i64) -> Result<i64> {
+        self.start_session_as(track_id, player_id, start_time, ContentType::Music)
+    }
+
+    /// Start a session recording what kind of content is being played
+    pub fn start_session_as(&self, track_id: &str, player_id: i64, start_time: i64, content_type: ContentType) -> Result<i64> {
+        // The track's rating is as the player reports it right now. A player has at most
+        // one active session, so a conflict means one is still running: finalize it as
+        // interrupted and take its place, in one transaction so a failure leaves it active.
+        let tx = self.conn.unchecked_transaction()?;
+        let insert = || tx.execute(
+            "INSERT INTO sessions (track_id, player_id, start_time, last_seen, status, content_type, rating)
+             VALUES (?1, ?2, ?3, ?3, 'active', ?4, (SELECT rating FROM tracks WHERE id = ?1))
+             ON CONFLICT (player_id) WHERE status = 'active' DO NOTHING",
+            params![track_id, player_id, start_time, content_type.as_str()],
+        );
+
+        if insert()? == 0 {
+            let existing_id: i64 = tx.query_row(
+                "SELECT id FROM sessions WHERE player_id = ?1 AND status = 'active'",
+                params![player_id],
+                |row| row.get(0),
+            )?;
+            self.finalize_session(existing_id, start_time, "interrupted")?;
+            if insert()? == 0 {
+                return Err(GopalError::invalid(format!("Player {} still has an active session", player_id)));
+            }
+        }
+        let session_id = tx.last_insert_rowid();
+        tx.commit()?;
+        Ok(session_id)
+    }
+
+    /// Set or clear a session's label. Returns false if there is no such session.
+    pub fn set_session_label(&self, session_id: i64, label: Option<&str>) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE sessions SET label = ?1 WHERE id = ?2",
+            params![label, session_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Record the site a web player's session was streamed from, see `sources::domain_from_url`
+    pub fn set_session_source_domain(&self, session_id: i64, domain: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET source_domain = ?1 WHERE id = ?2",
+            params![domain, session_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record whether a session's player had focus, see `focus::SessionFocus`
+    pub fn set_session_focus(&self, session_id: i64, focus: SessionFocus) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET focus = ?1 WHERE id = ?2",
+            params![focus.as_str(), session_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET paused_time = paused_time + ?1, paused_since = NULL WHERE id = ?2",
+            params![additional_pause_time, session_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()> {
+        let aggregates_fresh = self.artist_aggregates && self.artist_aggregates_fresh()?;
+
+        // Calculate listened_time = (end_time - start_time - paused_time). Orphaned
+        // sessions' times are guesses, so they never count as scrobbles. A session that
+        // was already closed, e.g. as interrupted by the one that replaced it, is left be.
+        let finalized = self.conn.execute(
+            &format!(
+                "UPDATE sessions
+                 SET end_time = ?1,
+                     listened_time = ?1 - start_time - paused_time,
+                     status = ?2,
+                     paused_since = NULL,
+                     scrobbled = CASE WHEN ?2 = 'orphaned' THEN 0 ELSE {} END,
+                     completion_ratio = {}
+                 WHERE id = ?3 AND status = 'active'",
+                scrobbled_sql("(?1 - start_time - paused_time)", "sessions.track_id"),
+                completion_ratio_sql("(?1 - start_time - paused_time)", "sessions.track_id")
+            ),
+            params![end_time, status, session_id],
+        )?;
+        if finalized == 0 {
+            return Ok(());
+        }
+        if self.min_session_duration > 0 {
+            self.handle_short_session(session_id)?;
+        }
+        self.update_artist_aggregate(session_id, aggregates_fresh)?;
+        self.queue_if_scrobbled(session_id)
+    }
+
+    /// Drop or mark a just-finalized session listened to for less than `min_session_duration`
+    fn handle_short_session(&self, session_id: i64) -> Result<()> {
+        if self.record_all_sessions {
+            self.conn.execute(
+                "UPDATE sessions SET status = 'too_short', listened_time = NULL, scrobbled = 0, completion_ratio = NULL
+                 WHERE id = ?1 AND listened_time < ?2",
+                params![session_id, self.min_session_duration],
+            )?;
+        } else {
+            self.conn.execute(
+                "DELETE FROM sessions WHERE id = ?1 AND listened_time < ?2",
+                params![session_id, self.min_session_duration],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint the progress of several active sessions in one transaction.
+    /// Each entry is a session id and the length of the pause it is currently in.
+    pub fn update_active_sessions_progress(&self, current_time: i64, sessions: &[(i64, i64)]) -> Result<()> {
+        if sessions.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "UPDATE sessions
+                 SET listened_time = ?1 - start_time - paused_time - ?2,
+                     last_seen = ?1,
+                     paused_since = CASE WHEN ?2 > 0 THEN ?1 - ?2 END
+                 WHERE id = ?3 AND status = 'active'",
+            )?;
+            for &(session_id, pending_pause_time) in sessions {
+                stmt.execute(params![current_time, pending_pause_time, session_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The most recent active session for a player, with when it was last checkpointed
+    pub fn get_session_checkpoint(&self, player_id: i64) -> Result<Option<SessionCheckpoint>> {
+        let result = self.conn.query_row(
+            &format!(
+                "SELECT {}, {} FROM sessions s
+                 WHERE s.player_id = ?1 AND s.status = 'active'
+                 ORDER BY s.start_time DESC
+                 LIMIT 1",
+                SESSION_COLUMNS, LAST_SEEN_SQL
+            ),
+            params![player_id],
+            |row| Ok(SessionCheckpoint {
+                session: session_from_row(row, 0)?,
+                last_seen: row.get(SESSION_COLUMN_COUNT)?,
+            }),
+        );
+
+        match result {
+            Ok(checkpoint) => Ok(Some(checkpoint)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Continue a persisted session, counting everything since its start that
+    /// wasn't checkpointed as listening (including the time the daemon was down) as paused
+    pub fn resume_session(&self, session_id: i64, current_time: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions
+             SET paused_time = ?1 - start_time - COALESCE(listened_time, 0),
+                 last_seen = ?1,
+                 paused_since = NULL
+             WHERE id = ?2 AND status = 'active'",
+            params![current_time, session_id],
+        )?;
+        Ok(())
+    }
+
+    /// Finalize an active session at its last checkpoint, keeping the listening recorded so far
+    pub fn close_session_at_checkpoint(&self, session_id: i64, status: &str) -> Result<()> {
+        let aggregates_fresh = self.artist_aggregates && self.artist_aggregates_fresh()?;
+        self.conn.execute(
+            &format!(
+                "UPDATE sessions AS s
+                 SET end_time = {last_seen},
+                     listened_time = MAX(COALESCE(s.listened_time, 0), 0),
+                     paused_time = {last_seen} - s.start_time - MAX(COALESCE(s.listened_time, 0), 0),
+                     status = ?1,
+                     paused_since = NULL,
+                     scrobbled = {scrobbled},
+                     completion_ratio = {completion_ratio}
+                 WHERE s.id = ?2 AND s.status = 'active'",
+                last_seen = LAST_SEEN_SQL,
+                scrobbled = scrobbled_sql("MAX(COALESCE(s.listened_time, 0), 0)", "s.track_id"),
+                completion_ratio = completion_ratio_sql("MAX(COALESCE(s.listened_time, 0), 0)", "s.track_id")
+            ),
+            params![status, session_id],
+        )?;
+        if self.min_session_duration > 0 {
+            self.handle_short_session(session_id)?;
+        }
+        self.update_artist_aggregate(session_id, aggregates_fresh)?;
+        self.queue_if_scrobbled(session_id)
+    }
+
+    fn queue_if_scrobbled(&self, session_id: i64) -> Result<()> {
+        if self.scrobble_queue {
+            self.enqueue_scrobble(session_id)?;
+        }
+        Ok(())
+    }
+
+    /// Recompute `artist_stats` for the artist of a session that just finished, if the
+    /// table was fresh before it did
+    fn update_artist_aggregate(&self, session_id: i64, was_fresh: bool) -> Result<()> {
+        if !was_fresh {
+            return Ok(());
+        }
+        let artist: Option<String> = self.conn.query_row(
+            "SELECT t.artist FROM sessions s JOIN tracks t ON t.id = s.track_id WHERE s.id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        ).optional()?;
+
+        // A savepoint rather than a transaction, as `start_session_as` finalizes the
+        // session it replaces inside its own
+        self.conn.execute_batch("SAVEPOINT artist_aggregate")?;
+        let update = || -> Result<()> {
+            if let Some(artist) = &artist {
+                self.conn.execute("DELETE FROM artist_stats WHERE artist = ?1", params![artist])?;
+                self.conn.execute(&format!("{} AND t.artist = ?1 GROUP BY t.artist", ARTIST_AGGREGATE_SQL), params![artist])?;
+            }
+            self.conn.execute(
+                "UPDATE artist_stats_state SET generation = (SELECT generation FROM stats_generation WHERE id = 1)",
+                [],
+            )?;
+            Ok(())
+        };
+        match update() {
+            Ok(()) => Ok(self.conn.execute_batch("RELEASE artist_aggregate")?),
+            Err(e) => {
+                self.conn.execute_batch("ROLLBACK TO artist_aggregate; RELEASE artist_aggregate")?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Rebuild `artist_stats` from every finished session, returning how many artists it
+    /// holds. All-time top artists are read from it until a session changes without
+    /// `with_artist_aggregates` keeping it up to date.
+    pub fn refresh_artist_aggregates(&self, current_time: i64) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM artist_stats", [])?;
+        let artists = tx.execute(&format!("{} GROUP BY t.artist", ARTIST_AGGREGATE_SQL), [])?;
+        tx.execute(
+            "UPDATE artist_stats_state
+             SET generation = (SELECT generation FROM stats_generation WHERE id = 1), refreshed_at = ?1",
+            params![current_time],
+        )?;
+        tx.commit()?;
+        Ok(artists)
+    }
+
+    /// Recompute the stored `completion_ratio` of every finished session from its listened
+    /// time and the track's current length. `insert_or_update_track` already does this
+    /// when a track's length changes, so it's only needed for ratios stored some other
+    /// way. Returns how many sessions changed.
+    pub fn recalculate_sessions(&self) -> Result<usize> {
+        self.update_completion_ratios(None)
+    }
+
+    /// `recalculate_sessions` for the sessions of one track, or of all with `None`
+    fn update_completion_ratios(&self, track_id: Option<&str>) -> Result<usize> {
+        let ratio = completion_ratio_sql("listened_time", "sessions.track_id");
+        Ok(self.conn.execute(
+            &format!(
+                "UPDATE sessions
+                 SET completion_ratio = {ratio}
+                 WHERE status NOT IN ('active', 'imported') AND completion_ratio IS NOT {ratio}
+                   AND (?1 IS NULL OR track_id = ?1)",
+                ratio = ratio
+            ),
+            params![track_id],
+        )?)
+    }
+
+    /// Whether `artist_stats` reflects every finished session
+    fn artist_aggregates_fresh(&self) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT a.generation IS g.generation FROM artist_stats_state a, stats_generation g",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// How many artists `artist_stats` holds, when it was last fully refreshed and
+    /// whether it is still fresh
+    pub fn get_artist_aggregate_status(&self) -> Result<ArtistAggregateStatus> {
+        let (artists, refreshed_at) = self.conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM artist_stats), refreshed_at FROM artist_stats_state",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(ArtistAggregateStatus { artists, refreshed_at, fresh: self.artist_aggregates_fresh()? })
+    }
+
+    /// The player's latest session if it was a finished play of `track_id` that ended at
+    /// most `max_gap` seconds before `start_time`, so a replay can continue it
+    pub fn find_mergeable_session(&self, track_id: &str, player_id: i64, start_time: i64, max_gap: i64) -> Result<Option<i64>> {
+        let latest: Option<(i64, String, String, Option<i64>)> = self.conn.query_row(
+            "SELECT id, track_id, status, end_time FROM sessions
+             WHERE player_id = ?1
+             ORDER BY start_time DESC, id DESC
+             LIMIT 1",
+            params![player_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        ).optional()?;
+
+        Ok(latest.and_then(|(session_id, latest_track_id, status, end_time)| {
+            let gap = start_time - end_time?;
+            let finished = status != "active" && status != "imported";
+            (finished && latest_track_id == track_id && (0..=max_gap).contains(&gap)).then_some(session_id)
+        }))
+    }
+
+    /// Make a finished session active again from `resume_time`, counting the time since
+    /// it ended as paused
+    pub fn reopen_session(&self, session_id: i64, resume_time: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions
+             SET paused_time = paused_time + MAX(?1 - end_time, 0),
+                 end_time = NULL,
+                 last_seen = ?1,
+                 status = 'active',
+                 scrobbled = 0,
+                 completion_ratio = NULL
+             WHERE id = ?2 AND status != 'active' AND end_time IS NOT NULL",
+            params![resume_time, session_id],
+        )?;
+        // The merged play is queued again when it finishes. One that was already
+        // submitted can't be taken back, so only unsent attempts are dropped.
+        self.conn.execute(
+            "DELETE FROM scrobble_queue WHERE session_id = ?1 AND attempts = 0",
+            params![session_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_active_session_for_player(&self, player_id: i64) -> Result<Option<Session>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}
+             FROM sessions s
+             WHERE s.player_id = ?1 AND s.status = 'active'
+             ORDER BY s.start_time DESC 
+             LIMIT 1",
+            SESSION_COLUMNS
+        ))?;
+
+        let session = stmt.query_row(params![player_id], |row| session_from_row(row, 0));
+
+        match session {
+            Ok(s) => Ok(Some(s)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// List all sessions currently marked active, most recent first
+    pub fn list_active_sessions(&self) -> Result<Vec<SessionWithMetadata>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, {}, {}
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             JOIN players p ON s.player_id = p.id
+             WHERE s.status = 'active'
+             ORDER BY s.start_time DESC",
+            SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS
+        ))?;
+
+        let sessions = stmt.query_map([], session_with_metadata_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Seconds since the start of the listening block that is still going, counting
+    /// track changes and short breaks as continuous. `None` when nothing is playing.
+    pub fn get_current_block_seconds(&self, current_time: i64, gap: i64) -> Result<Option<i64>> {
+        let active: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sessions WHERE status = 'active'", [], |row| row.get(0),
+        )?;
+        if active == 0 {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.start_time,
+                    CASE WHEN s.status = 'active' THEN ?1 ELSE COALESCE(s.end_time, {}) END
+             FROM sessions s
+             ORDER BY s.start_time DESC",
+            LAST_SEEN_SQL
+        ))?;
+        // Rows are streamed, so history is only read back as far as the block goes
+        let sessions = stmt.query_map(params![current_time], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let block_start = listening_block_start(sessions, gap)?;
+
+        Ok(block_start.map(|start| (current_time - start).max(0)))
+    }
+
+    pub fn get_listening_stats(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<ListeningStats> {
+        self.get_filtered_listening_stats(start_time, end_time, &StatsFilter::default())
+    }
+
+    pub fn get_filtered_listening_stats(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+    ) -> Result<ListeningStats> {
+        let cache_key = format!(
+            "{:?}:{:?}:{:?}:{:?}:{:?}",
+            start_time, end_time, self.play_dedupe_window, filter, self.artist_splitter
+        );
+        let stats = self.cached_stats(&cache_key, || self.compute_listening_stats(start_time, end_time, filter))?;
+        Ok(stats.with_active_days(self.count_active_days(start_time, end_time, filter)?))
+    }
+
+    /// Distinct listening days between `start_time` and `end_time` with any listening
+    /// that `filter` counts
+    pub fn count_active_days(&self, start_time: Option<i64>, end_time: Option<i64>, filter: &StatsFilter) -> Result<i64> {
+        self.load_stats_filter_values(filter)?;
+        let days = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT date(s.start_time, {}))
+                 FROM sessions s
+                 WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {} {}",
+                self.listening_day_modifiers(), time_filter(start_time, end_time), filter.sql()
+            ),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(days)
+    }
+
+    /// Summary, top tracks, artists and albums, and recent history for a period. Unlike
+    /// calling each query separately this reads the period's sessions only once.
+    pub fn get_dashboard(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+        limits: DashboardLimits,
+    ) -> Result<Dashboard> {
+        let cache_key = format!(
+            "dashboard:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+            start_time, end_time, self.play_dedupe_window, filter, self.artist_splitter, limits
+        );
+        let mut dashboard = self.cached_stats(&cache_key, || self.compute_dashboard(start_time, end_time, filter, limits))?;
+        dashboard.stats = dashboard.stats.with_active_days(self.count_active_days(start_time, end_time, filter)?);
+        Ok(dashboard)
+    }
+
+    /// Return the cached result for `cache_key` if nothing changed since it was stored,
+    /// otherwise compute and cache it
+    fn cached_stats<T: Serialize + DeserializeOwned>(&self, cache_key: &str, compute: impl FnOnce() -> Result<T>) -> Result<T> {
+        if !self.use_stats_cache {
+            return compute();
+        }
+
+        // Active sessions grow every second, so results that include them can't be reused
+        let has_active: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sessions WHERE status = 'active')",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_active {
+            return compute();
+        }
+
+        let generation: i64 = self.conn.query_row(
+            "SELECT generation FROM stats_generation WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let cached: Option<String> = self.conn.query_row(
+            "SELECT stats FROM stats_cache WHERE cache_key = ?1 AND generation = ?2",
+            params![cache_key, generation],
+            |row| row.get(0),
+        ).optional()?;
+        if let Some(stats) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok(stats);
+        }
+
+        let stats = compute()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO stats_cache (cache_key, generation, stats) VALUES (?1, ?2, ?3)",
+            params![cache_key, generation, serde_json::to_string(&stats)?],
+        )?;
+        // Relative periods move with the clock and would otherwise pile up entries
+        self.conn.execute(
+            "DELETE FROM stats_cache
+             WHERE generation != ?1
+                OR rowid NOT IN (SELECT rowid FROM stats_cache ORDER BY rowid DESC LIMIT ?2)",
+            params![generation, STATS_CACHE_ENTRIES],
+        )?;
+        Ok(stats)
+    }
+
+    fn compute_listening_stats(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+    ) -> Result<ListeningStats> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let time_filter = format!("{} {}", time_filter(start_time, end_time), filter.sql());
+
+        if self.in_memory_stats {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT {}, {}, {}
+                 FROM sessions s
+                 JOIN tracks t ON s.track_id = t.id
+                 JOIN players p ON s.player_id = p.id
+                 WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}",
+                SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS, time_filter
+            ))?;
+            let sessions = stmt.query_map([], session_with_metadata_from_row)?.collect::<Result<Vec<_>, _>>()?;
+            return Ok(compute_stats(&sessions, &StatsOptions {
+                current_time,
+                play_dedupe_window: self.play_dedupe_window,
+                artist_splitter: self.artist_splitter.clone(),
+            }));
+        }
+
+        // Get total listening time including active sessions, and the active sessions' share
+        let (total_listening_time, active_listening_time): (i64, i64) = self.conn.query_row(
+            &format!(
+                "SELECT COALESCE(SUM(listened), 0), COALESCE(SUM(CASE WHEN status = 'active' THEN listened END), 0)
+                 FROM (
+                     SELECT status,
+                            CASE
+                                WHEN listened_time IS NOT NULL THEN listened_time
+                                WHEN status = 'active' THEN {} - start_time - paused_time
+                                ELSE 0
+                            END as listened
+                     FROM sessions s WHERE (listened_time IS NOT NULL OR status = 'active') {}
+                 )",
+                current_time, time_filter
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        // Get top tracks including active sessions
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY t.id
+             ORDER BY total_time DESC
+             LIMIT 20",
+            TRACK_COLUMNS, current_time, time_filter
+        ))?;
+
+        let mut top_tracks: Vec<TrackStats> = stmt.query_map([], |row| {
+            Ok(TrackStats {
+                track: track_from_row(row, 0)?,
+                total_listened_time: row.get(TRACK_COLUMN_COUNT)?,
+                play_count: row.get(TRACK_COLUMN_COUNT + 1)?,
+                plays_per_active_day: None,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        let play_starts = match self.play_dedupe_window {
+            Some(_) => Some(self.get_play_start_times(&time_filter)?),
+            None => None,
+        };
+        if let (Some(window), Some(play_starts)) = (self.play_dedupe_window, &play_starts) {
+            for track_stat in &mut top_tracks {
+                if let Some(starts) = play_starts.get(&track_stat.track.id) {
+                    track_stat.play_count = count_deduped_plays(starts, window);
+                }
+            }
+        }
+
+        // Get top artists including active sessions. Deduping replays needs each track's
+        // plays, which the per-artist totals don't keep.
+        let all_time = start_time.is_none() && end_time.is_none() && *filter == StatsFilter::default();
+        let top_artists = if self.artist_splitter.is_some() || play_starts.is_some() {
+            self.compute_per_track_artist_stats(current_time, &time_filter, play_starts.as_ref())?
+        } else if all_time && self.artist_aggregates_fresh()? {
+            self.materialized_artist_stats(current_time)?
+        } else {
+            self.compute_artist_stats(current_time, &time_filter)?
+        };
+
+        // Get listening history including active sessions, excluding very short sessions
+        let mut stmt = self.conn.prepare(&history_sql(&time_filter))?;
+        let listening_history: Vec<SessionWithMetadata> = stmt
+            .query_map(params![current_time, 100], history_entry_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT COALESCE(s.content_type, 'music') as content_type,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY 1
+             ORDER BY total_time DESC",
+            time_filter
+        ))?;
+        let content_type_totals: Vec<ContentTypeTotal> = stmt
+            .query_map(params![current_time], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(content_type, total_listening_time)| {
+                ContentType::parse(&content_type).map(|content_type| ContentTypeTotal { content_type, total_listening_time })
+            })
+            .collect();
+
+        Ok(ListeningStats {
+            total_listening_time,
+            active_listening_time,
+            top_tracks,
+            top_artists,
+            listening_history,
+            content_type_totals,
+            active_days: 0,
+        })
+    }
+
+    fn compute_dashboard(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+        limits: DashboardLimits,
+    ) -> Result<Dashboard> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let time_filter = format!("{} {}", time_filter(start_time, end_time), filter.sql());
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, {}, {},
+                    CASE
+                        WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                        WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                        ELSE 0
+                    END as calculated_listened_time,
+                    COALESCE(s.content_type, 'music') as content_type,
+                    s.scrobbled
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             JOIN players p ON s.player_id = p.id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             ORDER BY s.start_time DESC",
+            SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS, time_filter
+        ))?;
+        let content_type_index = SESSION_COLUMN_COUNT + TRACK_COLUMN_COUNT + PLAYER_COLUMN_COUNT + 1;
+        let rows = stmt.query_map(params![current_time], |row| {
+            Ok((history_entry_from_row(row)?, row.get::<_, String>(content_type_index)?, row.get::<_, bool>(content_type_index + 1)?))
+        })?;
+
+        let mut total_listening_time = 0;
+        let mut active_listening_time = 0;
+        let mut plays = 0;
+        let mut scrobbles = 0;
+        // Each track's stats and its play start times, newest first
+        let mut tracks: HashMap<String, (TrackStats, Vec<i64>)> = HashMap::new();
+        let mut albums: HashMap<String, GroupedStats> = HashMap::new();
+        let mut content_types: HashMap<String, i64> = HashMap::new();
+        let mut listening_history = Vec::new();
+
+        for row in rows {
+            let (entry, content_type, scrobbled) = row?;
+            let listened = entry.session.listened_time.unwrap_or(0);
+            total_listening_time += listened;
+            if entry.session.status == "active" {
+                active_listening_time += listened;
+            }
+            plays += 1;
+            scrobbles += i64::from(scrobbled);
+            *content_types.entry(content_type).or_default() += listened;
+
+            let album_key = format!("{}::{}", entry.track.album, entry.track.artist);
+            let album = albums.entry(album_key.clone()).or_insert_with(|| GroupedStats {
+                key: album_key,
+                label: format!("{} - {}", metadata_label(&entry.track.album), metadata_label(&entry.track.artist)),
+                total_listened_time: 0,
+                play_count: 0,
+            });
+            album.total_listened_time += listened;
+            album.play_count += 1;
+
+            let (track, starts) = tracks.entry(entry.track.id.clone()).or_insert_with(|| {
+                (TrackStats { track: entry.track.clone(), total_listened_time: 0, play_count: 0, plays_per_active_day: None }, Vec::new())
+            });
+            track.total_listened_time += listened;
+            track.play_count += 1;
+            starts.push(entry.session.start_time);
+
+            // Like `history_sql`, skip finished sessions nothing was heard of
+            if listening_history.len() < limits.history && (entry.session.status == "active" || listened > 0) {
+                listening_history.push(entry);
+            }
+        }
+
+        let mut top_tracks: Vec<TrackStats> = tracks
+            .into_values()
+            .map(|(mut track, mut starts)| {
+                if let Some(window) = self.play_dedupe_window {
+                    starts.reverse();
+                    track.play_count = count_deduped_plays(&starts, window);
+                }
+                track
+            })
+            .collect();
+
+        // Credit every track's time and deduped plays to each of its artists, as
+        // `compute_per_track_artist_stats` does
+        let mut artists: HashMap<String, ArtistStats> = HashMap::new();
+        for track in &top_tracks {
+            for artist in self.credited_artists(&track.track.artist) {
+                let stats = artists.entry(artist.clone()).or_insert_with(|| ArtistStats {
+                    artist,
+                    total_listened_time: 0,
+                    track_count: 0,
+                    play_count: 0,
+                    plays_per_active_day: None,
+                });
+                stats.total_listened_time += track.total_listened_time;
+                stats.track_count += 1;
+                stats.play_count += track.play_count;
+            }
+        }
+
+        top_tracks.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.track.id.cmp(&b.track.id)));
+        top_tracks.truncate(limits.top);
+
+        let mut top_artists: Vec<ArtistStats> = artists.into_values().collect();
+        top_artists.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.artist.cmp(&b.artist)));
+        top_artists.truncate(limits.top);
+
+        let mut top_albums: Vec<GroupedStats> = albums.into_values().collect();
+        top_albums.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.key.cmp(&b.key)));
+        top_albums.truncate(limits.top);
+
+        let mut content_type_totals: Vec<ContentTypeTotal> = content_types
+            .into_iter()
+            .filter_map(|(content_type, total_listening_time)| {
+                ContentType::parse(&content_type).map(|content_type| ContentTypeTotal { content_type, total_listening_time })
+            })
+            .collect();
+        content_type_totals.sort_by(|a, b| b.total_listening_time.cmp(&a.total_listening_time).then_with(|| a.content_type.as_str().cmp(b.content_type.as_str())));
+
+        Ok(Dashboard {
+            stats: ListeningStats {
+                total_listening_time,
+                active_listening_time,
+                top_tracks,
+                top_artists,
+                listening_history,
+                content_type_totals,
+                active_days: 0,
+            },
+            top_albums,
+            plays,
+            scrobbles,
+            longest_session: self.get_longest_session(start_time, end_time, filter)?,
+        })
+    }
+
+    /// The finished session with the most listened time, the earliest on a tie. Active
+    /// sessions are left out, as their time is still growing.
+    pub fn get_longest_session(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+    ) -> Result<Option<SessionWithMetadata>> {
+        self.load_stats_filter_values(filter)?;
+        Ok(self.conn.query_row(
+            &format!(
+                "SELECT {}, {}, {}
+                 FROM sessions s
+                 JOIN tracks t ON s.track_id = t.id
+                 JOIN players p ON s.player_id = p.id
+                 WHERE s.status != 'active' AND s.listened_time > 0 {} {}
+                 ORDER BY s.listened_time DESC, s.start_time
+                 LIMIT 1",
+                SESSION_COLUMNS, TRACK_COLUMNS, PLAYER_COLUMNS, time_filter(start_time, end_time), filter.sql()
+            ),
+            [],
+            session_with_metadata_from_row,
+        ).optional()?)
+    }
+
+    /// All-time top 20 artists from `artist_stats`, with active sessions added live the
+    /// way `compute_artist_stats` counts them
+    fn materialized_artist_stats(&self, current_time: i64) -> Result<Vec<ArtistStats>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT artist, SUM(total_time) as total_time, SUM(track_count), SUM(play_count)
+             FROM (
+                 SELECT artist, total_listened_time as total_time, track_count, play_count FROM artist_stats
+                 UNION ALL
+                 SELECT t.artist,
+                        SUM(COALESCE(s.listened_time, ?1 - s.start_time - s.paused_time)),
+                        -- Only tracks with no finished session are new to the artist's count
+                        COUNT(DISTINCT CASE WHEN NOT EXISTS (
+                            SELECT 1 FROM sessions f
+                            WHERE f.track_id = t.id AND f.status != 'active' AND f.listened_time IS NOT NULL
+                        ) THEN t.id END),
+                        COUNT(s.id)
+                 FROM sessions s
+                 JOIN tracks t ON t.id = s.track_id
+                 WHERE s.status = 'active' AND t.artist != '{}'
+                 GROUP BY t.artist
+             )
+             GROUP BY artist
+             ORDER BY total_time DESC
+             LIMIT 20",
+            MISSING_METADATA
+        ))?;
+
+        let top_artists: Vec<ArtistStats> = stmt.query_map(params![current_time], |row| {
+            Ok(ArtistStats {
+                artist: row.get(0)?,
+                total_listened_time: row.get(1)?,
+                track_count: row.get(2)?,
+                play_count: row.get(3)?,
+                plays_per_active_day: None,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(top_artists)
+    }
+
+    /// Top 20 artists by their combined credit string
+    fn compute_artist_stats(&self, current_time: i64, time_filter: &str) -> Result<Vec<ArtistStats>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT t.artist,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(DISTINCT t.id) as track_count,
+                    COUNT(s.id) as play_count
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') AND t.artist != '{}' {}
+             GROUP BY t.artist
+             ORDER BY total_time DESC
+             LIMIT 20",
+            current_time, MISSING_METADATA, time_filter
+        ))?;
+
+        let top_artists: Vec<ArtistStats> = stmt.query_map([], |row| {
+            Ok(ArtistStats {
+                artist: row.get(0)?,
+                total_listened_time: row.get(1)?,
+                track_count: row.get(2)?,
+                play_count: row.get(3)?,
+                plays_per_active_day: None,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(top_artists)
+    }
+
+    /// Top 20 artists summed from their tracks, with every multi-artist track credited to
+    /// each of its artists. With `play_starts`, each track's plays are deduped first.
+    fn compute_per_track_artist_stats(
+        &self,
+        current_time: i64,
+        time_filter: &str,
+        play_starts: Option<&HashMap<String, Vec<i64>>>,
+    ) -> Result<Vec<ArtistStats>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT t.id, t.artist,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN {} - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY t.id",
+            current_time, time_filter
+        ))?;
+
+        let mut artists: HashMap<String, ArtistStats> = HashMap::new();
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?))
+        })?;
+        for row in rows {
+            let (track_id, credit, total_time, mut play_count) = row?;
+            if let (Some(window), Some(starts)) = (self.play_dedupe_window, play_starts.and_then(|starts| starts.get(&track_id))) {
+                play_count = count_deduped_plays(starts, window);
+            }
+            for artist in self.credited_artists(&credit) {
+                let stats = artists.entry(artist.clone()).or_insert_with(|| ArtistStats {
+                    artist,
+                    total_listened_time: 0,
+                    track_count: 0,
+                    play_count: 0,
+                    plays_per_active_day: None,
+                });
+                stats.total_listened_time += total_time;
+                stats.track_count += 1;
+                stats.play_count += play_count;
+            }
+        }
+
+        let mut top_artists: Vec<ArtistStats> = artists.into_values().collect();
+        top_artists.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.artist.cmp(&b.artist)));
+        top_artists.truncate(20);
+        Ok(top_artists)
+    }
+
+    /// Fill the connection's `stats_filter_values` table with the filter's names and label
+    fn load_stats_filter_values(&self, filter: &StatsFilter) -> Result<()> {
+        if !filter.has_bound_values() {
+            return Ok(());
+        }
+
+        self.conn.execute_batch(
+            "CREATE TEMP TABLE IF NOT EXISTS stats_filter_values (kind TEXT NOT NULL, value TEXT NOT NULL);
+             DELETE FROM temp.stats_filter_values;",
+        )?;
+        let mut stmt = self.conn.prepare("INSERT INTO temp.stats_filter_values (kind, value) VALUES (?1, ?2)")?;
+        for artist in &filter.exclude_artists {
+            stmt.execute(params!["artist", artist])?;
+        }
+        for title in &filter.exclude_tracks {
+            stmt.execute(params!["track", title])?;
+        }
+        if let Some(label) = &filter.label {
+            stmt.execute(params!["label", label])?;
+        }
+        for player in &filter.exclude_players {
+            for player_id in self.find_player_ids(player)? {
+                stmt.execute(params!["player", player_id.to_string()])?;
+            }
+        }
+        if let Some(title) = &filter.title_contains {
+            stmt.execute(params!["title_contains", like_pattern(title)])?;
+        }
+        if let Some(artist) = &filter.artist_contains {
+            stmt.execute(params!["artist_contains", like_pattern(artist)])?;
+        }
+        Ok(())
+    }
+
+    /// Top tracks and artists with each session's time scaled by `recency_weight`, so
+    /// recent listening outranks old favourites. Times in the result are weighted seconds;
+    /// play counts are not weighted. The history and content type totals are left empty.
+    pub fn get_recency_weighted_stats(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+        half_life_seconds: f64,
+    ) -> Result<ListeningStats> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    s.start_time,
+                    CASE
+                        WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                        WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                        ELSE 0
+                    END as listened
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {} {}
+             ORDER BY s.start_time",
+            TRACK_COLUMNS, time_filter(start_time, end_time), filter.sql()
+        ))?;
+
+        // track id -> (track, weighted seconds, start times)
+        let mut tracks: HashMap<String, (Track, f64, Vec<i64>)> = HashMap::new();
+        let rows = stmt.query_map(params![current_time], |row| {
+            Ok((track_from_row(row, 0)?, row.get::<_, i64>(TRACK_COLUMN_COUNT)?, row.get::<_, i64>(TRACK_COLUMN_COUNT + 1)?))
+        })?;
+        for row in rows {
+            let (track, start, listened) = row?;
+            let weighted = listened as f64 * recency_weight(current_time - start, half_life_seconds);
+            let entry = tracks.entry(track.id.clone()).or_insert_with(|| (track, 0.0, Vec::new()));
+            entry.1 += weighted;
+            entry.2.push(start);
+        }
+
+        // artist -> (weighted seconds, tracks, plays)
+        let mut artists: HashMap<String, (f64, i64, i64)> = HashMap::new();
+        let mut top_tracks: Vec<TrackStats> = Vec::with_capacity(tracks.len());
+        let mut total = 0.0;
+        for (track, weighted, starts) in tracks.into_values() {
+            let play_count = match self.play_dedupe_window {
+                Some(window) => count_deduped_plays(&starts, window),
+                None => starts.len() as i64,
+            };
+
+            for name in self.credited_artists(&track.artist) {
+                let artist = artists.entry(name).or_default();
+                artist.0 += weighted;
+                artist.1 += 1;
+                artist.2 += play_count;
+            }
+            total += weighted;
+
+            top_tracks.push(TrackStats { track, total_listened_time: weighted.round() as i64, play_count, plays_per_active_day: None });
+        }
+
+        top_tracks.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.track.id.cmp(&b.track.id)));
+        let mut top_artists: Vec<ArtistStats> = artists
+            .into_iter()
+            .map(|(artist, (weighted, track_count, play_count))| ArtistStats {
+                artist,
+                total_listened_time: weighted.round() as i64,
+                track_count,
+                play_count,
+                plays_per_active_day: None,
+            })
+            .collect();
+        top_artists.sort_by(|a, b| b.total_listened_time.cmp(&a.total_listened_time).then_with(|| a.artist.cmp(&b.artist)));
+
+        let stats = ListeningStats {
+            total_listening_time: total.round() as i64,
+            active_listening_time: 0,
+            top_tracks,
+            top_artists,
+            listening_history: Vec::new(),
+            content_type_totals: Vec::new(),
+            active_days: 0,
+        };
+        Ok(stats.with_active_days(self.count_active_days(start_time, end_time, filter)?))
+    }
+
+    /// Start times of every counted session, grouped by track and ordered within each track
+    fn get_play_start_times(&self, time_filter: &str) -> Result<HashMap<String, Vec<i64>>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.track_id, s.start_time
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             ORDER BY s.track_id, s.start_time",
+            time_filter
+        ))?;
+
+        let mut play_starts: HashMap<String, Vec<i64>> = HashMap::new();
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        for row in rows {
+            let (track_id, start_time) = row?;
+            play_starts.entry(track_id).or_default().push(start_time);
+        }
+
+        Ok(play_starts)
+    }
+
+    /// Listening per player for every track in the range, keyed by track id, each track's
+    /// players ordered by listening time. Play counts are raw session counts, without
+    /// the replay dedupe window.
+    pub fn get_track_player_breakdown(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+    ) -> Result<HashMap<String, Vec<PlayerShare>>> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.track_id, p.identity,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM sessions s
+             JOIN players p ON s.player_id = p.id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {} {}
+             GROUP BY s.track_id, s.player_id
+             ORDER BY s.track_id, total_time DESC, p.identity",
+            time_filter(start_time, end_time), filter.sql()
+        ))?;
+
+        let mut breakdown: HashMap<String, Vec<PlayerShare>> = HashMap::new();
+        let rows = stmt.query_map(params![current_time], |row| {
+            Ok((row.get::<_, String>(0)?, PlayerShare {
+                player: row.get(1)?,
+                total_listened_time: row.get(2)?,
+                play_count: row.get(3)?,
+            }))
+        })?;
+        for row in rows {
+            let (track_id, share) = row?;
+            breakdown.entry(track_id).or_default().push(share);
+        }
+
+        Ok(breakdown)
+    }
+
+    /// Rank of an artist or track by listening time within every bucket that has listening.
+    /// Entities tied on time share a rank, and the next rank skips accordingly.
+    pub fn get_rank_history(&self, entity: &RankEntity, bucket: TimeBucket) -> Result<Vec<RankPoint>> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {bucket} as bucket,
+                    {key} as entity_key,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             JOIN players p ON s.player_id = p.id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active')
+             GROUP BY bucket, entity_key
+             ORDER BY bucket",
+            bucket = bucket.label_sql("s.start_time", &self.listening_day_modifiers()),
+            key = entity.group_by().key_sql(),
+        ))?;
+
+        let rows = stmt.query_map(params![current_time], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        // Rows arrive ordered by bucket, so each bucket's totals are contiguous
+        let mut points: Vec<RankPoint> = Vec::new();
+        let mut bucket_totals: Vec<i64> = Vec::new();
+        let mut entity_time: Option<i64> = None;
+
+        let mut rows = rows.into_iter().peekable();
+        while let Some((bucket_label, key, total)) = rows.next() {
+            bucket_totals.push(total);
+            if key == entity.key() {
+                entity_time = Some(total);
+            }
+
+            let bucket_done = rows.peek().is_none_or(|(next, _, _)| *next != bucket_label);
+            if bucket_done {
+                let rank = entity_time.map(|time| 1 + bucket_totals.iter().filter(|&&t| t > time).count());
+                points.push(RankPoint {
+                    bucket: bucket_label,
+                    rank,
+                    listened_time: entity_time.unwrap_or(0),
+                    ranked_entities: bucket_totals.len(),
+                });
+                bucket_totals.clear();
+                entity_time = None;
+            }
+        }
+
+        Ok(points)
+    }
+
+    /// Listening on `month`/`day` in each year before the one `current_time` is in that
+    /// has any, newest first. Days are listening days, see `with_day_start_hour`, so
+    /// February 29 only matches leap years.
+    pub fn get_on_this_day(&self, month: u32, day: u32, current_time: i64) -> Result<Vec<YearSlice>> {
+        let current_year = listening_day(current_time, self.day_start_hour, self.day_zone)
+            .context("Invalid current time")?
+            .year();
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT DISTINCT CAST(strftime('%Y', start_time, {day}) AS INTEGER) as year
+             FROM sessions
+             WHERE strftime('%m-%d', start_time, {day}) = ?1
+             ORDER BY year DESC",
+            day = self.listening_day_modifiers()
+        ))?;
+        let years = stmt.query_map(params![format!("{:02}-{:02}", month, day)], |row| row.get::<_, i32>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut slices = Vec::new();
+        for year in years.into_iter().filter(|&year| year < current_year) {
+            let Some(date) = NaiveDate::from_ymd_opt(year, month, day) else {
+                continue;
+            };
+            let start = listening_day_start(date, self.day_start_hour, self.day_zone)?;
+            let end = listening_day_start(date.succ_opt().context("Date out of range")?, self.day_start_hour, self.day_zone)? - 1;
+
+            let stats = self.get_listening_stats(Some(start), Some(end))?;
+            slices.push(YearSlice {
+                year,
+                total_listening_time: stats.total_listening_time,
+                top_tracks: stats.top_tracks,
+            });
+        }
+
+        Ok(slices)
+    }
+
+    /// Seconds listened in each local hour of each weekday, Monday first. The weekday is
+    /// the listening day's, so with a 4am day start, 2am Tuesday is in Monday's row.
+    pub fn get_hourly_heatmap(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<[[i64; 24]; 7]> {
+        let current_time = Local::now().timestamp();
+
+        let mut heatmap = [[0i64; 24]; 7];
+        if self.split_day_boundary {
+            // Each day's part goes to the hour it starts in
+            for (part_start, listened) in self.day_parts(start_time, end_time, current_time)? {
+                let day = listening_day(part_start, self.day_start_hour, self.day_zone).context("Invalid session start")?;
+                let hour = self.day_zone.naive(part_start).context("Invalid session start")?.hour();
+                heatmap[day.weekday().num_days_from_monday() as usize][hour as usize] += listened;
+            }
+            return Ok(heatmap);
+        }
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT CAST(strftime('%w', s.start_time, {}) AS INTEGER) as weekday,
+                    CAST(strftime('%H', s.start_time, {}) AS INTEGER) as hour,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY weekday, hour",
+            self.listening_day_modifiers(), self.day_zone.sql_modifiers(), time_filter(start_time, end_time)
+        ))?;
+        let rows = stmt.query_map(params![current_time], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        for row in rows {
+            // strftime's %w counts from Sunday = 0
+            let (sunday_based, hour, total) = row?;
+            heatmap[((sunday_based + 6) % 7) as usize][hour] = total;
+        }
+
+        Ok(heatmap)
+    }
+
+    /// Compare the last `window_days` of listening with the average of the
+    /// `lookback_windows` windows of the same length before it
+    pub fn get_trend(&self, window_days: i64, lookback_windows: usize) -> Result<TrendReport> {
+        self.get_trend_at(chrono::Utc::now().timestamp(), window_days, lookback_windows)
+    }
+
+    /// `get_trend` with windows ending at `current_time`. Prior windows starting before
+    /// the first session are left out of the baseline, since their listening wasn't recorded.
+    pub fn get_trend_at(&self, current_time: i64, window_days: i64, lookback_windows: usize) -> Result<TrendReport> {
+        let window = window_days.max(1) * 86400;
+        let first_session: Option<i64> = self.conn.query_row(
+            "SELECT MIN(start_time) FROM sessions WHERE status != 'active'",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT COALESCE(SUM(listened_time), 0) FROM sessions
+             WHERE status != 'active' AND start_time >= ?1 AND start_time < ?2"
+        )?;
+        let mut listened_between = |start: i64, end: i64| -> Result<i64> {
+            Ok(stmt.query_row(params![start, end], |row| row.get(0))?)
+        };
+
+        let recent_listened_time = listened_between(current_time - window, current_time)?;
+        let mut prior = Vec::new();
+        for k in 1..=lookback_windows as i64 {
+            let start = current_time - (k + 1) * window;
+            if first_session.is_none_or(|first| first > start) {
+                break;
+            }
+            prior.push(listened_between(start, start + window)?);
+        }
+
+        let baseline_listened_time = (!prior.is_empty())
+            .then(|| prior.iter().sum::<i64>() as f64 / prior.len() as f64);
+        let change_percent = baseline_listened_time
+            .filter(|&baseline| baseline > 0.0)
+            .map(|baseline| (recent_listened_time as f64 - baseline) / baseline * 100.0);
+
+        let direction = match (baseline_listened_time, change_percent) {
+            (None, _) => TrendDirection::NotEnoughData,
+            (Some(_), Some(change)) if change >= TREND_FLAT_PERCENT => TrendDirection::Up,
+            (Some(_), Some(change)) if change <= -TREND_FLAT_PERCENT => TrendDirection::Down,
+            // Nothing at all before, so anything now is up
+            (Some(_), None) if recent_listened_time > 0 => TrendDirection::Up,
+            (Some(_), _) => TrendDirection::Flat,
+        };
+
+        Ok(TrendReport {
+            window_days: window / 86400,
+            lookback_windows: prior.len(),
+            recent_listened_time,
+            baseline_listened_time,
+            change_percent,
+            direction,
+        })
+    }
+
+    /// Average daily listening per weekday, Monday first. Each weekday's total is
+    /// divided by how many of that weekday the range covers, counting listening days.
+    /// An open start begins at the first session, an open end runs until now.
+    pub fn get_weekday_averages(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<[WeekdayAvg; 7]> {
+        let current_time = Local::now().timestamp();
+
+        let mut totals = [0i64; 7];
+        for (date, total) in self.daily_totals(start_time, end_time, current_time)? {
+            totals[date.weekday().num_days_from_monday() as usize] += total;
+        }
+
+        let range_start = match start_time {
+            Some(start) => Some(start),
+            None => self.conn.query_row("SELECT MIN(start_time) FROM sessions", [], |row| row.get(0))?,
+        };
+
+        let mut days = [0i64; 7];
+        if let Some(range_start) = range_start {
+            let first = listening_day(range_start, self.day_start_hour, self.day_zone).context("Invalid range start")?;
+            let last = listening_day(end_time.unwrap_or(current_time).min(current_time), self.day_start_hour, self.day_zone).context("Invalid range end")?;
+            for date in first.iter_days().take_while(|date| *date <= last) {
+                days[date.weekday().num_days_from_monday() as usize] += 1;
+            }
+        }
+
+        let mut weekday = Weekday::Mon;
+        Ok(std::array::from_fn(|i| {
+            let avg = WeekdayAvg {
+                weekday,
+                days: days[i],
+                total_listened_time: totals[i],
+                average_listened_time: if days[i] > 0 { totals[i] / days[i] } else { 0 },
+            };
+            weekday = weekday.succ();
+            avg
+        }))
+    }
+
+    /// Seconds listened on each listening day of sessions starting in the range. A
+    /// session counts toward the day it started unless `with_day_boundary_split` is on.
+    pub fn get_daily_totals(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<BTreeMap<NaiveDate, i64>> {
+        self.daily_totals(start_time, end_time, Local::now().timestamp())
+    }
+
+    fn daily_totals(&self, start_time: Option<i64>, end_time: Option<i64>, current_time: i64) -> Result<BTreeMap<NaiveDate, i64>> {
+        let mut totals = BTreeMap::new();
+        if self.split_day_boundary {
+            for (part_start, listened) in self.day_parts(start_time, end_time, current_time)? {
+                let day = listening_day(part_start, self.day_start_hour, self.day_zone).context("Invalid session start")?;
+                *totals.entry(day).or_insert(0) += listened;
+            }
+            return Ok(totals);
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT date(s.start_time, {}) as day,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY day",
+            self.listening_day_modifiers(), time_filter(start_time, end_time)
+        ))?;
+        let rows = stmt.query_map(params![current_time], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        for row in rows {
+            let (day, total) = row?;
+            let day = NaiveDate::parse_from_str(&day, "%Y-%m-%d").ok().with_context(|| format!("Invalid day '{}'", day))?;
+            totals.insert(day, total);
+        }
+        Ok(totals)
+    }
+
+    /// Listened time of sessions starting in the range, divided at listening day
+    /// boundaries in proportion to the wall-clock time spent in each day. Each part is
+    /// the time it starts at and the seconds listened in it.
+    fn day_parts(&self, start_time: Option<i64>, end_time: Option<i64>, current_time: i64) -> Result<Vec<(i64, i64)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.start_time,
+                    COALESCE(s.end_time, ?1),
+                    CASE
+                        WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                        WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                        ELSE 0
+                    END
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}",
+            time_filter(start_time, end_time)
+        ))?;
+        let sessions = stmt.query_map(params![current_time], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut parts = Vec::new();
+        for (session_start, session_end, listened) in sessions {
+            let span = session_end - session_start;
+            let mut part_start = session_start;
+            let mut allotted = 0;
+            while part_start < session_end {
+                let day = listening_day(part_start, self.day_start_hour, self.day_zone).context("Invalid session start")?;
+                let next_day = listening_day_start(day.succ_opt().context("Date out of range")?, self.day_start_hour, self.day_zone)?;
+                let part_end = next_day.min(session_end);
+                // Rounded cumulatively so the parts add up to the whole session
+                let allotted_by_end = listened * (part_end - session_start) / span;
+                parts.push((part_start, allotted_by_end - allotted));
+                allotted = allotted_by_end;
+                part_start = part_end;
+            }
+            if span <= 0 {
+                parts.push((session_start, listened));
+            }
+        }
+        Ok(parts)
+    }
+
+    /// How regularly the range was listened to, see `ConsistencyScore` for the formula.
+    /// Days are listening days; an open start begins at the first session, an open end
+    /// runs until now.
+    pub fn get_consistency(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<ConsistencyScore> {
+        let current_time = Local::now().timestamp();
+
+        let daily_totals: Vec<i64> = self.daily_totals(start_time, end_time, current_time)?
+            .into_values()
+            .filter(|&total| total > 0)
+            .collect();
+
+        let range_start = match start_time {
+            Some(start) => Some(start),
+            None => self.conn.query_row("SELECT MIN(start_time) FROM sessions", [], |row| row.get(0))?,
+        };
+        let days = match range_start {
+            Some(range_start) => {
+                let first = listening_day(range_start, self.day_start_hour, self.day_zone).context("Invalid range start")?;
+                let last = listening_day(end_time.unwrap_or(current_time).min(current_time), self.day_start_hour, self.day_zone).context("Invalid range end")?;
+                ((last - first).num_days() + 1).max(0)
+            }
+            None => 0,
+        };
+
+        // Sessions before an open start can't fall outside the range, so this only
+        // guards against an end before the start
+        let active_days = (daily_totals.len() as i64).min(days);
+        let active_day_fraction = if days > 0 { active_days as f64 / days as f64 } else { 0.0 };
+
+        let (mean, std_dev) = if daily_totals.is_empty() {
+            (0.0, 0.0)
+        } else {
+            let n = daily_totals.len() as f64;
+            let mean = daily_totals.iter().sum::<i64>() as f64 / n;
+            let variance = daily_totals.iter().map(|&total| (total as f64 - mean).powi(2)).sum::<f64>() / n;
+            (mean, variance.sqrt())
+        };
+        let coefficient_of_variation = if mean > 0.0 { std_dev / mean } else { 0.0 };
+
+        Ok(ConsistencyScore {
+            days,
+            active_days,
+            active_day_fraction,
+            mean_daily_listened_time: mean,
+            daily_std_dev: std_dev,
+            coefficient_of_variation,
+            score: 100.0 * active_day_fraction / (1.0 + coefficient_of_variation),
+        })
+    }
+
+    /// Every known track on an album in disc and track order, with its listening
+    /// totals. Tracks without a track number sort after numbered ones, by title.
+    pub fn get_album_tracks(&self, album: &str, artist: Option<&str>) -> Result<Vec<TrackStats>> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM tracks t
+             LEFT JOIN sessions s ON t.id = s.track_id
+                 AND (s.listened_time IS NOT NULL OR s.status = 'active')
+             WHERE t.album = ?2 AND (?3 IS NULL OR t.artist = ?3)
+             GROUP BY t.id
+             ORDER BY t.disc_number IS NULL, t.disc_number,
+                      t.track_number IS NULL, t.track_number,
+                      t.title",
+            TRACK_COLUMNS
+        ))?;
+
+        let tracks = stmt.query_map(params![current_time, album, artist], |row| {
+            Ok(TrackStats {
+                track: track_from_row(row, 0)?,
+                total_listened_time: row.get(TRACK_COLUMN_COUNT)?,
+                play_count: row.get(TRACK_COLUMN_COUNT + 1)?,
+                plays_per_active_day: None,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tracks)
+    }
+
+    /// Every play of the listening day `date`, oldest first, with the gaps between them
+    pub fn get_day_timeline(&self, date: NaiveDate) -> Result<Vec<TimelineEntry>> {
+        let start_time = listening_day_start(date, self.day_start_hour, self.day_zone)?;
+        let next_day = date.succ_opt().context("Date out of range")?;
+        let end_time = listening_day_start(next_day, self.day_start_hour, self.day_zone)? - 1;
+
+        let mut plays = Vec::new();
+        self.for_each_history_session(Some(start_time), Some(end_time), None, &StatsFilter::default(), |play| {
+            plays.push(play);
+            Ok::<_, GopalError>(())
+        })?;
+
+        let mut previous_end: Option<i64> = None;
+        Ok(plays.into_iter().rev().map(|play| {
+            let session = &play.session;
+            let end_time = session.end_time
+                .unwrap_or(session.start_time + session.paused_time + session.listened_time.unwrap_or(0));
+            let gap_before = previous_end.map(|previous| (session.start_time - previous).max(0));
+            previous_end = Some(previous_end.map_or(end_time, |previous| previous.max(end_time)));
+            TimelineEntry { play, end_time, gap_before }
+        }).collect())
+    }
+
+    /// Everything heard by an artist, matched case-insensitively against the full credit
+    /// or the primary artist, with the day each track was first played
+    pub fn get_artist_catalog(&self, artist: &str) -> Result<ArtistCatalog> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    MIN(s.start_time) as first_played,
+                    COUNT(s.id) as play_count,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active')
+               AND (t.artist = ?2 COLLATE NOCASE OR t.primary_artist = ?2 COLLATE NOCASE)
+             GROUP BY t.id
+             ORDER BY first_played, t.title",
+            TRACK_COLUMNS
+        ))?;
+
+        let discoveries = stmt.query_map(params![current_time, artist], |row| {
+            Ok(TrackDiscovery {
+                track: track_from_row(row, 0)?,
+                first_played: row.get(TRACK_COLUMN_COUNT)?,
+                play_count: row.get(TRACK_COLUMN_COUNT + 1)?,
+                total_listened_time: row.get(TRACK_COLUMN_COUNT + 2)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ArtistCatalog {
+            artist: artist.to_string(),
+            distinct_tracks: discoveries.len() as i64,
+            play_count: discoveries.iter().map(|d| d.play_count).sum(),
+            total_listened_time: discoveries.iter().map(|d| d.total_listened_time).sum(),
+            discoveries,
+        })
+    }
+
+    /// Albums with at least one track heard in the range, most completely heard first.
+    /// A track counts as heard once it has a play that counts as a scrobble, so skipping
+    /// through an album doesn't complete it. The album's size comes from every track of
+    /// it ever seen, not just the range.
+    pub fn get_album_completeness(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<Vec<AlbumCompleteness>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT t.album, t.artist, t.title, t.disc_number, t.track_number, COUNT(s.id) > 0 as heard
+             FROM tracks t
+             LEFT JOIN sessions s ON t.id = s.track_id AND s.scrobbled = 1 {}
+             WHERE t.album != ?1
+             GROUP BY t.id",
+            time_filter(start_time, end_time)
+        ))?;
+
+        #[derive(Default)]
+        struct Album {
+            heard: HashSet<(Option<i32>, Option<i32>, String)>,
+            last_track_by_disc: HashMap<Option<i32>, i32>,
+        }
+
+        let mut albums: BTreeMap<(String, String), Album> = BTreeMap::new();
+        let mut rows = stmt.query(params![MISSING_METADATA])?;
+        while let Some(row) = rows.next()? {
+            let album = albums.entry((row.get(0)?, row.get(1)?)).or_default();
+            let title: String = row.get(2)?;
+            let disc: Option<i32> = row.get(3)?;
+            let number: Option<i32> = row.get(4)?;
+
+            if let Some(number) = number {
+                let last = album.last_track_by_disc.entry(disc).or_insert(number);
+                *last = (*last).max(number);
+            }
+            if row.get(5)? {
+                // Numbered tracks are told apart by position, so a retitled copy isn't counted twice
+                let key = match number {
+                    Some(_) => (disc, number, String::new()),
+                    None => (disc, None, title),
+                };
+                album.heard.insert(key);
+            }
+        }
+
+        let mut completeness: Vec<AlbumCompleteness> = albums.into_iter()
+            .filter(|(_, album)| !album.heard.is_empty())
+            .map(|((album, artist), tracks)| {
+                let heard_tracks = tracks.heard.len() as i64;
+                let total_tracks = (!tracks.last_track_by_disc.is_empty()).then(|| {
+                    let numbered: i64 = tracks.last_track_by_disc.values().map(|&last| i64::from(last.max(0))).sum();
+                    numbered.max(heard_tracks)
+                });
+                AlbumCompleteness {
+                    album,
+                    artist,
+                    heard_tracks,
+                    total_tracks,
+                    percent: total_tracks.map(|total| heard_tracks as f64 / total as f64 * 100.0),
+                }
+            })
+            .collect();
+
+        completeness.sort_by(|a, b| b.percent.unwrap_or(-1.0).total_cmp(&a.percent.unwrap_or(-1.0))
+            .then(b.heard_tracks.cmp(&a.heard_tracks))
+            .then_with(|| a.album.cmp(&b.album)));
+        Ok(completeness)
+    }
+
+    /// Tracks missing any of the fields `Track::missing_fields` checks, most played first,
+    /// to find what needs correcting by hand
+    pub fn get_incomplete_tracks(&self) -> Result<Vec<Track>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}
+             FROM tracks t
+             LEFT JOIN sessions s ON t.id = s.track_id
+             WHERE t.artist = ?1 OR t.album = ?1
+                OR t.length IS NULL OR t.length <= 0
+                OR t.art_url IS NULL OR t.art_url = ''
+             GROUP BY t.id
+             ORDER BY COUNT(s.id) DESC, t.title",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt.query_map(params![MISSING_METADATA], |row| track_from_row(row, 0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Tracks played at least `min_plays` times in total but not since `not_since`, most
+    /// played first, for rediscovering old favorites
+    pub fn get_rediscovery(&self, min_plays: i64, not_since: i64, limit: usize) -> Result<Vec<TrackStats>> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active')
+             GROUP BY t.id
+             HAVING COUNT(s.id) >= ?2 AND MAX(s.start_time) < ?3
+             ORDER BY play_count DESC, total_time DESC, t.title
+             LIMIT ?4",
+            TRACK_COLUMNS
+        ))?;
+
+        let tracks = stmt.query_map(params![current_time, min_plays, not_since, limit as i64], |row| {
+            Ok(TrackStats {
+                track: track_from_row(row, 0)?,
+                total_listened_time: row.get(TRACK_COLUMN_COUNT)?,
+                play_count: row.get(TRACK_COLUMN_COUNT + 1)?,
+                plays_per_active_day: None,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tracks)
+    }
+
+    /// Tracks with a player-reported rating, best first by `weighted_rating`. Plays from
+    /// players that don't report ratings count toward `play_count` but not the average.
+    pub fn get_top_rated(&self, start_time: Option<i64>, end_time: Option<i64>, limit: usize) -> Result<Vec<RatedTrack>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {},
+                    COUNT(s.id) as play_count,
+                    COUNT(s.rating) as rated_plays,
+                    AVG(s.rating) as average_rating
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {}
+             GROUP BY t.id
+             HAVING COUNT(s.rating) > 0",
+            TRACK_COLUMNS, time_filter(start_time, end_time)
+        ))?;
+
+        let mut tracks = stmt.query_map([], |row| {
+            Ok(RatedTrack {
+                track: track_from_row(row, 0)?,
+                play_count: row.get(TRACK_COLUMN_COUNT)?,
+                rated_plays: row.get(TRACK_COLUMN_COUNT + 1)?,
+                average_rating: row.get(TRACK_COLUMN_COUNT + 2)?,
+                weighted_rating: 0.0,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        let rated_plays: i64 = tracks.iter().map(|t| t.rated_plays).sum();
+        let overall_mean = tracks.iter()
+            .map(|t| t.average_rating * t.rated_plays as f64)
+            .sum::<f64>() / rated_plays.max(1) as f64;
+
+        for track in &mut tracks {
+            let plays = track.play_count as f64;
+            track.weighted_rating = (track.average_rating * plays + overall_mean * RATING_PRIOR_PLAYS)
+                / (plays + RATING_PRIOR_PLAYS);
+        }
+
+        tracks.sort_by(|a, b| b.weighted_rating.total_cmp(&a.weighted_rating)
+            .then(b.play_count.cmp(&a.play_count))
+            .then_with(|| a.track.title.cmp(&b.track.title)));
+        tracks.truncate(limit);
+        Ok(tracks)
+    }
+
+    /// Visit history sessions newest first without loading them all into memory.
+    /// `limit` of `None` visits every matching session.
+    /// `visit` may fail with its own error type, which database errors convert into.
+    pub fn for_each_history_session<F, E>(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        limit: Option<usize>,
+        filter: &StatsFilter,
+        mut visit: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(SessionWithMetadata) -> Result<(), E>,
+        E: From<GopalError>,
+    {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        // SQLite treats a negative LIMIT as no limit
+        let limit = limit.map(|limit| limit as i64).unwrap_or(-1);
+
+        self.load_stats_filter_values(filter)?;
+        let mut stmt = self.conn.prepare(&history_sql(&format!("{} {}", time_filter(start_time, end_time), filter.sql())))
+            .map_err(GopalError::from)?;
+        let mut rows = stmt.query(params![current_time, limit]).map_err(GopalError::from)?;
+        while let Some(row) = rows.next().map_err(GopalError::from)? {
+            visit(history_entry_from_row(row).map_err(GopalError::from)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Aggregate listening time by an arbitrary dimension, including active sessions
+    pub fn get_grouped_stats(
+        &self,
+        group_by: GroupBy,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+        limit: usize,
+    ) -> Result<Vec<GroupedStats>> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {key} as group_key,
+                    MIN({label}) as group_label,
+                    COALESCE(SUM(
+                        CASE
+                            WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                            WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                            ELSE 0
+                        END
+                    ), 0) as total_time,
+                    COUNT(s.id) as play_count
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             JOIN players p ON s.player_id = p.id
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {time_filter} {filter}
+             GROUP BY group_key
+             ORDER BY total_time DESC
+             LIMIT ?2",
+            key = group_by.key_sql(),
+            label = group_by.label_sql(),
+            time_filter = time_filter(start_time, end_time),
+            filter = filter.sql(),
+        ))?;
+
+        let groups = stmt.query_map(params![current_time, limit as i64], |row| {
+            Ok(GroupedStats {
+                key: row.get(0)?,
+                label: row.get(1)?,
+                total_listened_time: row.get(2)?,
+                play_count: row.get(3)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(groups)
+    }
+
+    /// Listening time by source: the domain web players streamed from, or the player's
+    /// name for local players and anything else without a URL
+    pub fn get_source_stats(&self, start_time: Option<i64>, end_time: Option<i64>, limit: usize) -> Result<Vec<GroupedStats>> {
+        self.get_grouped_stats(GroupBy::Source, start_time, end_time, &StatsFilter::default(), limit)
+    }
+
+    /// Weight listening time by how continuously it was listened to, see `engaged_time`.
+    /// Engagement is summed per session so one heavily paused play can't hide behind others.
+    pub fn get_engagement_stats(
+        &self,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        filter: &StatsFilter,
+        pause_penalty: f64,
+    ) -> Result<EngagementStats> {
+        let current_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.load_stats_filter_values(filter)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.track_id,
+                    CASE
+                        WHEN s.listened_time IS NOT NULL THEN s.listened_time
+                        WHEN s.status = 'active' THEN ?1 - s.start_time - s.paused_time
+                        ELSE 0
+                    END as listened,
+                    s.paused_time
+             FROM sessions s
+             WHERE (s.listened_time IS NOT NULL OR s.status = 'active') {} {}",
+            time_filter(start_time, end_time), filter.sql()
+        ))?;
+
+        let sessions = stmt.query_map(params![current_time], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        // (listened, paused, engaged) per track
+        let mut totals: HashMap<String, (i64, i64, f64)> = HashMap::new();
+        for (track_id, listened, paused) in sessions {
+            let entry = totals.entry(track_id).or_default();
+            entry.0 += listened;
+            entry.1 += paused;
+            entry.2 += engaged_time(listened, paused, pause_penalty);
+        }
+
+        let mut tracks: Vec<TrackEngagement> = totals
+            .into_iter()
+            .map(|(track_id, (listened, paused, engaged))| TrackEngagement {
+                track_id,
+                total_listened_time: listened,
+                total_paused_time: paused,
+                engaged_time: engaged.round() as i64,
+            })
+            .collect();
+        tracks.sort_by(|a, b| b.engaged_time.cmp(&a.engaged_time).then_with(|| a.track_id.cmp(&b.track_id)));
+
+        Ok(EngagementStats {
+            pause_penalty: pause_penalty.clamp(0.0, 1.0),
+            total_listened_time: tracks.iter().map(|t| t.total_listened_time).sum(),
+            total_paused_time: tracks.iter().map(|t| t.total_paused_time).sum(),
+            total_engaged_time: tracks.iter().map(|t| t.engaged_time).sum(),
+            tracks,
+        })
+    }
+
+    /// Skip rate overall and per artist, plus the time skipping saved. Only finished,
+    /// locally recorded sessions count; imported plays carry no skip information.
+    pub fn get_skip_stats(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<SkipStats> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT t.artist,
+                    COUNT(s.id) as total_sessions,
+                    COALESCE(SUM(CASE WHEN {skipped} THEN 1 ELSE 0 END), 0) as skipped_sessions,
+                    COALESCE(SUM(
+                        CASE WHEN {skipped} THEN MAX(t.length / 1000000 - s.listened_time, 0) ELSE 0 END
+                    ), 0) as time_saved
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             WHERE s.listened_time IS NOT NULL AND s.status != 'imported' {filter}
+             GROUP BY t.artist",
+            skipped = SKIPPED_SESSION_SQL,
+            filter = time_filter(start_time, end_time),
+        ))?;
+
+        let mut artists: Vec<ArtistSkipStats> = stmt.query_map(params![SKIP_LISTEN_RATIO], |row| {
+            let total_sessions: i64 = row.get(1)?;
+            let skipped_sessions: i64 = row.get(2)?;
+            Ok(ArtistSkipStats {
+                artist: row.get(0)?,
+                total_sessions,
+                skipped_sessions,
+                skip_rate: skip_rate(skipped_sessions, total_sessions),
+                time_saved: row.get(3)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        artists.sort_by(|a, b| {
+            b.skip_rate.total_cmp(&a.skip_rate)
+                .then_with(|| b.total_sessions.cmp(&a.total_sessions))
+                .then_with(|| a.artist.cmp(&b.artist))
+        });
+
+        let total_sessions = artists.iter().map(|a| a.total_sessions).sum();
+        let skipped_sessions = artists.iter().map(|a| a.skipped_sessions).sum();
+
+        Ok(SkipStats {
+            total_sessions,
+            skipped_sessions,
+            skip_rate: skip_rate(skipped_sessions, total_sessions),
+            time_saved: artists.iter().map(|a| a.time_saved).sum(),
+            artists,
+        })
+    }
+
+    /// Import a ListenBrainz export, see `import_external_listens`
+    pub fn import_listenbrainz(&self, json: &str, default_duration: i64) -> Result<ImportSummary> {
+        let listens = import::parse_listenbrainz(json).map_err(|e| GopalError::Import(e.into()))?;
+        self.import_external_listens("listenbrainz", "ListenBrainz", &listens, default_duration)
+    }
+
+    /// Import a Last.fm scrobble CSV, see `import_external_listens`
+    pub fn import_lastfm_csv(&self, csv: &str, default_duration: i64) -> Result<ImportSummary> {
+        let listens = import::parse_lastfm_csv(csv).map_err(|e| GopalError::Import(e.into()))?;
+        self.import_external_listens("lastfm", "Last.fm", &listens, default_duration)
+    }
+
+    /// Record plays from another tracker as finalized sessions of an `import.<source>` player.
+    ///
+    /// Exports usually only say when a play happened, so listened time is taken from,
+    /// in order: the duration in the export, the length of the track if it's already
+    /// known here, and finally `default_duration` seconds. Plays already imported
+    /// (same track and start time) are skipped, so re-running an import is safe.
+    pub fn import_external_listens(
+        &self,
+        source: &str,
+        source_name: &str,
+        listens: &[ExternalListen],
+        default_duration: i64,
+    ) -> Result<ImportSummary> {
+        let tx = self.conn.unchecked_transaction()?;
+        let player_id = self.insert_or_update_player(&format!("import.{}", source), source_name)?;
+        let mut summary = ImportSummary::default();
+
+        for listen in listens {
+            let album = listen.album.as_deref().unwrap_or(MISSING_METADATA);
+            let track_id = metadata_rules::track_id(&listen.title, &listen.artist, album, self.case_insensitive_track_ids);
+
+            // Keep metadata recorded by the daemon, which is richer than any export
+            self.conn.prepare_cached(
+                "INSERT OR IGNORE INTO tracks (id, title, artist, album, length)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?.execute(
+                params![
+                    track_id,
+                    listen.title,
+                    listen.artist,
+                    album,
+                    listen.duration.map(|secs| secs * 1_000_000)
+                ],
+            )?;
+
+            let already_imported: bool = self.conn.prepare_cached(
+                "SELECT EXISTS(SELECT 1 FROM sessions WHERE track_id = ?1 AND start_time = ?2)",
+            )?.query_row(
+                params![track_id, listen.listened_at],
+                |row| row.get(0),
+            )?;
+            if already_imported {
+                summary.skipped_duplicates += 1;
+                continue;
+            }
+
+            let known_length: Option<i64> = self.conn.prepare_cached(
+                "SELECT length FROM tracks WHERE id = ?1",
+            )?.query_row(
+                params![track_id],
+                |row| row.get(0),
+            )?;
+            let duration = match listen.duration.or(known_length.map(|micros| micros / 1_000_000)) {
+                Some(duration) if duration > 0 => duration,
+                _ => {
+                    summary.estimated_durations += 1;
+                    default_duration
+                }
+            };
+
+            // Listening services only export plays they counted as scrobbles
+            self.conn.prepare_cached(
+                "INSERT INTO sessions (track_id, player_id, start_time, end_time, paused_time, listened_time, status, scrobbled)
+                 VALUES (?1, ?2, ?3, ?4, 0, ?5, 'imported', 1)",
+            )?.execute(
+                params![track_id, player_id, listen.listened_at, listen.listened_at + duration, duration],
+            )?;
+            summary.imported += 1;
+        }
+
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Every player, track and finished session, for `import_gopal_export` to restore
+    pub fn export_all(&self, exported_at: i64) -> Result<ExportData> {
+        let mut stmt = self.conn.prepare(&format!("SELECT {} FROM players p ORDER BY p.id", PLAYER_COLUMNS))?;
+        let players = stmt.query_map([], |row| player_from_row(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(&format!("SELECT {} FROM tracks t ORDER BY t.id", TRACK_COLUMNS))?;
+        let tracks = stmt.query_map([], |row| track_from_row(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, s.content_type, s.source_domain, s.focus, s.rating FROM sessions s
+             WHERE s.status != 'active'
+             ORDER BY s.start_time, s.id",
+            SESSION_COLUMNS
+        ))?;
+        let sessions = stmt.query_map([], |row| {
+            Ok(ExportedSession {
+                session: session_from_row(row, 0)?,
+                content_type: row.get(SESSION_COLUMN_COUNT)?,
+                source_domain: row.get(SESSION_COLUMN_COUNT + 1)?,
+                focus: row.get(SESSION_COLUMN_COUNT + 2)?,
+                rating: row.get(SESSION_COLUMN_COUNT + 3)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ExportData { format_version: EXPORT_FORMAT_VERSION, exported_at, players, tracks, sessions })
+    }
+
+    /// Totals of every track with finished listening, for sharing in place of `export_all`
+    pub fn export_anonymized(&self) -> Result<AnonymizedExport> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.title, t.artist, t.album, COUNT(s.id), SUM(s.listened_time) AS total_time
+             FROM tracks t
+             JOIN sessions s ON t.id = s.track_id
+             WHERE s.status != 'active' AND s.listened_time IS NOT NULL
+             GROUP BY t.id
+             ORDER BY total_time DESC, t.id",
+        )?;
+        let tracks = stmt.query_map([], |row| {
+            Ok(AnonymizedTrack {
+                title: row.get(0)?,
+                artist: row.get(1)?,
+                album: row.get(2)?,
+                play_count: row.get(3)?,
+                total_listened_time: row.get(4)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(AnonymizedExport {
+            anonymized: true,
+            total_listening_time: tracks.iter().map(|track| track.total_listened_time).sum(),
+            play_count: tracks.iter().map(|track| track.play_count).sum(),
+            tracks,
+        })
+    }
+
+    /// Import an export written by `export_all`, see `ExportData::parse` for how other
+    /// format versions are handled
+    pub fn import_gopal_export(&self, json: &str, force: bool) -> Result<ImportSummary> {
+        let data = ExportData::parse(json, force).map_err(|e| GopalError::Import(e.into()))?;
+        self.import_all(&data)
+    }
+
+    /// Add an export's sessions, skipping ones already recorded for the same track,
+    /// player and start time. Tracks already in the database keep their metadata.
+    pub fn import_all(&self, data: &ExportData) -> Result<ImportSummary> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut summary = ImportSummary::default();
+
+        let mut player_ids = HashMap::new();
+        for player in &data.players {
+            player_ids.insert(player.id, self.insert_or_update_player(&player.name, &player.identity)?);
+        }
+
+        for track in &data.tracks {
+            let exists: bool = self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM tracks WHERE id = ?1)",
+                params![track.id],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                self.insert_or_update_track(track)?;
+            }
+        }
+
+        for exported in &data.sessions {
+            let session = &exported.session;
+            let player_id = *player_ids.get(&session.player_id).ok_or_else(|| GopalError::Import(
+                format!("Export session {} refers to unknown player {}", session.id, session.player_id).into()
+            ))?;
+
+            let already_imported: bool = self.conn.prepare_cached(
+                "SELECT EXISTS(SELECT 1 FROM sessions WHERE track_id = ?1 AND player_id = ?2 AND start_time = ?3)",
+            )?.query_row(
+                params![session.track_id, player_id, session.start_time],
+                |row| row.get(0),
+            )?;
+            if already_imported {
+                summary.skipped_duplicates += 1;
+                continue;
+            }
+
+            self.conn.prepare_cached(&format!(
+                "INSERT INTO sessions (track_id, player_id, start_time, end_time, paused_time, listened_time, status, label, content_type, source_domain, focus, rating, scrobbled, completion_ratio)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, CASE WHEN ?7 = 'orphaned' THEN 0 ELSE {} END,
+                         CASE WHEN ?7 NOT IN ('active', 'imported') THEN {} END)",
+                scrobbled_sql("?6", "?1"),
+                completion_ratio_sql("?6", "?1")
+            ))?.execute(
+                params![
+                    session.track_id,
+                    player_id,
+                    session.start_time,
+                    session.end_time,
+                    session.paused_time,
+                    session.listened_time,
+                    session.status,
+                    session.label,
+                    exported.content_type,
+                    exported.source_domain,
+                    exported.focus,
+                    exported.rating
+                ],
+            )?;
+            summary.imported += 1;
+        }
+
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Insert sessions for a backfill, keeping every field but the id, which is assigned
+    /// fresh. Rows are written `BULK_INSERT_BATCH` at a time, one transaction per batch,
+    /// so a failure part way through keeps the batches already committed. The tracks
+    /// and players the sessions refer to must already exist.
+    pub fn bulk_insert_sessions(&self, sessions: &[Session]) -> Result<usize> {
+        for batch in sessions.chunks(BULK_INSERT_BATCH) {
+            let tx = self.conn.unchecked_transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(&format!(
+                    "INSERT INTO sessions (track_id, player_id, start_time, end_time, paused_time, listened_time, status, label, paused_since, content_type, scrobbled, completion_ratio)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, CASE WHEN ?7 IN ('active', 'orphaned') THEN 0 ELSE {} END,
+                             CASE WHEN ?7 NOT IN ('active', 'imported') THEN {} END)",
+                    scrobbled_sql("?6", "?1"),
+                    completion_ratio_sql("?6", "?1")
+                ))?;
+                for session in batch {
+                    stmt.execute(params![
+                        session.track_id,
+                        session.player_id,
+                        session.start_time,
+                        session.end_time,
+                        session.paused_time,
+                        session.listened_time,
+                        session.status,
+                        session.label,
+                        session.paused_since,
+                        session.content_type.map(|content_type| content_type.as_str())
+                    ])?;
+                }
+            }
+            tx.commit()?;
+        }
+        Ok(sessions.len())
+    }
+
+    /// Finished sessions whose listened plus paused time differs from their wall-clock
+    /// span by more than `tolerance` seconds, largest drift first. Every second of a
+    /// session is either listened or paused, so a mismatch points at a timing bug.
+    pub fn get_timing_report(&self, tolerance: i64) -> Result<Vec<TimingMismatch>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, t.title, t.artist, p.name, s.status, s.start_time, s.end_time,
+                    s.listened_time, s.paused_time,
+                    s.listened_time + s.paused_time - (s.end_time - s.start_time) as drift
+             FROM sessions s
+             JOIN tracks t ON s.track_id = t.id
+             JOIN players p ON s.player_id = p.id
+             WHERE s.end_time IS NOT NULL AND s.listened_time IS NOT NULL
+               AND ABS(s.listened_time + s.paused_time - (s.end_time - s.start_time)) > ?1
+             ORDER BY ABS(drift) DESC, s.start_time"
+        )?;
+
+        let mismatches = stmt.query_map(params![tolerance], |row| {
+            Ok(TimingMismatch {
+                session_id: row.get(0)?,
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                player: row.get(3)?,
+                status: row.get(4)?,
+                start_time: row.get(5)?,
+                end_time: row.get(6)?,
+                listened_time: row.get(7)?,
+                paused_time: row.get(8)?,
+                drift: row.get(9)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(mismatches)
+    }
+
+    /// Queue a finished session for submission if it counts as a scrobble, due right
+    /// away. Returns whether it was added; sessions already queued are left alone.
+    pub fn enqueue_scrobble(&self, session_id: i64) -> Result<bool> {
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO scrobble_queue (session_id, queued_at, next_attempt_at)
+             SELECT id, end_time, end_time FROM sessions
+             WHERE id = ?1 AND scrobbled = 1 AND end_time IS NOT NULL",
+            params![session_id],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Queued scrobbles, oldest listen first. With `due_at`, only those due for an
+    /// attempt by then; otherwise every row, including ones retries gave up on.
+    pub fn get_scrobble_queue(&self, due_at: Option<i64>) -> Result<Vec<QueuedScrobble>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT q.id, q.session_id, t.title, t.artist, t.album, s.start_time,
+                    COALESCE(s.listened_time, 0), q.queued_at, q.attempts, q.last_error, q.next_attempt_at
+             FROM scrobble_queue q
+             JOIN sessions s ON q.session_id = s.id
+             JOIN tracks t ON s.track_id = t.id
+             WHERE ?1 IS NULL OR q.next_attempt_at <= ?1
+             ORDER BY s.start_time, q.id"
+        )?;
+
+        let queue = stmt.query_map(params![due_at], |row| {
+            Ok(QueuedScrobble {
+                id: row.get(0)?,
+                session_id: row.get(1)?,
+                title: row.get(2)?,
+                artist: row.get(3)?,
+                album: row.get(4)?,
+                listened_at: row.get(5)?,
+                listened_time: row.get(6)?,
+                queued_at: row.get(7)?,
+                attempts: row.get(8)?,
+                last_error: row.get(9)?,
+                next_attempt_at: row.get(10)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(queue)
+    }
+
+    /// Drop a scrobble from the queue once it has been submitted
+    pub fn complete_scrobble(&self, queue_id: i64) -> Result<()> {
+        self.conn.execute("DELETE FROM scrobble_queue WHERE id = ?1", params![queue_id])?;
+        Ok(())
+    }
+
+    /// Record a failed submission, to be retried at `next_attempt_at` or never if `None`
+    pub fn fail_scrobble(&self, queue_id: i64, error: &str, next_attempt_at: Option<i64>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE scrobble_queue
+             SET attempts = attempts + 1, last_error = ?2, next_attempt_at = ?3
+             WHERE id = ?1",
+            params![queue_id, error, next_attempt_at],
+        )?;
+        Ok(())
+    }
+
+    /// Make every queued scrobble due at `current_time`, including ones retries gave
+    /// up on. Returns how many there are.
+    pub fn retry_scrobbles_now(&self, current_time: i64) -> Result<usize> {
+        Ok(self.conn.execute(
+            "UPDATE scrobble_queue SET next_attempt_at = ?1",
+            params![current_time],
+        )?)
+    }
+
+    /// Plays in the range that count as scrobbles, see `SCROBBLE_MIN_SECONDS`
+    pub fn get_scrobble_count(&self, start_time: Option<i64>, end_time: Option<i64>) -> Result<i64> {
+        let count = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions s WHERE s.scrobbled = 1 {}", time_filter(start_time, end_time)),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    /// Drop the raw metadata of all but the `max_rows` most recently played tracks that
+    /// have it, returning how many were dropped. A track gets it back the next time it
+    /// is played with raw metadata storage on.
+    pub fn prune_raw_metadata(&self, max_rows: usize) -> Result<usize> {
+        let pruned = self.conn.execute(
+            "UPDATE tracks SET raw_metadata = NULL
+             WHERE id IN (
+                 SELECT t.id FROM tracks t
+                 LEFT JOIN sessions s ON s.track_id = t.id
+                 WHERE t.raw_metadata IS NOT NULL
+                 GROUP BY t.id
+                 ORDER BY COALESCE(MAX(s.start_time), 0) DESC, t.id
+                 LIMIT -1 OFFSET ?1
+             )",
+            params![max_rows as i64],
+        )?;
+        Ok(pruned)
+    }
+
+    /// Clean up orphaned sessions (active sessions from previous runs), returning them
+    /// as they were closed. Ones dropped for being too short are left out.
+    pub fn cleanup_orphaned_sessions(&self, current_time: i64, max_session_duration: i64) -> Result<Vec<SessionWithMetadata>> {
+        // Find active sessions that are too old (likely from previous daemon runs)
+        let orphaned_sessions: Vec<SessionWithMetadata> = self.list_active_sessions()?
+            .into_iter()
+            .filter(|entry| current_time - entry.session.start_time > max_session_duration)
+            .collect();
+
+        let mut closed = Vec::with_capacity(orphaned_sessions.len());
+        for mut entry in orphaned_sessions {
+            // Calculate a reasonable end time (start_time + max_session_duration)
+            let estimated_end_time = entry.session.start_time + max_session_duration;
+            self.finalize_session(entry.session.id, estimated_end_time, "orphaned")?;
+
+            // Read the row back, as finalizing may have marked it too short or deleted it
+            let session = self.conn.query_row(
+                &format!("SELECT {} FROM sessions s WHERE s.id = ?1", SESSION_COLUMNS),
+                params![entry.session.id],
+                |row| session_from_row(row, 0),
+            ).optional()?;
+            if let Some(session) = session {
+                entry.session = session;
+                closed.push(entry);
+            }
+        }
+
+        Ok(closed)
+    }
+
+    /// Get database statistics
+    pub fn get_database_stats(&self) -> Result<DatabaseStats> {
+        let total_sessions: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sessions",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let active_sessions: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sessions WHERE status = 'active'",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let total_tracks: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM tracks",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let total_players: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM players",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let (earliest_session, latest_session, total_listened_time, total_scrobbles) = self.conn.query_row(
+            "SELECT MIN(start_time), MAX(start_time), COALESCE(SUM(listened_time), 0), COALESCE(SUM(scrobbled), 0) FROM sessions",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+        // Size of the main database file; pending WAL pages aren't counted
+        let file_size: i64 = self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(DatabaseStats {
+            total_sessions,
+            active_sessions,
+            total_tracks,
+            total_players,
+            earliest_session,
+            latest_session,
+            total_listened_time,
+            total_scrobbles,
+            file_size,
+            schema_version: self.schema_version()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseStats {
+    pub total_sessions: i64,
+    pub active_sessions: i64,
+    pub total_tracks: i64,
+    pub total_players: i64,
+    /// Start time of the oldest recorded session
+    pub earliest_session: Option<i64>,
+    /// Start time of the newest recorded session
+    pub latest_session: Option<i64>,
+    /// Seconds listened across all finished sessions
+    pub total_listened_time: i64,
+    /// Finished plays that count as scrobbles, see `SCROBBLE_MIN_SECONDS`
+    pub total_scrobbles: i64,
+    /// Database size on disk in bytes
+    pub file_size: i64,
+    pub schema_version: usize,
+}
+
+/// Drift allowed by `get_timing_report` before a session is reported, covering
+/// rounding between poll and checkpoint timestamps
+pub const DEFAULT_TIMING_TOLERANCE: i64 = 5;
+
+/// A finished session whose time doesn't add up, from `get_timing_report`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimingMismatch {
+    pub session_id: i64,
+    pub title: String,
+    pub artist: String,
+    pub player: String,
+    pub status: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub listened_time: i64,
+    pub paused_time: i64,
+    /// Listened plus paused seconds minus the wall-clock span; positive when more
+    /// time was counted than passed
+    pub drift: i64,
+}
+
+/// A scrobble waiting in the queue, from `get_scrobble_queue`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedScrobble {
+    pub id: i64,
+    pub session_id: i64,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// When the play started
+    pub listened_at: i64,
+    pub listened_time: i64,
+    pub queued_at: i64,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    /// When the next submission is due; `None` once retries have given up
+    pub next_attempt_at: Option<i64>,
+}
+
+impl QueuedScrobble {
+    /// "pending" before the first attempt, then "retrying", or "failed" once retries gave up
+    pub fn state(&self) -> &'static str {
+        match (self.next_attempt_at, self.attempts) {
+            (None, _) => "failed",
+            (Some(_), 0) => "pending",
+            (Some(_), _) => "retrying",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Database {
+        Database::new(":memory:").unwrap()
+    }
+
+    fn test_track(title: &str, artist: &str, album: &str) -> Track {
+        Track {
+            id: format!("{}::{}::{}", title, artist, album),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            length: Some(180_000_000),
+            art_url: None,
+            genre: None,
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        }
+    }
+
+    /// Record a finished session of `listened` seconds starting at `start`
+    fn add_session(db: &Database, track: &Track, player_id: i64, start: i64, listened: i64) -> i64 {
+        add_session_with_status(db, track, player_id, start, listened, "completed")
+    }
+
+    fn add_session_with_status(
+        db: &Database,
+        track: &Track,
+        player_id: i64,
+        start: i64,
+        listened: i64,
+        status: &str,
+    ) -> i64 {
+        db.insert_or_update_track(track).unwrap();
+        let session_id = db.start_session(&track.id, player_id, start).unwrap();
+        db.finalize_session(session_id, start + listened, status).unwrap();
+        session_id
+    }
+
+    #[test]
+    fn test_scrobbles_count_qualifying_plays_only() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let short = test_track("Short", "Artist", "Album"); // 3 minutes
+        let mut long = test_track("Long", "Artist", "Album");
+        long.length = Some(1_200_000_000);
+        let mut unknown = test_track("Stream", "Artist", "Album");
+        unknown.length = None;
+
+        add_session(&db, &short, player, 1000, 90); // half the track
+        add_session(&db, &short, player, 2000, 89);
+        add_session(&db, &long, player, 3000, SCROBBLE_MIN_SECONDS);
+        add_session(&db, &long, player, 4000, SCROBBLE_MIN_SECONDS - 1);
+        add_session(&db, &unknown, player, 5000, SCROBBLE_MIN_SECONDS);
+        add_session(&db, &unknown, player, 6000, 100);
+        add_session_with_status(&db, &long, player, 7000, 3600, "orphaned");
+
+        assert_eq!(db.get_scrobble_count(None, None).unwrap(), 3);
+        assert_eq!(db.get_scrobble_count(Some(2500), Some(5500)).unwrap(), 2);
+
+        let stats = db.get_database_stats().unwrap();
+        assert_eq!((stats.total_sessions, stats.total_scrobbles), (7, 3));
+        let dashboard = db.get_dashboard(None, None, &StatsFilter::default(), DashboardLimits { top: 10, history: 10 }).unwrap();
+        assert_eq!((dashboard.plays, dashboard.scrobbles), (7, 3));
+
+        // A replay reopening a scrobbled session counts once it's finished again
+        let reopened = add_session(&db, &short, player, 8000, 30);
+        db.reopen_session(reopened, 8040).unwrap();
+        assert_eq!(db.get_scrobble_count(Some(8000), None).unwrap(), 0);
+        db.finalize_session(reopened, 8110, "completed").unwrap();
+        assert_eq!(db.get_scrobble_count(Some(8000), None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_orphaned_sessions_listed_and_cleaned() {
+        let db = test_db();
+        let spotify = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let vlc = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        let old = test_track("Old", "Artist", "Album");
+        let new = test_track("New", "Artist", "Album");
+        db.insert_or_update_track(&old).unwrap();
+        db.insert_or_update_track(&new).unwrap();
+
+        let now = 10 * DEFAULT_ORPHAN_MAX_AGE;
+        let orphan = db.start_session(&old.id, spotify, now - 2 * DEFAULT_ORPHAN_MAX_AGE).unwrap();
+        db.update_session_pause_time(orphan, 60).unwrap();
+        let current = db.start_session(&new.id, vlc, now - 600).unwrap();
+
+        let active: Vec<i64> = db.list_active_sessions().unwrap().iter().map(|s| s.session.id).collect();
+        assert_eq!(active, vec![current, orphan]);
+
+        let closed = db.cleanup_orphaned_sessions(now, DEFAULT_ORPHAN_MAX_AGE).unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].track.title, "Old");
+        assert_eq!(closed[0].session.status, "orphaned");
+        assert_eq!(closed[0].session.listened_time, Some(DEFAULT_ORPHAN_MAX_AGE - 60));
+
+        let active: Vec<i64> = db.list_active_sessions().unwrap().iter().map(|s| s.session.id).collect();
+        assert_eq!(active, vec![current]);
+        assert!(db.cleanup_orphaned_sessions(now, DEFAULT_ORPHAN_MAX_AGE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_orphaned_sessions_too_short_are_reported_as_stored() {
+        let now = 10 * DEFAULT_ORPHAN_MAX_AGE;
+        let track = test_track("Paused", "Artist", "Album");
+        for record_all in [false, true] {
+            let db = test_db().with_min_session_duration(30).with_record_all_sessions(record_all);
+            let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+            db.insert_or_update_track(&track).unwrap();
+            // Paused for all but 10 seconds of the estimated session
+            let orphan = db.start_session(&track.id, player, now - 2 * DEFAULT_ORPHAN_MAX_AGE).unwrap();
+            db.update_session_pause_time(orphan, DEFAULT_ORPHAN_MAX_AGE - 10).unwrap();
+
+            let closed = db.cleanup_orphaned_sessions(now, DEFAULT_ORPHAN_MAX_AGE).unwrap();
+            if record_all {
+                assert_eq!(closed.len(), 1);
+                assert_eq!(closed[0].session.status, "too_short");
+                assert_eq!(closed[0].session.listened_time, None);
+            } else {
+                assert!(closed.is_empty());
+                assert_eq!(db.get_database_stats().unwrap().total_sessions, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bulk_insert_sessions() {
+        let db = test_db();
+        let player = db.insert_or_update_player("import.lastfm", "Last.fm").unwrap();
+        let track = test_track("Song", "Artist", "Album");
+        db.insert_or_update_track(&track).unwrap();
+
+        let mut sessions: Vec<Session> = (0..10_000)
+            .map(|i| Session {
+                id: 0,
+                track_id: track.id.clone(),
+                player_id: player,
+                start_time: i * 300,
+                end_time: Some(i * 300 + 200),
+                paused_time: 10,
+                listened_time: Some(190),
+                status: "imported".to_string(),
+                label: None,
+                paused_since: None,
+                content_type: (i % 4 == 0).then_some(ContentType::Podcast),
+            })
+            .collect();
+        // One more, in a third batch, still playing but paused
+        sessions.push(Session {
+            end_time: None,
+            listened_time: None,
+            status: "active".to_string(),
+            start_time: 10_000 * 300,
+            paused_since: Some(10_000 * 300 + 60),
+            content_type: Some(ContentType::Audiobook),
+            ..sessions[1].clone()
+        });
+
+        assert_eq!(db.bulk_insert_sessions(&sessions).unwrap(), 10_001);
+
+        let stats = db.get_database_stats().unwrap();
+        assert_eq!(stats.total_sessions, 10_001);
+        assert_eq!(stats.total_listened_time, 1_900_000);
+        assert_eq!((stats.earliest_session, stats.latest_session), (Some(0), Some(10_000 * 300)));
+
+        let podcasts: i64 = db.conn.query_row(
+            "SELECT COUNT(*) FROM sessions WHERE content_type = 'podcast'", [], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(podcasts, 2_500);
+        let active = db.list_active_sessions().unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].session.paused_since, Some(10_000 * 300 + 60));
+        assert_eq!(active[0].session.content_type, Some(ContentType::Audiobook));
+    }
+
+    #[test]
+    fn test_rediscovery_finds_old_favorites_only() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let old_favorite = test_track("Old Favorite", "Artist", "Album");
+        let current_favorite = test_track("Current Favorite", "Artist", "Album");
+        let one_off = test_track("One Off", "Artist", "Album");
+
+        for day in 0..12 {
+            add_session(&db, &old_favorite, player, 1_000_000 + day * 86400, 180);
+            add_session(&db, &current_favorite, player, 1_000_000 + day * 86400 + 3600, 180);
+        }
+        add_session(&db, &current_favorite, player, 9_000_000, 180);
+        add_session(&db, &one_off, player, 1_000_000, 180);
+
+        let tracks = db.get_rediscovery(10, 5_000_000, 20).unwrap();
+        let titles: Vec<&str> = tracks.iter().map(|t| t.track.title.as_str()).collect();
+        assert_eq!(titles, vec!["Old Favorite"]);
+        assert_eq!(tracks[0].play_count, 12);
+    }
+
+    #[test]
+    fn test_top_rated_weighs_ratings_by_play_count() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let rated = |title: &str, rating: Option<f64>| Track { rating, ..test_track(title, "Artist", "Album") };
+
+        // A perfect rating heard once loses to a good one heard ten times
+        add_session(&db, &rated("Once Loved", Some(1.0)), player, 1000, 180);
+        for i in 0..10 {
+            add_session(&db, &rated("Often Liked", Some(0.8)), player, 2000 + i * 200, 180);
+        }
+        for i in 0..5 {
+            add_session(&db, &rated("Meh", Some(0.4)), player, 5000 + i * 200, 180);
+        }
+        // A player that omits the rating adds a play but not a rating
+        add_session(&db, &rated("Meh", None), player, 7000, 180);
+        add_session(&db, &rated("Never Rated", None), player, 8000, 180);
+
+        let tracks = db.get_top_rated(None, None, 10).unwrap();
+        let titles: Vec<&str> = tracks.iter().map(|t| t.track.title.as_str()).collect();
+        assert_eq!(titles, vec!["Often Liked", "Once Loved", "Meh"]);
+        assert_eq!((tracks[2].play_count, tracks[2].rated_plays), (6, 5));
+        assert!((tracks[2].average_rating - 0.4).abs() < 1e-9);
+        assert!(tracks[1].weighted_rating < tracks[1].average_rating);
+
+        assert_eq!(db.get_top_rated(Some(5000), None, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_trend_compares_the_latest_window_with_earlier_ones() {
+        let now = 100 * 86400;
+        let week = 7 * 86400;
+        // A session a day, `minutes` long, for the current week then each of the four before it
+        let db_with = |minutes: [i64; 5]| {
+            let db = test_db();
+            let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+            let song = test_track("Song", "Artist", "Album");
+            for (weeks_ago, minutes) in minutes.iter().enumerate() {
+                for day in 0..7 {
+                    let start = now - (weeks_ago as i64 + 1) * week + day * 86400;
+                    add_session(&db, &song, player, start, minutes * 60);
+                }
+            }
+            db
+        };
+
+        let rising = db_with([60, 30, 30, 30, 30]).get_trend_at(now, 7, 4).unwrap();
+        assert_eq!(rising.direction, TrendDirection::Up);
+        assert_eq!(rising.recent_listened_time, 7 * 3600);
+        assert_eq!(rising.baseline_listened_time, Some(7.0 * 1800.0));
+        assert_eq!(rising.change_percent, Some(100.0));
+
+        let falling = db_with([15, 30, 30, 30, 30]).get_trend_at(now, 7, 4).unwrap();
+        assert_eq!((falling.direction, falling.change_percent), (TrendDirection::Down, Some(-50.0)));
+
+        let steady = db_with([31, 30, 30, 30, 30]).get_trend_at(now, 7, 4).unwrap();
+        assert_eq!(steady.direction, TrendDirection::Flat);
+
+        // Only windows with full history count toward the baseline
+        let partial = db_with([30, 30, 30, 30, 30]).get_trend_at(now, 7, 10).unwrap();
+        assert_eq!(partial.lookback_windows, 4);
+    }
+
+    #[test]
+    fn test_trend_needs_one_full_prior_window() {
+        let db = test_db();
+        assert_eq!(db.get_trend_at(100 * 86400, 7, 4).unwrap().direction, TrendDirection::NotEnoughData);
+
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song = test_track("Song", "Artist", "Album");
+        add_session(&db, &song, player, 95 * 86400, 3600);
+        let trend = db.get_trend_at(100 * 86400, 7, 4).unwrap();
+        assert_eq!((trend.direction, trend.baseline_listened_time, trend.change_percent), (TrendDirection::NotEnoughData, None, None));
+    }
+
+    fn artist_rows(artists: &[ArtistStats]) -> Vec<(String, i64, i64, i64)> {
+        artists.iter().map(|a| (a.artist.clone(), a.total_listened_time, a.track_count, a.play_count)).collect()
+    }
+
+    #[test]
+    fn test_artist_aggregates_match_live_stats() {
+        let db = test_db().with_artist_aggregates(true);
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let other_player = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        add_session(&db, &test_track("One", "Band", "Album"), player, 1000, 180);
+        add_session(&db, &test_track("Two", "Band", "Album"), player, 2000, 150);
+        add_session(&db, &test_track("One", "Band", "Album"), player, 3000, 100);
+        add_session(&db, &test_track("Solo", "Singer", "Single"), player, 4000, 200);
+
+        let live = |db: &Database| artist_rows(&db.compute_artist_stats(0, "").unwrap());
+        assert!(!db.get_artist_aggregate_status().unwrap().fresh);
+        assert_eq!(db.refresh_artist_aggregates(5000).unwrap(), 2);
+        assert_eq!(db.get_artist_aggregate_status().unwrap(),
+                   ArtistAggregateStatus { artists: 2, refreshed_at: Some(5000), fresh: true });
+        assert_eq!(artist_rows(&db.get_listening_stats(None, None).unwrap().top_artists), live(&db));
+
+        // Finished sessions keep the table fresh; active ones are added on top
+        add_session(&db, &test_track("Three", "Band", "Album"), player, 6000, 90);
+        let new_track = test_track("New", "Singer", "Single");
+        db.insert_or_update_track(&new_track).unwrap();
+        let active = db.start_session(&new_track.id, other_player, 7000).unwrap();
+        db.update_active_sessions_progress(7060, &[(active, 0)]).unwrap();
+        assert!(db.get_artist_aggregate_status().unwrap().fresh);
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(artist_rows(&stats.top_artists), live(&db));
+        assert_eq!(artist_rows(&stats.top_artists)[0], ("Band".to_string(), 520, 3, 4));
+
+        // A change the table isn't told about makes it stale, falling back to the live query
+        let unmaintained = db.with_artist_aggregates(false);
+        add_session(&unmaintained, &test_track("Solo", "Singer", "Single"), player, 8000, 400);
+        assert!(!unmaintained.get_artist_aggregate_status().unwrap().fresh);
+        assert_eq!(artist_rows(&unmaintained.get_listening_stats(None, None).unwrap().top_artists), live(&unmaintained));
+    }
+
+    #[test]
+    fn test_player_aliases_share_one_player() {
+        let db = test_db().with_player_aliases([
+            ("org.mpris.MediaPlayer2.spotifyd".to_string(), "org.mpris.MediaPlayer2.spotify".to_string()),
+            ("ncspot".to_string(), "org.mpris.MediaPlayer2.spotify".to_string()),
+        ]);
+        let spotify = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+
+        // Matched by bus name or by identity; the canonical player keeps its identity
+        assert_eq!(db.insert_or_update_player("org.mpris.MediaPlayer2.spotifyd", "spotifyd").unwrap(), spotify);
+        assert_eq!(db.insert_or_update_player("org.mpris.MediaPlayer2.ncspot.instance42", "ncspot").unwrap(), spotify);
+        assert_ne!(db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap(), spotify);
+
+        let players = db.get_player_summaries().unwrap();
+        let names: Vec<(&str, &str)> = players.iter().map(|p| (p.player.name.as_str(), p.player.identity.as_str())).collect();
+        assert_eq!(names, vec![("org.mpris.MediaPlayer2.spotify", "Spotify"), ("org.mpris.MediaPlayer2.vlc", "VLC")]);
+
+        // Aliases playing at once share the one active session, each ending the other's
+        let track = test_track("Song", "Artist", "Album");
+        db.insert_or_update_track(&track).unwrap();
+        let first = db.start_session(&track.id, spotify, 1000).unwrap();
+        let second = db.start_session(&track.id, spotify, 1010).unwrap();
+        let status: String = db.conn.query_row("SELECT status FROM sessions WHERE id = ?1", params![first], |row| row.get(0)).unwrap();
+        assert_eq!(status, "interrupted");
+        assert_eq!(db.get_active_session_for_player(spotify).unwrap().map(|s| s.id), Some(second));
+    }
+
+    #[test]
+    fn test_rekey_tracks_merges_tracks_differing_in_case() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Song", "Artist", "Album"), player, 1000, 100);
+        add_session(&db, &test_track("SONG", "Artist", "Album"), player, 2000, 100);
+        add_session(&db, &test_track("Other  Song", "Artist", "Album"), player, 3000, 100);
+
+        // Only the doubled space is out of line with case-sensitive ids
+        assert_eq!(db.rekey_tracks().unwrap(), 1);
+
+        let db = db.with_case_insensitive_track_ids(true);
+        assert_eq!(db.rekey_tracks().unwrap(), 3);
+        assert_eq!(db.rekey_tracks().unwrap(), 0);
+
+        let tracks: Vec<(String, i64)> = db.conn
+            .prepare("SELECT t.id, COUNT(s.id) FROM tracks t JOIN sessions s ON s.track_id = t.id GROUP BY t.id ORDER BY t.id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tracks, vec![("other song::artist::album".to_string(), 1), ("song::artist::album".to_string(), 2)]);
+        assert_eq!(db.conn.query_row("SELECT COUNT(*) FROM tracks", [], |row| row.get::<_, i64>(0)).unwrap(), 2);
+
+        // Imports use the same ids
+        db.import_listenbrainz(r#"[{"listened_at": 4000, "track_metadata": {"track_name": "Song", "artist_name": "ARTIST", "release_name": "Album"}}]"#, 180).unwrap();
+        assert_eq!(db.conn.query_row("SELECT COUNT(*) FROM tracks", [], |row| row.get::<_, i64>(0)).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_merge_players_moves_sessions() {
+        let db = test_db();
+        let spotify = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let spotifyd = db.insert_or_update_player("org.mpris.MediaPlayer2.spotifyd", "spotifyd").unwrap();
+        add_session(&db, &test_track("One", "Band", "Album"), spotify, 1000, 100);
+        add_session(&db, &test_track("Two", "Band", "Album"), spotifyd, 2000, 50);
+        add_session(&db, &test_track("Three", "Band", "Album"), spotifyd, 3000, 30);
+
+        assert!(db.merge_players("spotifyd", "nonexistent").is_err());
+        assert!(db.merge_players("spotify", "org.mpris.MediaPlayer2.spotify").is_err());
+        assert_eq!(db.merge_players("spotifyd", "org.mpris.MediaPlayer2.spotify").unwrap(), 2);
+
+        let players = db.get_player_summaries().unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!((players[0].player.id, players[0].session_count, players[0].total_listened_time), (spotify, 3, 180));
+    }
+
+    #[test]
+    fn test_second_active_session_for_a_player_replaces_the_first() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let other = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        db.insert_or_update_track(&track).unwrap();
+
+        let first = db.start_session(&track.id, player, 1000).unwrap();
+        let elsewhere = db.start_session(&track.id, other, 1000).unwrap();
+        let second = db.start_session(&track.id, player, 1100).unwrap();
+
+        let active: Vec<i64> = db.list_active_sessions().unwrap().iter().map(|active| active.session.id).collect();
+        assert_eq!(active.len(), 2);
+        assert!(active.contains(&second) && active.contains(&elsewhere));
+        let (status, end_time): (String, i64) = db.conn.query_row(
+            "SELECT status, end_time FROM sessions WHERE id = ?1",
+            params![first],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!((status.as_str(), end_time), ("interrupted", 1100));
+
+        // The index holds even for writes that skip `start_session`
+        assert!(db.conn.execute(
+            "INSERT INTO sessions (track_id, player_id, start_time, status) VALUES (?1, ?2, 1200, 'active')",
+            params![track.id, player],
+        ).is_err());
+    }
+
+    #[test]
+    fn test_replacing_an_active_session_keeps_artist_aggregates() {
+        let db = test_db().with_artist_aggregates(true);
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        db.insert_or_update_track(&track).unwrap();
+        add_session(&db, &track, player, 500, 100);
+        db.refresh_artist_aggregates(700).unwrap();
+
+        let first = db.start_session(&track.id, player, 1000).unwrap();
+        let second = db.start_session(&track.id, player, 1100).unwrap();
+        db.update_active_sessions_progress(1160, &[(second, 0)]).unwrap();
+        assert_eq!(db.get_active_session_for_player(player).unwrap().map(|s| s.id), Some(second));
+        assert!(db.get_artist_aggregate_status().unwrap().fresh);
+        assert_eq!(artist_rows(&db.get_listening_stats(None, None).unwrap().top_artists),
+                   artist_rows(&db.compute_artist_stats(0, "").unwrap()));
+
+        // The tracker finishing the replaced session later doesn't overwrite how it ended
+        db.finalize_session(first, 1500, "completed").unwrap();
+        let (status, end_time): (String, i64) = db.conn.query_row(
+            "SELECT status, end_time FROM sessions WHERE id = ?1",
+            params![first],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!((status.as_str(), end_time), ("interrupted", 1100));
+    }
+
+    #[test]
+    fn test_sessions_under_the_minimum_are_dropped_or_marked() {
+        let track = test_track("Song", "Band", "Album");
+        let session_status = |db: &Database, session_id: i64| -> Option<(String, Option<i64>)> {
+            db.conn.query_row(
+                "SELECT status, listened_time FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).optional().unwrap()
+        };
+
+        for record_all in [false, true] {
+            let db = test_db().with_min_session_duration(10).with_record_all_sessions(record_all);
+            let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+            db.insert_or_update_track(&track).unwrap();
+
+            // Skipped half a second in, which rounds down to no time at all
+            let short = db.start_session(&track.id, player, 1000).unwrap();
+            db.finalize_session(short, 1000, "interrupted").unwrap();
+            let expected = record_all.then(|| ("too_short".to_string(), None));
+            assert_eq!(session_status(&db, short), expected);
+
+            let long = db.start_session(&track.id, player, 2000).unwrap();
+            db.finalize_session(long, 2010, "completed").unwrap();
+            assert_eq!(session_status(&db, long), Some(("completed".to_string(), Some(10))));
+
+            // Closing at a checkpoint follows the same rule
+            let orphaned = db.start_session(&track.id, player, 3000).unwrap();
+            db.update_active_sessions_progress(3005, &[(orphaned, 0)]).unwrap();
+            db.close_session_at_checkpoint(orphaned, "orphaned").unwrap();
+            assert_eq!(session_status(&db, orphaned), expected);
+
+            let stats = db.get_listening_stats(None, None).unwrap();
+            assert_eq!((stats.total_listening_time, stats.top_tracks[0].play_count, stats.listening_history.len()), (10, 1, 1));
+        }
+    }
+
+    #[test]
+    fn test_prune_raw_metadata_keeps_most_recently_played() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        for (i, title) in ["Old", "Middle", "New"].into_iter().enumerate() {
+            let track = Track { raw_metadata: Some("{}".to_string()), ..test_track(title, "Artist", "Album") };
+            add_session(&db, &track, player, 1000 * (i as i64 + 1), 180);
+        }
+
+        assert_eq!(db.prune_raw_metadata(5).unwrap(), 0);
+        assert_eq!(db.prune_raw_metadata(2).unwrap(), 1);
+
+        let kept: Vec<(String, bool)> = db.get_listening_stats(None, None).unwrap().listening_history.into_iter()
+            .map(|session| (session.track.title, session.track.raw_metadata.is_some()))
+            .collect();
+        assert_eq!(kept, vec![("New".to_string(), true), ("Middle".to_string(), true), ("Old".to_string(), false)]);
+    }
+
+    #[test]
+    fn test_artist_catalog_lists_tracks_by_first_play() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let day = 86400;
+        let first = test_track("First", "Band", "Album");
+        let second = test_track("Second", "Band", "Album");
+        let third = test_track("Third", "Band", "Album");
+
+        add_session(&db, &first, player, day, 180);
+        add_session(&db, &first, player, 2 * day, 180);
+        add_session(&db, &second, player, 2 * day + 600, 120);
+        add_session(&db, &third, player, 5 * day, 60);
+        add_session(&db, &third, player, 6 * day, 60);
+        add_session(&db, &test_track("Other", "Someone Else", "Album"), player, day, 180);
+
+        let catalog = db.get_artist_catalog("band").unwrap();
+        assert_eq!((catalog.distinct_tracks, catalog.play_count, catalog.total_listened_time), (3, 5, 600));
+        let timeline: Vec<(&str, i64, i64)> = catalog.discoveries.iter()
+            .map(|d| (d.track.title.as_str(), d.first_played, d.play_count))
+            .collect();
+        assert_eq!(timeline, vec![("First", day, 2), ("Second", 2 * day + 600, 1), ("Third", 5 * day, 2)]);
+
+        assert_eq!(db.get_artist_catalog("Nobody").unwrap().distinct_tracks, 0);
+    }
+
+    #[test]
+    fn test_day_timeline_is_chronological_with_gaps() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 6, 12).unwrap();
+        let day_start = listening_day_start(date, 0, DayZone::Local).unwrap();
+        let track = |title: &str| test_track(title, "Band", "Album");
+
+        add_session(&db, &track("Late"), player, day_start + 10 * 3600, 240);
+        add_session(&db, &track("Morning"), player, day_start + 9 * 3600, 180);
+        add_session(&db, &track("Next"), player, day_start + 9 * 3600 + 180, 200);
+        add_session(&db, &track("Yesterday"), player, day_start - 600, 180);
+        add_session(&db, &track("Tomorrow"), player, listening_day_start(date.succ_opt().unwrap(), 0, DayZone::Local).unwrap(), 180);
+
+        let timeline: Vec<(String, i64, Option<i64>)> = db.get_day_timeline(date).unwrap().into_iter()
+            .map(|entry| (entry.play.track.title, entry.end_time - day_start, entry.gap_before))
+            .collect();
+        assert_eq!(timeline, vec![
+            ("Morning".to_string(), 9 * 3600 + 180, None),
+            ("Next".to_string(), 9 * 3600 + 380, Some(0)),
+            ("Late".to_string(), 10 * 3600 + 240, Some(3600 - 380)),
+        ]);
+    }
+
+    #[test]
+    fn test_incomplete_tracks_lack_length_or_art() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let complete = Track { art_url: Some("file:///cover.jpg".to_string()), ..test_track("Complete", "Artist", "Album") };
+        let no_art = test_track("No Art", "Artist", "Album");
+        let no_length = Track { length: None, ..complete.clone() };
+        let no_length = Track { id: "no-length".to_string(), title: "No Length".to_string(), ..no_length };
+        let bare = Track { length: Some(0), ..test_track("Bare", MISSING_METADATA, MISSING_METADATA) };
+        for track in [&complete, &no_art, &no_length, &bare] {
+            db.insert_or_update_track(track).unwrap();
+        }
+        add_session(&db, &no_length, player, 1000, 60);
+
+        let incomplete = db.get_incomplete_tracks().unwrap();
+        let titles: Vec<_> = incomplete.iter().map(|track| track.title.as_str()).collect();
+        assert_eq!(titles, vec!["No Length", "Bare", "No Art"]);
+        assert_eq!(incomplete[0].missing_fields(), vec!["length"]);
+        assert_eq!(incomplete[1].missing_fields(), vec!["artist", "album", "length", "art"]);
+        assert!(complete.missing_fields().is_empty());
+    }
+
+    #[test]
+    fn test_album_completeness_counts_heard_tracks() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let numbered = |number: i32| Track {
+            track_number: Some(number),
+            ..test_track(&format!("Track {}", number), "Band", "Ten Songs")
+        };
+
+        // Seven of ten tracks heard through; the last one only skipped
+        for number in 1..=7 {
+            add_session(&db, &numbered(number), player, 1000 + i64::from(number) * 300, 180);
+        }
+        add_session_with_status(&db, &numbered(10), player, 5000, 10, "skipped");
+        // Replays don't count twice
+        add_session(&db, &numbered(1), player, 6000, 180);
+        // An album whose player sends no track numbers
+        add_session(&db, &test_track("Loose", "Band", "Singles"), player, 7000, 180);
+
+        let albums = db.get_album_completeness(None, None).unwrap();
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums[0], AlbumCompleteness {
+            album: "Ten Songs".to_string(),
+            artist: "Band".to_string(),
+            heard_tracks: 7,
+            total_tracks: Some(10),
+            percent: Some(70.0),
+        });
+        assert_eq!((albums[1].album.as_str(), albums[1].heard_tracks, albums[1].total_tracks, albums[1].percent), ("Singles", 1, None, None));
+
+        // Only tracks heard in the range count, but the album's size doesn't shrink
+        let albums = db.get_album_completeness(Some(1000), Some(2000)).unwrap();
+        assert_eq!((albums[0].heard_tracks, albums[0].total_tracks), (3, Some(10)));
+    }
+
+    #[test]
+    fn test_export_round_trip_skips_duplicates() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song = Track { rating: Some(0.8), comment: Some("Live take".to_string()), ..test_track("Song", "Artist", "Album") };
+        let session_id = add_session(&db, &song, player, 1000, 180);
+        db.set_session_label(session_id, Some("gym")).unwrap();
+        db.start_session(&song.id, player, 2000).unwrap();
+
+        let json = serde_json::to_string(&db.export_all(3000).unwrap()).unwrap();
+
+        let restored = test_db();
+        let summary = restored.import_gopal_export(&json, false).unwrap();
+        assert_eq!((summary.imported, summary.skipped_duplicates), (1, 0));
+        let history = restored.get_listening_stats(None, None).unwrap().listening_history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].session.label.as_deref(), Some("gym"));
+        assert_eq!(history[0].player.identity, "Spotify");
+        assert_eq!((history[0].track.rating, history[0].track.comment.as_deref()), (Some(0.8), Some("Live take")));
+        let session_rating: Option<f64> = restored.conn.query_row(
+            "SELECT rating FROM sessions WHERE id = ?1", params![history[0].session.id], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(session_rating, Some(0.8));
+
+        let summary = restored.import_gopal_export(&json, false).unwrap();
+        assert_eq!((summary.imported, summary.skipped_duplicates), (0, 1));
+    }
+
+    #[test]
+    fn test_track_player_breakdown_splits_shared_track() {
+        let db = test_db();
+        let phone = db.insert_or_update_player("org.mpris.MediaPlayer2.kdeconnect", "Phone").unwrap();
+        let desktop = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song = test_track("Song", "Artist", "Album");
+
+        add_session(&db, &song, desktop, 1000, 180);
+        add_session(&db, &song, phone, 2000, 60);
+        add_session(&db, &song, desktop, 3000, 120);
+
+        // Both players still count toward the one track
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(stats.top_tracks.len(), 1);
+        assert_eq!(stats.top_tracks[0].total_listened_time, 360);
+
+        let breakdown = db.get_track_player_breakdown(None, None, &StatsFilter::default()).unwrap();
+        assert_eq!(breakdown[&song.id], vec![
+            PlayerShare { player: "Spotify".to_string(), total_listened_time: 300, play_count: 2 },
+            PlayerShare { player: "Phone".to_string(), total_listened_time: 60, play_count: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_timing_report_flags_inconsistent_sessions() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let consistent = test_track("Consistent", "Artist", "Album");
+        let broken = test_track("Broken", "Artist", "Album");
+
+        add_session(&db, &consistent, player, 1000, 180);
+        let broken_id = add_session(&db, &broken, player, 2000, 180);
+        // Pause recorded on top of the full listened time, so 60s more is counted than passed
+        db.conn.execute("UPDATE sessions SET paused_time = 60 WHERE id = ?1", params![broken_id]).unwrap();
+        db.insert_or_update_track(&consistent).unwrap();
+        db.start_session(&consistent.id, player, 3000).unwrap();
+
+        let report = db.get_timing_report(DEFAULT_TIMING_TOLERANCE).unwrap();
+        let offenders: Vec<(i64, &str, i64)> = report.iter()
+            .map(|m| (m.session_id, m.title.as_str(), m.drift))
+            .collect();
+        assert_eq!(offenders, vec![(broken_id, "Broken", 60)]);
+
+        assert!(db.get_timing_report(60).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_engaged_time_formula() {
+        // Equal listened time, one continuous and one paused 90% of the time
+        let continuous = engaged_time(100, 0, 0.5);
+        let paused = engaged_time(100, 900, 0.5);
+        assert_eq!(continuous, 100.0);
+        assert!((paused - 55.0).abs() < 1e-9);
+
+        assert_eq!(engaged_time(100, 900, 0.0), 100.0);
+        assert!((engaged_time(100, 900, 1.0) - 10.0).abs() < 1e-9);
+        // Out-of-range penalties are clamped rather than producing negative time
+        assert!((engaged_time(100, 900, 5.0) - 10.0).abs() < 1e-9);
+        assert_eq!(engaged_time(0, 0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_engagement_stats_discount_paused_sessions() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let focused = test_track("Focused", "Artist", "Album");
+        let distracted = test_track("Distracted", "Artist", "Album");
+
+        add_session(&db, &focused, player, 1000, 100);
+
+        // Same 100s of listening, spread over 1000s with 900s paused
+        db.insert_or_update_track(&distracted).unwrap();
+        let session_id = db.start_session(&distracted.id, player, 5000).unwrap();
+        db.update_session_pause_time(session_id, 900).unwrap();
+        db.finalize_session(session_id, 6000, "completed").unwrap();
+
+        let stats = db.get_engagement_stats(None, None, &StatsFilter::default(), 0.5).unwrap();
+        assert_eq!(stats.total_listened_time, 200);
+        assert_eq!(stats.total_paused_time, 900);
+        assert_eq!(stats.total_engaged_time, 155);
+
+        let engaged: Vec<(&str, i64, i64)> = stats.tracks
+            .iter()
+            .map(|t| (t.track_id.as_str(), t.total_listened_time, t.engaged_time))
+            .collect();
+        assert_eq!(engaged, vec![
+            (focused.id.as_str(), 100, 100),
+            (distracted.id.as_str(), 100, 55),
+        ]);
+    }
+
+    #[test]
+    fn test_count_deduped_plays() {
+        assert_eq!(count_deduped_plays(&[], 300), 0);
+        assert_eq!(count_deduped_plays(&[0, 20, 40], 300), 1);
+        // The window restarts from the last counted play, not the last replay
+        assert_eq!(count_deduped_plays(&[0, 200, 400, 600], 300), 2);
+        assert_eq!(count_deduped_plays(&[0, 20, 40], 10), 3);
+    }
+
+    #[test]
+    fn test_play_dedupe_window() {
+        let player_name = "org.mpris.MediaPlayer2.spotify";
+        let track = test_track("Loop", "Artist", "Album");
+        let other = test_track("Other", "Artist", "Album");
+
+        let seed = |db: &Database| {
+            let player = db.insert_or_update_player(player_name, "Spotify").unwrap();
+            // Three replays within 60 seconds, then one much later
+            add_session(db, &track, player, 1000, 15);
+            add_session(db, &track, player, 1020, 15);
+            add_session(db, &track, player, 1050, 10);
+            add_session(db, &track, player, 5000, 15);
+            add_session(db, &other, player, 6000, 30);
+        };
+
+        let play_counts = |db: &Database| -> Vec<(String, i64)> {
+            let mut counts: Vec<(String, i64)> = db.get_listening_stats(None, None).unwrap()
+                .top_tracks
+                .into_iter()
+                .map(|t| (t.track.title, t.play_count))
+                .collect();
+            counts.sort();
+            counts
+        };
+
+        let db = test_db();
+        seed(&db);
+        assert_eq!(play_counts(&db), vec![("Loop".to_string(), 4), ("Other".to_string(), 1)]);
+
+        let db = test_db().with_play_dedupe_window(Some(300));
+        seed(&db);
+        assert_eq!(play_counts(&db), vec![("Loop".to_string(), 2), ("Other".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_import_listenbrainz() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        // Length already known from the daemon is used when the export has none
+        let known = test_track("Known", "Artist", "Album");
+        add_session(&db, &known, player, 100, 180);
+
+        let export = r#"[
+            {"listened_at": 1000, "track_metadata": {"artist_name": "Artist", "track_name": "Timed",
+                "additional_info": {"duration_ms": 240000}}},
+            {"listened_at": 2000, "track_metadata": {"artist_name": "Artist", "track_name": "Known", "release_name": "Album"}},
+            {"listened_at": 3000, "track_metadata": {"artist_name": "Artist", "track_name": "Untimed"}}
+        ]"#;
+
+        let summary = db.import_listenbrainz(export, 200).unwrap();
+        assert_eq!(summary, ImportSummary { imported: 3, skipped_duplicates: 0, estimated_durations: 1 });
+
+        let stats = db.get_listening_stats(Some(500), None).unwrap();
+        assert_eq!(stats.total_listening_time, 240 + 180 + 200);
+
+        // Importing the same export again adds nothing
+        let summary = db.import_listenbrainz(export, 200).unwrap();
+        assert_eq!(summary, ImportSummary { imported: 0, skipped_duplicates: 3, estimated_durations: 0 });
+    }
+
+    #[test]
+    fn test_content_type_filter_and_totals() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        let song = test_track("Song", "Artist", "Album");
+        let book = test_track("Chapter 1", "Narrator", "The Book");
+        db.insert_or_update_track(&song).unwrap();
+        db.insert_or_update_track(&book).unwrap();
+
+        let session = db.start_session_as(&song.id, player, 1000, ContentType::Music).unwrap();
+        db.finalize_session(session, 1200, "completed").unwrap();
+        let session = db.start_session_as(&book.id, player, 2000, ContentType::Audiobook).unwrap();
+        db.finalize_session(session, 5600, "completed").unwrap();
+
+        let all = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(all.total_listening_time, 3800);
+        assert_eq!(all.top_tracks[0].track.title, "Chapter 1");
+        assert_eq!(all.content_type_totals, vec![
+            ContentTypeTotal { content_type: ContentType::Audiobook, total_listening_time: 3600 },
+            ContentTypeTotal { content_type: ContentType::Music, total_listening_time: 200 },
+        ]);
+
+        let music_only = StatsFilter { content_type: Some(ContentType::Music), ..StatsFilter::default() };
+        let music = db.get_filtered_listening_stats(None, None, &music_only).unwrap();
+        assert_eq!(music.total_listening_time, 200);
+        let titles: Vec<&str> = music.top_tracks.iter().map(|t| t.track.title.as_str()).collect();
+        assert_eq!(titles, vec!["Song"]);
+        assert_eq!(music.top_artists.len(), 1);
+        assert_eq!(music.listening_history.len(), 1);
+    }
+
+    #[test]
+    fn test_anonymized_output_has_no_timestamps_or_session_ids() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let mut track = test_track("Song", "Band", "Album");
+        track.raw_metadata = Some(r#"{"xesam:url":"file:///home/me/song.flac"}"#.to_string());
+        add_session(&db, &track, player, 1_700_000_000, 100);
+        add_session(&db, &track, player, 1_700_000_500, 60);
+
+        let stats = db.get_listening_stats(None, None).unwrap();
+        let history = stats.listening_history.clone();
+        let json = serde_json::to_string(&stats.anonymized()).unwrap();
+        assert!(json.contains("\"total_listening_time\":160"));
+        assert!(json.contains("\"play_count\":2"));
+        for field in ["start_time", "end_time", "session", "1700000000", "file://"] {
+            assert!(!json.contains(field), "{} leaked into {}", field, json);
+        }
+
+        let plays: Vec<AnonymousPlay> = history.iter().map(AnonymousPlay::from).collect();
+        let json = serde_json::to_string(&plays).unwrap();
+        assert!(json.contains("\"listened_time\":100"));
+        for field in ["start_time", "session", "\"id\"", "player", "1700000000"] {
+            assert!(!json.contains(field), "{} leaked into {}", field, json);
+        }
+    }
+
+    #[test]
+    fn test_listening_block_start() {
+        let block = |sessions: &[(i64, i64)]| {
+            listening_block_start(sessions.iter().map(|&s| Ok::<_, ()>(s)), 300).unwrap()
+        };
+
+        assert_eq!(block(&[]), None);
+        // Track change mid-block: the previous track ended right as the current one began
+        assert_eq!(block(&[(1200, 1500), (1000, 1200)]), Some(1000));
+        // A short break keeps the block going, a long one starts a new block
+        assert_eq!(block(&[(2000, 2300), (1500, 1800), (100, 400)]), Some(1500));
+        // Overlapping sessions from two players
+        assert_eq!(block(&[(1100, 1500), (1000, 1400)]), Some(1000));
+    }
+
+    #[test]
+    fn test_current_block_seconds() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Old", "Band", "Album"), player, 0, 200);
+        assert_eq!(db.get_current_block_seconds(5000, LISTENING_BLOCK_GAP).unwrap(), None);
+
+        add_session(&db, &test_track("First", "Band", "Album"), player, 3000, 200);
+        add_session(&db, &test_track("Second", "Band", "Album"), player, 3200, 250);
+        let track = test_track("Third", "Band", "Album");
+        db.insert_or_update_track(&track).unwrap();
+        db.start_session(&track.id, player, 3500).unwrap();
+
+        assert_eq!(db.get_current_block_seconds(3600, LISTENING_BLOCK_GAP).unwrap(), Some(600));
+    }
+
+    #[test]
+    fn test_hourly_heatmap_by_local_weekday_and_hour() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().timestamp();
+
+        // 2024-01-01 is a Monday, 2024-01-07 a Sunday
+        add_session(&db, &track, player, at(1, 9), 600);
+        add_session(&db, &track, player, at(8, 9), 300);
+        add_session(&db, &track, player, at(7, 23), 120);
+
+        let heatmap = db.get_hourly_heatmap(None, None).unwrap();
+        assert_eq!(heatmap[0][9], 900);
+        assert_eq!(heatmap[6][23], 120);
+        assert_eq!(heatmap.iter().flatten().sum::<i64>(), 1020);
+
+        let heatmap = db.get_hourly_heatmap(Some(at(2, 0)), None).unwrap();
+        assert_eq!((heatmap[0][9], heatmap[6][23]), (300, 120));
+    }
+
+    #[test]
+    fn test_plays_per_active_day() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song = test_track("Song", "Band", "Album");
+        let other = test_track("Other", "Band", "Album");
+        let filtered = test_track("Tone", "Test Signal", "Album");
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().timestamp();
+
+        // Four listening days: the 1st, 2nd, 5th and 6th. Two plays on the 1st share a day.
+        add_session(&db, &song, player, at(1, 9), 100);
+        add_session(&db, &song, player, at(1, 21), 100);
+        add_session(&db, &song, player, at(2, 9), 100);
+        add_session(&db, &other, player, at(5, 9), 100);
+        // Only an excluded artist played that day, so it doesn't count once they're filtered out
+        add_session(&db, &filtered, player, at(6, 9), 100);
+
+        let range = (Some(at(1, 0)), Some(at(6, 23)));
+        assert_eq!(db.count_active_days(range.0, range.1, &StatsFilter::default()).unwrap(), 4);
+
+        let filter = StatsFilter { exclude_artists: vec!["Test Signal".to_string()], ..StatsFilter::default() };
+        assert_eq!(db.count_active_days(range.0, range.1, &filter).unwrap(), 3);
+        let stats = db.get_filtered_listening_stats(range.0, range.1, &filter).unwrap();
+        assert_eq!(stats.active_days, 3);
+        assert_eq!(stats.top_tracks[0].track.title, "Song");
+        assert_eq!(stats.top_tracks[0].plays_per_active_day, Some(1.0));
+        assert_eq!(stats.top_tracks[1].plays_per_active_day, Some(1.0 / 3.0));
+        assert_eq!((stats.top_artists[0].play_count, stats.top_artists[0].plays_per_active_day), (4, Some(4.0 / 3.0)));
+
+        let dashboard = db.get_dashboard(range.0, range.1, &filter, DashboardLimits { top: 10, history: 0 }).unwrap();
+        assert_eq!(dashboard.stats.top_artists[0].plays_per_active_day, Some(4.0 / 3.0));
+
+        // No listening, no rate
+        let stats = db.get_listening_stats(Some(at(20, 0)), None).unwrap();
+        assert_eq!(stats.active_days, 0);
+    }
+
+    #[test]
+    fn test_day_start_hour_moves_late_sessions_to_the_previous_day() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().timestamp();
+
+        // Monday 10pm, then 2am and 5am on Tuesday
+        add_session(&db, &track, player, at(1, 22), 100);
+        add_session(&db, &track, player, at(2, 2), 200);
+        add_session(&db, &track, player, at(2, 5), 400);
+
+        assert_eq!(db.count_active_days(None, None, &StatsFilter::default()).unwrap(), 2);
+        let heatmap = db.get_hourly_heatmap(None, None).unwrap();
+        assert_eq!((heatmap[0][2], heatmap[1][2]), (0, 200));
+
+        let db = db.with_day_start_hour(4);
+        assert_eq!(listening_day(at(2, 2), 4, DayZone::Local), NaiveDate::from_ymd_opt(2024, 1, 1));
+        assert_eq!(listening_day_start(NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(), 4, DayZone::Local).unwrap(), at(2, 4));
+
+        // The 2am session is Monday's: still two days, but split differently
+        assert_eq!(db.count_active_days(None, None, &StatsFilter::default()).unwrap(), 2);
+        assert_eq!(db.count_active_days(Some(at(1, 12)), Some(at(2, 3)), &StatsFilter::default()).unwrap(), 1);
+        let heatmap = db.get_hourly_heatmap(None, None).unwrap();
+        assert_eq!((heatmap[0][2], heatmap[1][2], heatmap[1][5]), (200, 0, 400));
+
+        let averages = db.get_weekday_averages(Some(at(1, 4)), Some(at(3, 4) - 1)).unwrap();
+        assert_eq!((averages[0].total_listened_time, averages[1].total_listened_time), (300, 400));
+        assert_eq!((averages[0].days, averages[1].days), (1, 1));
+    }
+
+    #[test]
+    fn test_day_boundary_split_divides_sessions_across_midnight() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        let monday = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let tuesday = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+
+        // 11:50pm Monday to 12:10am Tuesday, with 5 of the 20 minutes paused
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 23, 50, 0).unwrap().timestamp();
+        db.insert_or_update_track(&track).unwrap();
+        let session_id = db.start_session(&track.id, player, start).unwrap();
+        db.update_session_pause_time(session_id, 300).unwrap();
+        db.finalize_session(session_id, start + 1200, "completed").unwrap();
+
+        let totals = db.get_daily_totals(None, None).unwrap();
+        assert_eq!(totals.into_iter().collect::<Vec<_>>(), vec![(monday, 900)]);
+
+        let db = db.with_day_boundary_split(true);
+        let totals = db.get_daily_totals(None, None).unwrap();
+        assert_eq!(totals.into_iter().collect::<Vec<_>>(), vec![(monday, 450), (tuesday, 450)]);
+        let heatmap = db.get_hourly_heatmap(None, None).unwrap();
+        assert_eq!((heatmap[0][23], heatmap[1][0]), (450, 450));
+
+        // The row itself is untouched
+        let row: (i64, i64) = db.conn.query_row(
+            "SELECT start_time, listened_time FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!(row, (start, 900));
+    }
+
+    #[test]
+    fn test_consistency_rewards_listening_every_day() {
+        let player_name = "org.mpris.MediaPlayer2.spotify";
+        let track = test_track("Song", "Band", "Album");
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().timestamp();
+        let start = at(1, 0);
+        let end = Local.with_ymd_and_hms(2024, 1, 10, 23, 59, 59).unwrap().timestamp();
+
+        // Half an hour every day of the ten
+        let steady = test_db();
+        let player = steady.insert_or_update_player(player_name, "Spotify").unwrap();
+        for day in 1..=10 {
+            add_session(&steady, &track, player, at(day, 12), 1800);
+        }
+        let score = steady.get_consistency(Some(start), Some(end)).unwrap();
+        assert_eq!((score.days, score.active_days), (10, 10));
+        assert_eq!(score.coefficient_of_variation, 0.0);
+        assert_eq!(score.score, 100.0);
+
+        // About the same total crammed into three uneven days
+        let bursty = test_db();
+        let player = bursty.insert_or_update_player(player_name, "Spotify").unwrap();
+        add_session(&bursty, &track, player, at(2, 12), 9000);
+        add_session(&bursty, &track, player, at(5, 12), 3600);
+        add_session(&bursty, &track, player, at(9, 12), 5400);
+        let score = bursty.get_consistency(Some(start), Some(end)).unwrap();
+        assert_eq!((score.days, score.active_days), (10, 3));
+        assert_eq!(score.mean_daily_listened_time, 6000.0);
+        assert!(score.coefficient_of_variation > 0.3);
+        assert!(score.score < 30.0);
+
+        let empty = test_db().get_consistency(None, None).unwrap();
+        assert_eq!((empty.days, empty.score), (0, 0.0));
+    }
+
+    #[test]
+    fn test_weekday_averages_over_two_weeks() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Band", "Album");
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().timestamp();
+
+        // 2024-01-01 and 2024-01-08 are Mondays
+        add_session(&db, &track, player, at(1, 12), 600);
+        add_session(&db, &track, player, at(8, 12), 1200);
+        add_session(&db, &track, player, at(2, 20), 300);
+        // Outside the range
+        add_session(&db, &track, player, at(15, 12), 5000);
+
+        let start = at(1, 0);
+        let end = Local.with_ymd_and_hms(2024, 1, 14, 23, 59, 59).unwrap().timestamp();
+        let averages = db.get_weekday_averages(Some(start), Some(end)).unwrap();
+
+        assert_eq!(averages[0].weekday, Weekday::Mon);
+        assert_eq!(averages.iter().map(|avg| avg.days).collect::<Vec<_>>(), vec![2; 7]);
+        assert_eq!((averages[0].total_listened_time, averages[0].average_listened_time), (1800, 900));
+        assert_eq!((averages[1].total_listened_time, averages[1].average_listened_time), (300, 150));
+        assert_eq!(averages[6].weekday, Weekday::Sun);
+        assert_eq!(averages[6].average_listened_time, 0);
+
+        // A range ending on Wednesday of the second week has two Mondays but one Thursday
+        let averages = db.get_weekday_averages(Some(start), Some(at(10, 23))).unwrap();
+        assert_eq!(averages.iter().map(|avg| avg.days).collect::<Vec<_>>(), vec![2, 2, 2, 1, 1, 1, 1]);
+        assert_eq!(averages[0].average_listened_time, 900);
+    }
+
+    #[test]
+    fn test_on_this_day_across_years() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let at = |year: i32, month: u32, day: u32, hour: u32| {
+            Local.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap().timestamp()
+        };
+
+        add_session(&db, &test_track("Old Song", "Band", "Album"), player, at(2021, 3, 14, 9), 200);
+        add_session(&db, &test_track("New Song", "Band", "Album"), player, at(2023, 3, 14, 23), 300);
+        add_session(&db, &test_track("New Song", "Band", "Album"), player, at(2023, 3, 14, 8), 100);
+        // Neighbouring days don't count
+        add_session(&db, &test_track("Other", "Band", "Album"), player, at(2023, 3, 15, 0), 500);
+        add_session(&db, &test_track("Other", "Band", "Album"), player, at(2023, 3, 13, 23), 500);
+
+        let slices = db.get_on_this_day(3, 14, at(2024, 6, 1, 12)).unwrap();
+        let summary: Vec<(i32, i64, &str)> = slices
+            .iter()
+            .map(|slice| (slice.year, slice.total_listening_time, slice.top_tracks[0].track.title.as_str()))
+            .collect();
+        assert_eq!(summary, vec![(2023, 400, "New Song"), (2021, 200, "Old Song")]);
+        assert_eq!(slices[0].top_tracks.len(), 1);
+
+        // Leap day only exists in leap years
+        add_session(&db, &test_track("Leap", "Band", "Album"), player, at(2020, 2, 29, 12), 60);
+        let slices = db.get_on_this_day(2, 29, at(2024, 6, 1, 12)).unwrap();
+        assert_eq!(slices.iter().map(|slice| slice.year).collect::<Vec<_>>(), vec![2020]);
+        assert!(db.get_on_this_day(4, 31, at(2024, 6, 1, 12)).unwrap().is_empty());
+
+        // Only years before the current one count
+        assert_eq!(db.get_on_this_day(3, 14, at(2023, 6, 1, 12)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_on_this_day_current_year_follows_the_day_start() {
+        let db = test_db().with_day_start_hour(4);
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let at = |year: i32, month: u32, day: u32, hour: u32| {
+            Local.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap().timestamp()
+        };
+        add_session(&db, &test_track("Last Year", "Band", "Album"), player, at(2023, 12, 31, 12), 200);
+        add_session(&db, &test_track("This Night", "Band", "Album"), player, at(2024, 12, 31, 22), 100);
+
+        // At 2am on January 1 it's still December 31's listening day, so 2024 is current
+        let slices = db.get_on_this_day(12, 31, at(2025, 1, 1, 2)).unwrap();
+        assert_eq!(slices.iter().map(|slice| slice.year).collect::<Vec<_>>(), vec![2023]);
+        let slices = db.get_on_this_day(12, 31, at(2025, 1, 1, 5)).unwrap();
+        assert_eq!(slices.iter().map(|slice| slice.year).collect::<Vec<_>>(), vec![2024, 2023]);
+    }
+
+    #[test]
+    fn test_session_labels_filter_stats() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let workout = add_session(&db, &test_track("Pump", "Band", "Album"), player, 1000, 300);
+        let focus = add_session(&db, &test_track("Calm", "Quiet", "Album"), player, 2000, 600);
+        add_session(&db, &test_track("Other", "Band", "Album"), player, 3000, 100);
+
+        assert!(db.set_session_label(workout, Some("gym")).unwrap());
+        assert!(db.set_session_label(focus, Some("focus")).unwrap());
+        assert!(!db.set_session_label(9999, Some("gym")).unwrap());
+
+        let gym = StatsFilter { label: Some("gym".to_string()), ..StatsFilter::default() };
+        let stats = db.get_filtered_listening_stats(None, None, &gym).unwrap();
+        assert_eq!(stats.total_listening_time, 300);
+        assert_eq!(stats.top_artists.iter().map(|a| a.artist.as_str()).collect::<Vec<_>>(), vec!["Band"]);
+        assert_eq!(stats.listening_history[0].session.label.as_deref(), Some("gym"));
+
+        // Relabelling a finished session invalidates cached stats
+        db.set_session_label(focus, Some("gym")).unwrap();
+        assert_eq!(db.get_filtered_listening_stats(None, None, &gym).unwrap().total_listening_time, 900);
+
+        db.set_session_label(workout, None).unwrap();
+        assert_eq!(db.get_filtered_listening_stats(None, None, &gym).unwrap().total_listening_time, 600);
+        assert_eq!(db.get_listening_stats(None, None).unwrap().total_listening_time, 1000);
+    }
+
+    #[test]
+    fn test_recency_weight_halves_every_half_life() {
+        assert_eq!(recency_weight(0, 100.0), 1.0);
+        assert!((recency_weight(100, 100.0) - 0.5).abs() < 1e-9);
+        assert!((recency_weight(300, 100.0) - 0.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_recency_weighting_favours_recent_plays() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let day = 24 * 3600;
+
+        let favourite = test_track("Favourite", "Old Band", "Album");
+        for i in 0..10 {
+            add_session(&db, &favourite, player, now - 365 * day + i * 600, 300);
+        }
+        let obsession = test_track("Obsession", "New Band", "Album");
+        for i in 0..3 {
+            add_session(&db, &obsession, player, now - day + i * 600, 300);
+        }
+
+        let plain = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(plain.top_tracks[0].track.title, "Favourite");
+        assert_eq!(plain.top_artists[0].artist, "Old Band");
+
+        let half_life = DEFAULT_RECENCY_HALF_LIFE_DAYS * day as f64;
+        let weighted = db.get_recency_weighted_stats(None, None, &StatsFilter::default(), half_life).unwrap();
+        let titles: Vec<&str> = weighted.top_tracks.iter().map(|t| t.track.title.as_str()).collect();
+        assert_eq!(titles, vec!["Obsession", "Favourite"]);
+        assert_eq!(weighted.top_artists[0].artist, "New Band");
+        // Play counts are not weighted
+        assert_eq!(weighted.top_tracks[1].play_count, 10);
+        // A day old keeps almost all of its weight, a year old almost none
+        assert!(weighted.top_tracks[0].total_listened_time > 850);
+        assert!(weighted.top_tracks[1].total_listened_time < 10);
+    }
+
+    #[test]
+    fn test_excluded_artists_and_tracks() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Song", "Band", "Album"), player, 1000, 100);
+        add_session(&db, &test_track("Breathe", "Calm App", "Sessions"), player, 2000, 600);
+        add_session(&db, &test_track("Test Tone", "Band", "Album"), player, 3000, 50);
+        // Quotes in names are bound, not spliced into the SQL
+        add_session(&db, &test_track("It's Fine", "O'Brien", "Album"), player, 4000, 30);
+
+        let filter = StatsFilter {
+            exclude_artists: vec!["Calm App".to_string(), "O'Brien".to_string()],
+            exclude_tracks: vec!["Test Tone".to_string()],
+            ..StatsFilter::default()
+        };
+        let stats = db.get_filtered_listening_stats(None, None, &filter).unwrap();
+        assert_eq!(stats.total_listening_time, 100);
+
+        let artists: Vec<(&str, i64)> = stats.top_artists.iter().map(|a| (a.artist.as_str(), a.total_listened_time)).collect();
+        assert_eq!(artists, vec![("Band", 100)]);
+        let tracks: Vec<&str> = stats.top_tracks.iter().map(|t| t.track.title.as_str()).collect();
+        assert_eq!(tracks, vec!["Song"]);
+        assert_eq!(stats.listening_history.len(), 1);
+
+        // Unfiltered stats are unaffected by an earlier filtered query
+        assert_eq!(db.get_listening_stats(None, None).unwrap().total_listening_time, 780);
+    }
+
+    #[test]
+    fn test_title_and_artist_substring_filters() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Song (Live)", "Band", "Album"), player, 1000, 100);
+        add_session(&db, &test_track("Alive", "Band", "Album"), player, 2000, 200);
+        add_session(&db, &test_track("Studio Song", "Band", "Album"), player, 3000, 400);
+        add_session(&db, &test_track("Live at Home", "Other Band", "Album"), player, 4000, 800);
+        // Wildcards in the substring are matched literally
+        add_session(&db, &test_track("100% Live", "Band", "Album"), player, 5000, 1600);
+
+        let filter = StatsFilter { title_contains: Some("live".to_string()), ..StatsFilter::default() };
+        let stats = db.get_filtered_listening_stats(None, None, &filter).unwrap();
+        assert_eq!(stats.total_listening_time, 2700);
+        let mut tracks: Vec<&str> = stats.top_tracks.iter().map(|t| t.track.title.as_str()).collect();
+        tracks.sort();
+        assert_eq!(tracks, vec!["100% Live", "Alive", "Live at Home", "Song (Live)"]);
+
+        let filter = StatsFilter {
+            title_contains: Some("(LIVE)".to_string()),
+            artist_contains: Some("band".to_string()),
+            ..StatsFilter::default()
+        };
+        let dashboard = db.get_dashboard(None, None, &filter, DashboardLimits { top: 10, history: 10 }).unwrap();
+        assert_eq!(dashboard.stats.total_listening_time, 100);
+
+        let filter = StatsFilter { title_contains: Some("0%".to_string()), artist_contains: Some("other".to_string()), ..StatsFilter::default() };
+        assert_eq!(db.get_filtered_listening_stats(None, None, &filter).unwrap().total_listening_time, 0);
+        let filter = StatsFilter { title_contains: Some("0%".to_string()), ..StatsFilter::default() };
+        assert_eq!(db.get_filtered_listening_stats(None, None, &filter).unwrap().total_listening_time, 1600);
+    }
+
+    #[test]
+    fn test_split_artist_stats() {
+        let db = test_db().with_artist_splitter(Some(ArtistSplitter::default()));
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Collab", "A, B feat. C", "Album"), player, 1000, 100);
+        add_session(&db, &test_track("Solo", "A", "Album"), player, 2000, 50);
+        add_session(&db, &test_track("Duet", "B & D", "Album"), player, 3000, 20);
+
+        let stats = db.get_listening_stats(None, None).unwrap();
+        let artists: Vec<(&str, i64, i64)> = stats.top_artists
+            .iter()
+            .map(|a| (a.artist.as_str(), a.total_listened_time, a.track_count))
+            .collect();
+        assert_eq!(artists, vec![("A", 150, 2), ("B", 120, 2), ("C", 100, 1), ("D", 20, 1)]);
+        // Splitting credits doesn't count listening time twice
+        assert_eq!(stats.total_listening_time, 170);
+
+        let weighted = db.get_recency_weighted_stats(None, None, &StatsFilter::default(), f64::INFINITY).unwrap();
+        assert_eq!(weighted.top_artists.len(), 4);
+
+        let combined = db.with_artist_splitter(None).get_listening_stats(None, None).unwrap();
+        assert_eq!(combined.top_artists[0].artist, "A, B feat. C");
+    }
+
+    #[test]
+    fn test_compute_stats_matches_the_sql_aggregates() {
+        type Summary = (i64, Vec<(String, i64, i64)>, Vec<(String, i64, i64, i64)>, Vec<i64>, Vec<(ContentType, i64)>);
+        fn summary(stats: &ListeningStats) -> Summary {
+            (
+                stats.total_listening_time,
+                stats.top_tracks.iter().map(|t| (t.track.id.clone(), t.total_listened_time, t.play_count)).collect(),
+                stats.top_artists.iter().map(|a| (a.artist.clone(), a.total_listened_time, a.track_count, a.play_count)).collect(),
+                stats.listening_history.iter().map(|entry| entry.session.id).collect(),
+                stats.content_type_totals.iter().map(|total| (total.content_type, total.total_listening_time)).collect(),
+            )
+        }
+
+        let populated = || {
+            let db = test_db();
+            let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+            let collab = test_track("Collab", "A, B feat. C", "Album");
+            add_session(&db, &collab, player, 1000, 100);
+            add_session(&db, &collab, player, 1150, 30); // a replay
+            add_session(&db, &test_track("Solo", "A", "Album"), player, 2000, 50);
+            add_session(&db, &test_track("Untagged", MISSING_METADATA, "Album"), player, 3000, 70);
+            add_session(&db, &test_track("Skipped", "D", "Album"), player, 3500, 0);
+            let episode = test_track("Episode", "Host", "Show");
+            db.insert_or_update_track(&episode).unwrap();
+            let session_id = db.start_session_as(&episode.id, player, 4000, ContentType::Podcast).unwrap();
+            db.finalize_session(session_id, 4600, "completed").unwrap();
+            db
+        };
+
+        for (window, splitter) in [(None, None), (Some(300), Some(ArtistSplitter::default()))] {
+            let db = populated().with_play_dedupe_window(window).with_artist_splitter(splitter);
+            let sql = db.get_listening_stats(None, None).unwrap();
+            let in_memory = db.with_in_memory_stats(true).get_listening_stats(None, None).unwrap();
+            assert_eq!(summary(&in_memory), summary(&sql));
+            assert_eq!(sql.listening_history.len(), 5);
+        }
+
+        // Without a database, active sessions count up to the given time
+        let mut sessions = populated().get_listening_stats(None, None).unwrap().listening_history;
+        let mut playing = sessions.pop().unwrap();
+        playing.session.listened_time = None;
+        playing.session.status = "active".to_string();
+        let stats = compute_stats(&[playing], &StatsOptions { current_time: 1400, ..StatsOptions::default() });
+        assert_eq!(stats.total_listening_time, 400);
+        assert_eq!(stats.listening_history[0].session.listened_time, Some(400));
+    }
+
+    #[test]
+    fn test_stats_cache_invalidated_by_finished_session() {
+        let db = test_db().with_stats_cache(true);
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Artist", "Album");
+        add_session(&db, &track, player, 1000, 100);
+
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(stats.total_listening_time, 100);
+
+        // Renaming a track is a write the next read must see
+        db.conn.execute("UPDATE tracks SET title = 'Renamed'", []).unwrap();
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(stats.top_tracks[0].track.title, "Renamed");
+
+        // Re-reporting the same metadata or pruning raw metadata keeps the cache
+        let cached = db.conn.query_row("SELECT generation FROM stats_generation", [], |row| row.get::<_, i64>(0)).unwrap();
+        db.insert_or_update_track(&Track { title: "Renamed".to_string(), raw_metadata: Some("{}".to_string()), ..track.clone() }).unwrap();
+        assert_eq!(db.prune_raw_metadata(0).unwrap(), 1);
+        assert_eq!(db.conn.query_row("SELECT generation FROM stats_generation", [], |row| row.get::<_, i64>(0)).unwrap(), cached);
+
+        // Renaming or removing a player invalidates too
+        db.conn.execute("UPDATE players SET name = 'renamed'", []).unwrap();
+        assert_ne!(db.conn.query_row("SELECT generation FROM stats_generation", [], |row| row.get::<_, i64>(0)).unwrap(), cached);
+
+        // An active session is live data and bypasses the cache, without invalidating it
+        let session_id = db.start_session(&track.id, player, 2000).unwrap();
+        assert_eq!(db.get_listening_stats(None, None).unwrap().top_tracks[0].track.title, "Renamed");
+
+        // Finishing it is a write the next read must see
+        db.finalize_session(session_id, 2050, "completed").unwrap();
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(stats.total_listening_time, 150);
+        assert_eq!(stats.top_tracks[0].track.title, "Renamed");
+
+        // Different bounds are cached separately
+        assert_eq!(db.get_listening_stats(Some(1500), None).unwrap().total_listening_time, 50);
+    }
+
+    #[test]
+    fn test_update_active_sessions_progress_batch() {
+        let db = test_db();
+        let track = test_track("Song", "Artist", "Album");
+        db.insert_or_update_track(&track).unwrap();
+
+        let mut session_ids = Vec::new();
+        for (i, name) in ["firefox", "spotify", "vlc"].iter().enumerate() {
+            let player = db.insert_or_update_player(&format!("org.mpris.MediaPlayer2.{}", name), name).unwrap();
+            session_ids.push(db.start_session(&track.id, player, 1000 + i as i64 * 10).unwrap());
+        }
+        // A finished session in the batch is left untouched
+        let finished_player = db.insert_or_update_player("org.mpris.MediaPlayer2.mpv", "mpv").unwrap();
+        let finished = add_session(&db, &track, finished_player, 900, 50);
+
+        db.update_active_sessions_progress(1100, &[
+            (session_ids[0], 0),
+            (session_ids[1], 15),
+            (session_ids[2], 0),
+            (finished, 0),
+        ]).unwrap();
+
+        let progress: Vec<(i64, Option<i64>, Option<i64>)> = db.conn
+            .prepare("SELECT id, listened_time, last_seen FROM sessions ORDER BY id").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(progress, vec![
+            (session_ids[0], Some(100), Some(1100)),
+            (session_ids[1], Some(75), Some(1100)),
+            (session_ids[2], Some(80), Some(1100)),
+            (finished, Some(50), Some(900)),
+        ]);
+    }
+
+    #[test]
+    fn test_database_stats_diagnostics() {
+        let db = test_db();
+        let stats = db.get_database_stats().unwrap();
+        assert_eq!(stats.total_sessions, 0);
+        assert_eq!((stats.earliest_session, stats.latest_session), (None, None));
+        assert_eq!(stats.total_listened_time, 0);
+        assert_eq!(stats.schema_version, MIGRATIONS.len());
+        assert!(stats.file_size > 0);
+
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let track = test_track("Song", "Artist", "Album");
+        add_session(&db, &track, player, 5000, 120);
+        add_session(&db, &track, player, 1000, 60);
+        db.start_session(&track.id, player, 9000).unwrap();
+
+        let stats = db.get_database_stats().unwrap();
+        assert_eq!(stats.total_sessions, 3);
+        assert_eq!(stats.active_sessions, 1);
+        assert_eq!(stats.earliest_session, Some(1000));
+        assert_eq!(stats.latest_session, Some(9000));
+        assert_eq!(stats.total_listened_time, 180);
+    }
+
+    #[test]
+    fn test_album_tracks_in_track_order() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+
+        let numbered = |title: &str, disc: Option<i32>, number: Option<i32>| Track {
+            track_number: number,
+            disc_number: disc,
+            ..test_track(title, "Band", "Record")
+        };
+        let opener = numbered("Opener", Some(1), Some(1));
+        let closer = numbered("Closer", Some(2), Some(1));
+        let second = numbered("Second", Some(1), Some(2));
+        let bonus = numbered("Bonus", None, None);
+        for track in [&closer, &bonus, &second, &opener] {
+            db.insert_or_update_track(track).unwrap();
+        }
+        db.insert_or_update_track(&test_track("Elsewhere", "Band", "Other Record")).unwrap();
+        add_session(&db, &second, player, 1000, 30);
+        add_session(&db, &second, player, 2000, 40);
+
+        let tracks = db.get_album_tracks("Record", Some("Band")).unwrap();
+        let order: Vec<(&str, Option<i32>, Option<i32>, i64)> = tracks
+            .iter()
+            .map(|t| (t.track.title.as_str(), t.track.disc_number, t.track.track_number, t.play_count))
+            .collect();
+        assert_eq!(order, vec![
+            ("Opener", Some(1), Some(1), 0),
+            ("Second", Some(1), Some(2), 2),
+            ("Closer", Some(2), Some(1), 0),
+            ("Bonus", None, None, 0),
+        ]);
+        assert_eq!(tracks[1].total_listened_time, 70);
+
+        assert!(db.get_album_tracks("Record", Some("Someone Else")).unwrap().is_empty());
+        assert_eq!(db.get_album_tracks("Record", None).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_rank_history_by_month() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song_a = test_track("Song A", "Artist A", "Album");
+        let song_b = test_track("Song B", "Artist B", "Album");
+        let song_c = test_track("Song C", "Artist C", "Album");
+
+        // Mid-month timestamps so the local timezone can't move them across buckets
+        let january = 1705320000; // 2024-01-15 12:00 UTC
+        let february = 1707998400; // 2024-02-15 12:00 UTC
+        let march = 1710504000; // 2024-03-15 12:00 UTC
+
+        add_session(&db, &song_a, player, january, 100);
+        add_session(&db, &song_b, player, january + 1000, 300);
+        add_session(&db, &song_c, player, january + 2000, 200);
+        add_session(&db, &song_a, player, february, 500);
+        add_session(&db, &song_b, player, february + 1000, 50);
+        add_session(&db, &song_b, player, march, 50);
+
+        let history = db.get_rank_history(&RankEntity::Artist("Artist A".to_string()), TimeBucket::Month).unwrap();
+        let summary: Vec<(&str, Option<usize>, i64, usize)> = history
+            .iter()
+            .map(|p| (p.bucket.as_str(), p.rank, p.listened_time, p.ranked_entities))
+            .collect();
+        assert_eq!(summary, vec![
+            ("2024-01", Some(3), 100, 3),
+            ("2024-02", Some(1), 500, 2),
+            ("2024-03", None, 0, 1),
+        ]);
+
+        let history = db.get_rank_history(&RankEntity::Track(song_b.id.clone()), TimeBucket::Month).unwrap();
+        let ranks: Vec<Option<usize>> = history.iter().map(|p| p.rank).collect();
+        assert_eq!(ranks, vec![Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn test_rank_history_uses_iso_weeks() {
+        let db = test_db().with_day_zone(DayZone::Utc);
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let song = test_track("Song", "Artist", "Album");
+
+        add_session(&db, &song, player, 1609675200, 100); // Sunday 2021-01-03, ISO 2020-W53
+        add_session(&db, &song, player, 1609761600, 100); // Monday 2021-01-04, 2021-W01
+        add_session(&db, &song, player, 1704196800, 100); // Tuesday 2024-01-02, 2024-W01
+        add_session(&db, &song, player, 1735560000, 100); // Monday 2024-12-30, ISO 2025-W01
+
+        let history = db.get_rank_history(&RankEntity::Track(song.id.clone()), TimeBucket::Week).unwrap();
+        let buckets: Vec<&str> = history.iter().map(|p| p.bucket.as_str()).collect();
+        assert_eq!(buckets, vec!["2020-W53", "2021-W01", "2024-W01", "2025-W01"]);
+    }
+
+    #[test]
+    fn test_excluded_players_drop_out_of_totals() {
+        let db = test_db();
+        let spotify = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let browser = db.insert_or_update_player("org.mpris.MediaPlayer2.firefox", "Mozilla Firefox").unwrap();
+        add_session(&db, &test_track("Song", "Artist", "Album"), spotify, 1000, 100);
+        add_session(&db, &test_track("Video", "Channel", ""), browser, 2000, 300);
+
+        let filter = StatsFilter { exclude_players: vec!["mozilla firefox".to_string()], ..StatsFilter::default() };
+        let stats = db.get_filtered_listening_stats(None, None, &filter).unwrap();
+        assert_eq!(stats.total_listening_time, 100);
+        assert_eq!(stats.top_tracks.len(), 1);
+
+        let mut titles = Vec::new();
+        db.for_each_history_session(None, None, None, &filter, |entry| {
+            titles.push(entry.track.title);
+            Ok::<_, GopalError>(())
+        }).unwrap();
+        assert_eq!(titles, vec!["Song"]);
+
+        // Every instance sharing the identity is excluded
+        let second = db.insert_or_update_player("org.mpris.MediaPlayer2.firefox.instance_2", "Mozilla Firefox").unwrap();
+        add_session(&db, &test_track("Clip", "Channel", ""), second, 3000, 50);
+        let stats = db.get_filtered_listening_stats(None, None, &filter).unwrap();
+        assert_eq!(stats.total_listening_time, 100);
+        assert_eq!(db.count_active_days(None, None, &filter).unwrap(), 1);
+        assert_eq!(db.get_engagement_stats(None, None, &filter, 0.5).unwrap().total_listened_time, 100);
+
+        // Bus names match ignoring case too
+        let by_name = StatsFilter { exclude_players: vec!["ORG.MPRIS.MediaPlayer2.Spotify".to_string()], ..StatsFilter::default() };
+        assert_eq!(db.get_filtered_listening_stats(None, None, &by_name).unwrap().total_listening_time, 350);
+
+        let unknown = StatsFilter { exclude_players: vec!["vlc".to_string()], ..StatsFilter::default() };
+        let error = db.get_filtered_listening_stats(None, None, &unknown).unwrap_err();
+        assert!(format!("{:#}", error).contains("No player named 'vlc'"));
+    }
+
+    #[test]
+    fn test_for_each_history_session() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        for i in 0..5 {
+            add_session(&db, &test_track(&format!("Song {}", i), "Artist", "Album"), player, 1000 + i * 100, 60);
+        }
+
+        let mut titles = Vec::new();
+        db.for_each_history_session(None, None, Some(3), &StatsFilter::default(), |entry| {
+            titles.push(entry.track.title);
+            Ok::<_, GopalError>(())
+        }).unwrap();
+        assert_eq!(titles, vec!["Song 4", "Song 3", "Song 2"]);
+
+        let mut count = 0;
+        db.for_each_history_session(Some(1200), None, None, &StatsFilter::default(), |_| {
+            count += 1;
+            Ok::<_, GopalError>(())
+        }).unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_completion_ratio_is_stored_and_backfilled() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let mut unknown = test_track("Unknown", "Artist", "Album");
+        unknown.length = None;
+
+        // test_track lengths are 180s
+        let half = add_session(&db, &test_track("Half", "Artist", "Album"), player, 1000, 90);
+        let looped = add_session(&db, &test_track("Looped", "Artist", "Album"), player, 2000, 400);
+        let no_length = add_session(&db, &unknown, player, 3000, 90);
+        let ratio = |session_id: i64| -> Option<f64> {
+            db.conn.query_row("SELECT completion_ratio FROM sessions WHERE id = ?1", params![session_id], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(ratio(half), Some(0.5));
+        assert_eq!(ratio(looped), Some(1.0));
+        assert_eq!(ratio(no_length), None);
+
+        // A length learned since updates the track's sessions
+        unknown.length = Some(360_000_000);
+        db.insert_or_update_track(&unknown).unwrap();
+        assert_eq!(ratio(no_length), Some(0.25));
+
+        // Rows from before the column existed
+        db.conn.execute("UPDATE sessions SET completion_ratio = NULL", []).unwrap();
+        assert_eq!(db.recalculate_sessions().unwrap(), 3);
+        assert_eq!(ratio(half), Some(0.5));
+        assert_eq!(ratio(looped), Some(1.0));
+        assert_eq!(ratio(no_length), Some(0.25));
+        assert_eq!(db.recalculate_sessions().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_longest_session_is_the_longest_finished_listen() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        assert!(db.get_longest_session(None, None, &StatsFilter::default()).unwrap().is_none());
+
+        add_session(&db, &test_track("Short", "Artist", "Album"), player, 1000, 60);
+        let longest = add_session(&db, &test_track("Long", "Artist", "Album"), player, 2000, 900);
+        add_session(&db, &test_track("Tied", "Artist", "Album"), player, 4000, 900);
+        add_session(&db, &test_track("Longer", "Excluded", "Album"), player, 6000, 1200);
+        // Still playing, and longer than any finished session
+        let playing = test_track("Playing", "Artist", "Album");
+        db.insert_or_update_track(&playing).unwrap();
+        db.start_session(&playing.id, player, 8000).unwrap();
+
+        let filter = StatsFilter { exclude_artists: vec!["Excluded".to_string()], ..StatsFilter::default() };
+        let found = db.get_longest_session(None, None, &filter).unwrap().unwrap();
+        assert_eq!((found.session.id, found.track.title.as_str(), found.session.listened_time), (longest, "Long", Some(900)));
+
+        assert_eq!(db.get_longest_session(None, None, &StatsFilter::default()).unwrap().unwrap().track.title, "Longer");
+        assert_eq!(db.get_longest_session(Some(3000), Some(5000), &filter).unwrap().unwrap().track.title, "Tied");
+
+        let dashboard = db.get_dashboard(None, None, &filter, DashboardLimits { top: 10, history: 10 }).unwrap();
+        assert_eq!(dashboard.longest_session.map(|entry| entry.session.id), Some(longest));
+    }
+
+    #[test]
+    fn test_active_sessions_are_reported_separately_in_stats() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Finished", "Artist", "Album"), player, 1000, 150);
+        let playing = test_track("Playing", "Artist", "Album");
+        db.insert_or_update_track(&playing).unwrap();
+        db.start_session(&playing.id, player, Local::now().timestamp() - 100).unwrap();
+
+        // The active session's time runs up to the moment of the query
+        let check = |stats: &ListeningStats| {
+            assert!((100..=105).contains(&stats.active_listening_time), "{}", stats.active_listening_time);
+            assert_eq!(stats.total_listening_time, 150 + stats.active_listening_time);
+        };
+        check(&db.get_listening_stats(None, None).unwrap());
+        check(&db.get_dashboard(None, None, &StatsFilter::default(), DashboardLimits { top: 10, history: 10 }).unwrap().stats);
+        let db = db.with_in_memory_stats(true);
+        check(&db.get_listening_stats(None, None).unwrap());
+    }
+
+    #[test]
+    fn test_skip_stats() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        // test_track lengths are 180s
+        let a1 = test_track("A1", "Artist A", "Album");
+        let a2 = test_track("A2", "Artist A", "Album");
+        let b1 = test_track("B1", "Artist B", "Album");
+
+        add_session(&db, &a1, player, 1000, 180);
+        add_session_with_status(&db, &a2, player, 2000, 30, "interrupted"); // skip, saves 150s
+        add_session_with_status(&db, &a1, player, 3000, 60, "interrupted"); // skip, saves 120s
+        add_session_with_status(&db, &b1, player, 4000, 120, "interrupted"); // past halfway, not a skip
+        add_session(&db, &b1, player, 5000, 20); // stopped, not replaced by another track
+
+        let stats = db.get_skip_stats(None, None).unwrap();
+        assert_eq!(stats.total_sessions, 5);
+        assert_eq!(stats.skipped_sessions, 2);
+        assert!((stats.skip_rate - 0.4).abs() < 1e-9);
+        assert_eq!(stats.time_saved, 270);
+
+        let artists: Vec<(&str, i64, i64, i64)> = stats.artists
+            .iter()
+            .map(|a| (a.artist.as_str(), a.total_sessions, a.skipped_sessions, a.time_saved))
+            .collect();
+        assert_eq!(artists, vec![("Artist A", 3, 2, 270), ("Artist B", 2, 0, 0)]);
+        assert!((stats.artists[0].skip_rate - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    fn seed_grouping_data(db: &Database) {
+        let spotify = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        let vlc = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+
+        let mut song_a = test_track("Song A", "Artist 1", "Album X");
+        song_a.genre = Some("Rock".to_string());
+        let mut song_b = test_track("Song B", "Artist 1", "Album Y");
+        song_b.genre = Some("Jazz".to_string());
+        let song_c = test_track("Song C", "Artist 2", "Album X");
+
+        add_session(db, &song_a, spotify, 1000, 100);
+        add_session(db, &song_a, vlc, 2000, 50);
+        add_session(db, &song_b, spotify, 3000, 200);
+        add_session(db, &song_c, vlc, 4000, 30);
+    }
+
+    fn grouped(db: &Database, group_by: GroupBy) -> Vec<(String, i64, i64)> {
+        db.get_grouped_stats(group_by, None, None, &StatsFilter::default(), 10)
+            .unwrap()
+            .into_iter()
+            .map(|g| (g.label, g.total_listened_time, g.play_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_unwritable_path_has_clear_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        // A regular file where a directory should be cannot hold a database, even for root
+        let not_a_dir = temp_dir.path().join("not-a-directory");
+        std::fs::write(&not_a_dir, b"").unwrap();
+        let db_path = not_a_dir.join("music.db");
+
+        let message = match Database::new(&db_path) {
+            Ok(_) => panic!("Opening a database under a file should fail"),
+            Err(e) => e.to_string(),
+        };
+        assert!(message.contains(&db_path.display().to_string()), "{}", message);
+        assert!(message.contains("os error"), "{}", message);
+    }
+
+    #[test]
+    fn test_write_probe_leaves_no_transaction_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("music.db");
+        let db = Database::open_writable(&db_path, None).unwrap();
+        assert!(db.conn.is_autocommit());
+        assert!(db.conn.query_row("SELECT 1 FROM sqlite_master WHERE name = 'write_probe'", [], |_| Ok(())).optional().unwrap().is_none());
+
+        // A probe that can't write is rolled back too
+        let other = Connection::open(&db_path).unwrap();
+        other.execute_batch("BEGIN IMMEDIATE").unwrap();
+        db.conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+        assert!(db.check_writable().is_err());
+        assert!(db.conn.is_autocommit());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_database_needs_its_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("music.db");
+        {
+            let db = Database::new_with_key(&db_path, Some("secret")).unwrap();
+            db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        }
+
+        for key in [None, Some("wrong")] {
+            let error = Database::new_with_key(&db_path, key).err().expect("opened without the right key");
+            assert!(matches!(&error, GopalError::Key(DatabaseKeyError::Wrong(_))), "{:#}", error);
+        }
+
+        let db = Database::new_with_key(&db_path, Some("secret")).unwrap();
+        assert_eq!(db.get_database_stats().unwrap().total_players, 1);
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn test_key_without_encryption_support_fails() {
+        let error = Database::new_with_key(":memory:", Some("secret")).err().expect("key silently ignored");
+        assert!(matches!(&error, GopalError::Key(DatabaseKeyError::Unsupported)));
+    }
+
+    #[test]
+    fn test_migrations_set_schema_version() {
+        let db = test_db();
+        assert_eq!(db.schema_version().unwrap(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_grouped_stats_by_track() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        assert_eq!(grouped(&db, GroupBy::Track), vec![
+            ("Song B - Artist 1".to_string(), 200, 1),
+            ("Song A - Artist 1".to_string(), 150, 2),
+            ("Song C - Artist 2".to_string(), 30, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_grouped_stats_by_artist() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        assert_eq!(grouped(&db, GroupBy::Artist), vec![
+            ("Artist 1".to_string(), 350, 3),
+            ("Artist 2".to_string(), 30, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_grouped_stats_by_album() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        // Albums sharing a name but not an artist stay separate
+        assert_eq!(grouped(&db, GroupBy::Album), vec![
+            ("Album Y - Artist 1".to_string(), 200, 1),
+            ("Album X - Artist 1".to_string(), 150, 2),
+            ("Album X - Artist 2".to_string(), 30, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_missing_artist_not_conflated_with_unknown() {
+        let db = test_db();
+        let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        add_session(&db, &test_track("Unknown", "Unknown", "Unknown"), player, 1000, 100);
+        add_session(&db, &test_track("Clip", MISSING_METADATA, MISSING_METADATA), player, 2000, 300);
+
+        // Metadata-less tracks count towards the total but aren't ranked as an artist
+        let stats = db.get_listening_stats(None, None).unwrap();
+        assert_eq!(stats.total_listening_time, 400);
+        let artists: Vec<(&str, i64)> = stats.top_artists.iter().map(|a| (a.artist.as_str(), a.total_listened_time)).collect();
+        assert_eq!(artists, vec![("Unknown", 100)]);
+
+        assert_eq!(grouped(&db, GroupBy::Artist), vec![
+            ("(unknown)".to_string(), 300, 1),
+            ("Unknown".to_string(), 100, 1),
+        ]);
+        assert_eq!(grouped(&db, GroupBy::Track)[0].0, "Clip - (unknown)");
+    }
+
+    #[test]
+    fn test_dashboard_matches_individual_queries() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        let limits = DashboardLimits { top: 10, history: 100 };
+        let dashboard = db.get_dashboard(None, None, &StatsFilter::default(), limits).unwrap();
+        let stats = db.get_filtered_listening_stats(None, None, &StatsFilter::default()).unwrap();
+
+        assert_eq!(dashboard.stats.total_listening_time, stats.total_listening_time);
+        let tracks = |stats: &ListeningStats| -> Vec<(String, i64, i64)> {
+            stats.top_tracks.iter().map(|t| (t.track.id.clone(), t.total_listened_time, t.play_count)).collect()
+        };
+        assert_eq!(tracks(&dashboard.stats), tracks(&stats));
+        let artists = |stats: &ListeningStats| -> Vec<(String, i64, i64)> {
+            stats.top_artists.iter().map(|a| (a.artist.clone(), a.total_listened_time, a.track_count)).collect()
+        };
+        assert_eq!(artists(&dashboard.stats), artists(&stats));
+        let history = |stats: &ListeningStats| -> Vec<i64> {
+            stats.listening_history.iter().map(|s| s.session.id).collect()
+        };
+        assert_eq!(history(&dashboard.stats), history(&stats));
+        assert_eq!(dashboard.stats.content_type_totals, stats.content_type_totals);
+
+        let albums: Vec<(String, i64, i64)> = dashboard.top_albums
+            .iter()
+            .map(|g| (g.label.clone(), g.total_listened_time, g.play_count))
+            .collect();
+        assert_eq!(albums, grouped(&db, GroupBy::Album));
+
+        let short = db.get_dashboard(None, None, &StatsFilter::default(), DashboardLimits { top: 1, history: 2 }).unwrap();
+        assert_eq!((short.stats.top_tracks.len(), short.top_albums.len(), short.stats.listening_history.len()), (1, 1, 2));
+    }
+
+    #[test]
+    fn test_artist_play_counts_agree_across_stats_paths() {
+        let seed = |db: Database| {
+            let player = db.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+            let looped = test_track("Loop", "Artist", "Album");
+            // Three quick replays, then one much later
+            for start in [1000, 1020, 1050, 5000] {
+                add_session(&db, &looped, player, start, 15);
+            }
+            add_session(&db, &test_track("Other", "Artist", "Album"), player, 6000, 30);
+            add_session(&db, &test_track("Duet", "Artist & Guest", "Album"), player, 7000, 60);
+            add_session(&db, &test_track("Duet", "Artist & Guest", "Album"), player, 7100, 60);
+            db
+        };
+        let artists = |stats: &ListeningStats| -> Vec<(String, i64, i64, i64)> {
+            let mut artists: Vec<_> = stats.top_artists.iter()
+                .map(|a| (a.artist.clone(), a.total_listened_time, a.track_count, a.play_count))
+                .collect();
+            artists.sort();
+            artists
+        };
+        let limits = DashboardLimits { top: 20, history: 0 };
+
+        for window in [None, Some(300)] {
+            for splitter in [None, Some(ArtistSplitter::default())] {
+                let db = seed(test_db().with_play_dedupe_window(window).with_artist_splitter(splitter.clone()));
+                let sql = artists(&db.get_filtered_listening_stats(None, None, &StatsFilter::default()).unwrap());
+                let dashboard = artists(&db.get_dashboard(None, None, &StatsFilter::default(), limits).unwrap().stats);
+                let db = db.with_in_memory_stats(true);
+                let in_memory = artists(&db.get_filtered_listening_stats(None, None, &StatsFilter::default()).unwrap());
+
+                assert_eq!(sql, dashboard, "window {:?}, splitter {}", window, splitter.is_some());
+                assert_eq!(sql, in_memory, "window {:?}, splitter {}", window, splitter.is_some());
+                let (loop_plays, duet_plays) = if window.is_some() { (2, 1) } else { (4, 2) };
+                let artist_plays = sql.iter().find(|a| a.0 == "Artist").unwrap().3;
+                assert_eq!(artist_plays, loop_plays + 1 + if splitter.is_some() { duet_plays } else { 0 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_grouped_stats_by_genre() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        assert_eq!(grouped(&db, GroupBy::Genre), vec![
+            ("Jazz".to_string(), 200, 1),
+            ("Rock".to_string(), 150, 2),
+            ("Unknown".to_string(), 30, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_grouped_stats_by_player() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        assert_eq!(grouped(&db, GroupBy::Player), vec![
+            ("Spotify".to_string(), 300, 2),
+            ("VLC".to_string(), 80, 2),
+        ]);
+    }
+
+    #[test]
+    fn test_source_stats_split_web_player_by_domain() {
+        let db = test_db();
+        let firefox = db.insert_or_update_player("org.mpris.MediaPlayer2.firefox", "Mozilla Firefox").unwrap();
+        let vlc = db.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        let song = test_track("Song", "Band", "Album");
+
+        let youtube = add_session(&db, &song, firefox, 1000, 200);
+        db.set_session_source_domain(youtube, Some("youtube.com")).unwrap();
+        let bandcamp = add_session(&db, &song, firefox, 2000, 100);
+        db.set_session_source_domain(bandcamp, Some("band.bandcamp.com")).unwrap();
+        add_session(&db, &song, vlc, 3000, 50);
+
+        let sources: Vec<(String, i64)> = db.get_source_stats(None, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|g| (g.label, g.total_listened_time))
+            .collect();
+        assert_eq!(sources, vec![
+            ("youtube.com".to_string(), 200),
+            ("band.bandcamp.com".to_string(), 100),
+            ("VLC".to_string(), 50),
+        ]);
+    }
+
+    #[test]
+    fn test_grouped_stats_respects_time_range_and_limit() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        let groups = db.get_grouped_stats(GroupBy::Track, Some(2500), None, &StatsFilter::default(), 1).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].label, "Song B - Artist 1");
+    }
+
+    #[test]
+    fn test_grouped_stats_apply_the_stats_filter() {
+        let db = test_db();
+        seed_grouping_data(&db);
+        let filter = StatsFilter {
+            exclude_artists: vec!["Artist 2".to_string()],
+            exclude_players: vec!["VLC".to_string()],
+            ..Default::default()
+        };
+        let groups: Vec<(String, i64)> = db.get_grouped_stats(GroupBy::Artist, None, None, &filter, 10)
+            .unwrap()
+            .into_iter()
+            .map(|g| (g.label, g.total_listened_time))
+            .collect();
+        assert_eq!(groups, vec![("Artist 1".to_string(), 300)]);
+    }
+
+    #[test]
+    fn test_query_log_inlines_parameters_and_explains_plans() {
+        let db = test_db().with_query_log(true, false);
+        Database::take_logged_queries();
+        db.get_album_tracks("Album", None).unwrap();
+        assert!(Database::take_logged_queries().is_empty());
+
+        let db = db.with_query_log(true, true);
+
+        db.get_album_tracks("It's \x1b[31mred", None).unwrap();
+        let queries = Database::take_logged_queries();
+        assert_eq!(queries.len(), 1);
+        // Bound values are quoted as SQL literals
+        assert!(queries[0].contains("'It''s \x1b[31mred'"), "{}", queries[0]);
+
+        let plan = db.query_plan(&queries[0]).unwrap();
+        assert!(plan.iter().any(|step| step.contains("sessions")), "{:?}", plan);
+        // Explaining doesn't log the EXPLAIN itself
+        assert!(Database::take_logged_queries().is_empty());
+    }
+}
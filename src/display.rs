@@ -0,0 +1,130 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Appended to text that was cut short
+const ELLIPSIS: char = '…';
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone_modifier(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// Split `s` into user-perceived characters: a base character with the combining
+/// marks, variation selectors and skin tones after it, emoji joined by zero-width
+/// joiners, and regional indicator pairs (flags). This covers what shows up in track
+/// titles without pulling in the full Unicode segmentation rules.
+fn grapheme_clusters(s: &str) -> Vec<&str> {
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<char> = None;
+    let mut cluster_chars = 0;
+
+    for (i, c) in s.char_indices() {
+        let extends = match previous {
+            None => true,
+            Some(ZERO_WIDTH_JOINER) => true,
+            Some(prev) if is_regional_indicator(prev) && is_regional_indicator(c) => cluster_chars == 1,
+            Some(_) => c.width() == Some(0) || is_skin_tone_modifier(c),
+        };
+        if !extends {
+            clusters.push(&s[start..i]);
+            start = i;
+            cluster_chars = 0;
+        }
+        previous = Some(c);
+        cluster_chars += 1;
+    }
+
+    if start < s.len() {
+        clusters.push(&s[start..]);
+    }
+    clusters
+}
+
+/// Columns `s` takes up in a terminal: 2 for wide characters such as CJK and most emoji
+pub fn display_width(s: &str) -> usize {
+    grapheme_clusters(s).iter().map(|cluster| cluster_width(cluster)).sum()
+}
+
+fn cluster_width(cluster: &str) -> usize {
+    // Joined emoji render as one glyph, as wide as their widest part
+    if cluster.contains(ZERO_WIDTH_JOINER) {
+        cluster.split(ZERO_WIDTH_JOINER).map(UnicodeWidthStr::width).max().unwrap_or(0)
+    } else {
+        cluster.width()
+    }
+}
+
+/// Shorten `s` to at most `width` terminal columns, ending it with an ellipsis when
+/// anything was cut. Never splits a character or grapheme cluster, so the result
+/// may be a column short when a wide character doesn't fit.
+pub fn truncate_display(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let budget = width - ELLIPSIS.width().unwrap_or(1);
+    let mut truncated = String::new();
+    let mut used = 0;
+    for cluster in grapheme_clusters(s) {
+        let cluster_width = cluster_width(cluster);
+        if used + cluster_width > budget {
+            break;
+        }
+        truncated.push_str(cluster);
+        used += cluster_width;
+    }
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_text_is_unchanged() {
+        assert_eq!(truncate_display("Song", 10), "Song");
+        assert_eq!(truncate_display("Song", 4), "Song");
+        assert_eq!(truncate_display("", 0), "");
+    }
+
+    #[test]
+    fn test_cjk_titles_truncate_by_columns() {
+        let title = "千と千尋の神隠し";
+        assert_eq!(display_width(title), 16);
+        assert_eq!(truncate_display(title, 7), "千と千…");
+        // A wide character that would overhang the limit is dropped rather than split
+        assert_eq!(truncate_display(title, 6), "千と…");
+
+        for width in 0..=20 {
+            assert!(display_width(&truncate_display(title, width)) <= width);
+        }
+    }
+
+    #[test]
+    fn test_emoji_and_combining_marks_stay_whole() {
+        let family = "👨\u{200D}👩\u{200D}👧";
+        assert_eq!(display_width(family), 2);
+        assert_eq!(truncate_display(&format!("{} Song Title", family), 6), format!("{} So…", family));
+
+        assert_eq!(truncate_display("🇯🇵🇰🇷 Flags", 3), "🇯🇵…");
+        assert_eq!(truncate_display("👍🏽👍🏽👍🏽", 5), "👍🏽👍🏽…");
+
+        // "e" followed by a combining acute accent
+        assert_eq!(truncate_display("Cafe\u{301} del Mar", 5), "Cafe\u{301}…");
+
+        for text in ["🎵 Ça plane pour moi", "ﾟ･✿ヾ╲(｡◕‿◕｡)╱✿･ﾟ", "Sigur Rós – Hoppípolla"] {
+            for width in 0..=25 {
+                assert!(display_width(&truncate_display(text, width)) <= width);
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Error type of the library's `database`, `store`, `mpris_monitor`, `screen_lock` and
+//! `focus` APIs, so callers can tell failure modes apart. Each variant keeps the error
+//! that caused it as its `source`; the binaries turn it into `anyhow::Error` with `?`.
+
+use rusqlite::ErrorCode;
+use std::path::PathBuf;
+
+use crate::database::DatabaseKeyError;
+
+pub type Result<T, E = GopalError> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GopalError {
+    /// No database exists at the path, from `Database::open_existing`
+    #[error("Database not found at: {}", .0.display())]
+    NotFound(PathBuf),
+    /// Another connection held a lock for longer than SQLite was willing to wait
+    #[error("Database is locked by another connection")]
+    DatabaseLocked(#[source] rusqlite::Error),
+    /// The database key is wrong or missing, see `DatabaseKeyError`
+    #[error(transparent)]
+    Key(#[from] DatabaseKeyError),
+    /// The database can't be created or written, e.g. a read-only or full disk.
+    /// `os_error` is what the filesystem says when asked directly, as SQLite's own
+    /// messages ("unable to open database file") hide the real reason.
+    #[error(
+        "Cannot write to database at {}{}. Check that the directory exists, is writable and has free space.",
+        path.display(),
+        os_error.as_ref().map(|e| format!(" ({})", e)).unwrap_or_default()
+    )]
+    Unwritable {
+        path: PathBuf,
+        os_error: Option<std::io::Error>,
+        #[source]
+        source: rusqlite::Error,
+    },
+    /// The tables couldn't be migrated to the current schema
+    #[error("Failed to apply schema migration {migration}")]
+    Schema {
+        migration: usize,
+        #[source]
+        source: rusqlite::Error,
+    },
+    /// The session D-Bus or an MPRIS player failed
+    #[error("{context}")]
+    Mpris {
+        context: &'static str,
+        #[source]
+        source: mpris::FindingError,
+    },
+    /// Any other SQLite failure
+    #[error(transparent)]
+    Sqlite(rusqlite::Error),
+    /// The system D-Bus failed, e.g. reading logind's lock state
+    #[error("{context}")]
+    DBus {
+        context: &'static str,
+        #[source]
+        source: dbus::Error,
+    },
+    /// The command reporting the focused window couldn't run, failed or timed out
+    #[error("Focus command '{command}' {reason}")]
+    FocusCommand {
+        command: String,
+        reason: String,
+        #[source]
+        source: Option<std::io::Error>,
+    },
+    /// Stored stats or an export couldn't be read or written as JSON
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// An export or another tracker's history couldn't be parsed
+    #[error(transparent)]
+    Import(Box<dyn std::error::Error + Send + Sync>),
+    /// A request that can't be carried out, e.g. naming an unknown player or a date
+    /// out of range
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl GopalError {
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        GopalError::Invalid(message.into())
+    }
+}
+
+/// Like `anyhow::Context` for options: a missing value becomes `GopalError::Invalid`
+pub(crate) trait OptionContext<T> {
+    fn context(self, message: &str) -> Result<T>;
+    fn with_context(self, message: impl FnOnce() -> String) -> Result<T>;
+}
+
+impl<T> OptionContext<T> for Option<T> {
+    fn context(self, message: &str) -> Result<T> {
+        self.ok_or_else(|| GopalError::invalid(message))
+    }
+
+    fn with_context(self, message: impl FnOnce() -> String) -> Result<T> {
+        self.ok_or_else(|| GopalError::invalid(message()))
+    }
+}
+
+/// Waiting on another connection's lock is told apart from other SQLite failures
+impl From<rusqlite::Error> for GopalError {
+    fn from(error: rusqlite::Error) -> Self {
+        match error.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => GopalError::DatabaseLocked(error),
+            _ => GopalError::Sqlite(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use rusqlite::ffi;
+    use std::error::Error;
+
+    #[test]
+    fn test_failures_map_to_their_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.db");
+        let error = Database::open_existing(&missing, None).err().unwrap();
+        assert!(matches!(&error, GopalError::NotFound(path) if *path == missing));
+        assert_eq!(error.to_string(), format!("Database not found at: {}", missing.display()));
+        assert!(!missing.exists());
+
+        let busy = rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_BUSY), None);
+        let error = GopalError::from(busy);
+        assert!(matches!(error, GopalError::DatabaseLocked(_)));
+        // The SQLite error stays reachable as the source
+        assert!(error.source().unwrap().is::<rusqlite::Error>());
+
+        // Context added on the way up in the binaries keeps the variant and the message
+        let wrapped = anyhow::Error::from(error).context("Failed to open database");
+        assert!(matches!(wrapped.downcast_ref::<GopalError>(), Some(GopalError::DatabaseLocked(_))));
+        assert!(format!("{:#}", wrapped).starts_with("Failed to open database: Database is locked"));
+
+        let other = GopalError::from(rusqlite::Error::QueryReturnedNoRows);
+        assert!(matches!(other, GopalError::Sqlite(rusqlite::Error::QueryReturnedNoRows)));
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::database::{Player, Session, Track};
+
+/// Version of the format `gopal-cli export` writes. Bump it whenever a change would make
+/// an older gopal misread or silently drop data, and add a step to `EXPORT_UPGRADES`.
+pub const EXPORT_FORMAT_VERSION: u32 = 2;
+
+/// Step `i` turns an export of version `i + 1` into version `i + 2`. Older exports are
+/// brought up to `EXPORT_FORMAT_VERSION` by running every step from their version on.
+const EXPORT_UPGRADES: &[fn(&mut Value)] = &[
+    // Version 2 added session ratings, which version 1 exports just lack
+    |_| {},
+];
+
+/// Everything needed to rebuild a database's history: players, tracks and finished sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportData {
+    pub format_version: u32,
+    pub exported_at: i64,
+    pub players: Vec<Player>,
+    pub tracks: Vec<Track>,
+    pub sessions: Vec<ExportedSession>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedSession {
+    #[serde(flatten)]
+    pub session: Session,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub source_domain: Option<String>,
+    #[serde(default)]
+    pub focus: Option<String>,
+    /// Rating the player reported when the session started
+    #[serde(default)]
+    pub rating: Option<f64>,
+}
+
+/// What `gopal-cli export --anonymize` writes instead of `ExportData`: per-track totals
+/// only, with no players, session ids or timestamps. It can't be imported back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnonymizedExport {
+    pub anonymized: bool,
+    pub total_listening_time: i64,
+    pub play_count: i64,
+    /// Most listened first
+    pub tracks: Vec<AnonymizedTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnonymizedTrack {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub play_count: i64,
+    pub total_listened_time: i64,
+}
+
+impl ExportData {
+    /// Parse an export, upgrading ones written by older versions. An export from a newer
+    /// gopal may hold data this version can't represent, so it's refused unless `force`
+    /// is set, in which case whatever this version doesn't understand is dropped.
+    pub fn parse(json: &str, force: bool) -> Result<Self> {
+        parse_versioned(json, force, EXPORT_FORMAT_VERSION, EXPORT_UPGRADES)
+    }
+}
+
+fn parse_versioned(json: &str, force: bool, current_version: u32, upgrades: &[fn(&mut Value)]) -> Result<ExportData> {
+    let mut value: Value = serde_json::from_str(json).context("Invalid gopal export")?;
+    let version = value.get("format_version")
+        .and_then(Value::as_u64)
+        .filter(|&version| version > 0)
+        .context("gopal export has no valid format_version")?;
+
+    if version > u64::from(current_version) {
+        if !force {
+            bail!(
+                "Export is format version {} but this gopal only understands up to version {}. \
+                 Upgrade gopal, or pass --force to import what this version understands",
+                version, current_version
+            );
+        }
+        warn!("Importing a version {} export with version {} support; newer fields are dropped", version, current_version);
+    } else {
+        for upgrade in &upgrades[version as usize - 1..] {
+            upgrade(&mut value);
+        }
+    }
+
+    value["format_version"] = Value::from(current_version);
+    serde_json::from_value(value).context("Invalid gopal export")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export_json(version: u32) -> String {
+        serde_json::json!({
+            "format_version": version,
+            "exported_at": 5000,
+            "players": [{ "id": 1, "name": "org.mpris.MediaPlayer2.spotify", "identity": "Spotify" }],
+            "tracks": [{
+                "id": "Song::Artist::Album", "title": "Song", "artist": "Artist", "album": "Album",
+                "length": 180000000, "art_url": null
+            }],
+            "sessions": [{
+                "id": 1, "track_id": "Song::Artist::Album", "player_id": 1, "start_time": 1000,
+                "end_time": 1180, "paused_time": 0, "listened_time": 180, "status": "completed",
+                "content_type": "music"
+            }],
+        }).to_string()
+    }
+
+    #[test]
+    fn test_current_version_is_accepted() {
+        let data = ExportData::parse(&export_json(EXPORT_FORMAT_VERSION), false).unwrap();
+        assert_eq!(data.format_version, EXPORT_FORMAT_VERSION);
+        assert_eq!(data.sessions[0].session.listened_time, Some(180));
+        assert_eq!(data.sessions[0].content_type.as_deref(), Some("music"));
+    }
+
+    #[test]
+    fn test_newer_version_needs_force() {
+        let newer = export_json(EXPORT_FORMAT_VERSION + 1);
+        let err = ExportData::parse(&newer, false).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+
+        let data = ExportData::parse(&newer, true).unwrap();
+        assert_eq!(data.format_version, EXPORT_FORMAT_VERSION);
+        assert_eq!(data.tracks.len(), 1);
+    }
+
+    #[test]
+    fn test_older_version_is_upgraded() {
+        // Pretend version 2 renamed `exported_at`, to exercise the upgrade path
+        fn rename_exported_at(value: &mut Value) {
+            if let Some(object) = value.as_object_mut() {
+                if let Some(exported_at) = object.remove("created_at") {
+                    object.insert("exported_at".to_string(), exported_at);
+                }
+            }
+        }
+
+        let old = export_json(1).replace("exported_at", "created_at");
+        let data = parse_versioned(&old, false, 2, &[rename_exported_at]).unwrap();
+        assert_eq!((data.format_version, data.exported_at), (2, 5000));
+    }
+
+    #[test]
+    fn test_version_1_is_read_without_session_ratings() {
+        let data = ExportData::parse(&export_json(1), false).unwrap();
+        assert_eq!(data.format_version, EXPORT_FORMAT_VERSION);
+        assert_eq!(data.sessions[0].rating, None);
+    }
+
+    #[test]
+    fn test_missing_version_is_rejected() {
+        assert!(ExportData::parse("{\"players\": []}", false).is_err());
+        assert!(ExportData::parse(&export_json(0), true).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Whether a player's window has focus, to tell listening you're engaged with from
+//! music left playing in the background
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::error::{GopalError, Result};
+
+/// Bus name prefix every MPRIS player shares
+const MPRIS_BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// How long the focus command may run by default before it's killed, as the monitor
+/// waits on it
+pub const DEFAULT_FOCUS_COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub type FocusFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<String>>> + 'a>>;
+
+/// Reports which application has the focused window
+pub trait FocusSource {
+    /// Name of the focused application, such as its window class or app id, or `None`
+    /// when nothing has focus
+    fn focused_application(&mut self) -> FocusFuture<'_>;
+}
+
+/// Runs a shell command that prints the focused application, such as
+/// `xdotool getactivewindow getwindowclassname` on X11. Wayland has no common way to
+/// ask, so each compositor's tool has to be plugged in here.
+pub struct CommandFocusSource {
+    command: String,
+    timeout: Duration,
+}
+
+impl CommandFocusSource {
+    pub fn new(command: impl Into<String>) -> Self {
+        CommandFocusSource { command: command.into(), timeout: DEFAULT_FOCUS_COMMAND_TIMEOUT }
+    }
+
+    /// Give up on, and kill, a command still running after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl FocusSource for CommandFocusSource {
+    fn focused_application(&mut self) -> FocusFuture<'_> {
+        Box::pin(async move {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(&self.command)
+                .kill_on_drop(true)
+                .output();
+            let failed = |reason: String, source| GopalError::FocusCommand { command: self.command.clone(), reason, source };
+            let output = tokio::time::timeout(self.timeout, output).await
+                .map_err(|_| failed("timed out".to_string(), None))?
+                .map_err(|e| failed("could not be run".to_string(), Some(e)))?;
+            if !output.status.success() {
+                return Err(failed(format!("exited with {}", output.status), None));
+            }
+
+            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Ok(Some(name).filter(|name| !name.is_empty()))
+        })
+    }
+}
+
+/// Whether a session's player had focus at any point while it played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionFocus {
+    Foreground,
+    Background,
+}
+
+impl SessionFocus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionFocus::Foreground => "foreground",
+            SessionFocus::Background => "background",
+        }
+    }
+}
+
+/// Whether the focused application `focused` is the player at `bus_name`, ignoring case.
+/// It must name the player's identity or the app in its bus name, so "spotify" matches
+/// the "Spotify" player and "firefox" matches org.mpris.MediaPlayer2.firefox.instance_1_42.
+/// An app id such as "org.gnome.Lollypop" is matched by its last part.
+pub fn is_player_focused(focused: &str, identity: &str, bus_name: &str) -> bool {
+    let focused = focused.trim().to_lowercase();
+    if focused.is_empty() {
+        return false;
+    }
+
+    let identity = identity.to_lowercase();
+    let bus_app = bus_name.strip_prefix(MPRIS_BUS_PREFIX)
+        .unwrap_or(bus_name)
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let app_id_name = focused.rsplit('.').next().unwrap_or_default();
+    [focused.as_str(), app_id_name].iter().any(|&name| name == bus_app || name == identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_names_match_player_names() {
+        assert!(is_player_focused("spotify", "Spotify", "org.mpris.MediaPlayer2.spotify"));
+        assert!(is_player_focused("firefox", "Mozilla Firefox", "org.mpris.MediaPlayer2.firefox.instance_1_42"));
+        assert!(is_player_focused("org.gnome.Lollypop", "Lollypop", "org.mpris.MediaPlayer2.Lollypop"));
+        assert!(!is_player_focused("Alacritty", "Spotify", "org.mpris.MediaPlayer2.spotify"));
+        assert!(!is_player_focused("  ", "Spotify", "org.mpris.MediaPlayer2.spotify"));
+        // Part of a name isn't enough
+        assert!(!is_player_focused("fire", "Mozilla Firefox", "org.mpris.MediaPlayer2.firefox"));
+        assert!(!is_player_focused("spotify-launcher", "Spotify", "org.mpris.MediaPlayer2.spotify"));
+    }
+
+    #[tokio::test]
+    async fn test_command_reports_the_focused_application() {
+        let mut source = CommandFocusSource::new("echo Spotify");
+        assert_eq!(source.focused_application().await.unwrap().as_deref(), Some("Spotify"));
+        assert_eq!(CommandFocusSource::new("true").focused_application().await.unwrap(), None);
+        let error = CommandFocusSource::new("exit 3").focused_application().await.unwrap_err();
+        assert!(matches!(&error, GopalError::FocusCommand { command, .. } if command == "exit 3"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn test_hanging_command_times_out() {
+        let mut source = CommandFocusSource::new("sleep 60").with_timeout(Duration::from_millis(100));
+        let error = source.focused_application().await.unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{:#}", error);
+    }
+}
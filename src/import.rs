@@ -0,0 +1,243 @@
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Listened time assumed for imported plays when neither the export nor an
+/// existing track records how long the track is
+pub const DEFAULT_IMPORT_DURATION_SECS: i64 = 210;
+
+/// A single play from another tracker's export
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalListen {
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+    /// When the play happened, in seconds since the epoch
+    pub listened_at: i64,
+    /// Track duration in seconds, when the export includes it
+    pub duration: Option<i64>,
+}
+
+/// Outcome of importing an external export
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Plays already in the database with the same track and start time
+    pub skipped_duplicates: usize,
+    /// Plays whose listened time fell back to the default estimate
+    pub estimated_durations: usize,
+}
+
+#[derive(Deserialize)]
+struct ListenBrainzListen {
+    listened_at: i64,
+    track_metadata: ListenBrainzTrack,
+}
+
+#[derive(Deserialize)]
+struct ListenBrainzTrack {
+    artist_name: String,
+    track_name: String,
+    release_name: Option<String>,
+    #[serde(default)]
+    additional_info: ListenBrainzInfo,
+}
+
+#[derive(Deserialize, Default)]
+struct ListenBrainzInfo {
+    duration_ms: Option<i64>,
+    duration: Option<i64>,
+}
+
+/// Parse a ListenBrainz export, either a JSON array of listens or one listen per line
+pub fn parse_listenbrainz(json: &str) -> Result<Vec<ExternalListen>> {
+    let listens: Vec<ListenBrainzListen> = if json.trim_start().starts_with('[') {
+        serde_json::from_str(json).context("Invalid ListenBrainz JSON export")?
+    } else {
+        json.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Invalid ListenBrainz listen on line {}", i + 1))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    Ok(listens
+        .into_iter()
+        .map(|listen| {
+            let info = listen.track_metadata.additional_info;
+            ExternalListen {
+                title: listen.track_metadata.track_name,
+                artist: listen.track_metadata.artist_name,
+                album: listen.track_metadata.release_name.filter(|album| !album.is_empty()),
+                listened_at: listen.listened_at,
+                duration: info.duration_ms.map(|ms| ms / 1000).or(info.duration).filter(|&d| d > 0),
+            }
+        })
+        .collect())
+}
+
+/// Column positions within a Last.fm CSV export
+struct LastFmColumns {
+    artist: usize,
+    album: usize,
+    title: usize,
+    timestamp: usize,
+}
+
+impl LastFmColumns {
+    /// Layout of headerless exports: `artist,album,title,date`
+    const HEADERLESS: LastFmColumns = LastFmColumns { artist: 0, album: 1, title: 2, timestamp: 3 };
+
+    fn from_header(header: &csv::StringRecord) -> Option<Self> {
+        let find = |names: &[&str]| {
+            header.iter().position(|column| names.contains(&column.trim().to_lowercase().as_str()))
+        };
+
+        Some(LastFmColumns {
+            artist: find(&["artist", "artist_name"])?,
+            album: find(&["album", "album_name"])?,
+            title: find(&["track", "title", "track_name"])?,
+            timestamp: find(&["uts", "timestamp", "date", "utc_time"])?,
+        })
+    }
+}
+
+/// Parse a Last.fm scrobble CSV. Headerless `artist,album,title,date` exports and
+/// exports with a header naming those columns (or `uts`/`utc_time`) are supported.
+/// Dates are read as UTC.
+pub fn parse_lastfm_csv(csv: &str) -> Result<Vec<ExternalListen>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+
+    let mut records = reader.records();
+    let first = match records.next() {
+        Some(record) => record.context("Invalid Last.fm CSV")?,
+        None => return Ok(Vec::new()),
+    };
+
+    let (columns, pending) = match LastFmColumns::from_header(&first) {
+        Some(columns) => (columns, None),
+        None => (LastFmColumns::HEADERLESS, Some(first)),
+    };
+
+    let mut listens = Vec::new();
+    for (i, record) in pending.into_iter().map(Ok).chain(records).enumerate() {
+        let record = record.context("Invalid Last.fm CSV")?;
+        let field = |index: usize| record.get(index).map(str::trim).unwrap_or("");
+
+        let timestamp = field(columns.timestamp);
+        let listened_at = parse_lastfm_timestamp(timestamp)
+            .with_context(|| format!("Invalid date '{}' in Last.fm CSV record {}", timestamp, i + 1))?;
+
+        let album = field(columns.album);
+        listens.push(ExternalListen {
+            title: field(columns.title).to_string(),
+            artist: field(columns.artist).to_string(),
+            album: (!album.is_empty()).then(|| album.to_string()),
+            listened_at,
+            // Last.fm exports only record when a scrobble happened
+            duration: None,
+        });
+    }
+
+    Ok(listens)
+}
+
+fn parse_lastfm_timestamp(value: &str) -> Result<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+
+    for format in ["%d %b %Y %H:%M", "%d %b %Y, %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(Utc.from_utc_datetime(&datetime).timestamp());
+        }
+    }
+
+    bail!("Unrecognized date format")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTENBRAINZ_SAMPLE: &str = r#"[
+        {
+            "listened_at": 1700000000,
+            "track_metadata": {
+                "artist_name": "Radiohead",
+                "track_name": "Reckoner",
+                "release_name": "In Rainbows",
+                "additional_info": { "duration_ms": 290000 }
+            }
+        },
+        {
+            "listened_at": 1700000400,
+            "track_metadata": {
+                "artist_name": "Björk",
+                "track_name": "Jóga"
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn test_parse_listenbrainz_array() {
+        let listens = parse_listenbrainz(LISTENBRAINZ_SAMPLE).unwrap();
+        assert_eq!(listens, vec![
+            ExternalListen {
+                title: "Reckoner".to_string(),
+                artist: "Radiohead".to_string(),
+                album: Some("In Rainbows".to_string()),
+                listened_at: 1700000000,
+                duration: Some(290),
+            },
+            ExternalListen {
+                title: "Jóga".to_string(),
+                artist: "Björk".to_string(),
+                album: None,
+                listened_at: 1700000400,
+                duration: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_listenbrainz_lines() {
+        let jsonl = r#"{"listened_at": 1, "track_metadata": {"artist_name": "A", "track_name": "T", "additional_info": {"duration": 100}}}
+
+{"listened_at": 2, "track_metadata": {"artist_name": "B", "track_name": "U"}}"#;
+        let listens = parse_listenbrainz(jsonl).unwrap();
+        assert_eq!(listens.len(), 2);
+        assert_eq!(listens[0].duration, Some(100));
+
+        assert!(parse_listenbrainz("{\"listened_at\": 1}").is_err());
+    }
+
+    #[test]
+    fn test_parse_lastfm_csv_headerless() {
+        let csv = "Radiohead,In Rainbows,Reckoner,14 Nov 2023 22:13\n\"Crosby, Stills & Nash\",,Helplessly Hoping,01 Jan 2020 00:00\n";
+        let listens = parse_lastfm_csv(csv).unwrap();
+        assert_eq!(listens.len(), 2);
+        assert_eq!(listens[0].title, "Reckoner");
+        assert_eq!(listens[0].listened_at, 1699999980);
+        assert_eq!(listens[1].artist, "Crosby, Stills & Nash");
+        assert_eq!(listens[1].album, None);
+        assert_eq!(listens[1].duration, None);
+    }
+
+    #[test]
+    fn test_parse_lastfm_csv_with_header() {
+        let csv = "uts,utc_time,artist,artist_mbid,album,album_mbid,track,track_mbid\n1700000000,\"14 Nov 2023, 22:13\",Radiohead,,In Rainbows,,Reckoner,\n";
+        let listens = parse_lastfm_csv(csv).unwrap();
+        assert_eq!(listens.len(), 1);
+        assert_eq!(listens[0].listened_at, 1700000000);
+        assert_eq!(listens[0].album.as_deref(), Some("In Rainbows"));
+
+        assert!(parse_lastfm_csv("Artist,Album,Title,yesterday\n").is_err());
+    }
+}
@@ -4,21 +4,47 @@
 //! This library provides components for monitoring media players, tracking sessions,
 //! and storing listening data in a SQLite database.
 
+#[cfg(feature = "thumbnails")]
+pub mod art;
+pub mod content_type;
+pub mod daemon_status;
 pub mod database;
+pub mod display;
+pub mod error;
+pub mod export;
+pub mod focus;
+pub mod import;
+pub mod locale;
+pub mod metadata_rules;
 pub mod mpris_monitor;
+pub mod now_playing;
+pub mod paths;
+pub mod recent_activity;
+pub mod screen_lock;
+pub mod scrobble;
+pub mod session_labels;
 pub mod session_tracker;
+pub mod sources;
+pub mod store;
 
-pub use database::{Database, Track, Player, Session, ListeningStats, DatabaseStats};
-pub use mpris_monitor::MprisMonitor;
+pub use content_type::ContentType;
+pub use database::{Database, Track, Player, Session, Dashboard, DashboardLimits, ListeningStats, DatabaseStats, EngagementStats, GroupBy, GroupedStats, RankEntity, RankPoint, SkipStats, StatsFilter, TimeBucket, WeekdayAvg, YearSlice};
+pub use mpris_monitor::{MonitorOptions, MprisMonitor, PlayerSnapshot, PlayerSource, ReconnectingSource, SessionEventStream};
+pub use now_playing::{NowPlaying, NowPlayingTemplate};
 pub use session_tracker::{SessionTracker, SessionEvent};
+pub use store::{DryRunStore, InMemoryStore, Store};
 
 /// Current version of the music tracker
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// Default database path relative to home directory
+/// Default database path when `XDG_DATA_HOME` is unset, see `paths::default_database_path`
 pub const DEFAULT_DB_PATH: &str = "~/.local/share/gopal/music.db";
 
-/// Default configuration directory
+/// Database written by releases from before the rename, when the daemon was `musicd`;
+/// see `paths::needs_legacy_migration`
+pub const LEGACY_DB_PATH: &str = "~/.local/share/musicd/music.db";
+
+/// Default configuration directory when `XDG_CONFIG_HOME` is unset, see `paths::resolve_config_path`
 pub const DEFAULT_CONFIG_DIR: &str = "~/.config/gopal";
 
 #[cfg(test)]
@@ -0,0 +1,149 @@
+//! Date order and number grouping for human-readable output. Only the conventions
+//! gopal prints are covered, from a small table rather than a full locale database.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local, NaiveDate};
+
+/// Environment variable with the locale to use when `--locale` isn't given
+pub const LOCALE_ENV: &str = "GOPAL_LOCALE";
+
+/// System locale variables, most specific first
+const SYSTEM_LOCALE_ENV: [&str; 3] = ["LC_ALL", "LC_TIME", "LANG"];
+
+/// Narrow no-break space, which French and others group thousands with
+const NARROW_NBSP: char = '\u{202F}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOrder {
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+/// How dates and numbers are written for one locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLocale {
+    pub date_order: DateOrder,
+    pub date_separator: char,
+    /// Between groups of three digits, `None` to not group
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for OutputLocale {
+    fn default() -> Self {
+        OutputLocale::ISO
+    }
+}
+
+impl OutputLocale {
+    /// ISO 8601 dates and ungrouped numbers, for the C locale or when none is set
+    pub const ISO: OutputLocale = OutputLocale {
+        date_order: DateOrder::YearMonthDay,
+        date_separator: '-',
+        thousands_separator: None,
+    };
+
+    /// Look up a locale name such as `de_DE.UTF-8`, `en-GB` or `fr`
+    pub fn parse(name: &str) -> Result<Self> {
+        let tag = name.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = tag.split(['_', '-']);
+        let language = parts.next().unwrap_or_default().to_lowercase();
+        let region = parts.next().unwrap_or_default().to_uppercase();
+
+        use DateOrder::*;
+        let locale = |date_order, date_separator, thousands_separator| OutputLocale {
+            date_order,
+            date_separator,
+            thousands_separator: Some(thousands_separator),
+        };
+        Ok(match (language.as_str(), region.as_str()) {
+            ("c" | "posix", _) => OutputLocale::ISO,
+            ("en", "" | "US" | "PH") => locale(MonthDayYear, '/', ','),
+            ("en", "CA") => locale(YearMonthDay, '-', ','),
+            ("en", _) => locale(DayMonthYear, '/', ','),
+            ("de", "CH") => locale(DayMonthYear, '.', '\''),
+            ("de" | "da" | "tr" | "id", _) => locale(DayMonthYear, '.', '.'),
+            ("ru" | "uk" | "pl" | "cs" | "sk" | "fi" | "nb" | "no" | "nn", _) => locale(DayMonthYear, '.', NARROW_NBSP),
+            ("fr", _) => locale(DayMonthYear, '/', NARROW_NBSP),
+            ("es" | "it" | "pt" | "el", _) => locale(DayMonthYear, '/', '.'),
+            ("nl", _) => locale(DayMonthYear, '-', '.'),
+            ("sv" | "lt", _) => locale(YearMonthDay, '-', NARROW_NBSP),
+            ("hu", _) => locale(YearMonthDay, '.', NARROW_NBSP),
+            ("ja" | "zh", _) => locale(YearMonthDay, '/', ','),
+            ("ko", _) => locale(YearMonthDay, '.', ','),
+            _ => bail!("Unknown locale '{}'", name),
+        })
+    }
+
+    /// `--locale` if given, then `GOPAL_LOCALE`, then the system locale. An unknown
+    /// system locale falls back to ISO; one the user asked for by name is an error.
+    pub fn resolve(requested: Option<&str>) -> Result<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        if let Some(name) = requested.map(str::to_string).or_else(|| env(LOCALE_ENV)) {
+            return OutputLocale::parse(&name);
+        }
+        Ok(SYSTEM_LOCALE_ENV.iter()
+            .find_map(|name| env(name))
+            .and_then(|name| OutputLocale::parse(&name).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn format_date(&self, date: NaiveDate) -> String {
+        let pattern = match self.date_order {
+            DateOrder::YearMonthDay => "%Y{}%m{}%d",
+            DateOrder::DayMonthYear => "%d{}%m{}%Y",
+            DateOrder::MonthDayYear => "%m{}%d{}%Y",
+        };
+        date.format(&pattern.replace("{}", &self.date_separator.to_string())).to_string()
+    }
+
+    /// Date and 24-hour time to the minute
+    pub fn format_datetime(&self, datetime: DateTime<Local>) -> String {
+        format!("{} {}", self.format_date(datetime.date_naive()), datetime.format("%H:%M"))
+    }
+
+    /// `number` with its digits grouped in threes
+    pub fn format_number(&self, number: i64) -> String {
+        let digits = number.unsigned_abs().to_string();
+        let Some(separator) = self.thousands_separator else {
+            return number.to_string();
+        };
+
+        let mut grouped = String::new();
+        if number < 0 {
+            grouped.push('-');
+        }
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales_order_dates_and_group_numbers() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+        let us = OutputLocale::parse("en_US.UTF-8").unwrap();
+        let german = OutputLocale::parse("de-DE").unwrap();
+
+        assert_eq!(us.format_date(date), "03/09/2024");
+        assert_eq!(german.format_date(date), "09.03.2024");
+        assert_eq!(OutputLocale::ISO.format_date(date), "2024-03-09");
+
+        assert_eq!(us.format_number(1234567), "1,234,567");
+        assert_eq!(german.format_number(1234567), "1.234.567");
+        assert_eq!(german.format_number(-1234), "-1.234");
+        assert_eq!(german.format_number(999), "999");
+        assert_eq!(OutputLocale::ISO.format_number(1234567), "1234567");
+
+        assert_eq!(OutputLocale::parse("C").unwrap(), OutputLocale::ISO);
+        assert!(OutputLocale::parse("xx_YY").is_err());
+    }
+}
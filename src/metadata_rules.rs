@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+/// Common ways web players and podcast apps pack the artist into the title.
+/// Each pattern must define `artist` and `title` named groups.
+pub const DEFAULT_TITLE_SPLIT_PATTERNS: &[&str] = &[
+    r"^(?P<artist>.+?)\s+[-–—]\s+(?P<title>.+)$",
+    r"^(?P<title>.+?)\s+by\s+(?P<artist>.+)$",
+];
+
+/// Separators between names in a multi-artist credit such as "A, B feat. C"
+pub const DEFAULT_ARTIST_SEPARATORS: &[&str] = &[", ", " & ", " feat. ", " ft. ", " featuring "];
+
+/// User-configurable rules for repairing tracks whose artist the player didn't report
+#[derive(Debug, Clone, Default)]
+pub struct MetadataRules {
+    title_patterns: Vec<Regex>,
+}
+
+impl MetadataRules {
+    /// Compile title split patterns, checking each has `artist` and `title` groups
+    pub fn new<S: AsRef<str>>(title_patterns: &[S]) -> Result<Self> {
+        let mut compiled = Vec::with_capacity(title_patterns.len());
+
+        for pattern in title_patterns {
+            let pattern = pattern.as_ref();
+            let regex = Regex::new(pattern)
+                .with_context(|| format!("Invalid title split pattern '{}'", pattern))?;
+
+            let names: Vec<&str> = regex.capture_names().flatten().collect();
+            if !names.contains(&"artist") || !names.contains(&"title") {
+                bail!("Title split pattern '{}' must define (?P<artist>...) and (?P<title>...) groups", pattern);
+            }
+
+            compiled.push(regex);
+        }
+
+        Ok(MetadataRules { title_patterns: compiled })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title_patterns.is_empty()
+    }
+
+    /// Split `title` into `(artist, title)` using the first matching pattern
+    pub fn split_title(&self, title: &str) -> Option<(String, String)> {
+        self.title_patterns.iter().find_map(|regex| {
+            let captures = regex.captures(title)?;
+            let artist = captures.name("artist")?.as_str().trim();
+            let title = captures.name("title")?.as_str().trim();
+
+            if artist.is_empty() || title.is_empty() {
+                None
+            } else {
+                Some((artist.to_string(), title.to_string()))
+            }
+        })
+    }
+}
+
+/// Splits combined artist credits into individual artists so they can be counted separately
+#[derive(Debug, Clone)]
+pub struct ArtistSplitter {
+    separator: Regex,
+}
+
+impl ArtistSplitter {
+    /// Split on any of `separators`, matched literally and case-insensitively
+    pub fn new<S: AsRef<str>>(separators: &[S]) -> Result<Self> {
+        if separators.is_empty() {
+            bail!("At least one artist separator is required");
+        }
+        if separators.iter().any(|s| s.as_ref().is_empty()) {
+            bail!("Artist separators must not be empty");
+        }
+
+        let alternation: Vec<String> = separators.iter().map(|s| regex::escape(s.as_ref())).collect();
+        let separator = Regex::new(&format!("(?i){}", alternation.join("|")))
+            .context("Invalid artist separators")?;
+        Ok(ArtistSplitter { separator })
+    }
+
+    /// Individual artists in credit order, without blanks or repeats
+    pub fn split(&self, artist: &str) -> Vec<String> {
+        let mut artists: Vec<String> = Vec::new();
+        for name in self.separator.split(artist).map(str::trim) {
+            if !name.is_empty() && !artists.iter().any(|a| a == name) {
+                artists.push(name.to_string());
+            }
+        }
+        if artists.is_empty() {
+            artists.push(artist.to_string());
+        }
+        artists
+    }
+
+    /// The first credited artist
+    pub fn primary(&self, artist: &str) -> String {
+        self.split(artist).swap_remove(0)
+    }
+}
+
+impl Default for ArtistSplitter {
+    fn default() -> Self {
+        ArtistSplitter::new(DEFAULT_ARTIST_SEPARATORS).expect("default artist separators are valid")
+    }
+}
+
+/// Content-based id of a track. Fields are trimmed and runs of whitespace collapsed, so
+/// "  Song " and "Song" are one track; `case_fold` also makes "SONG" and "Song" one.
+pub fn track_id(title: &str, artist: &str, album: &str, case_fold: bool) -> String {
+    let key = |field: &str| {
+        let field = field.split_whitespace().collect::<Vec<_>>().join(" ");
+        if case_fold { field.to_lowercase() } else { field }
+    };
+    format!("{}::{}::{}", key(title), key(artist), key(album))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_rules() -> MetadataRules {
+        MetadataRules::new(DEFAULT_TITLE_SPLIT_PATTERNS).unwrap()
+    }
+
+    #[test]
+    fn test_split_hyphenated_title() {
+        let rules = default_rules();
+        assert_eq!(
+            rules.split_title("Daft Punk - Get Lucky"),
+            Some(("Daft Punk".to_string(), "Get Lucky".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_split_only_on_first_separator() {
+        let rules = default_rules();
+        assert_eq!(
+            rules.split_title("Boards of Canada – Roygbiv - Remastered"),
+            Some(("Boards of Canada".to_string(), "Roygbiv - Remastered".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_split_title_by_artist() {
+        let rules = default_rules();
+        assert_eq!(
+            rules.split_title("Clair de Lune by Claude Debussy"),
+            Some(("Claude Debussy".to_string(), "Clair de Lune".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_no_match_leaves_title_alone() {
+        let rules = default_rules();
+        assert_eq!(rules.split_title("Episode 42: The Answer"), None);
+        // Hyphenated words are not separators
+        assert_eq!(rules.split_title("Lo-fi Beats"), None);
+    }
+
+    #[test]
+    fn test_custom_pattern() {
+        let rules = MetadataRules::new(&[r"^(?P<title>.+) \| (?P<artist>.+)$"]).unwrap();
+        assert_eq!(
+            rules.split_title("Song | Band"),
+            Some(("Band".to_string(), "Song".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_pattern_without_groups_rejected() {
+        assert!(MetadataRules::new(&[r"^(.+) - (.+)$"]).is_err());
+        assert!(MetadataRules::new(&[r"(?P<artist>"]).is_err());
+    }
+
+    #[test]
+    fn test_split_featured_artists() {
+        let splitter = ArtistSplitter::default();
+        assert_eq!(splitter.split("A, B feat. C"), vec!["A", "B", "C"]);
+        assert_eq!(splitter.split("A & B FEAT. A"), vec!["A", "B"]);
+        assert_eq!(splitter.split("Simon & Garfunkel"), vec!["Simon", "Garfunkel"]);
+        assert_eq!(splitter.primary("A, B feat. C"), "A");
+    }
+
+    #[test]
+    fn test_custom_artist_separators() {
+        let splitter = ArtistSplitter::new(&["; "]).unwrap();
+        assert_eq!(splitter.split("A; B, C"), vec!["A", "B, C"]);
+        assert_eq!(splitter.split("Solo"), vec!["Solo"]);
+        assert!(ArtistSplitter::new::<&str>(&[]).is_err());
+        assert!(ArtistSplitter::new(&[""]).is_err());
+    }
+}
@@ -1,147 +1,692 @@
-use anyhow::{Context, Result};
+use crate::error::OptionContext;
+use chrono::TimeZone;
 use log::{debug, error, info, warn};
-use mpris::{Metadata, PlaybackStatus, PlayerFinder};
-use std::collections::HashMap;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use mpris::{LoopStatus, Metadata, MetadataValue, PlaybackStatus, PlayerFinder};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
+use tokio::time::Interval;
+use tokio_stream::Stream;
 
-use crate::database::{Database, Track};
+use crate::content_type;
+use crate::daemon_status::{DaemonStatus, STATUS_WRITE_INTERVAL_SECS};
+use crate::database::{Database, Player, Track, MISSING_METADATA};
+use crate::error::{GopalError, Result};
+use crate::focus::{is_player_focused, FocusSource, SessionFocus};
+use crate::store::Store;
+use crate::metadata_rules::{self, ArtistSplitter, MetadataRules};
+use crate::recent_activity::RecentActivity;
+use crate::session_labels::SessionLabelRules;
+use crate::screen_lock::{locked_pause_time, LockSource, LockTracker};
 use crate::session_tracker::{SessionTracker, SessionEvent};
+use crate::sources;
 
 #[derive(Debug, Clone)]
 struct PlayerState {
     player_id: i64,
+    identity: String,
     current_metadata: Option<Metadata>,
     current_status: PlaybackStatus,
+    /// `None` for players that don't report it
+    loop_status: Option<LoopStatus>,
     last_update: i64,
+    /// When the metadata last changed, or the player was discovered
+    last_metadata_change: i64,
 }
 
-pub struct MprisMonitor {
-    db: Database,
-    session_tracker: SessionTracker,
+/// Point-in-time view of a single MPRIS player
+#[derive(Debug, Clone)]
+pub struct PlayerSnapshot {
+    pub bus_name: String,
+    pub identity: String,
+    pub status: PlaybackStatus,
+    pub metadata: Option<Metadata>,
+    /// Repeat mode, if the player reports one
+    pub loop_status: Option<LoopStatus>,
+    pub shuffle: Option<bool>,
+}
+
+/// What a player is playing right now, see `MprisMonitor::current_tracks`
+#[derive(Debug, Clone)]
+pub struct CurrentTrack {
+    pub player: Player,
+    pub track: Track,
+    /// Seconds listened so far in this session, pauses left out
+    pub elapsed: i64,
+    pub paused: bool,
+}
+
+/// Source of player snapshots, abstracted so the monitor can run without D-Bus
+pub trait PlayerSource {
+    fn players(&mut self) -> Result<Vec<PlayerSnapshot>>;
+}
+
+/// Player source backed by the session D-Bus
+pub struct DBusPlayerSource {
     player_finder: PlayerFinder,
-    player_states: HashMap<String, PlayerState>,
 }
 
-impl MprisMonitor {
-    pub fn new(db: Database) -> Result<Self> {
+impl DBusPlayerSource {
+    pub fn new() -> Result<Self> {
         let player_finder = PlayerFinder::new()
-            .context("Failed to create MPRIS player finder")?;
-        
-        let session_tracker = SessionTracker::new();
+            .map_err(|e| GopalError::Mpris { context: "Failed to create MPRIS player finder", source: e.into() })?;
+        Ok(DBusPlayerSource { player_finder })
+    }
+}
+
+impl PlayerSource for DBusPlayerSource {
+    fn players(&mut self) -> Result<Vec<PlayerSnapshot>> {
+        let players = self.player_finder.find_all()
+            .map_err(|source| GopalError::Mpris { context: "Failed to find MPRIS players", source })?;
+
+        Ok(players
+            .iter()
+            .map(|player| PlayerSnapshot {
+                bus_name: player.bus_name().to_string(),
+                identity: player.identity().to_string(),
+                status: player.get_playback_status().unwrap_or(PlaybackStatus::Stopped),
+                metadata: player.get_metadata().ok(),
+                loop_status: player.get_loop_status().ok(),
+                shuffle: player.get_shuffle().ok(),
+            })
+            .collect())
+    }
+}
+
+/// How often `ReconnectingSource::dbus` retries a session bus it couldn't connect to
+pub const DBUS_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Player source that connects on first use and, while it can't, reports no players and
+/// tries again every `retry_interval`. A source that fails later is dropped and
+/// reconnected, so the monitor survives the bus going away and coming back.
+pub struct ReconnectingSource {
+    connect: Box<dyn FnMut() -> Result<Box<dyn PlayerSource>>>,
+    source: Option<Box<dyn PlayerSource>>,
+    retry_interval: Duration,
+    /// When the next connection attempt is allowed, after one has failed
+    next_attempt: Option<Instant>,
+}
+
+impl ReconnectingSource {
+    pub fn new(connect: impl FnMut() -> Result<Box<dyn PlayerSource>> + 'static, retry_interval: Duration) -> Self {
+        ReconnectingSource {
+            connect: Box::new(connect),
+            source: None,
+            retry_interval,
+            next_attempt: None,
+        }
+    }
+
+    /// The session D-Bus, retried every `DBUS_RETRY_INTERVAL`
+    pub fn dbus() -> Self {
+        Self::new(
+            || Ok(Box::new(DBusPlayerSource::new()?) as Box<dyn PlayerSource>),
+            DBUS_RETRY_INTERVAL,
+        )
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+impl PlayerSource for ReconnectingSource {
+    fn players(&mut self) -> Result<Vec<PlayerSnapshot>> {
+        if self.source.is_none() {
+            if self.next_attempt.is_some_and(|at| Instant::now() < at) {
+                return Ok(Vec::new());
+            }
+
+            match (self.connect)() {
+                Ok(source) => {
+                    if self.next_attempt.take().is_some() {
+                        info!("Connected to D-Bus, watching players");
+                    }
+                    self.source = Some(source);
+                }
+                Err(e) => {
+                    // Only the first failure is worth a warning; the rest repeat it
+                    if self.next_attempt.is_none() {
+                        warn!("D-Bus is unavailable, retrying every {} seconds: {:#}", self.retry_interval.as_secs(), e);
+                    } else {
+                        debug!("D-Bus is still unavailable: {:#}", e);
+                    }
+                    self.next_attempt = Some(Instant::now() + self.retry_interval);
+                    return Ok(Vec::new());
+                }
+            }
+        }
+
+        let source = self.source.as_mut().expect("connected above");
+        let players = source.players();
+        if players.is_err() {
+            self.source = None;
+        }
+        players
+    }
+}
+
+/// Behavioral switches for the monitor, usually filled from the daemon config
+#[derive(Debug, Clone, Default)]
+pub struct MonitorOptions {
+    /// Count only one session when several players play the same track at once
+    pub dedup_simultaneous: bool,
+    /// Rules for recovering the artist from the title when the player omits it
+    pub metadata_rules: MetadataRules,
+    /// Store the first artist of multi-artist credits as the track's primary artist
+    pub artist_splitter: Option<ArtistSplitter>,
+    /// Keep each track's full MPRIS metadata map as JSON on the track row
+    pub store_raw_metadata: bool,
+    /// Most tracks whose raw metadata is kept; older ones lose it during cleanup.
+    /// Zero keeps all of it, as does turning `store_raw_metadata` off.
+    pub max_raw_metadata_rows: usize,
+    /// How often active sessions are checkpointed, defaulting to `CHECKPOINT_INTERVAL`
+    pub checkpoint_interval: Option<Duration>,
+    /// Seconds a playing player may vanish from D-Bus and come back on the same track
+    /// without its session being split in two. Zero finalizes immediately.
+    pub reappear_grace_period: i64,
+    /// Labels given to new sessions by player or time of day
+    pub session_labels: SessionLabelRules,
+    /// File rewritten every `STATUS_WRITE_INTERVAL_SECS` with the last successful poll time
+    pub status_file: Option<PathBuf>,
+    /// Seconds within which a title change by the same artist is treated as the same
+    /// track, for players that scroll ticker text through the title. Zero disables.
+    pub min_metadata_change_interval: i64,
+    /// Seconds after a session of a track ends within which the same player starting
+    /// that track again continues the old session, for players that glitch and restart
+    /// the song. The time in between counts as pause. Zero disables.
+    pub merge_consecutive_same_track_gap: i64,
+    /// Seconds a session may stay paused beyond what was left of its track before it
+    /// is completed as of the pause, for players that pause instead of stopping when
+    /// the queue runs out. Zero disables and leaves it to the stale timeout.
+    pub paused_past_end_margin: i64,
+    /// Pauses shorter than this many seconds add no pause time, for players that report
+    /// Paused for a moment while seeking. Zero counts every pause.
+    pub pause_debounce: i64,
+    /// Longest track length believed from metadata, defaulting to `MAX_TRACK_LENGTH`.
+    /// Longer lengths, and zero, are stored as unknown.
+    pub max_track_length: Option<Duration>,
+    /// While a player repeats one track, continue its session when the track restarts
+    /// instead of counting every loop as a play, see `REPEAT_ONE_MERGE_GAP`
+    pub merge_repeat_one: bool,
+    /// Longest a session may last before it is ended at this length with the `capped`
+    /// status, defaulting to `session_tracker::MAX_REASONABLE_DURATION`
+    pub max_reasonable_duration: Option<Duration>,
+    /// Ignore case when deriving track ids, so "SONG" and "Song" are one track. Titles
+    /// and artists are still stored as the player reported them.
+    pub case_insensitive_track_ids: bool,
+    /// While this file exists nothing is recorded, see `gopal-cli record`
+    pub recording_pause_file: Option<PathBuf>,
+}
+
+/// How often active sessions are checkpointed to the database by default
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest track length believed from metadata by default
+pub const MAX_TRACK_LENGTH: Duration = Duration::from_secs(24 * 3600);
+
+/// Seconds between one loop of a repeated track ending and the next starting within
+/// which `merge_repeat_one` continues the session. Players restart the track right
+/// away, at most briefly reporting Stopped in between.
+const REPEAT_ONE_MERGE_GAP: i64 = 30;
+
+/// Longest daemon downtime after which a still-playing session is resumed rather than restarted
+const MAX_RESUME_GAP: i64 = 300;
+
+/// Timers and the event channel of a running monitoring loop
+struct MonitorLoop {
+    session_rx: mpsc::UnboundedReceiver<SessionEvent>,
+    poll_interval: Interval,
+    discovery_interval: Interval,
+    cleanup_interval: Interval,
+    update_interval: Interval,
+    status_interval: Interval,
+    started_at: i64,
+}
+
+impl MonitorLoop {
+    fn new(session_rx: mpsc::UnboundedReceiver<SessionEvent>, options: &MonitorOptions) -> Self {
+        let checkpoint_interval = options.checkpoint_interval
+            .filter(|interval| !interval.is_zero())
+            .unwrap_or(CHECKPOINT_INTERVAL);
+
+        MonitorLoop {
+            session_rx,
+            poll_interval: tokio::time::interval(Duration::from_secs(2)),
+            discovery_interval: tokio::time::interval(Duration::from_secs(5)),
+            cleanup_interval: tokio::time::interval(Duration::from_secs(60)),
+            update_interval: tokio::time::interval(checkpoint_interval),
+            status_interval: tokio::time::interval(Duration::from_secs(STATUS_WRITE_INTERVAL_SECS)),
+            started_at: current_timestamp(),
+        }
+    }
+}
+
+type NextEvent<S> = Pin<Box<dyn Future<Output = (Option<SessionEvent>, MprisMonitor<S>, MonitorLoop)>>>;
+
+/// Session events from `MprisMonitor::event_stream`
+pub struct SessionEventStream<S: Store = Database> {
+    next: NextEvent<S>,
+}
+
+impl<S: Store + 'static> SessionEventStream<S> {
+    fn new(monitor: MprisMonitor<S>, monitor_loop: MonitorLoop) -> Self {
+        SessionEventStream { next: Self::next_event(monitor, monitor_loop) }
+    }
+
+    fn next_event(mut monitor: MprisMonitor<S>, mut monitor_loop: MonitorLoop) -> NextEvent<S> {
+        Box::pin(async move {
+            let event = monitor.next_event(&mut monitor_loop).await;
+            (event, monitor, monitor_loop)
+        })
+    }
+}
+
+impl<S: Store + 'static> Stream for SessionEventStream<S> {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<SessionEvent>> {
+        let (event, monitor, monitor_loop) = ready!(self.next.as_mut().poll(cx));
+        // The monitor keeps the sender, so the channel never closes and events keep coming
+        self.next = Self::next_event(monitor, monitor_loop);
+        Poll::Ready(event)
+    }
+}
+
+/// Watches MPRIS players and records their listening sessions in a `Store`
+pub struct MprisMonitor<S: Store = Database> {
+    store: S,
+    session_tracker: SessionTracker,
+    player_source: Box<dyn PlayerSource>,
+    player_states: HashMap<String, PlayerState>,
+    options: MonitorOptions,
+    session_rx: Option<mpsc::UnboundedReceiver<SessionEvent>>,
+    /// Tracker session id -> database row id
+    db_session_ids: HashMap<i64, i64>,
+    /// Players playing a track that another player is already recording
+    shadowed_players: HashMap<i64, String>,
+    /// Whether sessions left active by a previous run have been resumed or closed
+    persisted_sessions_reconciled: bool,
+    /// Playing players that dropped off the bus, by bus name, with when they vanished.
+    /// Their sessions stay open until they return or the grace period runs out.
+    vanished_players: HashMap<String, (i64, i64)>,
+    /// Screen lock state, when locked time should count as pause
+    lock_source: Option<Box<dyn LockSource>>,
+    lock_tracker: LockTracker,
+    /// Focused window, when sessions are tagged as foreground or background listening
+    focus_source: Option<Box<dyn FocusSource>>,
+    /// Database session id -> the focus last recorded for it
+    session_focus: HashMap<i64, SessionFocus>,
+    /// When players were last polled without error, for the status file
+    last_successful_poll: Option<i64>,
+    /// Listening per hour over the last day, for the status file
+    recent_activity: RecentActivity,
+    /// Whether `recording_pause_file` existed when last checked
+    recording_paused: bool,
+}
+
+impl<S: Store> MprisMonitor<S> {
+    /// Watch players on the session D-Bus. Construction never fails: without a bus the
+    /// monitor sees no players and keeps trying to connect, see `ReconnectingSource`.
+    pub fn new(store: S) -> Self {
+        Self::with_source(store, Box::new(ReconnectingSource::dbus()))
+    }
+
+    /// Like `new`, but fail right away if the session D-Bus can't be reached
+    pub fn connect(store: S) -> Result<Self> {
+        let player_source = DBusPlayerSource::new()?;
+        Ok(Self::with_source(store, Box::new(player_source)))
+    }
+
+    pub fn with_source(store: S, player_source: Box<dyn PlayerSource>) -> Self {
+        let mut session_tracker = SessionTracker::new();
+
+        // Set up event channel for session events
+        let (session_tx, session_rx) = mpsc::unbounded_channel();
+        session_tracker.set_event_sender(session_tx);
 
-        Ok(MprisMonitor {
-            db,
+        MprisMonitor {
+            store,
             session_tracker,
-            player_finder,
+            player_source,
             player_states: HashMap::new(),
-        })
+            options: MonitorOptions::default(),
+            session_rx: Some(session_rx),
+            db_session_ids: HashMap::new(),
+            shadowed_players: HashMap::new(),
+            persisted_sessions_reconciled: false,
+            vanished_players: HashMap::new(),
+            lock_source: None,
+            lock_tracker: LockTracker::default(),
+            focus_source: None,
+            session_focus: HashMap::new(),
+            last_successful_poll: None,
+            recent_activity: RecentActivity::new(),
+            recording_paused: false,
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn with_options(mut self, options: MonitorOptions) -> Self {
+        self.session_tracker.set_pause_debounce(options.pause_debounce);
+        if let Some(max_duration) = options.max_reasonable_duration {
+            self.session_tracker.set_max_reasonable_duration(max_duration.as_secs() as i64);
+        }
+        self.options = options;
+        self
+    }
+
+    /// Treat time the desktop session spends locked as pause time
+    pub fn with_lock_source(mut self, lock_source: Box<dyn LockSource>) -> Self {
+        self.lock_source = Some(lock_source);
+        self
+    }
+
+    /// Tag sessions as foreground when their player's window has focus while they play
+    pub fn with_focus_source(mut self, focus_source: Box<dyn FocusSource>) -> Self {
+        self.focus_source = Some(focus_source);
+        self
+    }
+
+    /// Tracks with an active session, one per player, ordered by player id. The
+    /// in-process equivalent of `gopal-cli now`; it reads the session tracker, so it's
+    /// up to date as of the last poll without going through the store.
+    pub fn current_tracks(&self) -> Vec<CurrentTrack> {
+        self.current_tracks_at(current_timestamp())
+    }
+
+    fn current_tracks_at(&self, current_time: i64) -> Vec<CurrentTrack> {
+        let mut current: Vec<_> = self.session_tracker.get_active_sessions()
+            .into_iter()
+            .filter_map(|(player_id, session)| {
+                let (bus_name, state) = self.player_states.iter().find(|(_, state)| state.player_id == player_id)?;
+                let pause_time = session.total_pause_time + self.session_tracker.pending_pause_time(session, current_time);
+                Some(CurrentTrack {
+                    player: Player { id: player_id, name: bus_name.clone(), identity: state.identity.clone() },
+                    track: session.track.clone(),
+                    elapsed: (current_time - session.start_time - pause_time).max(0),
+                    paused: session.is_paused,
+                })
+            })
+            .collect();
+        current.sort_by_key(|current| current.player.id);
+        current
     }
 
+    /// Run the monitoring loop, recording every session in the database
     pub async fn start_monitoring(&mut self) -> Result<()> {
         info!("Starting MPRIS monitoring...");
 
-        // Set up event channel for session events
-        let (session_tx, mut session_rx) = mpsc::unbounded_channel();
-        self.session_tracker.set_event_sender(session_tx);
+        let mut monitor_loop = self.take_loop()?;
+        while let Some(event) = self.next_event(&mut monitor_loop).await {
+            if let Err(e) = self.handle_session_event(event).await {
+                error!("Error handling session event: {}", e);
+            }
+        }
+        Ok(())
+    }
 
-        // Start the main monitoring loop
-        let mut poll_interval = tokio::time::interval(Duration::from_secs(2));
-        let mut discovery_interval = tokio::time::interval(Duration::from_secs(5));
-        let mut cleanup_interval = tokio::time::interval(Duration::from_secs(60));
-        let mut update_interval = tokio::time::interval(Duration::from_secs(30)); // Update active sessions every 30 seconds
+    /// Run the monitoring loop and hand each session event to the caller instead of
+    /// recording it, for embedding gopal in other applications. Players are still
+    /// registered in the monitor's store, which can be an `InMemoryStore`.
+    /// The loop only runs while the stream is polled.
+    pub fn event_stream(mut self) -> Result<SessionEventStream<S>>
+    where
+        S: 'static,
+    {
+        let monitor_loop = self.take_loop()?;
+        Ok(SessionEventStream::new(self, monitor_loop))
+    }
+
+    fn take_loop(&mut self) -> Result<MonitorLoop> {
+        let session_rx = self.session_rx.take()
+            .context("Monitoring has already been started")?;
+        Ok(MonitorLoop::new(session_rx, &self.options))
+    }
 
+    /// Drive polling, discovery, cleanup and checkpoints until the next session event
+    async fn next_event(&mut self, monitor_loop: &mut MonitorLoop) -> Option<SessionEvent> {
         loop {
             tokio::select! {
-                // Handle session events
-                Some(event) = session_rx.recv() => {
-                    if let Err(e) = self.handle_session_event(event).await {
-                        error!("Error handling session event: {}", e);
-                    }
-                }
-                
+                // Hand back session events
+                event = monitor_loop.session_rx.recv() => return event,
+
                 // Poll existing players for status changes
-                _ = poll_interval.tick() => {
-                    if let Err(e) = self.poll_players().await {
-                        error!("Error polling players: {}", e);
+                _ = monitor_loop.poll_interval.tick() => {
+                    match self.poll_players().await {
+                        Ok(()) => self.last_successful_poll = Some(current_timestamp()),
+                        Err(e) => error!("Error polling players: {}", e),
                     }
                 }
                 
                 // Discover new players
-                _ = discovery_interval.tick() => {
+                _ = monitor_loop.discovery_interval.tick() => {
                     if let Err(e) = self.discover_players().await {
                         error!("Error discovering players: {}", e);
                     }
                 }
                 
                 // Cleanup stale sessions and detect long idle periods
-                _ = cleanup_interval.tick() => {
-                    let current_time = Self::current_timestamp();
-                    
-                    // Check for sessions that might have been affected by system sleep/suspend
-                    if let Err(e) = self.check_for_sleep_resume(current_time).await {
-                        error!("Error checking for sleep/resume: {}", e);
-                    }
-                    
-                    // Regular cleanup of stale sessions
-                    if let Err(e) = self.session_tracker.cleanup_stale_sessions(current_time, 300).await {
-                        error!("Error cleaning up stale sessions: {}", e);
-                    }
+                _ = monitor_loop.cleanup_interval.tick() => {
+                    self.cleanup_sessions_at(current_timestamp()).await;
                 }
                 
-                // Update active sessions in database for real-time stats
-                _ = update_interval.tick() => {
-                    if let Err(e) = self.update_active_sessions().await {
+                // Checkpoint active sessions for real-time stats and crash recovery
+                _ = monitor_loop.update_interval.tick() => {
+                    if let Err(e) = self.update_active_sessions_at(current_timestamp()).await {
                         error!("Error updating active sessions: {}", e);
                     }
                 }
+
+                // Let health checks see the daemon is alive and polling
+                _ = monitor_loop.status_interval.tick(), if self.options.status_file.is_some() => {
+                    if let Some(path) = &self.options.status_file {
+                        self.write_status(path, monitor_loop.started_at);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn cleanup_sessions_at(&mut self, current_time: i64) {
+        // Check for sessions that might have been affected by system sleep/suspend
+        if let Err(e) = self.check_for_sleep_resume(current_time).await {
+            error!("Error checking for sleep/resume: {}", e);
+        }
+
+        // Players left paused at the end of a track end when they paused, not at the timeout
+        let margin = self.options.paused_past_end_margin;
+        if margin > 0 {
+            if let Err(e) = self.session_tracker.finalize_paused_past_end(current_time, margin).await {
+                error!("Error finalizing sessions paused past their track's end: {}", e);
             }
         }
+
+        // Regular cleanup of stale sessions
+        if let Err(e) = self.session_tracker.cleanup_stale_sessions(current_time, 300).await {
+            error!("Error cleaning up stale sessions: {}", e);
+        }
+
+        // With storage off there's nothing new to make room for
+        let max_rows = self.options.max_raw_metadata_rows;
+        if max_rows > 0 && self.options.store_raw_metadata {
+            match self.store.prune_raw_metadata(max_rows) {
+                Ok(0) => {}
+                Ok(pruned) => info!("Dropped raw metadata of {} tracks to stay within {} rows", pruned, max_rows),
+                Err(e) => error!("Error pruning raw metadata: {}", e),
+            }
+        }
+    }
+
+    fn write_status(&self, path: &Path, started_at: i64) {
+        let status = DaemonStatus {
+            pid: std::process::id(),
+            started_at,
+            last_poll: self.last_successful_poll,
+            updated_at: current_timestamp(),
+            recent_activity: self.recent_activity.hours(current_timestamp()),
+        };
+        if let Err(e) = status.write(path) {
+            warn!("Failed to update status file: {:#}", e);
+        }
     }
 
     async fn discover_players(&mut self) -> Result<()> {
-        let players = self.player_finder.find_all()
-            .context("Failed to find MPRIS players")?;
+        self.discover_players_at(current_timestamp()).await
+    }
+
+    async fn discover_players_at(&mut self, current_time: i64) -> Result<()> {
+        self.check_recording_paused(current_time).await?;
+        let players = self.player_source.players()?;
 
         for player in players {
-            let bus_name = player.bus_name().to_string();
+            let bus_name = player.bus_name;
             
             if !self.player_states.contains_key(&bus_name) {
                 info!("Discovered new player: {}", bus_name);
                 
                 // Register player in database
-                let identity = player.identity().to_string();
-                
-                let player_id = self.db.insert_or_update_player(&bus_name, &identity)
-                    .context("Failed to register player in database")?;
+                let player_id = self.store.insert_or_update_player(&bus_name, &player.identity)?;
 
                 // Initialize player state
-                let current_metadata = player.get_metadata().ok();
-                let current_status = player.get_playback_status().unwrap_or(PlaybackStatus::Stopped);
-                let current_time = Self::current_timestamp();
+                let current_metadata = player.metadata;
+                let current_status = player.status;
 
                 let player_state = PlayerState {
                     player_id,
+                    identity: player.identity.clone(),
                     current_metadata: current_metadata.clone(),
                     current_status,
+                    loop_status: player.loop_status,
                     last_update: current_time,
+                    last_metadata_change: current_time,
                 };
 
+                let vanished_at = self.vanished_players.remove(&bus_name).map(|(_, vanished_at)| vanished_at);
                 self.player_states.insert(bus_name, player_state);
 
-                // If currently playing, start a session
-                if current_status == PlaybackStatus::Playing {
-                    if let Some(metadata) = current_metadata {
-                        let track = Self::metadata_to_track(&metadata);
-                        self.session_tracker.handle_play_event(player_id, track, current_time).await?;
-                    } else {
-                        // Try to get metadata again for playing tracks
-                        if let Ok(metadata) = player.get_metadata() {
-                            let track = Self::metadata_to_track(&metadata);
-                            self.session_tracker.handle_play_event(player_id, track, current_time).await?;
-                        }
+                // If currently playing, pick up where a previous run left off or start a session
+                let track = current_metadata
+                    .filter(|_| current_status == PlaybackStatus::Playing)
+                    .map(|metadata| metadata_to_track(&metadata, &self.options));
+
+                if let Some(vanished_at) = vanished_at {
+                    if self.resume_vanished_session(player_id, track.as_ref(), vanished_at, current_time).await? {
+                        continue;
                     }
                 }
+
+                if let Some(track) = track {
+                    if !self.resume_persisted_session(player_id, &track, current_time)? {
+                        self.start_track(player_id, track, current_time).await?;
+                    }
+                }
+            }
+        }
+
+        if !self.persisted_sessions_reconciled {
+            self.close_unresumed_sessions()?;
+            self.persisted_sessions_reconciled = true;
+        }
+
+        Ok(())
+    }
+
+    /// Continue the session a previous daemon run left active for this player, if it
+    /// was checkpointed recently and the player is still playing the same track
+    fn resume_persisted_session(&mut self, player_id: i64, track: &Track, current_time: i64) -> Result<bool> {
+        if self.persisted_sessions_reconciled {
+            return Ok(false);
+        }
+
+        let Some(checkpoint) = self.store.get_session_checkpoint(player_id)? else {
+            return Ok(false);
+        };
+
+        let gap = current_time - checkpoint.last_seen;
+        if checkpoint.session.track_id != track.id || !(0..=MAX_RESUME_GAP).contains(&gap) {
+            return Ok(false);
+        }
+
+        info!("Resuming session {} for '{}' after {} seconds without a checkpoint",
+              checkpoint.session.id, track.title, gap);
+
+        self.store.resume_session(checkpoint.session.id, current_time)?;
+        let listened = checkpoint.session.listened_time.unwrap_or(0);
+        let total_pause_time = current_time - checkpoint.session.start_time - listened;
+        let session_id = self.session_tracker.resume_session(
+            player_id,
+            track.clone(),
+            checkpoint.session.start_time,
+            total_pause_time,
+        );
+        self.db_session_ids.insert(session_id, checkpoint.session.id);
+
+        Ok(true)
+    }
+
+    /// A player came back after briefly dropping off the bus. Keep its session going if it
+    /// is playing the same track, counting the time it was gone as pause; otherwise close
+    /// the session at the moment the player vanished.
+    async fn resume_vanished_session(
+        &mut self,
+        player_id: i64,
+        track: Option<&Track>,
+        vanished_at: i64,
+        current_time: i64,
+    ) -> Result<bool> {
+        let same_track = self.session_tracker
+            .get_active_sessions()
+            .into_iter()
+            .any(|(id, session)| id == player_id && track.is_some_and(|track| track.id == session.track.id));
+
+        if same_track {
+            let gap = current_time - vanished_at;
+            info!("Player {} came back after {} seconds on the same track - continuing its session", player_id, gap);
+            self.session_tracker.handle_sleep_gap(player_id, gap).await?;
+            return Ok(true);
+        }
+
+        if self.session_tracker.has_active_session(player_id) {
+            self.session_tracker.handle_stop_event(player_id, vanished_at).await?;
+        }
+        Ok(false)
+    }
+
+    /// Close sessions of vanished players whose grace period has run out
+    async fn expire_vanished_players(&mut self, current_time: i64) -> Result<()> {
+        let grace_period = self.options.reappear_grace_period;
+        let expired: Vec<String> = self.vanished_players
+            .iter()
+            .filter(|(_, &(_, vanished_at))| current_time - vanished_at > grace_period)
+            .map(|(bus_name, _)| bus_name.clone())
+            .collect();
+
+        for bus_name in expired {
+            if let Some((player_id, vanished_at)) = self.vanished_players.remove(&bus_name) {
+                info!("Player {} did not come back - finalizing its session", bus_name);
+                self.session_tracker.handle_stop_event(player_id, vanished_at).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finalize sessions left active by a previous run that weren't resumed
+    fn close_unresumed_sessions(&mut self) -> Result<()> {
+        let resumed: Vec<i64> = self.db_session_ids.values().copied().collect();
+
+        for stale in self.store.list_active_sessions()? {
+            if !resumed.contains(&stale.session.id) {
+                info!("Closing session {} left active by a previous run", stale.session.id);
+                self.store.close_session_at_checkpoint(stale.session.id, "orphaned")?;
             }
         }
 
@@ -149,14 +694,20 @@ impl MprisMonitor {
     }
 
     async fn poll_players(&mut self) -> Result<()> {
-        let players = self.player_finder.find_all()
-            .context("Failed to find MPRIS players")?;
+        self.poll_players_at(current_timestamp()).await
+    }
+
+    async fn poll_players_at(&mut self, current_time: i64) -> Result<()> {
+        self.check_recording_paused(current_time).await?;
+        self.check_screen_lock(current_time).await?;
+        self.check_focus().await?;
+
+        let players = self.player_source.players()?;
         
         let mut active_players = HashMap::new();
         
         for player in players {
-            let bus_name = player.bus_name().to_string();
-            active_players.insert(bus_name.clone(), player);
+            active_players.insert(player.bus_name.clone(), player);
         }
 
         // Check each tracked player
@@ -171,10 +722,13 @@ impl MprisMonitor {
                 // Player no longer exists
                 info!("Player {} disappeared", bus_name);
                 
-                // Finalize any active session
+                // Finalize any active session, unless the player may just be restarting
                 if player_state.current_status == PlaybackStatus::Playing {
-                    let current_time = Self::current_timestamp();
-                    self.session_tracker.handle_stop_event(player_state.player_id, current_time).await?;
+                    if self.options.reappear_grace_period > 0 {
+                        self.vanished_players.insert(bus_name.clone(), (player_state.player_id, current_time));
+                    } else {
+                        self.session_tracker.handle_stop_event(player_state.player_id, current_time).await?;
+                    }
                 }
                 
                 players_to_remove.push(bus_name.clone());
@@ -183,9 +737,8 @@ impl MprisMonitor {
 
         // Process state updates
         for (bus_name, player) in state_updates {
-            let current_time = Self::current_timestamp();
-            let new_status = player.get_playback_status().unwrap_or(PlaybackStatus::Stopped);
-            let new_metadata = player.get_metadata().ok();
+            let new_status = player.status;
+            let new_metadata = player.metadata.clone();
             
             if let Some(ref metadata) = new_metadata {
                 debug!("Polling player {}: status={:?}, track='{}'",
@@ -227,6 +780,7 @@ impl MprisMonitor {
             if let Some(player_state) = self.player_states.get_mut(&bus_name) {
                 player_state.current_status = new_status;
                 player_state.current_metadata = new_metadata;
+                player_state.loop_status = player.loop_status;
                 player_state.last_update = current_time;
             }
         }
@@ -236,6 +790,180 @@ impl MprisMonitor {
             self.player_states.remove(&bus_name);
         }
 
+        self.expire_vanished_players(current_time).await?;
+
+        if self.options.dedup_simultaneous {
+            self.promote_shadowed_players(current_time).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Follow `recording_pause_file`: when it appears, end the active sessions and stop
+    /// starting new ones; once it's gone, players still playing start a session again
+    async fn check_recording_paused(&mut self, current_time: i64) -> Result<()> {
+        let paused = self.options.recording_pause_file.as_ref().is_some_and(|path| path.exists());
+        if paused == self.recording_paused {
+            return Ok(());
+        }
+        self.recording_paused = paused;
+
+        if paused {
+            let active: Vec<i64> = self.session_tracker.get_active_sessions().into_iter().map(|(player_id, _)| player_id).collect();
+            info!("Recording paused, ending {} active sessions", active.len());
+            for player_id in active {
+                self.session_tracker.handle_stop_event(player_id, current_time).await?;
+            }
+            self.shadowed_players.clear();
+        } else {
+            info!("Recording resumed");
+            // Seen as starting to play on this poll
+            for state in self.player_states.values_mut() {
+                if state.current_status == PlaybackStatus::Playing {
+                    state.current_status = PlaybackStatus::Stopped;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Once the screen unlocks, count the locked interval as pause for sessions still playing.
+    /// Players that paused themselves already account for that time.
+    async fn check_screen_lock(&mut self, current_time: i64) -> Result<()> {
+        let Some(lock_source) = self.lock_source.as_mut() else {
+            return Ok(());
+        };
+
+        let locked = match lock_source.is_locked() {
+            Ok(locked) => locked,
+            Err(e) => {
+                debug!("Could not read screen lock state: {:#}", e);
+                return Ok(());
+            }
+        };
+
+        let Some((locked_at, unlocked_at)) = self.lock_tracker.update(locked, current_time) else {
+            return Ok(());
+        };
+
+        info!("Screen was locked for {} seconds - treating as pause time", unlocked_at - locked_at);
+
+        let locked_sessions: Vec<(i64, i64)> = self.session_tracker
+            .get_active_sessions()
+            .into_iter()
+            .filter(|(_, session)| !session.is_paused)
+            .map(|(player_id, session)| (player_id, locked_pause_time(locked_at, unlocked_at, session.start_time)))
+            .collect();
+
+        for (player_id, pause_time) in locked_sessions {
+            if pause_time > 0 {
+                self.session_tracker.handle_sleep_gap(player_id, pause_time).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tag each recorded session as background until its player is seen with focus, after
+    /// which it stays foreground
+    async fn check_focus(&mut self) -> Result<()> {
+        let Some(focus_source) = self.focus_source.as_mut() else {
+            return Ok(());
+        };
+
+        let focused = match focus_source.focused_application().await {
+            Ok(focused) => focused,
+            Err(e) => {
+                debug!("Could not read the focused window: {:#}", e);
+                return Ok(());
+            }
+        };
+
+        let mut active = HashMap::new();
+        for (player_id, session) in self.session_tracker.get_active_sessions() {
+            let Some(&db_session_id) = self.db_session_ids.get(&session.session_id) else {
+                continue;
+            };
+            let has_focus = focused.as_deref().is_some_and(|focused| {
+                self.player_states.iter()
+                    .find(|(_, state)| state.player_id == player_id)
+                    .is_some_and(|(bus_name, state)| is_player_focused(focused, &state.identity, bus_name))
+            });
+
+            let recorded = self.session_focus.get(&db_session_id).copied();
+            let focus = if has_focus || recorded == Some(SessionFocus::Foreground) {
+                SessionFocus::Foreground
+            } else {
+                SessionFocus::Background
+            };
+            if recorded != Some(focus) {
+                self.store.set_session_focus(db_session_id, focus)?;
+            }
+            active.insert(db_session_id, focus);
+        }
+        // Finished sessions keep the tag they were given
+        self.session_focus = active;
+
+        Ok(())
+    }
+
+    /// Start a session for `track`, unless deduplication hands it to another player
+    async fn start_track(&mut self, player_id: i64, track: Track, current_time: i64) -> Result<()> {
+        self.shadowed_players.remove(&player_id);
+
+        if self.recording_paused {
+            debug!("Recording is paused - not recording '{}' on player {}", track.title, player_id);
+            return Ok(());
+        }
+
+        if self.options.dedup_simultaneous {
+            let duplicate_of = self.session_tracker
+                .get_active_sessions()
+                .into_iter()
+                .find(|(other_id, session)| *other_id != player_id && session.track.id == track.id)
+                .map(|(other_id, _)| other_id);
+
+            if let Some(other_id) = duplicate_of {
+                debug!("Player {} is playing '{}' which player {} is already recording - not double counting",
+                       player_id, track.title, other_id);
+
+                // Close whatever this player was recording before it switched to the duplicate
+                if self.session_tracker.has_active_session(player_id) {
+                    self.session_tracker.handle_stop_event(player_id, current_time).await?;
+                }
+                self.shadowed_players.insert(player_id, track.id);
+                return Ok(());
+            }
+        }
+
+        self.session_tracker.handle_play_event(player_id, track, current_time).await
+    }
+
+    /// Hand recording over to a shadowed player once the player it deferred to stops
+    async fn promote_shadowed_players(&mut self, current_time: i64) -> Result<()> {
+        let shadowed: Vec<(i64, String)> = self.shadowed_players.drain().collect();
+
+        for (player_id, track_id) in shadowed {
+            let current_track = self.player_states
+                .values()
+                .find(|state| state.player_id == player_id && state.current_status == PlaybackStatus::Playing)
+                .and_then(|state| state.current_metadata.as_ref())
+                .map(|metadata| metadata_to_track(metadata, &self.options));
+
+            // The player stopped, vanished or moved on to another track
+            let track = match current_track {
+                Some(track) if track.id == track_id => track,
+                _ => continue,
+            };
+
+            if self.session_tracker.has_active_session(player_id) {
+                continue;
+            }
+
+            // start_track re-shadows the player if the track is still being recorded elsewhere
+            self.start_track(player_id, track, current_time).await?;
+        }
+
         Ok(())
     }
 
@@ -261,11 +989,11 @@ impl MprisMonitor {
                     if !self.session_tracker.has_active_session(player_id) {
                         debug!("No active session for resume, creating new session");
                         if let Some(ref metadata) = new_metadata {
-                            let track = Self::metadata_to_track(metadata);
-                            self.session_tracker.handle_play_event(player_id, track, current_time).await?;
+                            let track = metadata_to_track(metadata, &self.options);
+                            self.start_track(player_id, track, current_time).await?;
                         } else if let Some(ref metadata) = old_metadata {
-                            let track = Self::metadata_to_track(metadata);
-                            self.session_tracker.handle_play_event(player_id, track, current_time).await?;
+                            let track = metadata_to_track(metadata, &self.options);
+                            self.start_track(player_id, track, current_time).await?;
                         }
                     } else {
                         self.session_tracker.handle_resume_event(player_id, current_time).await?;
@@ -277,12 +1005,12 @@ impl MprisMonitor {
                 (_, PlaybackStatus::Playing) => {
                     // Started playing from stopped state
                     if let Some(ref metadata) = new_metadata {
-                        let track = Self::metadata_to_track(metadata);
-                        self.session_tracker.handle_play_event(player_id, track, current_time).await?;
+                        let track = metadata_to_track(metadata, &self.options);
+                        self.start_track(player_id, track, current_time).await?;
                     } else if let Some(ref metadata) = old_metadata {
                         // Use old metadata if new metadata is not available
-                        let track = Self::metadata_to_track(metadata);
-                        self.session_tracker.handle_play_event(player_id, track, current_time).await?;
+                        let track = metadata_to_track(metadata, &self.options);
+                        self.start_track(player_id, track, current_time).await?;
                     }
                 }
                 _ => {}
@@ -333,6 +1061,8 @@ impl MprisMonitor {
             }
         };
 
+        let metadata_changed = metadata_changed
+            && !self.is_metadata_flap(player_id, old_metadata.as_ref(), new_metadata.as_ref(), current_time);
         debug!("Final metadata_changed result: {}", metadata_changed);
 
         if metadata_changed {
@@ -348,8 +1078,8 @@ impl MprisMonitor {
             if new_status == PlaybackStatus::Playing {
                 if let Some(ref metadata) = new_metadata {
                     debug!("Starting new session for player {}", player_id);
-                    let track = Self::metadata_to_track(metadata);
-                    self.session_tracker.handle_play_event(player_id, track, current_time).await?;
+                    let track = metadata_to_track(metadata, &self.options);
+                    self.start_track(player_id, track, current_time).await?;
                 } else {
                     debug!("No metadata available for new session");
                 }
@@ -359,57 +1089,98 @@ impl MprisMonitor {
         Ok(())
     }
 
+    /// Whether a metadata change is a flapping title to ignore: the artist is unchanged and
+    /// the previous change came less than `min_metadata_change_interval` ago. Ignored
+    /// changes still restart the interval, so a title that keeps scrolling stays one track.
+    fn is_metadata_flap(&mut self, player_id: i64, old: Option<&Metadata>, new: Option<&Metadata>, current_time: i64) -> bool {
+        let Some(state) = self.player_states.values_mut().find(|state| state.player_id == player_id) else {
+            return false;
+        };
+        let previous_change = std::mem::replace(&mut state.last_metadata_change, current_time);
+
+        let interval = self.options.min_metadata_change_interval;
+        let same_artist = matches!((old, new), (Some(old), Some(new)) if old.artists() == new.artists());
+        let flap = interval > 0 && same_artist && current_time - previous_change < interval;
+        if flap {
+            debug!("Ignoring metadata change for player {} {}s after the previous one", player_id, current_time - previous_change);
+        }
+        flap
+    }
+
     async fn handle_session_event(&mut self, event: SessionEvent) -> Result<()> {
+        self.recent_activity.record(&event);
         match event {
             SessionEvent::SessionStarted { session_id, track, player_id, start_time } => {
-                debug!("Session started: {} for track: {}", session_id, track.title);
-                self.db.insert_or_update_track(&track)?;
-                self.db.start_session(&track.id, player_id, start_time)?;
-            }
-            
-            SessionEvent::SessionPaused { session_id, pause_duration } => {
-                debug!("Session paused: {} for {} seconds", session_id, pause_duration);
-                self.db.update_session_pause_time(session_id, pause_duration)?;
-            }
-            
+                debug!(session_id = session_id, player_id = player_id, track = track.title.as_str(), artist = track.artist.as_str();
+                       "Session started: {} for track: {}", session_id, track.title);
+                let player_state = self.player_states
+                    .values()
+                    .find(|state| state.player_id == player_id);
+                let identity = player_state
+                    .map(|state| state.identity.as_str())
+                    .unwrap_or_default();
+                let content_type = content_type::classify(&track, identity);
+                let repeating_one = self.options.merge_repeat_one
+                    && player_state.is_some_and(|state| state.loop_status == Some(LoopStatus::Track));
+
+                self.store.insert_or_update_track(&track)?;
+                let mut merge_gap = self.options.merge_consecutive_same_track_gap;
+                if repeating_one {
+                    merge_gap = merge_gap.max(REPEAT_ONE_MERGE_GAP);
+                }
+                let mergeable = if merge_gap > 0 {
+                    self.store.find_mergeable_session(&track.id, player_id, start_time, merge_gap)?
+                } else {
+                    None
+                };
+                if let Some(db_session_id) = mergeable {
+                    debug!("Replay of {} continues session {}", track.title, db_session_id);
+                    self.store.reopen_session(db_session_id, start_time)?;
+                    self.db_session_ids.insert(session_id, db_session_id);
+                    return Ok(());
+                }
+
+                let db_session_id = self.store.start_session_as(&track.id, player_id, start_time, content_type)?;
+
+                // Browsers play from many sites; remember which one this session came from
+                let source_domain = player_state
+                    .and_then(|state| state.current_metadata.as_ref())
+                    .and_then(|metadata| metadata.url())
+                    .and_then(sources::domain_from_url);
+                if source_domain.is_some() {
+                    self.store.set_session_source_domain(db_session_id, source_domain.as_deref())?;
+                }
+
+                if !self.options.session_labels.is_empty() {
+                    let local_time = chrono::Local.timestamp_opt(start_time, 0).earliest().map(|dt| dt.time());
+                    if let Some(label) = local_time.and_then(|time| self.options.session_labels.label_for(identity, time)) {
+                        self.store.set_session_label(db_session_id, Some(label))?;
+                    }
+                }
+                self.db_session_ids.insert(session_id, db_session_id);
+            }
+            
+            SessionEvent::SessionPaused { session_id, pause_duration } => {
+                debug!(session_id = session_id, pause_duration = pause_duration;
+                       "Session paused: {} for {} seconds", session_id, pause_duration);
+                let db_session_id = self.db_session_id(session_id)?;
+                self.store.update_session_pause_time(db_session_id, pause_duration)?;
+            }
+            
             SessionEvent::SessionFinalized { session_id, end_time, status } => {
-                debug!("Session finalized: {} with status: {}", session_id, status);
-                self.db.finalize_session(session_id, end_time, &status)?;
+                debug!(session_id = session_id, end_time = end_time, status = status.as_str();
+                       "Session finalized: {} with status: {}", session_id, status);
+                let db_session_id = self.db_session_id(session_id)?;
+                self.store.finalize_session(db_session_id, end_time, &status)?;
+                self.db_session_ids.remove(&session_id);
             }
         }
         Ok(())
     }
 
-    fn metadata_to_track(metadata: &Metadata) -> Track {
-        // Always generate a unique ID based on content to avoid issues with
-        // players that reuse MPRIS track IDs for different songs
-        let title = metadata.title().unwrap_or("Unknown");
-        let artist = metadata.artists()
-            .map(|artists| artists.join(", "))
-            .unwrap_or_else(|| "Unknown".to_string());
-        let album = metadata.album_name().unwrap_or("Unknown");
-        
-        // Create a content-based unique ID
-        let track_id = format!("{}::{}::{}", title, artist, album);
-
-        let track = Track {
-            id: track_id,
-            title: title.to_string(),
-            artist: artist,
-            album: album.to_string(),
-            length: metadata.length().map(|d| d.as_micros() as i64),
-            art_url: metadata.art_url().map(|url| url.to_string()),
-        };
-
-        debug!("Created track: {} - {} ({}) [ID: {}]", track.title, track.artist, track.album, track.id);
-        track
-    }
-
-    fn current_timestamp() -> i64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64
+    fn db_session_id(&self, session_id: i64) -> Result<i64> {
+        self.db_session_ids.get(&session_id).copied()
+            .with_context(|| format!("No database row recorded for session {}", session_id))
     }
 
     async fn check_for_sleep_resume(&mut self, current_time: i64) -> Result<()> {
@@ -442,27 +1213,154 @@ impl MprisMonitor {
         Ok(())
     }
 
-    async fn update_active_sessions(&mut self) -> Result<()> {
-        let current_time = Self::current_timestamp();
-        
+    async fn update_active_sessions_at(&mut self, current_time: i64) -> Result<()> {
         // Get all active sessions and update their progress in the database
         let active_sessions = self.session_tracker.get_active_sessions();
-        
+        let mut progress = Vec::with_capacity(active_sessions.len());
+
         for (player_id, session) in active_sessions {
             debug!("Updating progress for active session {} (player {})", session.session_id, player_id);
-            
-            if let Err(e) = self.db.update_active_session_progress(session.session_id, current_time) {
-                warn!("Failed to update progress for session {}: {}", session.session_id, e);
-            }
+
+            let Some(&db_session_id) = self.db_session_ids.get(&session.session_id) else {
+                continue;
+            };
+
+            let pending_pause_time = self.session_tracker.pending_pause_time(session, current_time);
+            progress.push((db_session_id, pending_pause_time));
         }
-        
+
+        if let Err(e) = self.store.update_active_sessions_progress(current_time, &progress) {
+            warn!("Failed to update progress for {} active sessions: {}", progress.len(), e);
+        }
+
         Ok(())
     }
 }
 
+fn metadata_to_track(metadata: &Metadata, options: &MonitorOptions) -> Track {
+    // Always generate a unique ID based on content to avoid issues with
+    // players that reuse MPRIS track IDs for different songs
+    let mut title = metadata.title().unwrap_or(MISSING_METADATA).to_string();
+    let mut artist = metadata.artists()
+        .map(|artists| artists.join(", "))
+        .unwrap_or_default();
+
+    // Web players and podcast apps often pack "Artist - Title" into the title
+    if artist == MISSING_METADATA {
+        if let Some((split_artist, split_title)) = options.metadata_rules.split_title(&title) {
+            debug!("Split title '{}' into artist '{}' and title '{}'", title, split_artist, split_title);
+            artist = split_artist;
+            title = split_title;
+        }
+    }
+
+    let primary_artist = options.artist_splitter.as_ref().map(|splitter| splitter.primary(&artist));
+
+    let album = metadata.album_name().unwrap_or(MISSING_METADATA);
+    let genre = metadata.get("xesam:genre").and_then(|value| match value.as_str_array() {
+        Some(genres) if !genres.is_empty() => Some(genres.join(", ")),
+        _ => value.as_str().map(|g| g.to_string()),
+    });
+    let comment = metadata.get("xesam:comment").and_then(|value| match value.as_str_array() {
+        Some(comments) if !comments.is_empty() => Some(comments.join("\n")),
+        _ => value.as_str().map(|c| c.to_string()),
+    });
+    
+    // A zero length would divide by zero in completion percentages. Negative lengths
+    // arrive wrapped around to huge ones, so the upper bound catches those too.
+    let max_length = options.max_track_length.unwrap_or(MAX_TRACK_LENGTH);
+    let length = metadata.length().filter(|length| !length.is_zero() && *length <= max_length);
+
+    // Create a content-based unique ID
+    let track_id = metadata_rules::track_id(&title, &artist, album, options.case_insensitive_track_ids);
+
+    let track = Track {
+        id: track_id,
+        title,
+        artist,
+        album: album.to_string(),
+        length: length.map(|d| d.as_micros() as i64),
+        art_url: metadata.art_url().map(|url| url.to_string()),
+        genre,
+        raw_metadata: options.store_raw_metadata.then(|| raw_metadata_json(metadata)),
+        track_number: metadata_number(metadata, "xesam:trackNumber"),
+        disc_number: metadata_number(metadata, "xesam:discNumber"),
+        primary_artist,
+        rating: metadata_rating(metadata),
+        comment,
+    };
+
+    debug!("Created track: {} - {} ({}) [ID: {}]", track.title, track.artist, track.album, track.id);
+    track
+}
+
+/// `xesam:userRating` is a float from 0.0 to 1.0; players that don't rate omit it,
+/// and out-of-range values are treated the same
+fn metadata_rating(metadata: &Metadata) -> Option<f64> {
+    match metadata.get("xesam:userRating")? {
+        MetadataValue::F64(rating) => Some(*rating).filter(|rating| (0.0..=1.0).contains(rating)),
+        _ => None,
+    }
+}
+
+/// Players disagree on the integer type of numeric fields, so accept any of them
+fn metadata_number(metadata: &Metadata, key: &str) -> Option<i32> {
+    match metadata.get(key)? {
+        MetadataValue::I16(n) => Some(i32::from(*n)),
+        MetadataValue::I32(n) => Some(*n),
+        MetadataValue::I64(n) => i32::try_from(*n).ok(),
+        MetadataValue::U8(n) => Some(i32::from(*n)),
+        MetadataValue::U16(n) => Some(i32::from(*n)),
+        MetadataValue::U32(n) => i32::try_from(*n).ok(),
+        MetadataValue::U64(n) => i32::try_from(*n).ok(),
+        _ => None,
+    }
+}
+
+/// Serialize the whole metadata map, with keys sorted so identical metadata gives identical JSON
+fn raw_metadata_json(metadata: &Metadata) -> String {
+    fn to_json(value: &MetadataValue) -> serde_json::Value {
+        match value {
+            MetadataValue::String(s) => serde_json::Value::from(s.as_str()),
+            MetadataValue::I16(n) => serde_json::Value::from(*n),
+            MetadataValue::I32(n) => serde_json::Value::from(*n),
+            MetadataValue::I64(n) => serde_json::Value::from(*n),
+            MetadataValue::U8(n) => serde_json::Value::from(*n),
+            MetadataValue::U16(n) => serde_json::Value::from(*n),
+            MetadataValue::U32(n) => serde_json::Value::from(*n),
+            MetadataValue::U64(n) => serde_json::Value::from(*n),
+            MetadataValue::F64(n) => serde_json::Value::from(*n),
+            MetadataValue::Bool(b) => serde_json::Value::from(*b),
+            MetadataValue::Array(values) => values.iter().map(to_json).collect(),
+            MetadataValue::Map(map) => map
+                .iter()
+                .map(|(key, value)| (key.clone(), to_json(value)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            MetadataValue::Unsupported => serde_json::Value::Null,
+        }
+    }
+
+    let map: BTreeMap<&str, serde_json::Value> = metadata
+        .iter()
+        .map(|(key, value)| (key, to_json(value)))
+        .collect();
+    serde_json::to_string(&map).unwrap_or_default()
+}
+
+fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{DryRunStore, InMemoryStore};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
     use tempfile::NamedTempFile;
 
     #[tokio::test]
@@ -471,12 +1369,894 @@ mod tests {
         let db = Database::new(temp_db.path()).unwrap();
         
         let monitor = MprisMonitor::new(db);
-        assert!(monitor.is_ok());
+        assert!(monitor.player_states.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_monitor_runs_without_dbus_and_connects_later() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+        let bus_up = Rc::new(Cell::new(false));
+
+        let connect = {
+            let source = source.clone();
+            let bus_up = bus_up.clone();
+            move || -> Result<Box<dyn PlayerSource>> {
+                if !bus_up.get() {
+                    return Err(GopalError::invalid("Failed to connect to the session bus"));
+                }
+                Ok(Box::new(source.clone()))
+            }
+        };
+        let mut monitor = in_memory_monitor_with(Box::new(ReconnectingSource::new(connect, Duration::ZERO)));
+
+        // No bus: nothing to see, but no error either
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.poll_players_at(1002).await.unwrap();
+        assert!(monitor.player_states.is_empty());
+
+        bus_up.set(true);
+        monitor.discover_players_at(1005).await.unwrap();
+        assert_eq!(monitor.player_states.len(), 1);
     }
 
     #[test]
     fn test_current_timestamp() {
-        let timestamp = MprisMonitor::current_timestamp();
+        let timestamp = current_timestamp();
         assert!(timestamp > 0);
     }
-}
\ No newline at end of file
+
+    /// Player source whose players can be changed between polls
+    #[derive(Clone, Default)]
+    struct MockPlayerSource {
+        players: Rc<RefCell<Vec<PlayerSnapshot>>>,
+    }
+
+    impl MockPlayerSource {
+        fn set_status(&self, bus_name: &str, status: PlaybackStatus) {
+            for player in self.players.borrow_mut().iter_mut() {
+                if player.bus_name == bus_name {
+                    player.status = status;
+                }
+            }
+        }
+    }
+
+    impl PlayerSource for MockPlayerSource {
+        fn players(&mut self) -> Result<Vec<PlayerSnapshot>> {
+            Ok(self.players.borrow().clone())
+        }
+    }
+
+    fn mock_metadata(title: &str, artist: &str) -> Metadata {
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String(title.to_string()));
+        values.insert("xesam:artist".to_string(),
+                      MetadataValue::Array(vec![MetadataValue::String(artist.to_string())]));
+        values.insert("xesam:album".to_string(), MetadataValue::String("Album".to_string()));
+        Metadata::from(values)
+    }
+
+    fn mock_player(bus_name: &str, status: PlaybackStatus, metadata: Metadata) -> PlayerSnapshot {
+        PlayerSnapshot {
+            bus_name: bus_name.to_string(),
+            identity: bus_name.to_string(),
+            status,
+            metadata: Some(metadata),
+            loop_status: None,
+            shuffle: None,
+        }
+    }
+
+    fn mock_monitor(source: &MockPlayerSource, options: MonitorOptions) -> (MprisMonitor, NamedTempFile) {
+        let temp_db = NamedTempFile::new().unwrap();
+        let db = Database::new(temp_db.path()).unwrap();
+        let monitor = MprisMonitor::with_source(db, Box::new(source.clone())).with_options(options);
+        (monitor, temp_db)
+    }
+
+    impl<S: Store> MprisMonitor<S> {
+        /// Apply queued session events to the store, as the monitoring loop would
+        async fn process_pending_events(&mut self) {
+            while let Some(event) = self.session_rx.as_mut().and_then(|rx| rx.try_recv().ok()) {
+                self.handle_session_event(event).await.unwrap();
+            }
+        }
+    }
+
+    fn sessions(db_path: &std::path::Path) -> Vec<(i64, String, i64, Option<i64>, String)> {
+        let db = Database::new(db_path).unwrap();
+        let mut sessions: Vec<_> = db.get_listening_stats(None, None).unwrap()
+            .listening_history
+            .into_iter()
+            .map(|s| (s.session.start_time, s.track.title, s.session.paused_time, s.session.listened_time, s.session.status))
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    fn total_listened(db_path: &std::path::Path) -> i64 {
+        let db = Database::new(db_path).unwrap();
+        db.get_listening_stats(None, None).unwrap().total_listening_time
+    }
+
+    #[test]
+    fn test_metadata_to_track_splits_unknown_artist() {
+        let options = MonitorOptions {
+            metadata_rules: MetadataRules::new(crate::metadata_rules::DEFAULT_TITLE_SPLIT_PATTERNS).unwrap(),
+            ..MonitorOptions::default()
+        };
+
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Some Band - Some Song".to_string()));
+        let track = metadata_to_track(&Metadata::from(values), &options);
+        assert_eq!(track.artist, "Some Band");
+        assert_eq!(track.title, "Some Song");
+        assert_eq!(track.id, "Some Song::Some Band::");
+
+        // A reported artist is never overridden
+        let track = metadata_to_track(&mock_metadata("Live - 1999", "Real Artist"), &options);
+        assert_eq!(track.artist, "Real Artist");
+        assert_eq!(track.title, "Live - 1999");
+
+        // Without rules the title is stored as reported
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Some Band - Some Song".to_string()));
+        let track = metadata_to_track(&Metadata::from(values), &MonitorOptions::default());
+        assert_eq!(track.artist, "");
+        assert_eq!(track.title, "Some Band - Some Song");
+    }
+
+    #[test]
+    fn test_metadata_to_track_normalizes_the_track_id() {
+        let track = metadata_to_track(&mock_metadata("  Some   Song ", "Artist\t"), &MonitorOptions::default());
+        assert_eq!(track.id, metadata_to_track(&mock_metadata("Some Song", "Artist"), &MonitorOptions::default()).id);
+        assert_eq!(track.id, "Some Song::Artist::Album");
+        // Shown as the player reported it
+        assert_eq!(track.title, "  Some   Song ");
+        assert_eq!(track.artist, "Artist\t");
+
+        // Case only counts unless case folding is on
+        let shouted = metadata_to_track(&mock_metadata("SOME SONG", "Artist"), &MonitorOptions::default());
+        assert_ne!(shouted.id, track.id);
+        let options = MonitorOptions { case_insensitive_track_ids: true, ..MonitorOptions::default() };
+        let shouted = metadata_to_track(&mock_metadata("SOME SONG", "Artist"), &options);
+        assert_eq!(shouted.id, metadata_to_track(&mock_metadata(" some song", "ARTIST"), &options).id);
+        assert_eq!(shouted.title, "SOME SONG");
+    }
+
+    #[test]
+    fn test_missing_metadata_is_distinct_from_unknown() {
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Unknown".to_string()));
+        let missing = metadata_to_track(&Metadata::from(values), &MonitorOptions::default());
+        assert_eq!((missing.artist.as_str(), missing.album.as_str()), (MISSING_METADATA, MISSING_METADATA));
+
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Unknown".to_string()));
+        values.insert("xesam:artist".to_string(), MetadataValue::Array(vec![MetadataValue::String("Unknown".to_string())]));
+        values.insert("xesam:album".to_string(), MetadataValue::String("Unknown".to_string()));
+        let real = metadata_to_track(&Metadata::from(values), &MonitorOptions::default());
+        assert_eq!(real.artist, "Unknown");
+        assert_ne!(real.id, missing.id);
+    }
+
+    #[test]
+    fn test_implausible_lengths_are_unknown() {
+        let length = |value: MetadataValue, options: &MonitorOptions| {
+            let mut values = HashMap::new();
+            values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+            values.insert("mpris:length".to_string(), value);
+            metadata_to_track(&Metadata::from(values), options).length
+        };
+        let defaults = MonitorOptions::default();
+
+        assert_eq!(length(MetadataValue::I64(180_000_000), &defaults), Some(180_000_000));
+        assert_eq!(length(MetadataValue::I64(0), &defaults), None);
+        assert_eq!(length(MetadataValue::I64(-5_000_000), &defaults), None);
+        assert_eq!(length(MetadataValue::U64(25 * 3600 * 1_000_000), &defaults), None);
+
+        let hour = MonitorOptions { max_track_length: Some(Duration::from_secs(3600)), ..MonitorOptions::default() };
+        assert_eq!(length(MetadataValue::I64(2 * 3600 * 1_000_000), &hour), None);
+        assert_eq!(length(MetadataValue::I64(180_000_000), &hour), Some(180_000_000));
+    }
+
+    #[test]
+    fn test_metadata_to_track_primary_artist() {
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+        values.insert("xesam:artist".to_string(), MetadataValue::Array(vec![
+            MetadataValue::String("A".to_string()),
+            MetadataValue::String("B feat. C".to_string()),
+        ]));
+        let metadata = Metadata::from(values);
+
+        let options = MonitorOptions { artist_splitter: Some(ArtistSplitter::default()), ..MonitorOptions::default() };
+        let track = metadata_to_track(&metadata, &options);
+        assert_eq!(track.artist, "A, B feat. C");
+        assert_eq!(track.primary_artist.as_deref(), Some("A"));
+
+        let track = metadata_to_track(&metadata, &MonitorOptions::default());
+        assert_eq!(track.primary_artist, None);
+    }
+
+    #[tokio::test]
+    async fn test_session_label_from_player_rule() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.vlc", PlaybackStatus::Playing, mock_metadata("Other", "Artist")));
+
+        let options = MonitorOptions {
+            session_labels: SessionLabelRules::default().with_player("spotify", "focus"),
+            ..MonitorOptions::default()
+        };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let db = Database::new(temp_db.path()).unwrap();
+        let mut labels: Vec<(String, Option<String>)> = db.list_active_sessions().unwrap()
+            .into_iter()
+            .map(|s| (s.track.title, s.session.label))
+            .collect();
+        labels.sort();
+        assert_eq!(labels, vec![("Other".to_string(), None), ("Song".to_string(), Some("focus".to_string()))]);
+    }
+
+    #[tokio::test]
+    async fn test_raw_metadata_is_stored_when_enabled() {
+        let source = MockPlayerSource::default();
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+        values.insert("xesam:artist".to_string(),
+                      MetadataValue::Array(vec![MetadataValue::String("Artist".to_string())]));
+        values.insert("xesam:trackNumber".to_string(), MetadataValue::I32(7));
+        values.insert("mpris:length".to_string(), MetadataValue::I64(180_000_000));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, Metadata::from(values)));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions { store_raw_metadata: true, ..MonitorOptions::default() });
+        monitor.discover_players_at(1000).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1100).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let db = Database::new(temp_db.path()).unwrap();
+        let history = db.get_listening_stats(None, None).unwrap().listening_history;
+        let raw = history[0].track.raw_metadata.as_deref().expect("raw metadata stored");
+        let raw: serde_json::Value = serde_json::from_str(raw).unwrap();
+        assert_eq!(raw["xesam:title"], "Song");
+        assert_eq!(raw["xesam:artist"], serde_json::json!(["Artist"]));
+        assert_eq!(raw["xesam:trackNumber"], 7);
+        assert_eq!(raw["mpris:length"], 180_000_000);
+
+        // Off by default
+        let track = metadata_to_track(&mock_metadata("Song", "Artist"), &MonitorOptions::default());
+        assert_eq!(track.raw_metadata, None);
+    }
+
+    #[tokio::test]
+    async fn test_raw_metadata_is_pruned_only_while_stored() {
+        let source = MockPlayerSource::default();
+        let options = MonitorOptions { max_raw_metadata_rows: 1, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options.clone());
+        let db = Database::new(temp_db.path()).unwrap();
+        for title in ["One", "Two"] {
+            let track = Track { raw_metadata: Some("{}".to_string()), ..metadata_to_track(&mock_metadata(title, "Artist"), &options) };
+            db.insert_or_update_track(&track).unwrap();
+        }
+        let stored = || -> i64 {
+            rusqlite::Connection::open(temp_db.path()).unwrap()
+                .query_row("SELECT COUNT(raw_metadata) FROM tracks", [], |row| row.get(0)).unwrap()
+        };
+
+        // Metadata kept from when storage was on stays while it's off
+        monitor.cleanup_sessions_at(1000).await;
+        assert_eq!(stored(), 2);
+
+        monitor.options.store_raw_metadata = true;
+        monitor.cleanup_sessions_at(1000).await;
+        assert_eq!(stored(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_track_and_disc_numbers_are_stored() {
+        let source = MockPlayerSource::default();
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+        values.insert("xesam:album".to_string(), MetadataValue::String("Album".to_string()));
+        values.insert("xesam:trackNumber".to_string(), MetadataValue::I32(7));
+        // Some players send the disc number with a different integer type
+        values.insert("xesam:discNumber".to_string(), MetadataValue::U32(2));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, Metadata::from(values)));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let db = Database::new(temp_db.path()).unwrap();
+        let tracks = db.get_album_tracks("Album", None).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].track.track_number, Some(7));
+        assert_eq!(tracks[0].track.disc_number, Some(2));
+
+        // Players that don't report them leave the columns empty
+        let track = metadata_to_track(&mock_metadata("Other", "Artist"), &MonitorOptions::default());
+        assert_eq!((track.track_number, track.disc_number), (None, None));
+    }
+
+    #[test]
+    fn test_rating_and_comment_are_captured() {
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+        values.insert("xesam:userRating".to_string(), MetadataValue::F64(0.8));
+        values.insert("xesam:comment".to_string(),
+                      MetadataValue::Array(vec![MetadataValue::String("Live".to_string()), MetadataValue::String("1979".to_string())]));
+        let track = metadata_to_track(&Metadata::from(values), &MonitorOptions::default());
+        assert_eq!(track.rating, Some(0.8));
+        assert_eq!(track.comment.as_deref(), Some("Live\n1979"));
+
+        // Players that don't rate, or send nonsense, leave them empty
+        let track = metadata_to_track(&mock_metadata("Other", "Artist"), &MonitorOptions::default());
+        assert_eq!((track.rating, track.comment), (None, None));
+        let mut values = HashMap::new();
+        values.insert("xesam:userRating".to_string(), MetadataValue::F64(5.0));
+        assert_eq!(metadata_to_track(&Metadata::from(values), &MonitorOptions::default()).rating, None);
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_players_are_not_double_counted() {
+        let source = MockPlayerSource::default();
+        let song = mock_metadata("Same Song", "Same Artist");
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Playing, song.clone()));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, song));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions { dedup_simultaneous: true, ..MonitorOptions::default() });
+
+        monitor.discover_players_at(1000).await.unwrap();
+        assert_eq!(monitor.session_tracker.get_active_session_count(), 1);
+
+        // One player stops; the other keeps playing and takes over recording
+        source.set_status("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1120).await.unwrap();
+        assert_eq!(monitor.session_tracker.get_active_session_count(), 1);
+
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1180).await.unwrap();
+        monitor.process_pending_events().await;
+
+        // 180 seconds of wall-clock listening, not 120 + 180
+        assert_eq!(total_listened(temp_db.path()), 180);
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_players_counted_separately_without_dedup() {
+        let source = MockPlayerSource::default();
+        let song = mock_metadata("Same Song", "Same Artist");
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Playing, song.clone()));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, song));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+
+        monitor.discover_players_at(1000).await.unwrap();
+        assert_eq!(monitor.session_tracker.get_active_session_count(), 2);
+
+        source.set_status("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Stopped);
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1100).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(total_listened(temp_db.path()), 200);
+    }
+
+    /// Simulate the daemon being killed: nothing is finalized, and a fresh monitor opens the same database
+    fn restart(source: &MockPlayerSource, db_path: &std::path::Path) -> MprisMonitor {
+        let db = Database::new(db_path).unwrap();
+        MprisMonitor::with_source(db, Box::new(source.clone()))
+    }
+
+    #[tokio::test]
+    async fn test_player_reappearing_on_same_track_keeps_session() {
+        let source = MockPlayerSource::default();
+        let player = mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist"));
+        source.players.borrow_mut().push(player.clone());
+
+        let options = MonitorOptions { reappear_grace_period: 10, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+
+        // The player restarts: gone for 3 seconds, then back on the same track
+        source.players.borrow_mut().clear();
+        monitor.poll_players_at(1100).await.unwrap();
+        source.players.borrow_mut().push(player);
+        monitor.discover_players_at(1103).await.unwrap();
+
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Song".to_string(), 3, Some(197), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_player_gone_past_grace_period_is_finalized() {
+        let source = MockPlayerSource::default();
+        let player = mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist"));
+        source.players.borrow_mut().push(player.clone());
+
+        let options = MonitorOptions { reappear_grace_period: 10, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+
+        source.players.borrow_mut().clear();
+        monitor.poll_players_at(1100).await.unwrap();
+        monitor.poll_players_at(1120).await.unwrap();
+
+        // Coming back after the grace period starts a new session
+        source.players.borrow_mut().push(player);
+        monitor.discover_players_at(1130).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let recorded = sessions(temp_db.path());
+        assert_eq!(recorded.len(), 2);
+        assert_eq!((recorded[0].0, recorded[0].3, recorded[0].4.as_str()), (1000, Some(100), "completed"));
+        assert_eq!((recorded[1].0, recorded[1].4.as_str()), (1130, "active"));
+    }
+
+    #[tokio::test]
+    async fn test_restart_resumes_still_playing_session() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+        monitor.update_active_sessions_at(1100).await.unwrap();
+        drop(monitor);
+
+        // Back up 5 seconds later with the same track still playing
+        let mut monitor = restart(&source, temp_db.path());
+        monitor.discover_players_at(1105).await.unwrap();
+        monitor.process_pending_events().await;
+        assert_eq!(monitor.session_tracker.get_active_session_count(), 1);
+
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+
+        // One continuous session; only the downtime is missing from the listening
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Song".to_string(), 5, Some(195), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_yields_events_without_recording() {
+        use tokio_stream::StreamExt;
+
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let (monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        let mut events = monitor.event_stream().unwrap();
+
+        // Discovery runs as soon as the stream is polled and starts a session for the playing player
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await.unwrap().unwrap();
+        let SessionEvent::SessionStarted { track, .. } = event else {
+            panic!("expected SessionStarted, got {:?}", event);
+        };
+        assert_eq!(track.title, "Song");
+
+        assert!(sessions(temp_db.path()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flapping_title_keeps_one_session() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.radio", PlaybackStatus::Playing, mock_metadata("Now playing: Song", "Radio")));
+
+        let options = MonitorOptions { min_metadata_change_interval: 5, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+
+        // The title scrolls every second while the artist stays the same
+        let frames = ["ow playing: Song N", "w playing: Song No", " playing: Song Now"];
+        for (i, frame) in frames.iter().cycle().take(30).enumerate() {
+            source.players.borrow_mut()[0].metadata = Some(mock_metadata(frame, "Radio"));
+            monitor.poll_players_at(1001 + i as i64).await.unwrap();
+        }
+
+        source.set_status("org.mpris.MediaPlayer2.radio", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1040).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Now playing: Song".to_string(), 0, Some(40), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_title_change_after_interval_starts_new_session() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let options = MonitorOptions { min_metadata_change_interval: 5, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Next Song", "Artist"));
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let titles: Vec<String> = sessions(temp_db.path()).into_iter().map(|s| s.1).collect();
+        assert_eq!(titles, vec!["Song", "Next Song"]);
+    }
+
+    /// Play a track, stop it at 1100 and start it again at 1110 until 1200
+    async fn play_with_restart(monitor: &mut MprisMonitor, source: &MockPlayerSource) {
+        monitor.discover_players_at(1000).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1100).await.unwrap();
+        monitor.process_pending_events().await;
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing);
+        monitor.poll_players_at(1110).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+    }
+
+    #[tokio::test]
+    async fn test_replay_within_gap_extends_previous_session() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let options = MonitorOptions { merge_consecutive_same_track_gap: 30, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        play_with_restart(&mut monitor, &source).await;
+
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Song".to_string(), 10, Some(190), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_outside_gap_starts_new_session() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let options = MonitorOptions { merge_consecutive_same_track_gap: 5, ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        play_with_restart(&mut monitor, &source).await;
+
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Song".to_string(), 0, Some(100), "completed".to_string()),
+            (1110, "Song".to_string(), 0, Some(90), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_repeat_one_loops_count_as_one_play() {
+        let repeating = |merge_repeat_one: bool| async move {
+            let source = MockPlayerSource::default();
+            let mut player = mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist"));
+            player.loop_status = Some(LoopStatus::Track);
+            source.players.borrow_mut().push(player);
+
+            let options = MonitorOptions { merge_repeat_one, ..MonitorOptions::default() };
+            let (mut monitor, temp_db) = mock_monitor(&source, options);
+            play_with_restart(&mut monitor, &source).await;
+            sessions(temp_db.path())
+        };
+
+        assert_eq!(repeating(true).await, vec![
+            (1000, "Song".to_string(), 10, Some(190), "completed".to_string()),
+        ]);
+        assert_eq!(repeating(false).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_past_the_max_duration_are_capped() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Stream", "Artist")));
+
+        let options = MonitorOptions { max_reasonable_duration: Some(Duration::from_secs(600)), ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+        for time in (1200..=2000).step_by(200) {
+            monitor.poll_players_at(time).await.unwrap();
+        }
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        monitor.poll_players_at(2100).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(sessions(temp_db.path()), vec![(1000, "Stream".to_string(), 0, Some(600), "capped".to_string())]);
+        let history = Database::new(temp_db.path()).unwrap().get_listening_stats(None, None).unwrap().listening_history;
+        assert_eq!(history[0].session.end_time, Some(1600));
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_recorded_while_recording_is_paused() {
+        let dir = tempfile::tempdir().unwrap();
+        let pause_file = dir.path().join("recording-paused");
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let options = MonitorOptions { recording_pause_file: Some(pause_file.clone()), ..MonitorOptions::default() };
+        let (mut monitor, temp_db) = mock_monitor(&source, options);
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.poll_players_at(1100).await.unwrap();
+
+        // Pausing ends the session in progress, and nothing played meanwhile is recorded
+        std::fs::write(&pause_file, "").unwrap();
+        monitor.poll_players_at(1200).await.unwrap();
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Private Song", "Artist"));
+        monitor.poll_players_at(1300).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Paused);
+        monitor.poll_players_at(1400).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing);
+        monitor.poll_players_at(1500).await.unwrap();
+        monitor.process_pending_events().await;
+        assert_eq!(sessions(temp_db.path()).len(), 1);
+
+        // The track still playing is picked up on resuming
+        std::fs::remove_file(&pause_file).unwrap();
+        monitor.poll_players_at(1600).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let recorded = sessions(temp_db.path());
+        assert_eq!(recorded.len(), 2);
+        assert_eq!((recorded[0].0, recorded[0].1.as_str(), recorded[0].3), (1000, "Song", Some(200)));
+        assert_eq!((recorded[1].0, recorded[1].1.as_str(), recorded[1].4.as_str()), (1600, "Private Song", "active"));
+    }
+
+    #[tokio::test]
+    async fn test_restart_closes_session_for_changed_track() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let (mut monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+        monitor.update_active_sessions_at(1100).await.unwrap();
+        drop(monitor);
+
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Next Song", "Artist"));
+        let mut monitor = restart(&source, temp_db.path());
+        monitor.discover_players_at(1130).await.unwrap();
+        monitor.process_pending_events().await;
+
+        // The old session ends at its last checkpoint instead of stretching over the downtime
+        let recorded = sessions(temp_db.path());
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0], (1000, "Song".to_string(), 0, Some(100), "orphaned".to_string()));
+        assert_eq!((recorded[1].0, recorded[1].1.as_str(), recorded[1].4.as_str()), (1130, "Next Song", "active"));
+    }
+
+    fn in_memory_monitor(source: &MockPlayerSource) -> MprisMonitor<InMemoryStore> {
+        in_memory_monitor_with(Box::new(source.clone()))
+    }
+
+    fn in_memory_monitor_with(source: Box<dyn PlayerSource>) -> MprisMonitor<InMemoryStore> {
+        MprisMonitor::with_source(InMemoryStore::new(), source)
+    }
+
+    fn recorded(monitor: &MprisMonitor<InMemoryStore>) -> Vec<(i64, String, i64, Option<i64>, String)> {
+        monitor.store().sessions()
+            .into_iter()
+            .map(|s| {
+                let title = monitor.store().track(&s.track_id).unwrap().title;
+                (s.start_time, title, s.paused_time, s.listened_time, s.status)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_records_pause_and_track_change() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let mut monitor = in_memory_monitor(&source);
+        monitor.discover_players_at(1000).await.unwrap();
+
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Paused);
+        monitor.poll_players_at(1060).await.unwrap();
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing);
+        monitor.poll_players_at(1090).await.unwrap();
+
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Next Song", "Artist"));
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(recorded(&monitor), vec![
+            (1000, "Song".to_string(), 30, Some(170), "completed".to_string()),
+            (1200, "Next Song".to_string(), 0, None, "active".to_string()),
+        ]);
+        assert_eq!(monitor.store().players().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_paused_past_track_end_completes_at_pause() {
+        let source = MockPlayerSource::default();
+        let mut values = HashMap::new();
+        values.insert("xesam:title".to_string(), MetadataValue::String("Song".to_string()));
+        values.insert("xesam:artist".to_string(),
+                      MetadataValue::Array(vec![MetadataValue::String("Artist".to_string())]));
+        values.insert("mpris:length".to_string(), MetadataValue::I64(180_000_000));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, Metadata::from(values)));
+        let mut monitor = in_memory_monitor(&source).with_options(MonitorOptions {
+            paused_past_end_margin: 60,
+            ..MonitorOptions::default()
+        });
+        monitor.discover_players_at(1000).await.unwrap();
+
+        // The player pauses at the end of the track instead of stopping
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Paused);
+        monitor.poll_players_at(1178).await.unwrap();
+
+        // 2s left plus the margin hasn't passed yet
+        monitor.cleanup_sessions_at(1230).await;
+        monitor.process_pending_events().await;
+        assert_eq!(recorded(&monitor)[0].4, "active");
+
+        monitor.cleanup_sessions_at(1260).await;
+        monitor.process_pending_events().await;
+        assert_eq!(recorded(&monitor), vec![
+            (1000, "Song".to_string(), 0, Some(178), "completed".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_checkpoints_active_sessions() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.vlc", PlaybackStatus::Playing, mock_metadata("Other Song", "Other Artist")));
+
+        let mut monitor = in_memory_monitor(&source);
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+        monitor.update_active_sessions_at(1100).await.unwrap();
+
+        let active = monitor.store().list_active_sessions().unwrap();
+        assert_eq!(active.len(), 2);
+        assert!(active.iter().all(|s| s.session.listened_time == Some(100)));
+    }
+
+    #[tokio::test]
+    async fn test_current_tracks_follow_the_session_tracker() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let mut monitor = in_memory_monitor(&source);
+        assert!(monitor.current_tracks_at(1000).is_empty());
+        monitor.discover_players_at(1000).await.unwrap();
+
+        let current = monitor.current_tracks_at(1090);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].player.name, "org.mpris.MediaPlayer2.spotify");
+        assert_eq!(current[0].track.title, "Song");
+        assert_eq!((current[0].elapsed, current[0].paused), (90, false));
+
+        // Elapsed stops while paused
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Paused);
+        monitor.poll_players_at(1100).await.unwrap();
+        let current = monitor.current_tracks_at(1160);
+        assert_eq!((current[0].elapsed, current[0].paused), (100, true));
+
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Next Song", "Artist"));
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing);
+        monitor.poll_players_at(1200).await.unwrap();
+        let current = monitor.current_tracks_at(1230);
+        assert_eq!(current.len(), 1);
+        assert_eq!((current[0].track.title.as_str(), current[0].elapsed), ("Next Song", 30));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_leaves_database_untouched() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let temp_db = NamedTempFile::new().unwrap();
+        Database::new(temp_db.path()).unwrap();
+
+        let mut monitor = MprisMonitor::with_source(DryRunStore::new(), Box::new(source.clone()));
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+        monitor.update_active_sessions_at(1060).await.unwrap();
+        source.players.borrow_mut()[0].metadata = Some(mock_metadata("Next Song", "Artist"));
+        monitor.poll_players_at(1200).await.unwrap();
+        monitor.process_pending_events().await;
+
+        // The pipeline ran in full...
+        let recorded = monitor.store().recorded().sessions();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].listened_time, Some(200));
+
+        // ...but nothing reached the database
+        let stats = Database::new(temp_db.path()).unwrap().get_database_stats().unwrap();
+        assert_eq!((stats.total_sessions, stats.total_tracks, stats.total_players), (0, 0, 0));
+    }
+
+    #[derive(Clone, Default)]
+    struct MockLockSource {
+        locked: Rc<RefCell<bool>>,
+    }
+
+    impl LockSource for MockLockSource {
+        fn is_locked(&mut self) -> Result<bool> {
+            Ok(*self.locked.borrow())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locked_interval_counts_as_pause() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+
+        let lock = MockLockSource::default();
+        let (monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        let mut monitor = monitor.with_lock_source(Box::new(lock.clone()));
+
+        monitor.discover_players_at(1000).await.unwrap();
+
+        // The browser keeps reporting Playing while the screen is locked
+        *lock.locked.borrow_mut() = true;
+        monitor.poll_players_at(1100).await.unwrap();
+        *lock.locked.borrow_mut() = false;
+        monitor.poll_players_at(1400).await.unwrap();
+
+        source.set_status("org.mpris.MediaPlayer2.firefox", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1500).await.unwrap();
+        monitor.process_pending_events().await;
+
+        assert_eq!(sessions(temp_db.path()), vec![
+            (1000, "Song".to_string(), 300, Some(200), "completed".to_string()),
+        ]);
+    }
+
+    #[derive(Clone, Default)]
+    struct MockFocusSource {
+        focused: Rc<RefCell<Option<String>>>,
+    }
+
+    impl FocusSource for MockFocusSource {
+        fn focused_application(&mut self) -> crate::focus::FocusFuture<'_> {
+            let focused = self.focused.borrow().clone();
+            Box::pin(async move { Ok(focused) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_tagged_by_player_focus() {
+        let source = MockPlayerSource::default();
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Playing, mock_metadata("Song", "Artist")));
+        source.players.borrow_mut().push(mock_player("org.mpris.MediaPlayer2.vlc", PlaybackStatus::Playing, mock_metadata("Other", "Artist")));
+
+        let focus = MockFocusSource::default();
+        let (monitor, temp_db) = mock_monitor(&source, MonitorOptions::default());
+        let mut monitor = monitor.with_focus_source(Box::new(focus.clone()));
+
+        monitor.discover_players_at(1000).await.unwrap();
+        monitor.process_pending_events().await;
+
+        // Both start out in the background, and Spotify stays foreground once focused
+        monitor.poll_players_at(1100).await.unwrap();
+        *focus.focused.borrow_mut() = Some("Spotify".to_string());
+        monitor.poll_players_at(1200).await.unwrap();
+        *focus.focused.borrow_mut() = Some("Alacritty".to_string());
+        monitor.poll_players_at(1300).await.unwrap();
+
+        source.set_status("org.mpris.MediaPlayer2.spotify", PlaybackStatus::Stopped);
+        source.set_status("org.mpris.MediaPlayer2.vlc", PlaybackStatus::Stopped);
+        monitor.poll_players_at(1400).await.unwrap();
+        monitor.process_pending_events().await;
+
+        let db = Database::new(temp_db.path()).unwrap();
+        let mut focus: Vec<(String, i64)> = db.get_grouped_stats(crate::database::GroupBy::Focus, None, None, &Default::default(), 10).unwrap()
+            .into_iter()
+            .map(|group| (group.label, group.play_count))
+            .collect();
+        focus.sort();
+        assert_eq!(focus, vec![("background".to_string(), 1), ("foreground".to_string(), 1)]);
+    }
+}
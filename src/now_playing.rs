@@ -0,0 +1,334 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{metadata_label, SessionWithMetadata};
+use crate::display::truncate_display;
+
+/// Template used when no `--format-template` is given
+pub const DEFAULT_NOW_PLAYING_TEMPLATE: &str = "{artist} - {title} ({elapsed}/{length})";
+
+/// Rendered in place of optional fields the player didn't report
+const MISSING_VALUE: &str = "-";
+
+/// Snapshot of what a player is currently playing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowPlaying {
+    pub session_id: i64,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub player: String,
+    /// Seconds listened so far in this session, frozen while it's paused
+    pub elapsed: i64,
+    /// Track length in seconds, if the player reports it
+    pub length: Option<i64>,
+    /// Elapsed as a percentage of the track length
+    pub percent: Option<f64>,
+    /// Seconds of continuous listening across track changes, see `get_current_block_seconds`
+    #[serde(default)]
+    pub current_block_seconds: Option<i64>,
+    /// Whether the player was paused at the daemon's last checkpoint
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl NowPlaying {
+    /// Snapshot of an active session at `current_time`. Elapsed is listened time: pauses
+    /// are left out, including the current one, unless `include_paused` asks for the
+    /// wall-clock time since the session started.
+    pub fn from_session(session: &SessionWithMetadata, current_time: i64, include_paused: bool) -> Self {
+        let paused_since = session.session.paused_since;
+        let elapsed = if include_paused {
+            current_time - session.session.start_time
+        } else {
+            paused_since.unwrap_or(current_time).min(current_time) - session.session.start_time - session.session.paused_time
+        }.max(0);
+        let length = session.track.length.map(|micros| micros / 1_000_000);
+        let percent = length
+            .filter(|&len| len > 0)
+            .map(|len| (elapsed as f64 / len as f64 * 100.0).min(100.0));
+
+        NowPlaying {
+            session_id: session.session.id,
+            title: session.track.title.clone(),
+            artist: session.track.artist.clone(),
+            album: session.track.album.clone(),
+            player: session.player.identity.clone(),
+            elapsed,
+            length,
+            percent,
+            current_block_seconds: None,
+            paused: paused_since.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    Player,
+    Elapsed,
+    Length,
+    Percent,
+    Block,
+}
+
+impl Field {
+    const NAMES: [&'static str; 8] = ["title", "artist", "album", "player", "elapsed", "length", "percent", "block"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "player" => Some(Field::Player),
+            "elapsed" => Some(Field::Elapsed),
+            "length" => Some(Field::Length),
+            "percent" => Some(Field::Percent),
+            "block" => Some(Field::Block),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// A field, cut to at most this many terminal columns if a width was given
+    Field(Field, Option<usize>),
+}
+
+/// A parsed now-playing format string such as `"{artist} - {title:30}"`
+#[derive(Debug, Clone)]
+pub struct NowPlayingTemplate {
+    segments: Vec<Segment>,
+}
+
+impl NowPlayingTemplate {
+    /// Parse a template, rejecting unknown or unterminated placeholders
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '{' {
+                literal.push(c);
+                continue;
+            }
+
+            let mut name = String::new();
+            let mut closed = false;
+            for c in chars.by_ref() {
+                if c == '}' {
+                    closed = true;
+                    break;
+                }
+                name.push(c);
+            }
+
+            if !closed {
+                bail!("Unterminated placeholder '{{{}' in template", name);
+            }
+
+            let (field_name, width) = match name.split_once(':') {
+                Some((field_name, width)) => match width.trim().parse::<usize>() {
+                    Ok(width) => (field_name, Some(width)),
+                    Err(_) => bail!("Invalid width in placeholder '{{{}}}', expected e.g. {{title:30}}", name),
+                },
+                None => (name.as_str(), None),
+            };
+
+            let field = match Field::from_name(field_name.trim()) {
+                Some(field) => field,
+                None => bail!(
+                    "Unknown placeholder '{{{}}}' in template. Available: {}",
+                    name,
+                    Field::NAMES.map(|n| format!("{{{}}}", n)).join(", ")
+                ),
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Field(field, width));
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(NowPlayingTemplate { segments })
+    }
+
+    pub fn render(&self, now: &NowPlaying) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => output.push_str(text),
+                Segment::Field(field, None) => output.push_str(&Self::field_value(*field, now)),
+                Segment::Field(field, Some(width)) => {
+                    output.push_str(&truncate_display(&Self::field_value(*field, now), *width))
+                }
+            }
+        }
+        output
+    }
+
+    fn field_value(field: Field, now: &NowPlaying) -> String {
+        match field {
+            Field::Title => metadata_label(&now.title).to_string(),
+            Field::Artist => metadata_label(&now.artist).to_string(),
+            Field::Album => metadata_label(&now.album).to_string(),
+            Field::Player => now.player.clone(),
+            Field::Elapsed => format_clock(now.elapsed),
+            Field::Length => now.length.map(format_clock).unwrap_or_else(|| MISSING_VALUE.to_string()),
+            Field::Percent => now
+                .percent
+                .map(|p| format!("{:.0}%", p))
+                .unwrap_or_else(|| MISSING_VALUE.to_string()),
+            Field::Block => now
+                .current_block_seconds
+                .map(format_clock)
+                .unwrap_or_else(|| MISSING_VALUE.to_string()),
+        }
+    }
+}
+
+impl Default for NowPlayingTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_NOW_PLAYING_TEMPLATE).expect("default template is valid")
+    }
+}
+
+/// Format seconds as `m:ss`, or `h:mm:ss` for anything over an hour
+fn format_clock(seconds: i64) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let secs = seconds % 60;
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Player, Session, Track};
+
+    fn sample(length: Option<i64>) -> NowPlaying {
+        NowPlaying {
+            session_id: 1,
+            title: "Song".to_string(),
+            artist: "Band".to_string(),
+            album: "Record".to_string(),
+            player: "Spotify".to_string(),
+            elapsed: 83,
+            length,
+            percent: length.map(|len| 83.0 / len as f64 * 100.0),
+            current_block_seconds: Some(4000),
+            paused: false,
+        }
+    }
+
+    fn active_session(paused_time: i64, paused_since: Option<i64>) -> SessionWithMetadata {
+        SessionWithMetadata {
+            session: Session {
+                id: 1,
+                track_id: "Song::Band::Record".to_string(),
+                player_id: 1,
+                start_time: 1000,
+                end_time: None,
+                paused_time,
+                listened_time: None,
+                status: "active".to_string(),
+                label: None,
+                paused_since,
+                content_type: None,
+            },
+            track: Track {
+                id: "Song::Band::Record".to_string(),
+                title: "Song".to_string(),
+                artist: "Band".to_string(),
+                album: "Record".to_string(),
+                length: Some(200_000_000),
+                art_url: None,
+                genre: None,
+                raw_metadata: None,
+                track_number: None,
+                disc_number: None,
+                primary_artist: None,
+                rating: None,
+                comment: None,
+            },
+            player: Player { id: 1, name: "org.mpris.MediaPlayer2.spotify".to_string(), identity: "Spotify".to_string() },
+        }
+    }
+
+    #[test]
+    fn test_elapsed_leaves_out_pauses() {
+        // Playing, after an earlier 20s pause
+        let playing = NowPlaying::from_session(&active_session(20, None), 1100, false);
+        assert_eq!((playing.elapsed, playing.percent, playing.paused), (80, Some(40.0), false));
+
+        // Paused since 1050: elapsed stays put however long the pause runs
+        let paused = active_session(20, Some(1050));
+        for now in [1060, 1100, 5000] {
+            let snapshot = NowPlaying::from_session(&paused, now, false);
+            assert_eq!((snapshot.elapsed, snapshot.paused), (30, true));
+        }
+
+        // Unless asked for wall-clock time
+        assert_eq!(NowPlaying::from_session(&paused, 1100, true).elapsed, 100);
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = NowPlayingTemplate::default();
+        assert_eq!(template.render(&sample(Some(200))), "Band - Song (1:23/3:20)");
+    }
+
+    #[test]
+    fn test_render_all_fields() {
+        let template = NowPlayingTemplate::parse("{player}: {title} / {album} [{percent}]").unwrap();
+        assert_eq!(template.render(&sample(Some(166))), "Spotify: Song / Record [50%]");
+    }
+
+    #[test]
+    fn test_render_missing_length() {
+        let template = NowPlayingTemplate::parse("{elapsed}/{length} {percent}").unwrap();
+        assert_eq!(template.render(&sample(None)), "1:23/- -");
+    }
+
+    #[test]
+    fn test_render_block() {
+        let template = NowPlayingTemplate::parse("{title}, listening for {block}").unwrap();
+        assert_eq!(template.render(&sample(None)), "Song, listening for 1:06:40");
+    }
+
+    #[test]
+    fn test_render_field_width() {
+        let mut now = sample(Some(200));
+        now.title = "千と千尋の神隠し".to_string();
+        let template = NowPlayingTemplate::parse("{artist:10} - {title:7}").unwrap();
+        assert_eq!(template.render(&now), "Band - 千と千…");
+
+        assert!(NowPlayingTemplate::parse("{title:wide}").is_err());
+    }
+
+    #[test]
+    fn test_unknown_placeholder_errors() {
+        let err = NowPlayingTemplate::parse("{artist} - {song}").unwrap_err();
+        assert!(err.to_string().contains("{song}"));
+        assert!(err.to_string().contains("{title}"));
+    }
+
+    #[test]
+    fn test_unterminated_placeholder_errors() {
+        assert!(NowPlayingTemplate::parse("{artist").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Application directory name under the XDG base directories
+const APP_DIR: &str = "gopal";
+
+/// Database file name inside the data directory
+pub const DATABASE_FILE: &str = "music.db";
+
+/// Configuration file name inside the config directory
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Daemon status file name, see `daemon_status`
+pub const STATUS_FILE: &str = "gopald.status";
+
+/// Recording pause file name, see `gopal-cli record`
+pub const RECORDING_PAUSE_FILE: &str = "recording-paused";
+
+/// Where `gopal-cli stats --since-last` left off, inside the config directory
+pub const STATS_CHECKPOINT_FILE: &str = "stats-checkpoint";
+
+/// Expand a leading `~` to the user's home directory
+pub fn expand_path(path: &str) -> Result<PathBuf> {
+    if path.starts_with('~') {
+        let home = std::env::var("HOME")
+            .context("HOME environment variable not set")?;
+        Ok(PathBuf::from(path.replacen('~', &home, 1)))
+    } else {
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// Directory for gopal's data: `$XDG_DATA_HOME/gopal`, or `~/.local/share/gopal`
+pub fn resolve_data_path() -> Result<PathBuf> {
+    xdg_app_dir(env_var("XDG_DATA_HOME"), env_var("HOME"), ".local/share")
+}
+
+/// Directory for gopal's configuration: `$XDG_CONFIG_HOME/gopal`, or `~/.config/gopal`
+pub fn resolve_config_path() -> Result<PathBuf> {
+    xdg_app_dir(env_var("XDG_CONFIG_HOME"), env_var("HOME"), ".config")
+}
+
+/// Database used when no `--database` is given
+pub fn default_database_path() -> Result<PathBuf> {
+    Ok(resolve_data_path()?.join(DATABASE_FILE))
+}
+
+/// Configuration file used when no `--config` is given
+pub fn default_config_file() -> Result<PathBuf> {
+    Ok(resolve_config_path()?.join(CONFIG_FILE))
+}
+
+/// Checkpoint file for `gopal-cli stats --since-last`
+pub fn default_stats_checkpoint_file() -> Result<PathBuf> {
+    Ok(resolve_config_path()?.join(STATS_CHECKPOINT_FILE))
+}
+
+/// Status file used when none is configured: `$XDG_RUNTIME_DIR/gopal/gopald.status`,
+/// or the data directory when there is no runtime directory
+pub fn default_status_file() -> Result<PathBuf> {
+    let dir = match env_var("XDG_RUNTIME_DIR").map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        Some(runtime_dir) => runtime_dir.join(APP_DIR),
+        None => resolve_data_path()?,
+    };
+    Ok(dir.join(STATUS_FILE))
+}
+
+/// Recording pause file used when none is configured, next to the default status file.
+/// In the runtime directory it lasts until logout, surviving daemon restarts.
+pub fn default_recording_pause_file() -> Result<PathBuf> {
+    Ok(default_status_file()?.with_file_name(RECORDING_PAUSE_FILE))
+}
+
+/// Whether the database at `legacy` should be brought over to `current`: only when it has
+/// data and `current` is missing or has no sessions, so history is never overwritten
+pub fn needs_legacy_migration(legacy: &Path, current: &Path) -> bool {
+    let has_data = |path: &Path| std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.len() > 0)
+        .unwrap_or(false);
+
+    legacy != current && has_data(legacy) && !(has_data(current) && has_sessions(current))
+}
+
+/// Whether the database at `path` has recorded any sessions. One that can't be read,
+/// e.g. because it is encrypted, counts as having some.
+fn has_sessions(path: &Path) -> bool {
+    let read = || -> rusqlite::Result<bool> {
+        let conn = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let initialized: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sessions')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !initialized {
+            return Ok(false);
+        }
+        conn.query_row("SELECT EXISTS(SELECT 1 FROM sessions)", [], |row| row.get(0))
+    };
+    read().unwrap_or(true)
+}
+
+/// Copy the database at `legacy` to `current` along with its WAL files, removing the
+/// originals afterwards if `remove_legacy` is set. The daemon must not be running.
+pub fn migrate_legacy_database(legacy: &Path, current: &Path, remove_legacy: bool) -> Result<()> {
+    if let Some(parent) = current.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create database directory {}", parent.display()))?;
+    }
+
+    let (files, stale): (Vec<(PathBuf, PathBuf)>, Vec<_>) = ["", "-wal", "-shm"].iter()
+        .map(|suffix| (sibling(legacy, suffix), sibling(current, suffix)))
+        .partition(|(from, _)| from.exists());
+
+    // WAL files of the database being replaced would be replayed into the copy
+    for (_, to) in stale.iter().filter(|(_, to)| to.exists()) {
+        std::fs::remove_file(to)
+            .with_context(|| format!("Failed to remove {}", to.display()))?;
+    }
+
+    for (from, to) in &files {
+        std::fs::copy(from, to)
+            .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+    }
+
+    if remove_legacy {
+        for (from, _) in &files {
+            std::fs::remove_file(from)
+                .with_context(|| format!("Failed to remove {}", from.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` with `suffix` appended to its file name, as SQLite names WAL files
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// The XDG spec says to ignore unset, empty or relative base directories
+fn xdg_app_dir(xdg_base: Option<String>, home: Option<String>, home_fallback: &str) -> Result<PathBuf> {
+    let base = match xdg_base.map(PathBuf::from).filter(|base| base.is_absolute()) {
+        Some(base) => base,
+        None => {
+            let home = home
+                .filter(|home| !home.is_empty())
+                .context("HOME environment variable not set")?;
+            PathBuf::from(home).join(home_fallback)
+        }
+    };
+
+    Ok(base.join(APP_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn test_expand_path() {
+        // Test regular path
+        let path = expand_path("/tmp/test.db").unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/test.db"));
+
+        // Test relative path
+        let path = expand_path("./test.db").unwrap();
+        assert_eq!(path, PathBuf::from("./test.db"));
+    }
+
+    #[test]
+    fn test_expand_home_path() {
+        std::env::set_var("HOME", "/home/testuser");
+        let path = expand_path("~/.local/share/test.db").unwrap();
+        assert_eq!(path, PathBuf::from("/home/testuser/.local/share/test.db"));
+    }
+
+    #[test]
+    fn test_xdg_base_set() {
+        let path = xdg_app_dir(some("/data"), some("/home/testuser"), ".local/share").unwrap();
+        assert_eq!(path, PathBuf::from("/data/gopal"));
+    }
+
+    #[test]
+    fn test_xdg_base_unset() {
+        let path = xdg_app_dir(None, some("/home/testuser"), ".local/share").unwrap();
+        assert_eq!(path, PathBuf::from("/home/testuser/.local/share/gopal"));
+
+        let path = xdg_app_dir(None, some("/home/testuser"), ".config").unwrap();
+        assert_eq!(path, PathBuf::from("/home/testuser/.config/gopal"));
+    }
+
+    #[test]
+    fn test_invalid_xdg_base_falls_back_to_home() {
+        let path = xdg_app_dir(some(""), some("/home/testuser"), ".config").unwrap();
+        assert_eq!(path, PathBuf::from("/home/testuser/.config/gopal"));
+
+        let path = xdg_app_dir(some("relative/dir"), some("/home/testuser"), ".config").unwrap();
+        assert_eq!(path, PathBuf::from("/home/testuser/.config/gopal"));
+
+        assert!(xdg_app_dir(None, None, ".config").is_err());
+    }
+
+    #[test]
+    fn test_legacy_migration_only_fills_a_missing_or_empty_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("musicd/music.db");
+        let current = dir.path().join("gopal/music.db");
+
+        // Nothing to migrate
+        assert!(!needs_legacy_migration(&legacy, &current));
+
+        std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        std::fs::write(&legacy, b"old data").unwrap();
+        std::fs::write(sibling(&legacy, "-wal"), b"old wal").unwrap();
+        assert!(needs_legacy_migration(&legacy, &current));
+        assert!(!needs_legacy_migration(&legacy, &legacy));
+
+        // An empty file, e.g. from a daemon that failed to initialize, is replaced
+        std::fs::create_dir_all(current.parent().unwrap()).unwrap();
+        std::fs::write(&current, b"").unwrap();
+        assert!(needs_legacy_migration(&legacy, &current));
+
+        // So is a database the daemon initialized but never recorded to
+        drop(crate::database::Database::new(&current).unwrap());
+        assert!(needs_legacy_migration(&legacy, &current));
+        std::fs::write(sibling(&current, "-shm"), b"stale shm").unwrap();
+
+        migrate_legacy_database(&legacy, &current, false).unwrap();
+        assert_eq!(std::fs::read(&current).unwrap(), b"old data");
+        assert_eq!(std::fs::read(sibling(&current, "-wal")).unwrap(), b"old wal");
+        assert!(!sibling(&current, "-shm").exists());
+        assert!(legacy.exists());
+
+        // Once migrated, the new database is never overwritten
+        assert!(!needs_legacy_migration(&legacy, &current));
+    }
+
+    #[test]
+    fn test_legacy_migration_keeps_a_database_with_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("old.db");
+        let current = dir.path().join("music.db");
+        std::fs::write(&legacy, b"old data").unwrap();
+
+        crate::database::Database::new(&current).unwrap()
+            .import_listenbrainz(r#"[{"listened_at": 1000, "track_metadata": {"track_name": "Song", "artist_name": "Artist"}}]"#, 180)
+            .unwrap();
+        assert!(!needs_legacy_migration(&legacy, &current));
+    }
+
+    #[test]
+    fn test_legacy_migration_move_removes_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("old.db");
+        let current = dir.path().join("new/music.db");
+        std::fs::write(&legacy, b"old data").unwrap();
+
+        migrate_legacy_database(&legacy, &current, true).unwrap();
+        assert_eq!(std::fs::read(&current).unwrap(), b"old data");
+        assert!(!legacy.exists());
+    }
+}
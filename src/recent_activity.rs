@@ -0,0 +1,164 @@
+//! Listening over the last day, kept in memory by the daemon so a recent-activity
+//! sparkline doesn't need a database query
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::session_tracker::SessionEvent;
+
+/// Hours of listening `RecentActivity` remembers
+pub const RECENT_ACTIVITY_HOURS: usize = 24;
+
+const HOUR: i64 = 3600;
+
+/// Seconds listened during one clock hour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HourlyActivity {
+    /// Unix time the hour starts at
+    pub hour_start: i64,
+    pub listened_time: i64,
+}
+
+/// Ring buffer of listened seconds per hour over the last `RECENT_ACTIVITY_HOURS`,
+/// fed from session events. A session's listening is counted when it finishes, spread
+/// over the hours just before its end, since pauses aren't timestamped.
+#[derive(Debug, Default, Clone)]
+pub struct RecentActivity {
+    buckets: [i64; RECENT_ACTIVITY_HOURS],
+    /// Hour number (unix time / 3600) of the newest bucket
+    newest_hour: i64,
+    /// Start time and pause so far of each session that hasn't finished
+    sessions: HashMap<i64, (i64, i64)>,
+}
+
+impl RecentActivity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::SessionStarted { session_id, start_time, .. } => {
+                self.sessions.insert(*session_id, (*start_time, 0));
+            }
+            SessionEvent::SessionPaused { session_id, pause_duration } => {
+                if let Some((_, paused)) = self.sessions.get_mut(session_id) {
+                    *paused += pause_duration;
+                }
+            }
+            SessionEvent::SessionFinalized { session_id, end_time, .. } => {
+                if let Some((start_time, paused)) = self.sessions.remove(session_id) {
+                    let listened = (end_time - start_time - paused).max(0);
+                    self.add_listening(end_time - listened, *end_time);
+                }
+            }
+        }
+    }
+
+    /// Count listening from `start` to `end` in the hours it falls in
+    fn add_listening(&mut self, start: i64, end: i64) {
+        let mut time = start;
+        while time < end {
+            let hour = time.div_euclid(HOUR);
+            let until = end.min((hour + 1) * HOUR);
+            self.add_to_hour(hour, until - time);
+            time = until;
+        }
+    }
+
+    fn add_to_hour(&mut self, hour: i64, seconds: i64) {
+        if hour > self.newest_hour {
+            // Hours skipped over had no listening
+            let skipped = (hour - self.newest_hour).min(RECENT_ACTIVITY_HOURS as i64);
+            for h in hour - skipped + 1..=hour {
+                self.buckets[Self::index(h)] = 0;
+            }
+            self.newest_hour = hour;
+        }
+        if hour > self.newest_hour - RECENT_ACTIVITY_HOURS as i64 {
+            self.buckets[Self::index(hour)] += seconds;
+        }
+    }
+
+    fn index(hour: i64) -> usize {
+        hour.rem_euclid(RECENT_ACTIVITY_HOURS as i64) as usize
+    }
+
+    /// Listening in each of the `RECENT_ACTIVITY_HOURS` hours up to the one containing
+    /// `current_time`, oldest first
+    pub fn hours(&self, current_time: i64) -> Vec<HourlyActivity> {
+        let current_hour = current_time.div_euclid(HOUR);
+        (current_hour - RECENT_ACTIVITY_HOURS as i64 + 1..=current_hour)
+            .map(|hour| {
+                let remembered = hour <= self.newest_hour && hour > self.newest_hour - RECENT_ACTIVITY_HOURS as i64;
+                HourlyActivity {
+                    hour_start: hour * HOUR,
+                    listened_time: if remembered { self.buckets[Self::index(hour)] } else { 0 },
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Track;
+
+    fn started(session_id: i64, start_time: i64) -> SessionEvent {
+        let track = Track {
+            id: "track".to_string(),
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            length: None,
+            art_url: None,
+            genre: None,
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        };
+        SessionEvent::SessionStarted { session_id, track: Box::new(track), player_id: 1, start_time }
+    }
+
+    fn finalized(session_id: i64, end_time: i64) -> SessionEvent {
+        SessionEvent::SessionFinalized { session_id, end_time, status: "completed".to_string() }
+    }
+
+    fn listened(activity: &RecentActivity, current_time: i64) -> Vec<i64> {
+        activity.hours(current_time).iter().map(|hour| hour.listened_time).collect()
+    }
+
+    #[test]
+    fn test_listening_is_bucketed_by_hour() {
+        let base = 1_700_000_000 / HOUR * HOUR;
+        let mut activity = RecentActivity::new();
+
+        // Ten minutes either side of an hour boundary, one minute of it paused
+        activity.record(&started(1, base + 3000));
+        activity.record(&SessionEvent::SessionPaused { session_id: 1, pause_duration: 60 });
+        activity.record(&finalized(1, base + 4200));
+        let hours = activity.hours(base + 4200);
+        assert_eq!(hours.len(), RECENT_ACTIVITY_HOURS);
+        assert_eq!(hours.last().unwrap().hour_start, base + HOUR);
+        assert_eq!(listened(&activity, base + 4200)[22..], [540, 600]);
+
+        // Nothing counts until a session finishes
+        activity.record(&started(2, base + 2 * HOUR + 100));
+        assert_eq!(listened(&activity, base + 2 * HOUR + 200)[21..], [540, 600, 0]);
+        activity.record(&finalized(2, base + 2 * HOUR + 400));
+        assert_eq!(listened(&activity, base + 2 * HOUR + 400)[21..], [540, 600, 300]);
+
+        // A day later the first hour has rolled out of the window
+        let later = base + 24 * HOUR;
+        assert_eq!(listened(&activity, later)[..2], [600, 300]);
+        activity.record(&started(3, later + 10));
+        activity.record(&finalized(3, later + 70));
+        assert_eq!(listened(&activity, later)[..2], [600, 300]);
+        assert_eq!(listened(&activity, later)[23], 60);
+        assert_eq!(listened(&activity, base + 40 * HOUR).iter().sum::<i64>(), 60);
+    }
+}
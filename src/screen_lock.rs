@@ -0,0 +1,92 @@
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::time::Duration;
+
+use crate::error::{GopalError, Result};
+
+/// Reports whether the user's desktop session is currently locked
+pub trait LockSource {
+    fn is_locked(&mut self) -> Result<bool>;
+}
+
+/// Reads logind's `LockedHint` property for the user's session: the
+/// `org.freedesktop.login1.Session` interface at `/org/freedesktop/login1/session/auto`
+/// on the system bus. Lock screens set the hint when they lock and clear it on unlock,
+/// and logind announces each change with `PropertiesChanged`. The monitor reads it on
+/// every poll instead of subscribing, so it needs no extra D-Bus event loop.
+pub struct LogindLockSource {
+    conn: Connection,
+}
+
+impl LogindLockSource {
+    pub fn new() -> Result<Self> {
+        let conn = Connection::new_system()
+            .map_err(|source| GopalError::DBus { context: "Failed to connect to the system D-Bus", source })?;
+        Ok(LogindLockSource { conn })
+    }
+}
+
+impl LockSource for LogindLockSource {
+    fn is_locked(&mut self) -> Result<bool> {
+        let proxy = self.conn.with_proxy(
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1/session/auto",
+            Duration::from_secs(2),
+        );
+        proxy.get("org.freedesktop.login1.Session", "LockedHint")
+            .map_err(|source| GopalError::DBus { context: "Failed to read logind LockedHint", source })
+    }
+}
+
+/// Turns lock state samples into completed lock intervals
+#[derive(Debug, Default)]
+pub struct LockTracker {
+    locked_since: Option<i64>,
+}
+
+impl LockTracker {
+    /// Record a lock state sample, returning `(locked_at, unlocked_at)` when the session unlocks
+    pub fn update(&mut self, locked: bool, current_time: i64) -> Option<(i64, i64)> {
+        match (self.locked_since, locked) {
+            (None, true) => {
+                self.locked_since = Some(current_time);
+                None
+            }
+            (Some(locked_at), false) => {
+                self.locked_since = None;
+                Some((locked_at, current_time))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Part of a lock interval to count as pause for a session that started at `session_start`
+pub fn locked_pause_time(locked_at: i64, unlocked_at: i64, session_start: i64) -> i64 {
+    (unlocked_at - locked_at.max(session_start)).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_tracker_reports_interval_on_unlock() {
+        let mut tracker = LockTracker::default();
+        assert_eq!(tracker.update(false, 100), None);
+        assert_eq!(tracker.update(true, 200), None);
+        // Repeated locked samples keep the original lock time
+        assert_eq!(tracker.update(true, 250), None);
+        assert_eq!(tracker.update(false, 500), Some((200, 500)));
+        assert_eq!(tracker.update(false, 600), None);
+    }
+
+    #[test]
+    fn test_locked_pause_time() {
+        // Session already playing when the screen locked
+        assert_eq!(locked_pause_time(200, 500, 100), 300);
+        // Session started while locked only pauses from its own start
+        assert_eq!(locked_pause_time(200, 500, 400), 100);
+        assert_eq!(locked_pause_time(200, 500, 600), 0);
+    }
+}
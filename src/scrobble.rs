@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::io::{BufWriter, Read};
+use std::process::{Command, Stdio};
+
+use crate::database::{Database, QueuedScrobble};
+
+/// Submission attempts before a scrobble is left in the queue as failed
+pub const DEFAULT_MAX_ATTEMPTS: i64 = 10;
+
+/// Wait before the first retry; each later one waits twice as long as the last
+const FIRST_RETRY_DELAY: i64 = 60;
+
+/// Longest wait between retries (in seconds)
+const MAX_RETRY_DELAY: i64 = 6 * 60 * 60;
+
+/// Sends a listen to a scrobbling service
+pub trait ScrobbleSubmitter {
+    fn submit(&mut self, scrobble: &QueuedScrobble) -> Result<()>;
+}
+
+/// Hands each scrobble to a user-supplied program as JSON on stdin, so any service can
+/// be fed by a small script. A zero exit status means the listen was accepted; anything
+/// the program writes to stderr becomes the recorded error otherwise.
+pub struct CommandSubmitter {
+    command: String,
+}
+
+impl CommandSubmitter {
+    pub fn new(command: impl Into<String>) -> Self {
+        CommandSubmitter { command: command.into() }
+    }
+}
+
+impl ScrobbleSubmitter for CommandSubmitter {
+    fn submit(&mut self, scrobble: &QueuedScrobble) -> Result<()> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run scrobble command '{}'", self.command))?;
+
+        // Dropping stdin after writing lets the command see end of input
+        if let Some(stdin) = child.stdin.take() {
+            serde_json::to_writer(BufWriter::new(stdin), scrobble)?;
+        }
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            match stderr.trim() {
+                "" => anyhow::bail!("Scrobble command failed with {}", status),
+                message => anyhow::bail!("Scrobble command failed with {}: {}", status, message),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Seconds to wait after the `attempts`th failed submission before trying again
+pub fn retry_delay(attempts: i64) -> i64 {
+    let doublings = attempts.clamp(1, 32) - 1;
+    FIRST_RETRY_DELAY.saturating_mul(1 << doublings).min(MAX_RETRY_DELAY)
+}
+
+/// What one pass over the queue did
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FlushSummary {
+    pub submitted: usize,
+    pub failed: usize,
+    /// Failures that used up the last attempt
+    pub gave_up: usize,
+}
+
+/// Submit every scrobble due by `current_time`, removing the ones that succeed and
+/// scheduling a retry with exponential backoff for the rest. After `max_attempts`
+/// failures a scrobble stays in the queue as failed until retried by hand.
+pub fn flush_scrobble_queue(
+    database: &Database,
+    submitter: &mut dyn ScrobbleSubmitter,
+    current_time: i64,
+    max_attempts: i64,
+) -> Result<FlushSummary> {
+    let mut summary = FlushSummary::default();
+
+    for scrobble in database.get_scrobble_queue(Some(current_time))? {
+        match submitter.submit(&scrobble) {
+            Ok(()) => {
+                debug!("Scrobbled {} - {}", scrobble.artist, scrobble.title);
+                database.complete_scrobble(scrobble.id)?;
+                summary.submitted += 1;
+            }
+            Err(e) => {
+                let attempts = scrobble.attempts + 1;
+                let next_attempt_at = (attempts < max_attempts).then(|| current_time + retry_delay(attempts));
+                if next_attempt_at.is_none() {
+                    warn!("Giving up on scrobbling {} - {} after {} attempts: {:#}", scrobble.artist, scrobble.title, attempts, e);
+                    summary.gave_up += 1;
+                }
+                database.fail_scrobble(scrobble.id, &format!("{:#}", e), next_attempt_at)?;
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Track;
+
+    /// Accepts or rejects everything, remembering what it was given
+    struct MockSubmitter {
+        fail: bool,
+        submitted: Vec<i64>,
+    }
+
+    impl ScrobbleSubmitter for MockSubmitter {
+        fn submit(&mut self, scrobble: &QueuedScrobble) -> Result<()> {
+            self.submitted.push(scrobble.session_id);
+            if self.fail {
+                anyhow::bail!("service unavailable");
+            }
+            Ok(())
+        }
+    }
+
+    fn queue_db() -> (Database, i64) {
+        let db = Database::new(":memory:").unwrap().with_scrobble_queue(true);
+        let track = Track {
+            id: "track-1".to_string(),
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            length: Some(180_000_000),
+            art_url: None,
+            genre: None,
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        };
+        db.insert_or_update_track(&track).unwrap();
+        let player_id = db.insert_or_update_player("spotify", "Spotify").unwrap();
+
+        // Too short to scrobble, so never queued
+        let skipped = db.start_session(&track.id, player_id, 1000).unwrap();
+        db.finalize_session(skipped, 1010, "skipped").unwrap();
+
+        let session_id = db.start_session(&track.id, player_id, 2000).unwrap();
+        db.finalize_session(session_id, 2180, "completed").unwrap();
+        (db, session_id)
+    }
+
+    #[test]
+    fn test_finished_scrobbles_are_queued() {
+        let (db, session_id) = queue_db();
+
+        let queue = db.get_scrobble_queue(None).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].session_id, session_id);
+        assert_eq!((queue[0].title.as_str(), queue[0].listened_at, queue[0].listened_time), ("Song", 2000, 180));
+        assert_eq!((queue[0].state(), queue[0].next_attempt_at), ("pending", Some(2180)));
+
+        // Queueing is idempotent
+        assert!(!db.enqueue_scrobble(session_id).unwrap());
+        assert_eq!(db.get_scrobble_queue(None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_successful_flush_clears_the_queue() {
+        let (db, session_id) = queue_db();
+        let mut submitter = MockSubmitter { fail: false, submitted: Vec::new() };
+
+        // Nothing is due before the session ended
+        assert_eq!(flush_scrobble_queue(&db, &mut submitter, 2000, 3).unwrap(), FlushSummary::default());
+
+        let summary = flush_scrobble_queue(&db, &mut submitter, 2200, 3).unwrap();
+        assert_eq!(summary, FlushSummary { submitted: 1, failed: 0, gave_up: 0 });
+        assert_eq!(submitter.submitted, vec![session_id]);
+        assert!(db.get_scrobble_queue(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_flush_backs_off_then_gives_up() {
+        let (db, _) = queue_db();
+        let mut submitter = MockSubmitter { fail: true, submitted: Vec::new() };
+
+        let summary = flush_scrobble_queue(&db, &mut submitter, 3000, 2).unwrap();
+        assert_eq!(summary, FlushSummary { submitted: 0, failed: 1, gave_up: 0 });
+        let queued = &db.get_scrobble_queue(None).unwrap()[0];
+        assert_eq!((queued.state(), queued.attempts), ("retrying", 1));
+        assert_eq!(queued.last_error.as_deref(), Some("service unavailable"));
+        assert_eq!(queued.next_attempt_at, Some(3000 + retry_delay(1)));
+
+        // Not retried until the backoff has passed
+        flush_scrobble_queue(&db, &mut submitter, 3001, 2).unwrap();
+        assert_eq!(submitter.submitted.len(), 1);
+
+        let summary = flush_scrobble_queue(&db, &mut submitter, 4000, 2).unwrap();
+        assert_eq!(summary, FlushSummary { submitted: 0, failed: 1, gave_up: 1 });
+        let queued = &db.get_scrobble_queue(None).unwrap()[0];
+        assert_eq!((queued.state(), queued.attempts), ("failed", 2));
+        assert!(db.get_scrobble_queue(Some(i64::MAX)).unwrap().is_empty());
+
+        // Retrying by hand makes it due again
+        assert_eq!(db.retry_scrobbles_now(5000).unwrap(), 1);
+        submitter.fail = false;
+        let summary = flush_scrobble_queue(&db, &mut submitter, 5000, 2).unwrap();
+        assert_eq!(summary.submitted, 1);
+        assert!(db.get_scrobble_queue(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_a_cap() {
+        assert_eq!(retry_delay(1), 60);
+        assert_eq!(retry_delay(2), 120);
+        assert_eq!(retry_delay(4), 480);
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use chrono::NaiveTime;
+
+/// Rules the daemon uses to label new sessions, so listening can later be filtered
+/// with `stats --label`. A matching player wins over a matching time of day.
+#[derive(Debug, Clone, Default)]
+pub struct SessionLabelRules {
+    /// Lowercased player identity substring and its label
+    players: Vec<(String, String)>,
+    times: Vec<TimeLabel>,
+}
+
+#[derive(Debug, Clone)]
+struct TimeLabel {
+    start: NaiveTime,
+    end: NaiveTime,
+    label: String,
+}
+
+impl TimeLabel {
+    /// Ranges ending before they start wrap past midnight
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl SessionLabelRules {
+    /// Label sessions of players whose identity contains `identity` (case-insensitive)
+    pub fn with_player(mut self, identity: &str, label: &str) -> Self {
+        self.players.push((identity.to_lowercase(), label.to_string()));
+        self
+    }
+
+    /// Label sessions starting between `start` and `end`, both `HH:MM` local time
+    pub fn with_time_range(mut self, start: &str, end: &str, label: &str) -> Result<Self> {
+        let parse = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .with_context(|| format!("Invalid time '{}' for label '{}'. Use HH:MM", value, label))
+        };
+
+        self.times.push(TimeLabel {
+            start: parse(start)?,
+            end: parse(end)?,
+            label: label.to_string(),
+        });
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.times.is_empty()
+    }
+
+    /// Label for a session started by `player_identity` at local `time`, if any rule matches
+    pub fn label_for(&self, player_identity: &str, time: NaiveTime) -> Option<&str> {
+        let identity = player_identity.to_lowercase();
+        let by_player = self.players
+            .iter()
+            .find(|(player, _)| identity.contains(player.as_str()))
+            .map(|(_, label)| label.as_str());
+
+        by_player.or_else(|| {
+            self.times
+                .iter()
+                .find(|range| range.contains(time))
+                .map(|range| range.label.as_str())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_player_label_wins_over_time() {
+        let rules = SessionLabelRules::default()
+            .with_player("Spotify", "music")
+            .with_time_range("06:00", "09:00", "gym")
+            .unwrap();
+
+        assert_eq!(rules.label_for("spotify", time(7, 0)), Some("music"));
+        assert_eq!(rules.label_for("Mozilla Firefox", time(7, 0)), Some("gym"));
+        assert_eq!(rules.label_for("Mozilla Firefox", time(9, 0)), None);
+    }
+
+    #[test]
+    fn test_time_range_wraps_midnight() {
+        let rules = SessionLabelRules::default()
+            .with_time_range("22:00", "02:00", "late")
+            .unwrap();
+
+        assert_eq!(rules.label_for("VLC", time(23, 30)), Some("late"));
+        assert_eq!(rules.label_for("VLC", time(1, 59)), Some("late"));
+        assert_eq!(rules.label_for("VLC", time(12, 0)), None);
+    }
+
+    #[test]
+    fn test_invalid_time_errors() {
+        let err = SessionLabelRules::default().with_time_range("6am", "09:00", "gym").unwrap_err();
+        assert!(err.to_string().contains("6am"));
+    }
+}
@@ -1,15 +1,19 @@
-use anyhow::Result;
+use crate::error::Result;
 use log::{debug, warn};
 use std::collections::HashMap;
 use tokio::sync::mpsc;
 
 use crate::database::Track;
 
+/// Longest a session may last by default before it is cut off at this many seconds and
+/// given the `capped` status, as more likely a stuck player than real listening
+pub const MAX_REASONABLE_DURATION: i64 = 24 * 3600;
+
 #[derive(Debug, Clone)]
 pub enum SessionEvent {
     SessionStarted {
         session_id: i64,
-        track: Track,
+        track: Box<Track>,
         player_id: i64,
         start_time: i64,
     },
@@ -40,6 +44,10 @@ pub struct SessionTracker {
     active_sessions: HashMap<i64, ActiveSession>, // player_id -> session
     event_sender: Option<mpsc::UnboundedSender<SessionEvent>>,
     next_session_id: i64,
+    /// Pauses shorter than this many seconds add no pause time, see `set_pause_debounce`
+    pause_debounce: i64,
+    /// Sessions are ended this many seconds after they start, see `set_max_reasonable_duration`
+    max_reasonable_duration: i64,
 }
 
 impl SessionTracker {
@@ -48,6 +56,8 @@ impl SessionTracker {
             active_sessions: HashMap::new(),
             event_sender: None,
             next_session_id: 1,
+            pause_debounce: 0,
+            max_reasonable_duration: MAX_REASONABLE_DURATION,
         }
     }
 
@@ -55,6 +65,34 @@ impl SessionTracker {
         self.event_sender = Some(sender);
     }
 
+    /// Ignore pauses shorter than `seconds`, for players that briefly report Paused
+    /// while seeking. A pause only counts once it has lasted that long, and then in full.
+    pub fn set_pause_debounce(&mut self, seconds: i64) {
+        self.pause_debounce = seconds.max(0);
+    }
+
+    /// End sessions that last longer than `seconds` at that length, with the `capped`
+    /// status so they can be told apart, instead of at `MAX_REASONABLE_DURATION`
+    pub fn set_max_reasonable_duration(&mut self, seconds: i64) {
+        self.max_reasonable_duration = seconds.max(1);
+    }
+
+    /// Pause time a pause of `pause_duration` seconds adds to its session
+    fn committed_pause(&self, pause_duration: i64) -> i64 {
+        if pause_duration < self.pause_debounce {
+            0
+        } else {
+            pause_duration
+        }
+    }
+
+    /// Pause time the session's ongoing pause would add if it ended at `current_time`
+    pub fn pending_pause_time(&self, session: &ActiveSession, current_time: i64) -> i64 {
+        session.pause_start_time
+            .map(|pause_start| self.committed_pause(current_time - pause_start))
+            .unwrap_or(0)
+    }
+
     pub async fn handle_play_event(
         &mut self,
         player_id: i64,
@@ -88,7 +126,7 @@ impl SessionTracker {
         if let Some(ref sender) = self.event_sender {
             let _ = sender.send(SessionEvent::SessionStarted {
                 session_id,
-                track,
+                track: Box::new(track),
                 player_id,
                 start_time: timestamp,
             });
@@ -97,6 +135,33 @@ impl SessionTracker {
         Ok(())
     }
 
+    /// Adopt a session persisted by a previous run instead of starting a new one.
+    /// No `SessionStarted` event is sent since the session is already stored.
+    pub fn resume_session(
+        &mut self,
+        player_id: i64,
+        track: Track,
+        start_time: i64,
+        total_pause_time: i64,
+    ) -> i64 {
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+
+        debug!("Resuming session {} for player {} started at {}", session_id, player_id, start_time);
+
+        self.active_sessions.insert(player_id, ActiveSession {
+            session_id,
+            track,
+            player_id,
+            start_time,
+            pause_start_time: None,
+            total_pause_time,
+            is_paused: false,
+        });
+
+        session_id
+    }
+
     pub async fn handle_pause_event(&mut self, player_id: i64, timestamp: i64) -> Result<()> {
         debug!("Handling pause event for player {} at {}", player_id, timestamp);
 
@@ -118,14 +183,23 @@ impl SessionTracker {
     pub async fn handle_resume_event(&mut self, player_id: i64, timestamp: i64) -> Result<()> {
         debug!("Handling resume event for player {} at {}", player_id, timestamp);
 
+        let pause_debounce = self.pause_debounce;
         if let Some(session) = self.active_sessions.get_mut(&player_id) {
             if session.is_paused {
                 if let Some(pause_start) = session.pause_start_time {
                     let pause_duration = timestamp - pause_start;
-                    session.total_pause_time += pause_duration;
                     session.pause_start_time = None;
                     session.is_paused = false;
 
+                    if pause_duration < pause_debounce {
+                        debug!(
+                            "Session {} resumed after {} seconds, too soon to count as a pause",
+                            session.session_id, pause_duration
+                        );
+                        return Ok(());
+                    }
+                    session.total_pause_time += pause_duration;
+
                     debug!(
                         "Session {} resumed after {} seconds of pause",
                         session.session_id, pause_duration
@@ -188,10 +262,21 @@ impl SessionTracker {
         status: &str,
     ) -> Result<()> {
         if let Some(mut session) = self.active_sessions.remove(&player_id) {
+            let duration = end_time - session.start_time;
+
+            // Only cap extremely long sessions (likely a stuck player or system issues)
+            let (capped_end_time, status) = if duration > self.max_reasonable_duration {
+                warn!("Session {} duration ({}s) exceeds {}s, capping to prevent system sleep inflation",
+                      session.session_id, duration, self.max_reasonable_duration);
+                (session.start_time + self.max_reasonable_duration, "capped")
+            } else {
+                (end_time, status)
+            };
+
             // If the session was paused when it ended, calculate the final pause duration
             if session.is_paused {
                 if let Some(pause_start) = session.pause_start_time {
-                    let final_pause_duration = end_time - pause_start;
+                    let final_pause_duration = self.committed_pause((capped_end_time - pause_start).max(0));
                     session.total_pause_time += final_pause_duration;
 
                     // Send the final pause duration event
@@ -204,18 +289,6 @@ impl SessionTracker {
                 }
             }
 
-            let duration = end_time - session.start_time;
-            let max_reasonable_duration = 24 * 3600; // 24 hours - very generous for long listening sessions
-            
-            // Only cap extremely long sessions (likely from system issues)
-            let capped_end_time = if duration > max_reasonable_duration {
-                warn!("Session {} duration ({} hours) exceeds 24 hours, capping to prevent system sleep inflation",
-                      session.session_id, duration / 3600);
-                session.start_time + max_reasonable_duration
-            } else {
-                end_time
-            };
-
             let final_duration = capped_end_time - session.start_time;
             debug!(
                 "Finalizing session {} for track '{}' - Duration: {}s, Paused: {}s",
@@ -257,6 +330,41 @@ impl SessionTracker {
         self.active_sessions.iter().map(|(&k, v)| (k, v)).collect()
     }
 
+    /// Complete paused sessions whose track would have ended more than `margin` seconds
+    /// ago had it kept playing, for players that sit in Paused after the last track
+    /// instead of reporting Stopped. They end when they were paused.
+    pub async fn finalize_paused_past_end(&mut self, current_time: i64, margin: i64) -> Result<()> {
+        let mut ended = Vec::new();
+
+        for (&player_id, session) in &self.active_sessions {
+            let (Some(pause_start), Some(length)) = (session.pause_start_time, session.track.length) else {
+                continue;
+            };
+            if length <= 0 {
+                continue;
+            }
+
+            let listened = pause_start - session.start_time - session.total_pause_time;
+            let remaining = (length / 1_000_000 - listened).max(0);
+            if current_time - pause_start > remaining + margin {
+                debug!(
+                    "Session {} for player {} paused {}s with {}s of the track left, treating it as ended",
+                    session.session_id,
+                    player_id,
+                    current_time - pause_start,
+                    remaining
+                );
+                ended.push((player_id, pause_start));
+            }
+        }
+
+        for (player_id, pause_start) in ended {
+            self.finalize_session(player_id, pause_start, "completed").await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn cleanup_stale_sessions(&mut self, current_time: i64, max_idle_time: i64) -> Result<()> {
         let mut stale_players = Vec::new();
 
@@ -300,6 +408,13 @@ mod tests {
             album: "Test Album".to_string(),
             length: Some(180_000_000), // 3 minutes in microseconds
             art_url: None,
+            genre: None,
+            raw_metadata: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
         }
     }
 
@@ -349,6 +464,34 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_pause_debounce_ignores_brief_pauses() {
+        let mut tracker = SessionTracker::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tracker.set_event_sender(tx);
+        tracker.set_pause_debounce(2);
+
+        tracker.handle_play_event(1, create_test_track(), 1000).await.unwrap();
+        rx.recv().await.unwrap();
+
+        // A seek reported as a one second pause adds nothing
+        tracker.handle_pause_event(1, 1050).await.unwrap();
+        let session = &tracker.active_sessions[&1];
+        assert_eq!(tracker.pending_pause_time(session, 1051), 0);
+        tracker.handle_resume_event(1, 1051).await.unwrap();
+        assert_eq!(tracker.active_sessions[&1].total_pause_time, 0);
+        assert!(rx.try_recv().is_err());
+
+        // A real pause counts in full
+        tracker.handle_pause_event(1, 1100).await.unwrap();
+        tracker.handle_resume_event(1, 1130).await.unwrap();
+        assert_eq!(tracker.active_sessions[&1].total_pause_time, 30);
+        match rx.recv().await {
+            Some(SessionEvent::SessionPaused { pause_duration, .. }) => assert_eq!(pause_duration, 30),
+            _ => panic!("Expected SessionPaused event"),
+        }
+    }
+
     #[tokio::test]
     async fn test_multiple_players() {
         let mut tracker = SessionTracker::new();
@@ -0,0 +1,488 @@
+use crate::error::Result;
+use log::{debug, info};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::content_type::ContentType;
+use crate::database::{Database, Player, Session, SessionCheckpoint, SessionWithMetadata, Track};
+use crate::focus::SessionFocus;
+
+/// Storage the monitor records players and sessions in. `Database` is the SQLite
+/// store the daemon uses; `InMemoryStore` keeps everything in memory for tests, and
+/// `DryRunStore` does the same while logging what would have been written.
+pub trait Store {
+    /// Register a player by bus name, returning its id
+    fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64>;
+    fn insert_or_update_track(&self, track: &Track) -> Result<()>;
+    /// Start an active session, first interrupting any the player already has
+    fn start_session_as(&self, track_id: &str, player_id: i64, start_time: i64, content_type: ContentType) -> Result<i64>;
+    fn start_session(&self, track_id: &str, player_id: i64, start_time: i64) -> Result<i64> {
+        self.start_session_as(track_id, player_id, start_time, ContentType::Music)
+    }
+    /// Returns false if there is no such session
+    fn set_session_label(&self, session_id: i64, label: Option<&str>) -> Result<bool>;
+    fn set_session_source_domain(&self, session_id: i64, domain: Option<&str>) -> Result<()>;
+    fn set_session_focus(&self, session_id: i64, focus: SessionFocus) -> Result<()>;
+    fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()>;
+    fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()>;
+    /// Checkpoint active sessions, given as session ids and the pause each is currently in
+    fn update_active_sessions_progress(&self, current_time: i64, sessions: &[(i64, i64)]) -> Result<()>;
+    fn get_session_checkpoint(&self, player_id: i64) -> Result<Option<SessionCheckpoint>>;
+    fn resume_session(&self, session_id: i64, current_time: i64) -> Result<()>;
+    fn close_session_at_checkpoint(&self, session_id: i64, status: &str) -> Result<()>;
+    /// The player's latest session, if it's a finished play of the track that ended within `max_gap`
+    fn find_mergeable_session(&self, track_id: &str, player_id: i64, start_time: i64, max_gap: i64) -> Result<Option<i64>>;
+    /// Make a finished session active again, counting the time since it ended as pause
+    fn reopen_session(&self, session_id: i64, resume_time: i64) -> Result<()>;
+    /// All active sessions, most recent first
+    fn list_active_sessions(&self) -> Result<Vec<SessionWithMetadata>>;
+    /// Drop raw metadata beyond the `max_rows` most recently played tracks, returning
+    /// how many were dropped. Stores that don't persist tracks have nothing to prune.
+    fn prune_raw_metadata(&self, _max_rows: usize) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Store for Database {
+    fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64> {
+        Database::insert_or_update_player(self, name, identity)
+    }
+
+    fn insert_or_update_track(&self, track: &Track) -> Result<()> {
+        Database::insert_or_update_track(self, track)
+    }
+
+    fn prune_raw_metadata(&self, max_rows: usize) -> Result<usize> {
+        Database::prune_raw_metadata(self, max_rows)
+    }
+
+    fn start_session_as(&self, track_id: &str, player_id: i64, start_time: i64, content_type: ContentType) -> Result<i64> {
+        Database::start_session_as(self, track_id, player_id, start_time, content_type)
+    }
+
+    fn set_session_label(&self, session_id: i64, label: Option<&str>) -> Result<bool> {
+        Database::set_session_label(self, session_id, label)
+    }
+
+    fn set_session_source_domain(&self, session_id: i64, domain: Option<&str>) -> Result<()> {
+        Database::set_session_source_domain(self, session_id, domain)
+    }
+
+    fn set_session_focus(&self, session_id: i64, focus: SessionFocus) -> Result<()> {
+        Database::set_session_focus(self, session_id, focus)
+    }
+
+    fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()> {
+        Database::update_session_pause_time(self, session_id, additional_pause_time)
+    }
+
+    fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()> {
+        Database::finalize_session(self, session_id, end_time, status)
+    }
+
+    fn update_active_sessions_progress(&self, current_time: i64, sessions: &[(i64, i64)]) -> Result<()> {
+        Database::update_active_sessions_progress(self, current_time, sessions)
+    }
+
+    fn get_session_checkpoint(&self, player_id: i64) -> Result<Option<SessionCheckpoint>> {
+        Database::get_session_checkpoint(self, player_id)
+    }
+
+    fn resume_session(&self, session_id: i64, current_time: i64) -> Result<()> {
+        Database::resume_session(self, session_id, current_time)
+    }
+
+    fn close_session_at_checkpoint(&self, session_id: i64, status: &str) -> Result<()> {
+        Database::close_session_at_checkpoint(self, session_id, status)
+    }
+
+    fn find_mergeable_session(&self, track_id: &str, player_id: i64, start_time: i64, max_gap: i64) -> Result<Option<i64>> {
+        Database::find_mergeable_session(self, track_id, player_id, start_time, max_gap)
+    }
+
+    fn reopen_session(&self, session_id: i64, resume_time: i64) -> Result<()> {
+        Database::reopen_session(self, session_id, resume_time)
+    }
+
+    fn list_active_sessions(&self) -> Result<Vec<SessionWithMetadata>> {
+        Database::list_active_sessions(self)
+    }
+}
+
+/// `Store` that keeps players, tracks and sessions in memory, mirroring how `Database`
+/// updates them. Content types, source domains and focus are accepted but not kept.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    state: RefCell<InMemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct InMemoryState {
+    players: Vec<Player>,
+    tracks: HashMap<String, Track>,
+    /// Sessions in the order they started, with when each was last checkpointed
+    sessions: Vec<(Session, i64)>,
+}
+
+impl InMemoryState {
+    fn session_mut(&mut self, session_id: i64) -> Option<&mut (Session, i64)> {
+        self.sessions.iter_mut().find(|(session, _)| session.id == session_id)
+    }
+
+    fn active_session_mut(&mut self, session_id: i64) -> Option<&mut (Session, i64)> {
+        self.session_mut(session_id).filter(|(session, _)| session.status == "active")
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every recorded session, in the order they started
+    pub fn sessions(&self) -> Vec<Session> {
+        self.state.borrow().sessions.iter().map(|(session, _)| session.clone()).collect()
+    }
+
+    pub fn track(&self, track_id: &str) -> Option<Track> {
+        self.state.borrow().tracks.get(track_id).cloned()
+    }
+
+    pub fn players(&self) -> Vec<Player> {
+        self.state.borrow().players.clone()
+    }
+}
+
+impl Store for InMemoryStore {
+    fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64> {
+        let mut state = self.state.borrow_mut();
+        if let Some(player) = state.players.iter_mut().find(|player| player.name == name) {
+            player.identity = identity.to_string();
+            return Ok(player.id);
+        }
+
+        let id = state.players.len() as i64 + 1;
+        state.players.push(Player { id, name: name.to_string(), identity: identity.to_string() });
+        Ok(id)
+    }
+
+    fn insert_or_update_track(&self, track: &Track) -> Result<()> {
+        self.state.borrow_mut().tracks.insert(track.id.clone(), track.clone());
+        Ok(())
+    }
+
+    fn start_session_as(&self, track_id: &str, player_id: i64, start_time: i64, content_type: ContentType) -> Result<i64> {
+        let existing_active = self.state.borrow().sessions
+            .iter()
+            .find(|(session, _)| session.player_id == player_id && session.status == "active")
+            .map(|(session, _)| session.id);
+        if let Some(existing_id) = existing_active {
+            self.finalize_session(existing_id, start_time, "interrupted")?;
+        }
+
+        let mut state = self.state.borrow_mut();
+        let id = state.sessions.len() as i64 + 1;
+        state.sessions.push((Session {
+            id,
+            track_id: track_id.to_string(),
+            player_id,
+            start_time,
+            end_time: None,
+            paused_time: 0,
+            listened_time: None,
+            status: "active".to_string(),
+            label: None,
+            paused_since: None,
+            content_type: Some(content_type),
+        }, start_time));
+        Ok(id)
+    }
+
+    fn set_session_label(&self, session_id: i64, label: Option<&str>) -> Result<bool> {
+        let mut state = self.state.borrow_mut();
+        let Some((session, _)) = state.session_mut(session_id) else {
+            return Ok(false);
+        };
+        session.label = label.map(str::to_string);
+        Ok(true)
+    }
+
+    fn set_session_source_domain(&self, _session_id: i64, _domain: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_session_focus(&self, _session_id: i64, _focus: SessionFocus) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()> {
+        if let Some((session, _)) = self.state.borrow_mut().session_mut(session_id) {
+            session.paused_time += additional_pause_time;
+            session.paused_since = None;
+        }
+        Ok(())
+    }
+
+    fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()> {
+        if let Some((session, _)) = self.state.borrow_mut().active_session_mut(session_id) {
+            session.end_time = Some(end_time);
+            session.listened_time = Some(end_time - session.start_time - session.paused_time);
+            session.status = status.to_string();
+            session.paused_since = None;
+        }
+        Ok(())
+    }
+
+    fn update_active_sessions_progress(&self, current_time: i64, sessions: &[(i64, i64)]) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        for &(session_id, pending_pause_time) in sessions {
+            if let Some((session, last_seen)) = state.active_session_mut(session_id) {
+                session.listened_time = Some(current_time - session.start_time - session.paused_time - pending_pause_time);
+                session.paused_since = (pending_pause_time > 0).then_some(current_time - pending_pause_time);
+                *last_seen = current_time;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_session_checkpoint(&self, player_id: i64) -> Result<Option<SessionCheckpoint>> {
+        Ok(self.state.borrow().sessions
+            .iter()
+            .filter(|(session, _)| session.player_id == player_id && session.status == "active")
+            .max_by_key(|(session, _)| session.start_time)
+            .map(|(session, last_seen)| SessionCheckpoint { session: session.clone(), last_seen: *last_seen }))
+    }
+
+    fn resume_session(&self, session_id: i64, current_time: i64) -> Result<()> {
+        if let Some((session, last_seen)) = self.state.borrow_mut().active_session_mut(session_id) {
+            session.paused_time = current_time - session.start_time - session.listened_time.unwrap_or(0);
+            *last_seen = current_time;
+        }
+        Ok(())
+    }
+
+    fn close_session_at_checkpoint(&self, session_id: i64, status: &str) -> Result<()> {
+        if let Some((session, last_seen)) = self.state.borrow_mut().active_session_mut(session_id) {
+            let listened = session.listened_time.unwrap_or(0).max(0);
+            session.end_time = Some(*last_seen);
+            session.listened_time = Some(listened);
+            session.paused_time = *last_seen - session.start_time - listened;
+            session.status = status.to_string();
+        }
+        Ok(())
+    }
+
+    fn find_mergeable_session(&self, track_id: &str, player_id: i64, start_time: i64, max_gap: i64) -> Result<Option<i64>> {
+        let state = self.state.borrow();
+        let latest = state.sessions
+            .iter()
+            .filter(|(session, _)| session.player_id == player_id)
+            .max_by_key(|(session, _)| (session.start_time, session.id));
+
+        Ok(latest.and_then(|(session, _)| {
+            let gap = start_time - session.end_time?;
+            let finished = session.status != "active" && session.status != "imported";
+            (finished && session.track_id == track_id && (0..=max_gap).contains(&gap)).then_some(session.id)
+        }))
+    }
+
+    fn reopen_session(&self, session_id: i64, resume_time: i64) -> Result<()> {
+        if let Some((session, last_seen)) = self.state.borrow_mut().session_mut(session_id) {
+            let Some(end_time) = session.end_time.filter(|_| session.status != "active") else {
+                return Ok(());
+            };
+            session.paused_time += (resume_time - end_time).max(0);
+            session.end_time = None;
+            session.status = "active".to_string();
+            *last_seen = resume_time;
+        }
+        Ok(())
+    }
+
+    fn list_active_sessions(&self) -> Result<Vec<SessionWithMetadata>> {
+        let state = self.state.borrow();
+        let mut active: Vec<SessionWithMetadata> = state.sessions
+            .iter()
+            .filter(|(session, _)| session.status == "active")
+            .filter_map(|(session, _)| {
+                Some(SessionWithMetadata {
+                    session: session.clone(),
+                    track: state.tracks.get(&session.track_id)?.clone(),
+                    player: state.players.iter().find(|player| player.id == session.player_id)?.clone(),
+                })
+            })
+            .collect();
+        active.sort_by_key(|s| std::cmp::Reverse(s.session.start_time));
+        Ok(active)
+    }
+}
+
+/// `Store` for `gopald --dry-run`: keeps sessions in memory like `InMemoryStore` so the
+/// monitor works as usual, and logs every write instead of touching the database
+#[derive(Debug, Default)]
+pub struct DryRunStore {
+    inner: InMemoryStore,
+}
+
+impl DryRunStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What the monitor would have recorded
+    pub fn recorded(&self) -> &InMemoryStore {
+        &self.inner
+    }
+
+    fn track_title(&self, track_id: &str) -> String {
+        self.inner.track(track_id).map(|track| format!("{} - {}", track.artist, track.title)).unwrap_or_default()
+    }
+}
+
+impl Store for DryRunStore {
+    fn insert_or_update_player(&self, name: &str, identity: &str) -> Result<i64> {
+        let known = self.inner.players().iter().any(|player| player.name == name);
+        let player_id = self.inner.insert_or_update_player(name, identity)?;
+        if !known {
+            info!(player_id = player_id, player = name; "[dry run] Would register player {} ({})", identity, name);
+        }
+        Ok(player_id)
+    }
+
+    fn insert_or_update_track(&self, track: &Track) -> Result<()> {
+        debug!("[dry run] Would save track {:?}", track);
+        self.inner.insert_or_update_track(track)
+    }
+
+    fn start_session_as(&self, track_id: &str, player_id: i64, start_time: i64, content_type: ContentType) -> Result<i64> {
+        let session_id = self.inner.start_session_as(track_id, player_id, start_time, content_type)?;
+        info!(session_id = session_id, player_id = player_id, track_id = track_id, content_type = content_type.as_str();
+              "[dry run] Would start session {} for {} ({})", session_id, self.track_title(track_id), content_type.as_str());
+        Ok(session_id)
+    }
+
+    fn set_session_label(&self, session_id: i64, label: Option<&str>) -> Result<bool> {
+        info!("[dry run] Would label session {} as {:?}", session_id, label);
+        self.inner.set_session_label(session_id, label)
+    }
+
+    fn set_session_source_domain(&self, session_id: i64, domain: Option<&str>) -> Result<()> {
+        info!("[dry run] Would record session {} as streamed from {:?}", session_id, domain);
+        self.inner.set_session_source_domain(session_id, domain)
+    }
+
+    fn set_session_focus(&self, session_id: i64, focus: SessionFocus) -> Result<()> {
+        info!("[dry run] Would record session {} as played in the {}", session_id, focus.as_str());
+        self.inner.set_session_focus(session_id, focus)
+    }
+
+    fn update_session_pause_time(&self, session_id: i64, additional_pause_time: i64) -> Result<()> {
+        info!(session_id = session_id, pause_duration = additional_pause_time;
+              "[dry run] Would add {}s of pause to session {}", additional_pause_time, session_id);
+        self.inner.update_session_pause_time(session_id, additional_pause_time)
+    }
+
+    fn finalize_session(&self, session_id: i64, end_time: i64, status: &str) -> Result<()> {
+        self.inner.finalize_session(session_id, end_time, status)?;
+        let listened = self.inner.sessions().iter()
+            .find(|session| session.id == session_id)
+            .and_then(|session| session.listened_time);
+        info!(session_id = session_id, status = status;
+              "[dry run] Would finalize session {} as {} with {}s listened", session_id, status, listened.unwrap_or(0));
+        Ok(())
+    }
+
+    fn update_active_sessions_progress(&self, current_time: i64, sessions: &[(i64, i64)]) -> Result<()> {
+        debug!("[dry run] Would checkpoint {} active sessions", sessions.len());
+        self.inner.update_active_sessions_progress(current_time, sessions)
+    }
+
+    fn get_session_checkpoint(&self, player_id: i64) -> Result<Option<SessionCheckpoint>> {
+        self.inner.get_session_checkpoint(player_id)
+    }
+
+    fn resume_session(&self, session_id: i64, current_time: i64) -> Result<()> {
+        self.inner.resume_session(session_id, current_time)
+    }
+
+    fn close_session_at_checkpoint(&self, session_id: i64, status: &str) -> Result<()> {
+        info!("[dry run] Would close session {} at its last checkpoint as {}", session_id, status);
+        self.inner.close_session_at_checkpoint(session_id, status)
+    }
+
+    fn find_mergeable_session(&self, track_id: &str, player_id: i64, start_time: i64, max_gap: i64) -> Result<Option<i64>> {
+        self.inner.find_mergeable_session(track_id, player_id, start_time, max_gap)
+    }
+
+    fn reopen_session(&self, session_id: i64, resume_time: i64) -> Result<()> {
+        info!("[dry run] Would continue session {} for a replay", session_id);
+        self.inner.reopen_session(session_id, resume_time)
+    }
+
+    fn list_active_sessions(&self) -> Result<Vec<SessionWithMetadata>> {
+        self.inner.list_active_sessions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str) -> Track {
+        Track {
+            id: id.to_string(),
+            title: id.to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            length: Some(200_000_000),
+            genre: None,
+            raw_metadata: None,
+            art_url: None,
+            track_number: None,
+            disc_number: None,
+            primary_artist: None,
+            rating: None,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn test_starting_a_session_interrupts_the_players_active_one() {
+        let store = InMemoryStore::new();
+        let player_id = store.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap();
+        assert_eq!(store.insert_or_update_player("org.mpris.MediaPlayer2.spotify", "Spotify").unwrap(), player_id);
+
+        store.insert_or_update_track(&track("a")).unwrap();
+        store.insert_or_update_track(&track("b")).unwrap();
+        let first = store.start_session("a", player_id, 1000).unwrap();
+        store.update_session_pause_time(first, 20).unwrap();
+        store.start_session("b", player_id, 1100).unwrap();
+
+        let sessions = store.sessions();
+        assert_eq!((sessions[0].status.as_str(), sessions[0].listened_time), ("interrupted", Some(80)));
+        assert_eq!(sessions[1].status, "active");
+        assert_eq!(store.list_active_sessions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_checkpoint_resume_and_close() {
+        let store = InMemoryStore::new();
+        let player_id = store.insert_or_update_player("org.mpris.MediaPlayer2.vlc", "VLC").unwrap();
+        store.insert_or_update_track(&track("a")).unwrap();
+        let session_id = store.start_session("a", player_id, 1000).unwrap();
+
+        store.update_active_sessions_progress(1100, &[(session_id, 10)]).unwrap();
+        let checkpoint = store.get_session_checkpoint(player_id).unwrap().unwrap();
+        assert_eq!((checkpoint.last_seen, checkpoint.session.listened_time), (1100, Some(90)));
+
+        // Downtime between the checkpoint and the resume counts as pause
+        store.resume_session(session_id, 1200).unwrap();
+        store.update_active_sessions_progress(1250, &[(session_id, 0)]).unwrap();
+        store.close_session_at_checkpoint(session_id, "orphaned").unwrap();
+
+        let session = &store.sessions()[0];
+        assert_eq!((session.end_time, session.paused_time, session.listened_time), (Some(1250), 110, Some(140)));
+        assert_eq!(session.status, "orphaned");
+        assert!(store.get_session_checkpoint(player_id).unwrap().is_none());
+    }
+}